@@ -0,0 +1,202 @@
+use crate::server::PhasmoMessage;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum PlayerStatus {
+    Lobby,
+    Alive,
+    Dead,
+}
+
+// Who sent a message. An admin can be a player too, a spectator can't.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Sender {
+    pub admin: bool,
+    pub spectator: bool,
+    pub player: Option<PlayerStatus>,
+    // whether their lobby has a round going
+    pub in_round: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Requires {
+    // joining and saying hello
+    Anyone,
+    // looking but not touching, spectators too
+    Watching,
+    // any player or admin, dead or alive
+    Registered,
+    // moving around and touching things, the dead can't
+    Physical,
+    Admin,
+}
+
+impl Requires {
+    pub fn allows(self, sender: Sender) -> bool {
+        match self {
+            Requires::Anyone => true,
+            Requires::Watching => sender.admin || sender.spectator || sender.player.is_some(),
+            Requires::Registered => sender.admin || sender.player.is_some(),
+            Requires::Physical => matches!(
+                sender.player,
+                Some(PlayerStatus::Lobby) | Some(PlayerStatus::Alive)
+            ),
+            Requires::Admin => sender.admin,
+        }
+    }
+}
+
+// Every message says what it needs from its sender here, so handlers don't
+// each have to remember
+pub(crate) fn required(msg: &PhasmoMessage) -> Requires {
+    match msg {
+        PhasmoMessage::Hello { .. }
+        | PhasmoMessage::JoinLobby { .. }
+        | PhasmoMessage::ConnectAsAdmin { .. }
+        | PhasmoMessage::ConnectAsSpectator { .. }
+        | PhasmoMessage::CreateLobby {}
+        | PhasmoMessage::GetSoundManifest {} => Requires::Anyone,
+
+        PhasmoMessage::LeaveLobby {}
+        | PhasmoMessage::SetReady { .. }
+        | PhasmoMessage::GetProfile {}
+        // the dead still get a say in the journal
+        | PhasmoMessage::SubmitGuess { .. }
+        | PhasmoMessage::UnsubscribeGhostDebug {} => Requires::Registered,
+
+        PhasmoMessage::GetMap {} | PhasmoMessage::RequestFullState {} => Requires::Watching,
+
+        PhasmoMessage::LocationUpdate { .. }
+        | PhasmoMessage::SetFlashlight { .. }
+        | PhasmoMessage::SetActiveEquipment { .. }
+        | PhasmoMessage::PlaceCamera { .. }
+        | PhasmoMessage::PlaceDots { .. }
+        | PhasmoMessage::PlaceBook { .. }
+        | PhasmoMessage::PlaceCrucifix { .. }
+        | PhasmoMessage::UseSmudge {}
+        | PhasmoMessage::UseSanityPills {}
+        | PhasmoMessage::RemoveBook {}
+        | PhasmoMessage::RemoveCamera { .. }
+        | PhasmoMessage::PlaceSensor { .. }
+        | PhasmoMessage::RemoveSensor { .. }
+        | PhasmoMessage::Equip { .. }
+        | PhasmoMessage::Drop {}
+        | PhasmoMessage::SwapSlot { .. }
+        | PhasmoMessage::ReadThermometer {}
+        | PhasmoMessage::UseParabolic { .. }
+        | PhasmoMessage::ReadEmf {}
+        | PhasmoMessage::UseSpiritBox {}
+        | PhasmoMessage::UseUvLight {}
+        | PhasmoMessage::SetHidden { .. }
+        | PhasmoMessage::SetLights { .. }
+        | PhasmoMessage::SetBreaker { .. }
+        | PhasmoMessage::SetDoorState { .. } => Requires::Physical,
+
+        PhasmoMessage::StartSim { .. }
+        | PhasmoMessage::SelectMap { .. }
+        | PhasmoMessage::FastForward { .. }
+        | PhasmoMessage::PauseSim {}
+        | PhasmoMessage::ResumeSim {}
+        | PhasmoMessage::TeleportGhost { .. }
+        | PhasmoMessage::SetGhostRoom { .. }
+        | PhasmoMessage::SetGhostType { .. }
+        | PhasmoMessage::ResetSim { .. }
+        | PhasmoMessage::Kick { .. }
+        | PhasmoMessage::SubscribeGhostDebug {}
+        | PhasmoMessage::GetEventLog {}
+        | PhasmoMessage::ReloadConfig {} => Requires::Admin,
+    }
+}
+
+// Messages that mean nothing outside a running round. They get turned
+// away up front rather than each handler finding out the hard way.
+pub(crate) fn needs_round(msg: &PhasmoMessage) -> bool {
+    matches!(
+        msg,
+        PhasmoMessage::LocationUpdate { .. }
+            | PhasmoMessage::SetHidden { .. }
+            | PhasmoMessage::SetDoorState { .. }
+            | PhasmoMessage::SetLights { .. }
+            | PhasmoMessage::SetBreaker { .. }
+            | PhasmoMessage::PlaceCamera { .. }
+            | PhasmoMessage::PlaceDots { .. }
+            | PhasmoMessage::PlaceBook { .. }
+            | PhasmoMessage::PlaceCrucifix { .. }
+            | PhasmoMessage::RemoveBook {}
+            | PhasmoMessage::RemoveCamera { .. }
+            | PhasmoMessage::PlaceSensor { .. }
+            | PhasmoMessage::RemoveSensor { .. }
+            | PhasmoMessage::UseSmudge {}
+            | PhasmoMessage::UseSanityPills {}
+            | PhasmoMessage::ReadThermometer {}
+            | PhasmoMessage::UseParabolic { .. }
+            | PhasmoMessage::ReadEmf {}
+            | PhasmoMessage::UseSpiritBox {}
+            | PhasmoMessage::UseUvLight {}
+            | PhasmoMessage::SubmitGuess { .. }
+            | PhasmoMessage::FastForward { .. }
+            | PhasmoMessage::PauseSim {}
+            | PhasmoMessage::ResumeSim {}
+            | PhasmoMessage::TeleportGhost { .. }
+            | PhasmoMessage::SetGhostRoom { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn msg(value: serde_json::Value) -> PhasmoMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn sender(admin: bool, spectator: bool, player: Option<PlayerStatus>) -> Sender {
+        Sender {
+            admin,
+            spectator,
+            player,
+            in_round: player.is_some_and(|p| p != PlayerStatus::Lobby),
+        }
+    }
+
+    #[test]
+    fn who_may_send_what() {
+        let stranger = sender(false, false, None);
+        let watcher = sender(false, true, None);
+        let waiting = sender(false, false, Some(PlayerStatus::Lobby));
+        let alive = sender(false, false, Some(PlayerStatus::Alive));
+        let dead = sender(false, false, Some(PlayerStatus::Dead));
+        let admin = sender(true, false, None);
+        let everyone = [stranger, watcher, waiting, alive, dead, admin];
+
+        // stranger, watcher, waiting, alive, dead, admin
+        let table = [
+            (json!({"JoinLobby": {"name": "ann"}}), [true, true, true, true, true, true]),
+            (json!({"GetMap": {}}), [false, true, true, true, true, true]),
+            (
+                json!({"SubmitGuess": {"ghost_type": "Spirit"}}),
+                [false, false, true, true, true, true],
+            ),
+            (
+                json!({"LocationUpdate": {"location": 0}}),
+                [false, false, true, true, false, false],
+            ),
+            (json!({"UseSmudge": {}}), [false, false, true, true, false, false]),
+            (json!({"PauseSim": {}}), [false, false, false, false, false, true]),
+        ];
+        for (value, allowed) in table {
+            let needed = required(&msg(value));
+            let got: Vec<_> = everyone.iter().map(|s| needed.allows(*s)).collect();
+            assert_eq!(got, allowed, "{:?}", needed);
+        }
+    }
+
+    #[test]
+    fn round_only_messages_are_the_ones_about_the_house() {
+        assert!(needs_round(&msg(json!({"UseSmudge": {}}))));
+        assert!(needs_round(&msg(json!({"TeleportGhost": {"location": 2}}))));
+        assert!(!needs_round(&msg(json!({"SetReady": {"ready": true}}))));
+        assert!(!needs_round(&msg(json!({"GetMap": {}}))));
+    }
+}
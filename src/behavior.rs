@@ -0,0 +1,533 @@
+use std::ops::Range;
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::ghost::{EvidenceType, GhostType};
+
+use EvidenceType::*;
+
+// Everything that differs between ghost types. The defaults are a plain
+// ghost, each type only overrides what makes it special, so adding a ghost
+// means one impl here and a line in behavior_for.
+pub trait GhostBehavior: Send {
+    fn evidence(&self) -> &'static [EvidenceType];
+
+    fn has_evidence(&self, evidence: EvidenceType) -> bool {
+        self.evidence().contains(&evidence)
+    }
+
+    // how cold the favorite room gets, given the evidence showing this round
+    fn ghost_room_min_temp(&self, shown: &[EvidenceType]) -> i32 {
+        if shown.contains(&Freezing) {
+            28
+        } else {
+            35
+        }
+    }
+
+    fn max_emf(&self, shown: &[EvidenceType]) -> u32 {
+        if shown.contains(&Emf) {
+            5
+        } else {
+            3
+        }
+    }
+
+    // objects thrown by one ThrowObject interaction
+    fn max_thrown(&self) -> u32 {
+        1
+    }
+
+    // cools its room at the sim's fast cooling rate
+    fn fast_cooling(&self) -> bool {
+        false
+    }
+
+    // won't go through closed doors unless hunting
+    fn avoids_closed_doors(&self) -> bool {
+        false
+    }
+
+    // scales the time between moves outside hunts, above 1 is slower
+    fn move_interval_multiplier(&self) -> f64 {
+        1.0
+    }
+
+    // scales how fast it moves while hunting, above 1 is faster
+    fn hunt_speed(&self) -> f64 {
+        1.0
+    }
+
+    // on top of hunt_speed while electronics are on in or next to its room
+    fn electronics_hunt_speed(&self) -> f64 {
+        1.0
+    }
+
+    // scales how much more a flashlight draws it during a hunt
+    fn electronics_attraction(&self) -> f64 {
+        1.0
+    }
+
+    // added to the sanity below which it can hunt
+    fn hunt_threshold_bonus(&self) -> f64 {
+        0.0
+    }
+
+    fn interaction_multiplier(&self) -> f64 {
+        1.0
+    }
+
+    // scales how often it takes to a new favorite room
+    fn room_shift_multiplier(&self) -> f64 {
+        1.0
+    }
+
+    // scales everyone's passive sanity drain
+    fn sanity_drain_multiplier(&self) -> f64 {
+        1.0
+    }
+
+    // only answers the spirit box when the asker is alone in the room
+    fn spirit_box_alone(&self) -> bool {
+        false
+    }
+
+    // scales how long a smudge keeps it from hunting
+    fn smudge_multiplier(&self) -> f64 {
+        1.0
+    }
+
+    // where this round's personality is rolled from, see Personality
+    fn aggression_range(&self) -> Range<f64> {
+        0.75..1.25
+    }
+
+    fn wanderlust_range(&self) -> Range<f64> {
+        0.5..1.5
+    }
+
+    fn shyness_range(&self) -> Range<f64> {
+        0.0..0.5
+    }
+}
+
+// Rolled each round so two ghosts of the same type still feel different
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Personality {
+    // scales the chance of interactions, events and hunts
+    pub aggression: f64,
+    // scales how often it leaves its favorite room
+    pub wanderlust: f64,
+    // how much quieter it gets with players in the room, 0 to 1
+    pub shyness: f64,
+}
+
+impl Default for Personality {
+    fn default() -> Self {
+        Personality {
+            aggression: 1.0,
+            wanderlust: 1.0,
+            shyness: 0.0,
+        }
+    }
+}
+
+pub fn roll_personality<R: Rng + ?Sized>(behavior: &dyn GhostBehavior, rng: &mut R) -> Personality {
+    Personality {
+        aggression: rng.gen_range(behavior.aggression_range()),
+        wanderlust: rng.gen_range(behavior.wanderlust_range()),
+        shyness: rng.gen_range(behavior.shyness_range()),
+    }
+}
+
+pub fn behavior_for(ghost_type: GhostType) -> Box<dyn GhostBehavior> {
+    match ghost_type {
+        GhostType::Spirit => Box::new(Spirit),
+        GhostType::Wraith => Box::new(Wraith),
+        GhostType::Phantom => Box::new(Phantom),
+        GhostType::Poltergeist => Box::new(Poltergeist),
+        GhostType::Banshee => Box::new(Banshee),
+        GhostType::Jinn => Box::new(Jinn),
+        GhostType::Mare => Box::new(Mare),
+        GhostType::Revenant => Box::new(Revenant),
+        GhostType::Shade => Box::new(Shade),
+        GhostType::Demon => Box::new(Demon),
+        GhostType::Yurei => Box::new(Yurei),
+        GhostType::Oni => Box::new(Oni),
+        GhostType::Yokai => Box::new(Yokai),
+        GhostType::Hantu => Box::new(Hantu),
+        GhostType::Goryo => Box::new(Goryo),
+        GhostType::Myling => Box::new(Myling),
+        GhostType::Onryo => Box::new(Onryo),
+        GhostType::Twins => Box::new(Twins),
+        GhostType::Obake => Box::new(Obake),
+        GhostType::Moroi => Box::new(Moroi),
+        GhostType::Raiju => Box::new(Raiju),
+    }
+}
+
+// Harder rounds only show some of the ghost's evidence, picked here
+pub fn choose_shown_evidence<R: Rng + ?Sized>(
+    ghost_type: GhostType,
+    given: u8,
+    rng: &mut R,
+) -> Vec<EvidenceType> {
+    let mut evidence = behavior_for(ghost_type).evidence().to_vec();
+    evidence.shuffle(rng);
+    evidence.truncate(given as usize);
+    evidence
+}
+
+// A ghost fits if it has everything that was shown and wouldn't have had to
+// show more than the round allows
+pub fn is_consistent(ghost_type: GhostType, shown: &[EvidenceType], given: u8) -> bool {
+    let behavior = behavior_for(ghost_type);
+    shown.len() <= given as usize && shown.iter().all(|e| behavior.has_evidence(*e))
+}
+
+pub fn consistent_ghosts(shown: &[EvidenceType], given: u8) -> Vec<GhostType> {
+    GhostType::ALL
+        .into_iter()
+        .filter(|g| is_consistent(*g, shown, given))
+        .collect()
+}
+
+// With no evidence at all there's nothing to tell ghosts apart, so any
+// guess that fits what was seen counts
+pub fn guess_accepted(
+    guess: GhostType,
+    actual: GhostType,
+    shown: &[EvidenceType],
+    given: u8,
+) -> bool {
+    guess == actual || (given == 0 && is_consistent(guess, shown, given))
+}
+
+struct Spirit;
+
+impl GhostBehavior for Spirit {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, SpiritBox, Writing]
+    }
+
+    // a smudge keeps it off the hunt twice as long
+    fn smudge_multiplier(&self) -> f64 {
+        2.0
+    }
+}
+
+struct Wraith;
+
+impl GhostBehavior for Wraith {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, SpiritBox, Dots]
+    }
+
+    // never settles anywhere for long
+    fn room_shift_multiplier(&self) -> f64 {
+        3.0
+    }
+}
+
+struct Phantom;
+
+impl GhostBehavior for Phantom {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[SpiritBox, Ultraviolet, Dots]
+    }
+}
+
+struct Poltergeist;
+
+impl GhostBehavior for Poltergeist {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[SpiritBox, Ultraviolet, Writing]
+    }
+
+    fn max_thrown(&self) -> u32 {
+        3
+    }
+}
+
+struct Banshee;
+
+impl GhostBehavior for Banshee {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Ultraviolet, GhostOrbs, Dots]
+    }
+}
+
+struct Jinn;
+
+impl GhostBehavior for Jinn {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, Ultraviolet, Freezing]
+    }
+
+    // quick on its feet and hard on the nerves
+    fn move_interval_multiplier(&self) -> f64 {
+        0.7
+    }
+
+    fn sanity_drain_multiplier(&self) -> f64 {
+        1.5
+    }
+}
+
+struct Mare;
+
+impl GhostBehavior for Mare {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[SpiritBox, GhostOrbs, Writing]
+    }
+}
+
+struct Revenant;
+
+impl GhostBehavior for Revenant {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[GhostOrbs, Writing, Freezing]
+    }
+
+    fn wanderlust_range(&self) -> Range<f64> {
+        1.0..1.8
+    }
+
+    // slow until it's hunting, then very fast
+    fn move_interval_multiplier(&self) -> f64 {
+        1.5
+    }
+
+    fn hunt_speed(&self) -> f64 {
+        2.0
+    }
+}
+
+struct Shade;
+
+impl GhostBehavior for Shade {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, Writing, Freezing]
+    }
+
+    fn avoids_closed_doors(&self) -> bool {
+        true
+    }
+
+    fn shyness_range(&self) -> Range<f64> {
+        0.5..0.9
+    }
+
+    // quiet, and only hunts a team that's nearly gone
+    fn interaction_multiplier(&self) -> f64 {
+        0.6
+    }
+
+    fn hunt_threshold_bonus(&self) -> f64 {
+        -15.0
+    }
+
+    fn spirit_box_alone(&self) -> bool {
+        true
+    }
+}
+
+struct Demon;
+
+impl GhostBehavior for Demon {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Ultraviolet, Writing, Freezing]
+    }
+
+    fn aggression_range(&self) -> Range<f64> {
+        1.1..1.6
+    }
+
+    fn hunt_threshold_bonus(&self) -> f64 {
+        20.0
+    }
+}
+
+struct Yurei;
+
+impl GhostBehavior for Yurei {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[GhostOrbs, Freezing, Dots]
+    }
+}
+
+struct Oni;
+
+impl GhostBehavior for Oni {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, Freezing, Dots]
+    }
+}
+
+struct Yokai;
+
+impl GhostBehavior for Yokai {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[SpiritBox, GhostOrbs, Dots]
+    }
+}
+
+struct Hantu;
+
+impl GhostBehavior for Hantu {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Ultraviolet, GhostOrbs, Freezing]
+    }
+
+    fn fast_cooling(&self) -> bool {
+        true
+    }
+}
+
+struct Goryo;
+
+impl GhostBehavior for Goryo {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, Ultraviolet, Dots]
+    }
+
+    // stays put, like the real one
+    fn room_shift_multiplier(&self) -> f64 {
+        0.0
+    }
+}
+
+struct Myling;
+
+impl GhostBehavior for Myling {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, Ultraviolet, Writing]
+    }
+}
+
+struct Onryo;
+
+impl GhostBehavior for Onryo {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[SpiritBox, GhostOrbs, Freezing]
+    }
+}
+
+struct Twins;
+
+impl GhostBehavior for Twins {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, SpiritBox, Freezing]
+    }
+}
+
+struct Obake;
+
+impl GhostBehavior for Obake {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, Ultraviolet, GhostOrbs]
+    }
+}
+
+struct Moroi;
+
+impl GhostBehavior for Moroi {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[SpiritBox, Writing, Freezing]
+    }
+}
+
+struct Raiju;
+
+// Feeds on electronics, faster and more drawn to anyone with them on
+impl GhostBehavior for Raiju {
+    fn evidence(&self) -> &'static [EvidenceType] {
+        &[Emf, GhostOrbs, Dots]
+    }
+
+    fn electronics_hunt_speed(&self) -> f64 {
+        1.5
+    }
+
+    fn electronics_attraction(&self) -> f64 {
+        2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // all it takes for a new ghost is its evidence and what sets it apart
+    struct Dummy;
+
+    impl GhostBehavior for Dummy {
+        fn evidence(&self) -> &'static [EvidenceType] {
+            &[Emf, Freezing, GhostOrbs]
+        }
+
+        fn max_thrown(&self) -> u32 {
+            7
+        }
+    }
+
+    #[test]
+    fn a_new_ghost_only_says_what_makes_it_different() {
+        let dummy = Dummy;
+        assert!(dummy.has_evidence(Freezing) && !dummy.has_evidence(Dots));
+        assert_eq!(dummy.max_thrown(), 7);
+        // everything else is a plain ghost's
+        assert_eq!(dummy.ghost_room_min_temp(&[Freezing]), 28);
+        assert_eq!(dummy.ghost_room_min_temp(&[]), 35);
+        assert_eq!(dummy.max_emf(&[Emf]), 5);
+        assert_eq!(dummy.max_emf(&[Freezing]), 3);
+        assert_eq!(dummy.hunt_speed(), 1.0);
+    }
+
+    #[test]
+    fn every_ghost_has_three_kinds_of_evidence() {
+        for ghost in GhostType::ALL {
+            let evidence = behavior_for(ghost).evidence();
+            assert_eq!(evidence.len(), 3, "{:?}", ghost);
+            assert!(evidence.iter().all(|e| evidence.iter().filter(|o| *o == e).count() == 1));
+        }
+    }
+
+    #[test]
+    fn shown_evidence_is_always_the_ghosts_own() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for ghost in GhostType::ALL {
+            for given in 0..=3 {
+                let shown = choose_shown_evidence(ghost, given, &mut rng);
+                assert_eq!(shown.len(), given as usize);
+                assert!(is_consistent(ghost, &shown, given), "{:?}", ghost);
+                assert!(consistent_ghosts(&shown, given).contains(&ghost));
+            }
+        }
+        // with nothing to go on, any guess is as good as another
+        assert!(guess_accepted(GhostType::Demon, GhostType::Spirit, &[], 0));
+        assert!(!guess_accepted(GhostType::Demon, GhostType::Spirit, &[], 3));
+    }
+
+    #[test]
+    fn personalities_keep_to_the_ghosts_ranges() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mean_aggression = |ghost, rng: &mut StdRng| {
+            let behavior = behavior_for(ghost);
+            let rolls: Vec<_> =
+                (0..200).map(|_| roll_personality(behavior.as_ref(), rng)).collect();
+            for p in &rolls {
+                assert!(behavior.aggression_range().contains(&p.aggression));
+                assert!(behavior.wanderlust_range().contains(&p.wanderlust));
+                assert!(behavior.shyness_range().contains(&p.shyness));
+            }
+            rolls.iter().map(|p| p.aggression).sum::<f64>() / rolls.len() as f64
+        };
+        // a Demon's spoiling for it
+        let demon = mean_aggression(GhostType::Demon, &mut rng);
+        assert!(demon > mean_aggression(GhostType::Spirit, &mut rng));
+    }
+}
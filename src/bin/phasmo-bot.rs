@@ -0,0 +1,243 @@
+// Headless clients for load testing a running server.
+//
+//   phasmo-bot --addr 127.0.0.1:2000 --bots 20 --rate 2 --duration 1800
+//
+// Each bot joins the lobby, the one --code names if the server has more
+// than one, and then reports a random location `rate` times a
+// second. Latency is measured from a LocationUpdate to the next game update.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use phasmo_server::{
+    map::{Map, RoomRef},
+    server::{PhasmoMessage, DEFAULT_PORT, PROTOCOL, SUBPROTOCOL_JSON},
+};
+use tokio::time::{interval, sleep, Instant};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+        Message,
+    },
+    Connector,
+};
+
+struct BotConfig {
+    addr: String,
+    tls: bool,
+    bots: usize,
+    rate: f64,
+    duration: Duration,
+    code: Option<String>,
+}
+
+impl BotConfig {
+    fn from_args() -> Result<Self, String> {
+        let mut config = BotConfig {
+            addr: format!("127.0.0.1:{}", DEFAULT_PORT),
+            tls: true,
+            bots: 5,
+            rate: 1.0,
+            duration: Duration::from_secs(60),
+            code: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--addr" => config.addr = value()?,
+                "--no-tls" => config.tls = false,
+                "--bots" => config.bots = parse(&value()?)?,
+                "--rate" => config.rate = parse(&value()?)?,
+                "--duration" => config.duration = Duration::from_secs(parse(&value()?)?),
+                "--code" => config.code = Some(value()?),
+                _ => return Err(format!("Unknown argument {}", arg)),
+            }
+        }
+
+        // NaN and inf parse as f64 too
+        if !(config.rate.is_finite() && config.rate > 0.0) {
+            return Err("--rate must be a positive number".to_owned());
+        }
+        Ok(config)
+    }
+
+    fn url(&self) -> String {
+        let scheme = if self.tls { "wss" } else { "ws" };
+        format!("{}://{}", scheme, self.addr)
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {}", value))
+}
+
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    updates_received: usize,
+    errors: usize,
+    disconnects: usize,
+}
+
+impl Stats {
+    fn print_summary(&mut self) {
+        self.latencies.sort();
+        let percentile = |p: usize| {
+            if self.latencies.is_empty() {
+                Duration::ZERO
+            } else {
+                self.latencies[(self.latencies.len() - 1) * p / 100]
+            }
+        };
+
+        println!("Updates received: {}", self.updates_received);
+        println!("Latency samples:  {}", self.latencies.len());
+        println!("Latency p50:      {:?}", percentile(50));
+        println!("Latency p95:      {:?}", percentile(95));
+        println!("Latency max:      {:?}", percentile(100));
+        println!("Errors:           {}", self.errors);
+        println!("Disconnects:      {}", self.disconnects);
+    }
+}
+
+fn is_game_update(text: &str) -> bool {
+    let value: Result<serde_json::Value, _> = serde_json::from_str(text);
+    match value {
+        Ok(serde_json::Value::Object(obj)) => {
+            obj.contains_key("Lobby") || obj.contains_key("Sim")
+        }
+        _ => false,
+    }
+}
+
+async fn run_bot(id: usize, config: Arc<BotConfig>, stats: Arc<Mutex<Stats>>) {
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let connector = if config.tls {
+        Connector::NativeTls(connector)
+    } else {
+        Connector::Plain
+    };
+
+    let mut request = config.url().into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(SUBPROTOCOL_JSON));
+    let ws_stream = connect_async_tls_with_config(request, None, false, Some(connector)).await;
+    let (mut outgoing, mut incoming) = match ws_stream {
+        Ok((ws_stream, _)) => ws_stream.split(),
+        Err(e) => {
+            println!("Bot {} failed to connect: {}", id, e);
+            stats.lock().unwrap().errors += 1;
+            return;
+        }
+    };
+
+    let name = format!("bot-{}", id);
+    let rooms = Map::new().rooms.len();
+    let to_message = |msg: &PhasmoMessage| Message::text(serde_json::to_string(msg).unwrap());
+
+    let hello = PhasmoMessage::Hello {
+        protocol: PROTOCOL,
+        encoding: None,
+    };
+    let join = PhasmoMessage::JoinLobby {
+        name: name.clone(),
+        protocol: PROTOCOL,
+        code: config.code.clone(),
+        deltas: false,
+    };
+    // bots are always ready, only real players hold up a start
+    let ready = PhasmoMessage::SetReady { ready: true };
+    for msg in [hello, join, ready] {
+        if outgoing.send(to_message(&msg)).await.is_err() {
+            stats.lock().unwrap().errors += 1;
+            return;
+        }
+    }
+
+    let mut ticker = interval(Duration::from_secs_f64(1.0 / config.rate));
+    let mut last_sent: Option<Instant> = None;
+    let deadline = sleep(config.duration);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = ticker.tick() => {
+                let location = rand::Rng::gen_range(&mut rand::thread_rng(), 0..rooms);
+                let location = RoomRef::Label(location);
+                let update = PhasmoMessage::LocationUpdate { location };
+                if outgoing.send(to_message(&update)).await.is_err() {
+                    stats.lock().unwrap().errors += 1;
+                    break;
+                }
+                last_sent.get_or_insert_with(Instant::now);
+            }
+            msg = incoming.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    if is_game_update(&text) {
+                        let mut stats = stats.lock().unwrap();
+                        stats.updates_received += 1;
+                        if let Some(sent) = last_sent.take() {
+                            stats.latencies.push(sent.elapsed());
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    println!("Bot {} disconnected", id);
+                    stats.lock().unwrap().disconnects += 1;
+                    return;
+                }
+                Some(Ok(_)) => (),
+                Some(Err(e)) => {
+                    println!("Bot {} error: {}", id, e);
+                    stats.lock().unwrap().errors += 1;
+                    return;
+                }
+            }
+        }
+    }
+
+    let _ = outgoing.send(Message::Close(None)).await;
+}
+
+#[tokio::main]
+async fn main() {
+    let config = match BotConfig::from_args() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "Running {} bots against {} for {:?}",
+        config.bots,
+        config.url(),
+        config.duration
+    );
+
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let handles: Vec<_> = (0..config.bots)
+        .map(|id| tokio::spawn(run_bot(id, config.clone(), stats.clone())))
+        .collect();
+
+    for handle in handles {
+        if handle.await.is_err() {
+            stats.lock().unwrap().errors += 1;
+        }
+    }
+
+    stats.lock().unwrap().print_summary();
+}
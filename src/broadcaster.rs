@@ -0,0 +1,801 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc::UnboundedReceiver,
+    time::{interval, MissedTickBehavior},
+};
+use serde_json::{Map, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    server::{Encoding, Handle, PeerMap},
+    sim::GameUpdate,
+};
+
+// Clients on incremental lobby events still get a full lobby this often,
+// in case they missed something
+const LOBBY_RESYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+// A state, and the copies of it players get when they can't see all of it
+pub(crate) struct Frame {
+    pub full: GameUpdate,
+    // by player, anyone without one gets the full state
+    pub views: HashMap<SocketAddr, GameUpdate>,
+    // for peers that aren't playing or watching, none sends them the full
+    // state as well
+    pub outsider: Option<GameUpdate>,
+}
+
+impl Frame {
+    pub fn new(full: GameUpdate) -> Self {
+        Frame {
+            full,
+            views: HashMap::new(),
+            outsider: None,
+        }
+    }
+}
+
+// Which of a frame's states a peer gets
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Full,
+    View(SocketAddr),
+    Outsider,
+}
+
+// What a delta peer was last sent. None until it has something to build
+// on, so the next one goes out whole.
+#[derive(Default)]
+struct DeltaBase {
+    seq: u64,
+    last: Option<Map<String, Value>>,
+}
+
+// Peers on SimDelta and where each of them is
+struct Deltas {
+    peers: Handle<HashSet<SocketAddr>>,
+    bases: HashMap<SocketAddr, DeltaBase>,
+}
+
+impl Deltas {
+    fn wants(&self, addr: &SocketAddr) -> bool {
+        self.peers.lock().unwrap().contains(addr)
+    }
+
+    // The next state to a peer goes out whole
+    fn reset(&mut self, addr: SocketAddr) {
+        if let Some(base) = self.bases.get_mut(&addr) {
+            base.last = None;
+        }
+    }
+
+    fn reset_all(&mut self) {
+        for base in self.bases.values_mut() {
+            base.last = None;
+        }
+    }
+}
+
+pub(crate) enum Outbound {
+    // the latest full state, held until the next flush. An older one still
+    // pending is dropped. Boxed, a frame is a few states.
+    State(Box<Frame>),
+    // sends the pending state right away, for things that can't wait
+    Flush,
+    // everyone gets this one, in order
+    Broadcast(GameUpdate),
+    Direct(SocketAddr, GameUpdate),
+    // player list changes. Peers in the event set get the events, the rest
+    // get the snapshot.
+    Lobby {
+        events: Vec<(GameUpdate, Vec<SocketAddr>)>,
+        snapshot: GameUpdate,
+    },
+    // only for admins watching the ghost
+    Debug(GameUpdate),
+}
+
+// Serializes and fans out everything sent to peers, so none of that
+// happens while the sim or a message handler holds a lock. States go out at
+// most `rate` times a second. Notices in them only go to each peer once.
+// Peers that asked for deltas get only what changed since their last state.
+pub(crate) async fn run_broadcaster(
+    peer_map: PeerMap,
+    lobby_peers: Handle<HashSet<SocketAddr>>,
+    debug_peers: Handle<HashSet<SocketAddr>>,
+    unredacted: Handle<HashSet<SocketAddr>>,
+    delta_peers: Handle<HashSet<SocketAddr>>,
+    mut rx: UnboundedReceiver<Outbound>,
+    rate: u32,
+) {
+    let mut ticker = interval(Duration::from_secs_f64(1.0 / rate as f64));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut resync = interval(LOBBY_RESYNC_INTERVAL);
+
+    let mut pending: Option<Box<Frame>> = None;
+    // the newest notice each peer has been sent
+    let mut cursors = HashMap::new();
+    let mut deltas = Deltas {
+        peers: delta_peers,
+        bases: HashMap::new(),
+    };
+    // the lobby as last sent, only kept until the round starts
+    let mut last_lobby = None;
+    loop {
+        tokio::select! {
+            out = rx.recv() => match out {
+                // the newest state carries every notice the older one did
+                Some(Outbound::State(frame)) => pending = Some(frame),
+                Some(Outbound::Flush) => flush(
+                    &peer_map,
+                    &unredacted,
+                    &mut cursors,
+                    &mut deltas,
+                    &mut pending,
+                    &mut last_lobby,
+                ),
+                Some(Outbound::Broadcast(update)) => {
+                    // keeps it ordered after the states queued before it
+                    flush(
+                        &peer_map,
+                        &unredacted,
+                        &mut cursors,
+                        &mut deltas,
+                        &mut pending,
+                        &mut last_lobby,
+                    );
+                    send_all(&peer_map, &update)
+                }
+                Some(Outbound::Direct(addr, update)) => {
+                    let since = advance_cursor(&mut cursors, addr, &update);
+                    send_direct(&peer_map, &mut deltas, addr, &update, since);
+                    // a joiner's lobby is as new as any, the next events
+                    // are measured against it
+                    if matches!(update, GameUpdate::Lobby { .. }) {
+                        last_lobby = Some(update);
+                    }
+                }
+                Some(Outbound::Lobby { events, snapshot }) => {
+                    flush(
+                        &peer_map,
+                        &unredacted,
+                        &mut cursors,
+                        &mut deltas,
+                        &mut pending,
+                        &mut last_lobby,
+                    );
+                    // a delta on top of a round from before would be wrong
+                    deltas.reset_all();
+                    let incremental = lobby_peers.lock().unwrap().clone();
+                    for (event, skip) in events {
+                        send_to(&peer_map, &event, |addr| {
+                            incremental.contains(addr) && !skip.contains(addr)
+                        });
+                    }
+                    // the events only say who's in it, anything else that
+                    // changed needs the whole lobby
+                    let all = !same_but_players(last_lobby.as_ref(), &snapshot);
+                    send_to(&peer_map, &snapshot, |addr| all || !incremental.contains(addr));
+                    last_lobby = Some(snapshot);
+                }
+                Some(Outbound::Debug(update)) => {
+                    let watching = debug_peers.lock().unwrap().clone();
+                    send_to(&peer_map, &update, |addr| watching.contains(addr));
+                }
+                None => break,
+            },
+            _ = ticker.tick() => flush(
+                &peer_map,
+                &unredacted,
+                &mut cursors,
+                &mut deltas,
+                &mut pending,
+                &mut last_lobby,
+            ),
+            _ = resync.tick() => {
+                if let Some(lobby) = &last_lobby {
+                    let incremental = lobby_peers.lock().unwrap().clone();
+                    send_to(&peer_map, lobby, |addr| incremental.contains(addr));
+                }
+            }
+        }
+    }
+}
+
+fn flush(
+    peer_map: &PeerMap,
+    unredacted: &Handle<HashSet<SocketAddr>>,
+    cursors: &mut HashMap<SocketAddr, u64>,
+    deltas: &mut Deltas,
+    pending: &mut Option<Box<Frame>>,
+    last_lobby: &mut Option<GameUpdate>,
+) {
+    if let Some(frame) = pending.take() {
+        send_frame(peer_map, unredacted, cursors, deltas, &frame);
+        *last_lobby = match frame.full {
+            GameUpdate::Lobby { .. } => Some(frame.full),
+            _ => None,
+        };
+    }
+}
+
+// Admins and spectators get the full state, players their own view and
+// everyone else the outsider's. Peers that are caught up on notices share
+// the serialization, delta peers share the fields they're diffed from.
+fn send_frame(
+    peer_map: &PeerMap,
+    unredacted: &Handle<HashSet<SocketAddr>>,
+    cursors: &mut HashMap<SocketAddr, u64>,
+    deltas: &mut Deltas,
+    frame: &Frame,
+) {
+    let unredacted = unredacted.lock().unwrap().clone();
+    let delta_peers = deltas.peers.lock().unwrap().clone();
+    let peers: Vec<_> = peer_map
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(addr, peer)| (*addr, peer.tx.clone(), peer.encoding))
+        .collect();
+    // whoever's gone doesn't need a cursor
+    cursors.retain(|addr, _| peers.iter().any(|(peer, _, _)| peer == addr));
+    deltas.bases.retain(|addr, _| delta_peers.contains(addr));
+
+    let mut messages = HashMap::new();
+    let mut fields = HashMap::new();
+    for (addr, peer, encoding) in peers {
+        let source = if frame.outsider.is_none() || unredacted.contains(&addr) {
+            Source::Full
+        } else if frame.views.contains_key(&addr) {
+            Source::View(addr)
+        } else {
+            Source::Outsider
+        };
+        let since = advance_cursor(cursors, addr, &frame.full);
+        let update = match source {
+            Source::Full => &frame.full,
+            Source::View(addr) => &frame.views[&addr],
+            Source::Outsider => frame.outsider.as_ref().unwrap(),
+        };
+        let msg = if delta_peers.contains(&addr) {
+            let base = deltas.bases.entry(addr).or_default();
+            match fields.entry((source, since)).or_insert_with(|| sim_fields(update, since)) {
+                Some(fields) => next_delta(base, fields, encoding),
+                None => {
+                    base.last = None;
+                    let msg = messages.entry((source, since, encoding));
+                    msg.or_insert_with(|| encoding.message(update)).clone()
+                }
+            }
+        } else {
+            let msg = messages.entry((source, since, encoding));
+            msg.or_insert_with(|| to_message_since(update, since, encoding)).clone()
+        };
+        if let Err(e) = peer.unbounded_send(msg) {
+            println!("Send failed: {}", e);
+        }
+    }
+}
+
+// Whether the lobby's the same as last sent, other than the players in it
+fn same_but_players(last: Option<&GameUpdate>, lobby: &GameUpdate) -> bool {
+    let without_players = |update: &GameUpdate| {
+        let mut value = serde_json::to_value(update).unwrap();
+        if let Some(lobby) = value.get_mut("Lobby") {
+            lobby["players"] = Value::Null;
+        }
+        value
+    };
+    last.is_some_and(|last| without_players(last) == without_players(lobby))
+}
+
+// Moves a peer's cursor up to the state's newest notice and returns where
+// it was. A peer's first state only sets it, what came before is stale.
+fn advance_cursor(
+    cursors: &mut HashMap<SocketAddr, u64>,
+    addr: SocketAddr,
+    update: &GameUpdate,
+) -> u64 {
+    match update {
+        GameUpdate::Sim { last_notice, .. } => {
+            cursors.insert(addr, *last_notice).unwrap_or(*last_notice)
+        }
+        _ => 0,
+    }
+}
+
+// A Sim's fields as they'd be sent, without the notices up to `since`.
+// None for anything that isn't a Sim.
+fn sim_fields(update: &GameUpdate, since: u64) -> Option<Map<String, Value>> {
+    if !matches!(update, GameUpdate::Sim { .. }) {
+        return None;
+    }
+    let mut update = update.clone();
+    if let GameUpdate::Sim { notifications, .. } = &mut update {
+        notifications.retain(|n| n.id > since);
+    }
+    match serde_json::to_value(&update).unwrap() {
+        Value::Object(mut tagged) => match tagged.remove("Sim") {
+            Some(Value::Object(fields)) => Some(fields),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// What changed since the peer's last state. Notices are new every time
+// so they're only there when there are some. A field that's gone, say a
+// redacted one, can't be said in a delta, so that one goes out whole.
+fn next_delta(
+    base: &mut DeltaBase,
+    fields: &Map<String, Value>,
+    encoding: Encoding,
+) -> Message {
+    let full = match &base.last {
+        Some(last) => last.keys().any(|key| !fields.contains_key(key)),
+        None => true,
+    };
+    let changes: Map<String, Value> = if full {
+        fields.clone()
+    } else {
+        let last = base.last.as_ref().unwrap();
+        fields
+            .iter()
+            .filter(|(key, value)| match key.as_str() {
+                "notifications" => value.as_array().is_some_and(|n| !n.is_empty()),
+                _ => last.get(*key) != Some(*value),
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    };
+    base.seq += 1;
+    base.last = Some(fields.clone());
+    encoding.message(&GameUpdate::SimDelta {
+        seq: base.seq,
+        full,
+        changes,
+    })
+}
+
+// Leaves out the notices up to `since`, ids start at 1 so 0 keeps them all
+fn to_message_since(update: &GameUpdate, since: u64, encoding: Encoding) -> Message {
+    match update {
+        GameUpdate::Sim { notifications, .. } if notifications.iter().any(|n| n.id <= since) => {
+            let mut update = update.clone();
+            if let GameUpdate::Sim { notifications, .. } = &mut update {
+                notifications.retain(|n| n.id > since);
+            }
+            encoding.message(&update)
+        }
+        _ => encoding.message(update),
+    }
+}
+
+fn send_all(peer_map: &PeerMap, update: &GameUpdate) {
+    send_to(peer_map, update, |_| true);
+}
+
+// Serialized once for each encoding someone's using
+fn send_to(peer_map: &PeerMap, update: &GameUpdate, filter: impl Fn(&SocketAddr) -> bool) {
+    let peers: Vec<_> = peer_map
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(addr, _)| filter(addr))
+        .map(|(_, peer)| (peer.tx.clone(), peer.encoding))
+        .collect();
+    let mut messages = HashMap::new();
+    for (peer, encoding) in peers {
+        let msg = messages.entry(encoding).or_insert_with(|| encoding.message(update));
+        if let Err(e) = peer.unbounded_send(msg.clone()) {
+            println!("Send failed: {}", e);
+        }
+    }
+}
+
+// A state sent straight to a delta peer is one it asked for whole, so it
+// starts over from there
+fn send_direct(
+    peer_map: &PeerMap,
+    deltas: &mut Deltas,
+    addr: SocketAddr,
+    update: &GameUpdate,
+    since: u64,
+) {
+    let peer = peer_map.lock().unwrap().get(&addr).map(|p| (p.tx.clone(), p.encoding));
+    if let Some((peer, encoding)) = peer {
+        let fields = if deltas.wants(&addr) {
+            sim_fields(update, since)
+        } else {
+            None
+        };
+        let msg = match fields {
+            Some(fields) => {
+                deltas.reset(addr);
+                next_delta(deltas.bases.entry(addr).or_default(), &fields, encoding)
+            }
+            None => to_message_since(update, since, encoding),
+        };
+        if let Err(e) = peer.unbounded_send(msg) {
+            println!("Send to {} failed: {}", addr, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use futures_channel::mpsc::{unbounded, UnboundedReceiver as PeerRx};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+    use serde_json::json;
+
+    use crate::{
+        map::MapCatalog,
+        messages::{MessageKey, Notice, NoticeEntry},
+        server::Peer,
+        sim::{Difficulty, OptionOverrides, Simulation},
+    };
+
+    fn addr(n: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 6000 + n))
+    }
+
+    fn handle<T>(value: T) -> Handle<T> {
+        Arc::new(Mutex::new(value))
+    }
+
+    // A broadcaster running for peers that all speak JSON
+    struct Harness {
+        tx: UnboundedSender<Outbound>,
+        peer_map: PeerMap,
+        peers: Vec<PeerRx<Message>>,
+        // the peers on incremental lobby events
+        lobby_peers: Handle<HashSet<SocketAddr>>,
+        // the admins watching the ghost think
+        debug_peers: Handle<HashSet<SocketAddr>>,
+        delta_peers: Handle<HashSet<SocketAddr>>,
+    }
+
+    fn harness(peers: u16, rate: u32) -> Harness {
+        let peer_map = handle(HashMap::new());
+        let rxs = (0..peers).map(|n| connect(&peer_map, n)).collect();
+        let lobby_peers = handle(HashSet::new());
+        let debug_peers = handle(HashSet::new());
+        let delta_peers = handle(HashSet::new());
+        let (tx, rx) = unbounded_channel();
+        let broadcaster = run_broadcaster(
+            peer_map.clone(),
+            lobby_peers.clone(),
+            debug_peers.clone(),
+            handle(HashSet::new()),
+            delta_peers.clone(),
+            rx,
+            rate,
+        );
+        tokio::spawn(broadcaster);
+        Harness {
+            tx,
+            peer_map,
+            peers: rxs,
+            lobby_peers,
+            debug_peers,
+            delta_peers,
+        }
+    }
+
+    fn connect(peer_map: &PeerMap, n: u16) -> PeerRx<Message> {
+        let (tx, rx) = unbounded();
+        let peer = Peer {
+            tx,
+            subprotocol: None,
+            encoding: Encoding::Json,
+            protocol: Some(3),
+        };
+        peer_map.lock().unwrap().insert(addr(n), peer);
+        rx
+    }
+
+    impl Harness {
+        fn send(&self, out: Outbound) {
+            self.tx.send(out).ok().unwrap();
+        }
+    }
+
+    // Whatever the peer's been sent so far
+    fn received(peer: &mut PeerRx<Message>) -> Vec<Value> {
+        let mut seen = Vec::new();
+        while let Ok(Some(msg)) = peer.try_next() {
+            seen.push(serde_json::from_str(msg.to_text().unwrap()).unwrap());
+        }
+        seen
+    }
+
+    // long enough for the broadcaster to get through what it's been sent
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    fn state(sim: &Simulation) -> Outbound {
+        Outbound::State(Box::new(Frame::new(sim.get_gameupdate())))
+    }
+
+    #[tokio::test]
+    async fn states_queued_together_go_out_once() {
+        // a second between flushes, long past the test
+        let mut harness = harness(2, 1);
+        settle().await;
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        for count in 1..=5 {
+            sim.set_spectators(count);
+            harness.send(state(&sim));
+        }
+        harness.send(Outbound::Flush);
+        settle().await;
+        for peer in &mut harness.peers {
+            let seen = received(peer);
+            assert_eq!(seen.len(), 1);
+            assert_eq!(seen[0]["Lobby"]["spectators"], 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_broadcast_goes_out_after_the_state_before_it() {
+        let mut harness = harness(1, 1);
+        settle().await;
+        let sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        harness.send(state(&sim));
+        harness.send(Outbound::Broadcast(sim.get_map()));
+        settle().await;
+        let seen = received(&mut harness.peers[0]);
+        let kinds: Vec<_> = seen.iter().map(|v| v.as_object().unwrap().keys().next()).collect();
+        assert_eq!(kinds, [Some(&"Lobby".to_owned()), Some(&"Map".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn ghost_debug_only_goes_to_those_watching() {
+        let mut harness = harness(3, 1);
+        settle().await;
+        let debug = || Outbound::Debug(GameUpdate::GhostDebug { decisions: Vec::new() });
+        harness.debug_peers.lock().unwrap().insert(addr(1));
+        harness.send(debug());
+        settle().await;
+        let seen: Vec<_> = harness.peers.iter_mut().map(|p| received(p).len()).collect();
+        assert_eq!(seen, [0, 1, 0]);
+
+        // unsubscribing, or leaving, stops it
+        harness.debug_peers.lock().unwrap().clear();
+        harness.send(debug());
+        settle().await;
+        let seen: Vec<_> = harness.peers.iter_mut().map(|p| received(p).len()).collect();
+        assert_eq!(seen, [0, 0, 0]);
+    }
+
+    // What a peer gets over a second of a new state every sim tick
+    async fn states_in_a_second(sim_rate: u32, broadcast_rate: u32) -> usize {
+        let mut harness = harness(1, broadcast_rate);
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        let tick = Duration::from_secs_f64(1.0 / sim_rate as f64);
+        for count in 0..sim_rate as usize {
+            sim.set_spectators(count);
+            harness.send(state(&sim));
+            tokio::time::sleep(tick).await;
+        }
+        received(&mut harness.peers[0]).len()
+    }
+
+    #[tokio::test]
+    async fn states_go_out_at_the_broadcast_rate() {
+        let slow = states_in_a_second(40, 4).await;
+        assert!((3..=6).contains(&slow), "{}", slow);
+        let fast = states_in_a_second(20, 10).await;
+        assert!((8..=12).contains(&fast), "{}", fast);
+    }
+
+    #[tokio::test]
+    async fn urgent_states_dont_wait_for_the_next_flush() {
+        let mut harness = harness(1, 1);
+        settle().await;
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        for count in 1..=3 {
+            sim.set_spectators(count);
+            harness.send(state(&sim));
+            harness.send(Outbound::Flush);
+        }
+        settle().await;
+        assert_eq!(received(&mut harness.peers[0]).len(), 3);
+    }
+
+    // ann's in the lobby on incremental events when bob joins, and the
+    // map's changed or not along with it
+    async fn bob_joining(map: &str) -> Vec<String> {
+        let mut harness = harness(1, 1);
+        harness.lobby_peers.lock().unwrap().insert(addr(0));
+        settle().await;
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.add_player(addr(0), "ann").unwrap();
+        harness.send(Outbound::Direct(addr(0), sim.get_gameupdate()));
+
+        sim.take_lobby_events();
+        sim.add_player(addr(1), "bob").unwrap();
+        sim.select_map(map).unwrap();
+        let events = sim.take_lobby_events();
+        let snapshot = sim.get_gameupdate();
+        harness.send(Outbound::Lobby { events, snapshot });
+        settle().await;
+        let seen = received(&mut harness.peers[0]);
+        seen.iter().map(|v| v.as_object().unwrap().keys().next().unwrap().clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn incremental_peers_only_get_the_whole_lobby_when_more_than_players_changed() {
+        assert_eq!(bob_joining("default").await, ["Lobby", "PlayerJoined"]);
+        assert_eq!(bob_joining("school").await, ["Lobby", "PlayerJoined", "Lobby"]);
+    }
+
+    // A round's state, with notices up to `last` as if it'd had that many
+    fn noticed(sim: &Simulation, last: u64) -> Outbound {
+        let mut update = sim.get_gameupdate();
+        if let GameUpdate::Sim {
+            notifications,
+            last_notice,
+            ..
+        } = &mut update
+        {
+            *notifications = (1..=last)
+                .map(|id| NoticeEntry {
+                    id,
+                    notice: Notice::new(MessageKey::HuntStarted, json!({}), id.to_string()),
+                })
+                .collect();
+            *last_notice = last;
+        }
+        Outbound::State(Box::new(Frame::new(update)))
+    }
+
+    fn running() -> Simulation {
+        running_with(sim_tunables())
+    }
+
+    fn sim_tunables() -> OptionOverrides {
+        OptionOverrides {
+            setup_duration: Some(0.0),
+            ..Default::default()
+        }
+    }
+
+    fn running_with(tunables: OptionOverrides) -> Simulation {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.set_tunables(tunables).unwrap();
+        sim.add_player(addr(9), "ann").unwrap();
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        sim
+    }
+
+    // The ids of every notice in what the peer's been sent
+    fn notice_ids(peer: &mut PeerRx<Message>) -> Vec<u64> {
+        let seen = received(peer);
+        let notices = seen.iter().flat_map(|v| v["Sim"]["notifications"].as_array().unwrap());
+        notices.map(|n| n["id"].as_u64().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn a_late_peer_gets_no_stale_notices() {
+        let mut harness = harness(1, 1);
+        settle().await;
+        let sim = running();
+        // a peer's first state is where it starts from
+        harness.send(noticed(&sim, 3));
+        harness.send(Outbound::Flush);
+        settle().await;
+        assert_eq!(notice_ids(&mut harness.peers[0]), Vec::<u64>::new());
+        harness.send(noticed(&sim, 5));
+        harness.send(Outbound::Flush);
+        settle().await;
+        assert_eq!(notice_ids(&mut harness.peers[0]), [4, 5]);
+
+        let mut late = connect(&harness.peer_map, 1);
+        harness.send(noticed(&sim, 6));
+        harness.send(Outbound::Flush);
+        settle().await;
+        assert_eq!(notice_ids(&mut harness.peers[0]), [6]);
+        assert_eq!(notice_ids(&mut late), Vec::<u64>::new());
+        harness.send(noticed(&sim, 7));
+        harness.send(Outbound::Flush);
+        settle().await;
+        assert_eq!(notice_ids(&mut late), [7]);
+    }
+
+    #[tokio::test]
+    async fn notices_back_to_back_all_arrive_once() {
+        let mut harness = harness(1, 1);
+        settle().await;
+        let sim = running();
+        harness.send(noticed(&sim, 0));
+        harness.send(Outbound::Flush);
+        for last in [2, 4] {
+            harness.send(noticed(&sim, last));
+            harness.send(Outbound::Flush);
+        }
+        // the second replaces the first before it's sent, and has its notices
+        harness.send(noticed(&sim, 6));
+        harness.send(noticed(&sim, 8));
+        harness.send(Outbound::Flush);
+        settle().await;
+        assert_eq!(notice_ids(&mut harness.peers[0]), [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    // A state from every few seconds of a busy round, deltas for peer 1 and
+    // whole states for peer 0
+    async fn busy_round(harness: &Harness, sim: &mut Simulation) {
+        harness.delta_peers.lock().unwrap().insert(addr(1));
+        sim.update_player_loc(addr(9), 0).unwrap();
+        for _ in 0..20 {
+            sim.advance(Duration::from_secs(3));
+            harness.send(state(sim));
+            harness.send(Outbound::Flush);
+        }
+        settle().await;
+    }
+
+    // The notices aside, which only ever come as new ones
+    fn without_notices(mut fields: Map<String, Value>) -> Map<String, Value> {
+        fields.remove("notifications");
+        fields
+    }
+
+    #[tokio::test]
+    async fn deltas_add_up_to_the_whole_state() {
+        let mut harness = harness(2, 1);
+        settle().await;
+        let mut tunables = sim_tunables();
+        tunables.ghost_interaction_rate = Some(6.0);
+        let mut sim = running_with(tunables);
+        busy_round(&harness, &mut sim).await;
+
+        let deltas = received(&mut harness.peers[1]);
+        let mut built = Map::new();
+        for (n, delta) in deltas.iter().enumerate() {
+            let delta = &delta["SimDelta"];
+            assert_eq!(delta["seq"], n as u64 + 1);
+            assert_eq!(delta["full"], n == 0, "{}", n);
+            for (key, value) in delta["changes"].as_object().unwrap() {
+                built.insert(key.clone(), value.clone());
+            }
+        }
+        // some of them only said what changed
+        let sizes = deltas.iter().map(|d| d["SimDelta"]["changes"].as_object().unwrap().len());
+        assert!(sizes.min().unwrap() < built.len());
+        let whole = received(&mut harness.peers[0]).pop().unwrap();
+        let whole = whole["Sim"].as_object().unwrap().clone();
+        assert_eq!(without_notices(built), without_notices(whole));
+    }
+
+    #[tokio::test]
+    async fn a_state_asked_for_starts_the_deltas_again() {
+        let mut harness = harness(2, 1);
+        settle().await;
+        let mut sim = running();
+        busy_round(&harness, &mut sim).await;
+        let seen = received(&mut harness.peers[1]);
+        let last = seen.last().unwrap()["SimDelta"]["seq"].as_u64().unwrap();
+
+        harness.send(Outbound::Direct(addr(1), sim.get_gameupdate()));
+        sim.advance(Duration::from_secs(3));
+        harness.send(state(&sim));
+        harness.send(Outbound::Flush);
+        settle().await;
+        let seen = received(&mut harness.peers[1]);
+        let flags: Vec<_> = seen.iter().map(|d| d["SimDelta"]["full"].clone()).collect();
+        assert_eq!(flags, [true, false]);
+        assert_eq!(seen[0]["SimDelta"]["seq"], last + 1);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fs, path::Path, str::FromStr};
+
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::sim::OptionOverrides;
+
+// Loaded at startup if it's there, --config points somewhere else
+pub const CONFIG_PATH: &str = "phasmo.toml";
+// Sim tunables on their own, read if it's there and nothing else says
+// where they are
+pub const SIM_OPTIONS_PATH: &str = "sim_options.json";
+
+// Everything the server can be configured with. The command line wins over
+// the file, which wins over the defaults. Only log_level and sim are picked
+// up again on a reload, the rest needs a restart.
+//
+// bind = "0.0.0.0:2000"
+// map = "maps/farmhouse.json"
+// sim_rate = 10
+// broadcast_rate = 10
+// persist = true
+// tls = true
+// keystore = "identity.p12"
+// keystore_pass = "secret"
+// cert_cache = "cert"
+// require_subprotocol = false
+// admin_token = "secret"
+// insecure_admin = false
+// redact = true
+// peer_timeout = 15
+// seed = 42
+// record = "events.jsonl"
+// log_level = "info"
+//
+// [sim]
+// sanity_drain_rate = 0.1
+//
+// sim takes the same keys as a custom difficulty. They can go in a file of
+// their own instead, named by sim_options, but not both.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub bind: Option<String>,
+    pub map: Option<String>,
+    pub sim_rate: Option<u32>,
+    pub broadcast_rate: Option<u32>,
+    pub persist: Option<bool>,
+    pub tls: Option<bool>,
+    pub keystore: Option<String>,
+    pub keystore_pass: Option<String>,
+    pub cert_cache: Option<String>,
+    pub require_subprotocol: Option<bool>,
+    pub admin_token: Option<String>,
+    // anyone can be admin when there's no token
+    pub insecure_admin: Option<bool>,
+    pub redact: Option<bool>,
+    // seconds
+    pub peer_timeout: Option<u64>,
+    pub seed: Option<u64>,
+    pub record: Option<String>,
+    pub log_level: Option<String>,
+    pub sim: Option<OptionOverrides>,
+    pub sim_options: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let file: ConfigFile = toml::from_str(&text).map_err(|e| bad_config(path, &text, e))?;
+        file.log_level()?;
+        if file.sim.is_some() && file.sim_options.is_some() {
+            return Err(format!("Bad config {}: has both sim and sim_options", path));
+        }
+        Ok(file)
+    }
+
+    // The sim tunables, from the file --sim-options named, then the config,
+    // then sim_options.json. Whether they're in range is up to the sim.
+    pub fn sim_tunables(&self, flag: Option<&str>) -> Result<OptionOverrides, String> {
+        let path = match (flag, &self.sim, &self.sim_options) {
+            (Some(path), _, _) => path,
+            (None, Some(sim), _) => return Ok(sim.clone()),
+            (None, None, Some(path)) => path.as_str(),
+            (None, None, None) if Path::new(SIM_OPTIONS_PATH).exists() => SIM_OPTIONS_PATH,
+            (None, None, None) => return Ok(OptionOverrides::default()),
+        };
+        let json =
+            fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Bad sim options {}: {}", path, e))
+    }
+
+    pub fn log_level(&self) -> Result<Option<LevelFilter>, String> {
+        self.log_level
+            .as_deref()
+            .map(|level| {
+                LevelFilter::from_str(level).map_err(|_| format!("Bad config log_level: {}", level))
+            })
+            .transpose()
+    }
+
+    // Keys that changed but won't do anything until a restart
+    pub fn restart_only_changes(&self, new: &ConfigFile) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        let mut check = |differs: bool, key| {
+            if differs {
+                changed.push(key);
+            }
+        };
+        check(self.bind != new.bind, "bind");
+        check(self.map != new.map, "map");
+        check(self.sim_rate != new.sim_rate, "sim_rate");
+        check(self.broadcast_rate != new.broadcast_rate, "broadcast_rate");
+        check(self.persist != new.persist, "persist");
+        check(self.tls != new.tls, "tls");
+        check(self.keystore != new.keystore, "keystore");
+        check(self.keystore_pass != new.keystore_pass, "keystore_pass");
+        check(self.cert_cache != new.cert_cache, "cert_cache");
+        check(self.require_subprotocol != new.require_subprotocol, "require_subprotocol");
+        check(self.admin_token != new.admin_token, "admin_token");
+        check(self.insecure_admin != new.insecure_admin, "insecure_admin");
+        check(self.redact != new.redact, "redact");
+        check(self.peer_timeout != new.peer_timeout, "peer_timeout");
+        check(self.seed != new.seed, "seed");
+        check(self.record != new.record, "record");
+        changed
+    }
+}
+
+// toml's own errors quote the file around the mistake, this names the key
+// and the line it's on instead
+fn bad_config(path: &str, text: &str, e: toml::de::Error) -> String {
+    let Some(span) = e.span() else {
+        return format!("Bad config {}: {}", path, e.message());
+    };
+    let line = text[..span.start].matches('\n').count() + 1;
+    let key = text.lines().nth(line - 1).and_then(|l| l.split_once('=')).map(|(k, _)| k.trim());
+    match key {
+        Some(key) => format!("Bad config {} line {}, {}: {}", path, line, key, e.message()),
+        None => format!("Bad config {} line {}: {}", path, line, e.message()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempPath;
+
+    #[test]
+    fn bad_keys_are_named() {
+        let cases = [
+            ("bind = \"0.0.0.0:2000\"\nbnid = 1\n", "line 2, bnid: unknown field `bnid`"),
+            ("sim_rate = \"fast\"\n", "line 1, sim_rate: invalid type"),
+            ("[sim]\nsanity_drian_rate = 0.1\n", "line 2, sanity_drian_rate: unknown field"),
+            ("log_level = \"loud\"\n", "log_level: loud"),
+            ("sim_options = \"sim.json\"\n[sim]\n", "has both sim and sim_options"),
+        ];
+        for (i, (text, expected)) in cases.iter().enumerate() {
+            let file = TempPath::with_text(&format!("config-bad-{}.toml", i), text);
+            let e = ConfigFile::load(file.as_str()).err().unwrap();
+            assert!(e.contains(expected), "{}", e);
+        }
+    }
+
+    #[test]
+    fn the_flag_wins_over_the_file_over_the_defaults() {
+        let text = "log_level = \"info\"\n[sim]\nsanity_drain_rate = 0.2\n";
+        let file = TempPath::with_text("config-layered.toml", text);
+        let flag = TempPath::with_text("config-layered.json", r#"{"sanity_drain_rate": 0.3}"#);
+        let config = ConfigFile::load(file.as_str()).unwrap();
+        assert_eq!(config.log_level(), Ok(Some(LevelFilter::Info)));
+
+        let drain = |flag: Option<&str>, config: &ConfigFile| {
+            config.sim_tunables(flag).unwrap().sanity_drain_rate
+        };
+        assert_eq!(drain(Some(flag.as_str()), &config), Some(0.3));
+        assert_eq!(drain(None, &config), Some(0.2));
+        assert_eq!(drain(None, &ConfigFile::default()), None);
+        assert_eq!(ConfigFile::default().log_level(), Ok(None));
+    }
+}
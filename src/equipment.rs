@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Most lists from clients are tiny, anything past this is junk
+pub const MAX_ACTIVE_EQUIPMENT: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum EquipmentKind {
+    Flashlight,
+    EmfReader,
+    Thermometer,
+    SpiritBox,
+    UvLight,
+    VideoCamera,
+    DotsProjector,
+    ParabolicMicrophone,
+    Book,
+    Crucifix,
+    Smudge,
+    Salt,
+    SanityPills,
+}
+
+impl EquipmentKind {
+    // Whether the ghost can sense the item when it's switched on
+    pub fn is_electronic(&self) -> bool {
+        !matches!(
+            self,
+            EquipmentKind::Book
+                | EquipmentKind::Crucifix
+                | EquipmentKind::Smudge
+                | EquipmentKind::Salt
+                | EquipmentKind::SanityPills
+        )
+    }
+}
+
+pub const INVENTORY_SLOTS: usize = 3;
+
+#[derive(Serialize, Clone)]
+pub struct Inventory {
+    slots: [Option<EquipmentKind>; INVENTORY_SLOTS],
+    held_slot: usize,
+}
+
+impl Inventory {
+    pub(crate) fn new() -> Self {
+        Inventory {
+            slots: [None; INVENTORY_SLOTS],
+            held_slot: 0,
+        }
+    }
+
+    pub fn contains(&self, item: EquipmentKind) -> bool {
+        self.slots.contains(&Some(item))
+    }
+
+    // Puts the item in the held slot if it's free, otherwise the first free one
+    pub fn add(&mut self, item: EquipmentKind) -> Result<(), String> {
+        let free_slot = if self.slots[self.held_slot].is_none() {
+            Some(self.held_slot)
+        } else {
+            self.slots.iter().position(|s| s.is_none())
+        };
+
+        match free_slot {
+            Some(ix) => {
+                self.slots[ix] = Some(item);
+                Ok(())
+            }
+            None => Err("Inventory full".to_owned()),
+        }
+    }
+
+    pub fn take_held(&mut self) -> Option<EquipmentKind> {
+        self.slots[self.held_slot].take()
+    }
+
+    pub fn remove(&mut self, item: EquipmentKind) -> Result<(), String> {
+        match self.slots.iter().position(|s| *s == Some(item)) {
+            Some(ix) => {
+                self.slots[ix] = None;
+                Ok(())
+            }
+            None => Err("Item not in inventory".to_owned()),
+        }
+    }
+
+    pub fn swap_to(&mut self, index: usize) -> Result<(), String> {
+        if index >= INVENTORY_SLOTS {
+            return Err("No such slot".to_owned());
+        }
+        self.held_slot = index;
+        Ok(())
+    }
+}
+
+// How good the team's version of an item is. Only sensors care so far.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Tier {
+    #[default]
+    I,
+    II,
+    III,
+}
+
+// What a tier changes about the sensors, looked up when they're read
+pub struct SensorModifiers {
+    // readings are off by up to this many degrees either way
+    pub thermometer_noise: i32,
+    // how long before the thermometer can be read again
+    pub thermometer_cooldown: Duration,
+    // lower tiers only tell activity from no activity
+    pub emf_exact: bool,
+    // how many past readings the reader keeps
+    pub emf_history: usize,
+    // rooms away a spirit box still picks the ghost up, 1 is next door
+    pub spirit_box_radius: u32,
+}
+
+impl Tier {
+    pub fn modifiers(&self) -> SensorModifiers {
+        match self {
+            Tier::I => SensorModifiers {
+                thermometer_noise: 4,
+                thermometer_cooldown: Duration::from_secs(3),
+                emf_exact: false,
+                emf_history: 0,
+                spirit_box_radius: 1,
+            },
+            Tier::II => SensorModifiers {
+                thermometer_noise: 2,
+                thermometer_cooldown: Duration::from_secs(1),
+                emf_exact: true,
+                emf_history: 0,
+                spirit_box_radius: 2,
+            },
+            Tier::III => SensorModifiers {
+                thermometer_noise: 0,
+                thermometer_cooldown: Duration::from_millis(200),
+                emf_exact: true,
+                emf_history: 5,
+                spirit_box_radius: 3,
+            },
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc::UnboundedReceiver};
+
+use crate::{
+    ghost::GhostType,
+    map::RoomLabel,
+    replay::SimInput,
+    rewards::LineItem,
+    sim::{GameUpdate, InteractionType},
+};
+
+// how many a sim keeps, a recording has every one
+const EVENT_LOG_CAP: usize = 2048;
+
+// Everything worth arguing about after a round
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum SimEvent {
+    RoundStarted {
+        map: String,
+        ghost_type: GhostType,
+    },
+    RoundEnded,
+    GhostMoved {
+        from: RoomLabel,
+        to: RoomLabel,
+    },
+    Interaction {
+        kind: InteractionType,
+        room: RoomLabel,
+    },
+    OrbsShown {
+        room: RoomLabel,
+    },
+    OrbsGone,
+    EmfBlast {
+        room: RoomLabel,
+        level: u32,
+    },
+    EmfEnded {
+        room: RoomLabel,
+        level: u32,
+    },
+    HuntStarted {
+        room: RoomLabel,
+    },
+    HuntEnded,
+    FavoriteRoomChanged {
+        from: RoomLabel,
+        to: RoomLabel,
+    },
+    // by is none when the ghost or too many lights tripped it
+    Breaker {
+        on: bool,
+        by: Option<String>,
+    },
+    // only on the way down, pills don't get a line
+    SanityBelow {
+        name: String,
+        threshold: u32,
+    },
+    PlayerJoined {
+        name: String,
+    },
+    PlayerLeft {
+        name: String,
+    },
+    PlayerDied {
+        name: String,
+        room: Option<RoomLabel>,
+    },
+    // what a player earned, itemized, just before the round ends
+    Rewarded {
+        name: String,
+        items: Vec<LineItem>,
+        total: u32,
+    },
+}
+
+// Ids only go up, like notices, so a reader only needs the ones past the
+// last it saw
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LoggedEvent {
+    pub id: u64,
+    // round time, in seconds
+    pub at: f64,
+    pub event: SimEvent,
+}
+
+// The newest EVENT_LOG_CAP events, older ones only live on in a recording
+pub(crate) struct EventLog {
+    entries: VecDeque<LoggedEvent>,
+    last_id: u64,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            entries: VecDeque::new(),
+            last_id: 0,
+        }
+    }
+
+    pub fn push(&mut self, time: Duration, event: SimEvent) {
+        self.last_id += 1;
+        if self.entries.len() == EVENT_LOG_CAP {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LoggedEvent {
+            id: self.last_id,
+            at: time.as_secs_f64(),
+            event,
+        });
+    }
+
+    pub fn entries(&self) -> Vec<LoggedEvent> {
+        self.entries.iter().cloned().collect()
+    }
+
+    pub fn since(&self, id: u64) -> impl Iterator<Item = &LoggedEvent> {
+        self.entries.iter().filter(move |e| e.id > id)
+    }
+
+    pub fn last_id(&self) -> u64 {
+        self.last_id
+    }
+}
+
+// A line of a recording. Every lobby goes in the same file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedEvent {
+    pub lobby: String,
+    #[serde(flatten)]
+    pub entry: LoggedEvent,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordedInput {
+    pub lobby: String,
+    pub input: SimInput,
+}
+
+// What went into a sim, and what came out of it, so it can be run again.
+// Each input comes before the events it caused.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum RecordLine {
+    Input(Box<RecordedInput>),
+    Event(RecordedEvent),
+}
+
+impl RecordLine {
+    pub fn lobby(&self) -> &str {
+        match self {
+            RecordLine::Input(input) => &input.lobby,
+            RecordLine::Event(event) => &event.lobby,
+        }
+    }
+}
+
+// Appends lines as they come in. Flushed each time, tokio's File writes in
+// the background and would lose the last lines when it's dropped.
+pub(crate) async fn record(path: String, mut file: File, mut rx: UnboundedReceiver<RecordLine>) {
+    while let Some(event) = rx.recv().await {
+        let mut line = serde_json::to_string(&event).unwrap();
+        line.push('\n');
+        let written = match file.write_all(line.as_bytes()).await {
+            Ok(()) => file.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            println!("Could not record to {}: {}", path, e);
+        }
+    }
+}
+
+// What a review screen shows at one point in a recorded round, as far as
+// the events tell it
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct ReviewState {
+    pub at: f64,
+    pub map: Option<String>,
+    pub ghost_type: Option<GhostType>,
+    pub ghost_location: Option<RoomLabel>,
+    // only known once it's changed, the round doesn't log the first
+    pub favorite_room: Option<RoomLabel>,
+    pub hunting: bool,
+    pub power_out: bool,
+    pub orbs_visible: bool,
+    // room and level of every blast still going
+    pub emf: Vec<(RoomLabel, u32)>,
+    pub players: BTreeMap<String, ReviewPlayer>,
+    pub ended: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReviewPlayer {
+    pub connected: bool,
+    pub alive: bool,
+    // the lowest threshold they've dropped under
+    pub sanity_below: Option<u32>,
+    pub earned: Option<u32>,
+}
+
+impl ReviewState {
+    fn apply(&mut self, entry: &LoggedEvent) {
+        self.at = entry.at;
+        match &entry.event {
+            SimEvent::RoundStarted { map, ghost_type } => {
+                // whoever's still around plays again
+                let players = std::mem::take(&mut self.players)
+                    .into_iter()
+                    .filter(|(_, p)| p.connected)
+                    .map(|(name, _)| (name, ReviewPlayer::new()))
+                    .collect();
+                *self = ReviewState {
+                    at: entry.at,
+                    map: Some(map.clone()),
+                    ghost_type: Some(*ghost_type),
+                    players,
+                    ..Default::default()
+                };
+            }
+            SimEvent::RoundEnded => {
+                self.ended = true;
+                self.hunting = false;
+            }
+            SimEvent::GhostMoved { to, .. } => self.ghost_location = Some(*to),
+            SimEvent::Interaction { room, .. } => self.ghost_location = Some(*room),
+            SimEvent::OrbsShown { .. } => self.orbs_visible = true,
+            SimEvent::OrbsGone => self.orbs_visible = false,
+            SimEvent::EmfBlast { room, level } => self.emf.push((*room, *level)),
+            SimEvent::EmfEnded { room, level } => {
+                if let Some(ix) = self.emf.iter().position(|b| *b == (*room, *level)) {
+                    self.emf.remove(ix);
+                }
+            }
+            SimEvent::HuntStarted { room } => {
+                self.hunting = true;
+                self.ghost_location = Some(*room);
+            }
+            SimEvent::HuntEnded => self.hunting = false,
+            SimEvent::FavoriteRoomChanged { to, .. } => self.favorite_room = Some(*to),
+            SimEvent::Breaker { on, .. } => self.power_out = !on,
+            SimEvent::SanityBelow { name, threshold } => {
+                self.player(name).sanity_below = Some(*threshold);
+            }
+            SimEvent::PlayerJoined { name } => self.player(name).connected = true,
+            SimEvent::PlayerLeft { name } => self.player(name).connected = false,
+            SimEvent::PlayerDied { name, .. } => self.player(name).alive = false,
+            SimEvent::Rewarded { name, total, .. } => self.player(name).earned = Some(*total),
+        }
+    }
+
+    fn player(&mut self, name: &str) -> &mut ReviewPlayer {
+        self.players.entry(name.to_owned()).or_insert_with(ReviewPlayer::new)
+    }
+}
+
+impl ReviewPlayer {
+    fn new() -> Self {
+        ReviewPlayer {
+            connected: true,
+            alive: true,
+            sanity_below: None,
+            earned: None,
+        }
+    }
+}
+
+// Turns a recording back into a Review update per event, in order. A
+// recording of more than one lobby needs the code of the one to replay.
+pub fn replay(recording: &str, lobby: Option<&str>) -> Result<Vec<GameUpdate>, String> {
+    let mut events = Vec::new();
+    for (i, line) in recording.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: RecordLine =
+            serde_json::from_str(line).map_err(|e| format!("Bad line {}: {}", i + 1, e))?;
+        // a review only goes by what happened
+        if let RecordLine::Event(event) = line {
+            events.push(event);
+        }
+    }
+
+    let lobby = match lobby {
+        Some(code) => code.to_owned(),
+        None => {
+            let mut codes: Vec<&str> = events.iter().map(|e| e.lobby.as_str()).collect();
+            codes.sort_unstable();
+            codes.dedup();
+            match codes.as_slice() {
+                [] => return Ok(Vec::new()),
+                [code] => code.to_string(),
+                _ => return Err(format!("Recorded lobbies {}, which one?", codes.join(", "))),
+            }
+        }
+    };
+
+    let mut state = ReviewState::default();
+    let updates = events
+        .into_iter()
+        .filter(|e| e.lobby == lobby)
+        .map(|e| {
+            state.apply(&e.entry);
+            GameUpdate::Review {
+                state: state.clone(),
+                event: e.entry,
+            }
+        })
+        .collect();
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use crate::test_util::TempPath;
+
+    // A line of a recording, the way the recorder writes it
+    fn line(lobby: &str, id: u64, at: f64, event: SimEvent) -> String {
+        let entry = LoggedEvent { id, at, event };
+        let event = RecordedEvent {
+            lobby: lobby.to_owned(),
+            entry,
+        };
+        serde_json::to_string(&RecordLine::Event(event)).unwrap()
+    }
+
+    fn recording(lobby: &str, events: Vec<SimEvent>) -> String {
+        let lines: Vec<_> = events
+            .into_iter()
+            .enumerate()
+            .map(|(i, e)| line(lobby, i as u64 + 1, i as f64, e))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn states(updates: Vec<GameUpdate>) -> Vec<ReviewState> {
+        updates
+            .into_iter()
+            .map(|u| match u {
+                GameUpdate::Review { state, .. } => state,
+                _ => panic!("Not a review"),
+            })
+            .collect()
+    }
+
+    fn joined(name: &str) -> SimEvent {
+        SimEvent::PlayerJoined {
+            name: name.to_owned(),
+        }
+    }
+
+    fn started() -> SimEvent {
+        SimEvent::RoundStarted {
+            map: "default".to_owned(),
+            ghost_type: GhostType::Spirit,
+        }
+    }
+
+    #[test]
+    fn the_log_keeps_only_the_newest() {
+        let mut log = EventLog::new();
+        for i in 0..EVENT_LOG_CAP + 5 {
+            log.push(Duration::from_secs(i as u64), SimEvent::OrbsGone);
+        }
+        let entries = log.entries();
+        assert_eq!(entries.len(), EVENT_LOG_CAP);
+        assert_eq!(entries[0].id, 6);
+        assert_eq!(entries[0].at, 5.0);
+        assert_eq!(log.last_id(), EVENT_LOG_CAP as u64 + 5);
+        // the ids carry on past what's been dropped
+        let since: Vec<_> = log.since(EVENT_LOG_CAP as u64 + 3).map(|e| e.id).collect();
+        assert_eq!(since, [EVENT_LOG_CAP as u64 + 4, EVENT_LOG_CAP as u64 + 5]);
+        assert_eq!(log.since(0).count(), EVENT_LOG_CAP);
+    }
+
+    #[test]
+    fn a_review_follows_the_events() {
+        let events = vec![
+            joined("ann"),
+            started(),
+            SimEvent::GhostMoved { from: 4, to: 2 },
+            SimEvent::EmfBlast { room: 2, level: 5 },
+            SimEvent::EmfBlast { room: 2, level: 2 },
+            SimEvent::EmfEnded { room: 2, level: 5 },
+            SimEvent::OrbsShown { room: 2 },
+            SimEvent::HuntStarted { room: 6 },
+            SimEvent::SanityBelow {
+                name: "ann".to_owned(),
+                threshold: 50,
+            },
+            SimEvent::PlayerDied {
+                name: "ann".to_owned(),
+                room: Some(6),
+            },
+            SimEvent::HuntEnded,
+            SimEvent::Breaker { on: false, by: None },
+            SimEvent::RoundEnded,
+        ];
+        let states = states(replay(&recording("ABCD", events), None).unwrap());
+        assert_eq!(states.len(), 13);
+        assert_eq!(states[1].map.as_deref(), Some("default"));
+        assert_eq!(states[2].ghost_location, Some(2));
+        assert_eq!(states[4].emf, [(2, 5), (2, 2)]);
+        // only the one that ended goes
+        assert_eq!(states[5].emf, [(2, 2)]);
+        assert!(states[6].orbs_visible);
+        assert!(states[7].hunting);
+        assert_eq!(states[7].ghost_location, Some(6));
+        let ann = &states[9].players["ann"];
+        assert_eq!(ann.sanity_below, Some(50));
+        assert!(!ann.alive && ann.connected);
+        assert!(!states[10].hunting);
+        assert!(states[11].power_out);
+        assert!(states[12].ended);
+        assert_eq!(states[12].at, 12.0);
+    }
+
+    #[test]
+    fn a_new_round_starts_over_with_whoever_stayed() {
+        let events = vec![
+            joined("ann"),
+            joined("bob"),
+            started(),
+            SimEvent::PlayerDied {
+                name: "ann".to_owned(),
+                room: None,
+            },
+            SimEvent::PlayerLeft {
+                name: "bob".to_owned(),
+            },
+            SimEvent::OrbsShown { room: 2 },
+            SimEvent::RoundEnded,
+            started(),
+        ];
+        let last = states(replay(&recording("ABCD", events), None).unwrap()).pop().unwrap();
+        assert_eq!(last.players.keys().collect::<Vec<_>>(), ["ann"]);
+        assert!(last.players["ann"].alive);
+        assert!(!last.orbs_visible && !last.ended);
+    }
+
+    #[test]
+    fn several_lobbies_need_a_code_and_inputs_are_skipped() {
+        let left = RecordLine::Input(Box::new(RecordedInput {
+            lobby: "ABCD".to_owned(),
+            input: SimInput::Left {
+                addr: "127.0.0.1:4000".parse().unwrap(),
+            },
+        }));
+        let mixed = [
+            serde_json::to_string(&left).unwrap(),
+            line("ABCD", 1, 0.0, joined("ann")),
+            String::new(),
+            line("WXYZ", 1, 0.0, joined("bob")),
+            line("ABCD", 2, 1.0, started()),
+        ]
+        .join("\n");
+        let e = replay(&mixed, None).err().unwrap();
+        assert_eq!(e, "Recorded lobbies ABCD, WXYZ, which one?");
+        assert_eq!(replay(&mixed, Some("ABCD")).unwrap().len(), 2);
+        assert_eq!(replay(&mixed, Some("WXYZ")).unwrap().len(), 1);
+        assert!(replay("", None).unwrap().is_empty());
+
+        let bad = format!("{}\n{}", line("ABCD", 1, 0.0, joined("ann")), json!({"lobby": 1}));
+        assert!(replay(&bad, None).err().unwrap().starts_with("Bad line 2:"));
+    }
+
+    #[tokio::test]
+    async fn the_recorder_writes_a_line_each() {
+        let path = TempPath::new("record.jsonl");
+        let file = File::create(&path).await.unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let recorder = tokio::spawn(record(path.as_str().to_owned(), file, rx));
+        for (i, name) in ["ann", "bob"].iter().enumerate() {
+            let entry = LoggedEvent {
+                id: i as u64 + 1,
+                at: 0.5,
+                event: joined(name),
+            };
+            let lobby = "ABCD".to_owned();
+            tx.send(RecordLine::Event(RecordedEvent { lobby, entry })).unwrap();
+        }
+        // it's done once everyone sending to it is
+        drop(tx);
+        recorder.await.unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        assert!(written.ends_with('\n'));
+        assert_eq!(replay(&written, None).unwrap().len(), 2);
+    }
+}
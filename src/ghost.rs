@@ -1,4 +1,7 @@
-use crate::map::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{map::*, utils};
 
 pub struct Ghost {
     pub current_room: RoomLabel,
@@ -7,21 +10,45 @@ pub struct Ghost {
 }
 
 impl Ghost {
-    pub fn new() -> Self {
+    // Starts out in its favorite room
+    pub(crate) fn new<R: Rng + ?Sized>(
+        map: &Map,
+        category_weights: &[(RoomCategory, f64)],
+        rng: &mut R,
+    ) -> Self {
+        let ghost_room = pick_ghost_room(map, category_weights, None, rng).unwrap_or(0);
+
         Ghost {
-            current_room: 0,
-            ghost_room: 7,
+            current_room: ghost_room,
+            ghost_room,
             path_to_target: None,
         }
     }
 
-    pub fn move_room(&mut self, map: &Map) {
+    pub fn move_room<R: Rng + ?Sized>(&mut self, map: &Map, cost: EdgeCost, rng: &mut R) {
         println!("Moving ghost");
         let new_path = match self.path_to_target.clone() {
             None => {
-                let target = self.next_target(map);
-                Some(map.get_path(self.current_room, target))
+                let target = self.next_target(map, rng);
+                match map.get_path_with(self.current_room, target, cost) {
+                    Some(path) if path.is_empty() => None,
+                    Some(path) => Some(path),
+                    None => {
+                        // wander to a neighbour instead and try again from there
+                        println!("Ghost can't reach room {}", target);
+                        let neighbours: Vec<RoomLabel> = map
+                            .connections(self.current_room)
+                            .iter()
+                            .filter(|c| cost(c).is_some())
+                            .map(|c| c.to)
+                            .collect();
+                        utils::rng_select(&neighbours, rng)
+                            .map(|room| vec![*room])
+                    }
+                }
             }
+            // the way ahead got shut since planning, so plan again next time
+            Some(path) if !self.can_step(map, cost, &path) => None,
             Some(path) => {
                 let mut path = path.clone();
                 self.current_room = path.pop().unwrap();
@@ -36,59 +63,173 @@ impl Ghost {
         self.path_to_target = new_path;
     }
 
-    fn target(&self) -> Option<RoomLabel> {
+    // Hunting: plans afresh toward the prey every step, since it moves
+    pub fn chase<R: Rng + ?Sized>(
+        &mut self,
+        map: &Map,
+        cost: EdgeCost,
+        prey: RoomLabel,
+        rng: &mut R,
+    ) {
+        match map.get_path_with(self.current_room, prey, cost) {
+            Some(mut path) => {
+                if let Some(next) = path.pop() {
+                    self.current_room = next;
+                    println!("Chased into room {}", self.current_room);
+                }
+                self.path_to_target = if path.is_empty() { None } else { Some(path) };
+            }
+            None => {
+                println!("Ghost can't reach prey in room {}", prey);
+                self.move_room(map, cost, rng);
+            }
+        }
+    }
+
+    pub fn return_home(&mut self, map: &Map, cost: EdgeCost) {
+        self.path_to_target = match map.get_path_with(self.current_room, self.ghost_room, cost) {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(path),
+            None => {
+                println!("Ghost can't return home to room {}", self.ghost_room);
+                None
+            }
+        };
+    }
+
+    // How many move intervals the next step takes, 1 when not travelling
+    pub fn next_step_weight(&self, map: &Map, cost: EdgeCost) -> u32 {
+        self.path_to_target
+            .as_ref()
+            .and_then(|path| path.last())
+            .and_then(|next| map.connection(self.current_room, *next))
+            .and_then(cost)
+            .unwrap_or(1)
+    }
+
+    fn can_step(&self, map: &Map, cost: EdgeCost, path: &Path) -> bool {
+        path.last()
+            .and_then(|next| map.connection(self.current_room, *next))
+            .and_then(cost)
+            .is_some()
+    }
+
+    // Puts the ghost straight into a room, it plans a new route from there
+    pub fn teleport(&mut self, room: RoomLabel) {
+        self.current_room = room;
+        self.path_to_target = None;
+    }
+
+    pub fn target(&self) -> Option<RoomLabel> {
         self.path_to_target.clone().and_then(|p| p.first().copied())
     }
 
-    pub fn has_evidence_type(&self, evidence: EvidenceType) -> bool {
-        match evidence {
-            EvidenceType::Emf => false,
-            _ => true,
-        }
+    // Rooms still to go, in the order they'll be walked
+    pub fn planned_path(&self) -> Vec<RoomLabel> {
+        let mut path = self.path_to_target.clone().unwrap_or_default();
+        path.reverse();
+        path
     }
 
-    fn next_target(&self, map: &Map) -> RoomLabel {
+    fn next_target<R: Rng + ?Sized>(&self, map: &Map, rng: &mut R) -> RoomLabel {
         if self.current_room == self.ghost_room {
             let other_rooms: Vec<RoomLabel> = map
-                .rooms
-                .clone()
+                .interior_rooms()
                 .into_iter()
-                .filter(|r| r.label != self.current_room)
-                .map(|r| r.label)
+                .filter(|r| *r != self.current_room)
                 .collect();
 
-            let mut rng = rand::thread_rng();
-            let selected_ix = rand::Rng::gen_range(&mut rng, 0..other_rooms.len());
-            other_rooms[selected_ix]
+            utils::rng_select(&other_rooms, rng)
+                .copied()
+                .unwrap_or(self.current_room)
         } else {
             self.ghost_room
         }
     }
 }
 
+// A room inside other than the safe room, weighted by category. None if
+// there's nothing to pick besides `except`.
+pub(crate) fn pick_ghost_room<R: Rng + ?Sized>(
+    map: &Map,
+    category_weights: &[(RoomCategory, f64)],
+    except: Option<RoomLabel>,
+    rng: &mut R,
+) -> Option<RoomLabel> {
+    let candidates: Vec<(RoomLabel, f64)> = map
+        .rooms
+        .iter()
+        .filter(|r| !r.safe_room && !r.exterior && Some(r.label) != except)
+        .map(|r| (r.label, category_weight(category_weights, r.category)))
+        .collect();
+    let interior: Vec<RoomLabel> =
+        map.interior_rooms().into_iter().filter(|r| Some(*r) != except).collect();
+    utils::weighted_select(&candidates, rng)
+        .or_else(|| utils::rng_select(&interior, rng))
+        .copied()
+}
+
+// Rooms without a category, or with one that isn't listed, count as 1
+fn category_weight(weights: &[(RoomCategory, f64)], category: Option<RoomCategory>) -> f64 {
+    category
+        .and_then(|c| weights.iter().find(|(wc, _)| *wc == c))
+        .map(|(_, w)| *w)
+        .unwrap_or(1.0)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 pub enum GhostType {
     Spirit,
+    Wraith,
+    Phantom,
     Poltergeist,
+    Banshee,
     Jinn,
     Mare,
     Revenant,
     Shade,
     Demon,
+    Yurei,
+    Oni,
+    Yokai,
     Hantu,
+    Goryo,
     Myling,
     Onryo,
     Twins,
     Obake,
     Moroi,
+    Raiju,
     // Mimic
 }
 
 impl GhostType {
-    pub fn has_evidence_type(&self, evidence: EvidenceType) -> bool {
-        return true;
-    }
+    pub const ALL: [GhostType; 21] = [
+        GhostType::Spirit,
+        GhostType::Wraith,
+        GhostType::Phantom,
+        GhostType::Poltergeist,
+        GhostType::Banshee,
+        GhostType::Jinn,
+        GhostType::Mare,
+        GhostType::Revenant,
+        GhostType::Shade,
+        GhostType::Demon,
+        GhostType::Yurei,
+        GhostType::Oni,
+        GhostType::Yokai,
+        GhostType::Hantu,
+        GhostType::Goryo,
+        GhostType::Myling,
+        GhostType::Onryo,
+        GhostType::Twins,
+        GhostType::Obake,
+        GhostType::Moroi,
+        GhostType::Raiju,
+    ];
 }
 
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
 pub enum EvidenceType {
     Emf,
     Ultraviolet,
@@ -96,4 +237,107 @@ pub enum EvidenceType {
     GhostOrbs,
     Writing,
     SpiritBox,
+    Dots,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // 0 - 1 - 2 - 3
+    fn line() -> Map {
+        Map::from_json(
+            r#"{"rooms": [
+                {"label": 0, "name": "A", "connections": [1], "safe_room": true},
+                {"label": 1, "name": "B", "connections": [0, 2]},
+                {"label": 2, "name": "C", "connections": [1, 3]},
+                {"label": 3, "name": "D", "connections": [2]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn at(room: RoomLabel) -> Ghost {
+        Ghost {
+            current_room: room,
+            ghost_room: room,
+            path_to_target: None,
+        }
+    }
+
+    #[test]
+    fn favorite_rooms_follow_the_category_weights() {
+        let map = Map::from_json(
+            r#"{"rooms": [
+                {"label": 0, "name": "A", "connections": [1], "safe_room": true},
+                {"label": 1, "name": "B", "connections": [0, 2, 3], "category": "Hallway"},
+                {"label": 2, "name": "C", "connections": [1], "category": "Basement"},
+                {"label": 3, "name": "D", "connections": [1], "category": "Bedroom"}
+            ]}"#,
+        )
+        .unwrap();
+        let weights = [
+            (RoomCategory::Hallway, 0.0),
+            (RoomCategory::Basement, 3.0),
+        ];
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut picked = [0; 4];
+        for _ in 0..4000 {
+            picked[pick_ghost_room(&map, &weights, None, &mut rng).unwrap()] += 1;
+        }
+        // never the safe room or a zero weight, the bedroom counts as 1
+        assert_eq!(picked[0], 0);
+        assert_eq!(picked[1], 0);
+        let ratio = picked[2] as f64 / picked[3] as f64;
+        assert!((ratio - 3.0).abs() < 0.3, "{:?}", picked);
+
+        // with nothing else left it's the bedroom every time
+        for _ in 0..100 {
+            assert_eq!(pick_ghost_room(&map, &weights, Some(2), &mut rng), Some(3));
+        }
+        assert_eq!(map.directory()[2].category, Some(RoomCategory::Basement));
+    }
+
+    #[test]
+    fn a_ghost_that_cant_reach_its_target_wanders_instead() {
+        let map = line();
+        // the way past B is shut, only A's left
+        let shut = |c: &Connection| (c.to != 2).then_some(c.weight);
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut ghost = at(1);
+            ghost.move_room(&map, &shut, &mut rng);
+            ghost.move_room(&map, &shut, &mut rng);
+            assert_eq!(ghost.current_room, 0, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn a_ghost_that_cant_reach_its_prey_moves_on() {
+        let map = line();
+        let shut = |c: &Connection| (c.to != 2).then_some(c.weight);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut ghost = at(1);
+        ghost.chase(&map, &shut, 3, &mut rng);
+        assert_ne!(ghost.current_room, 3);
+        ghost.return_home(&map, &shut);
+        assert_eq!(ghost.target(), None);
+    }
+
+    #[test]
+    fn rooms_off_the_map_are_never_reached() {
+        let map = line();
+        let open = |c: &Connection| Some(c.weight);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut ghost = at(2);
+        for _ in 0..20 {
+            ghost.chase(&map, &open, 99, &mut rng);
+            assert!(map.contains(ghost.current_room));
+        }
+        ghost.ghost_room = 99;
+        ghost.return_home(&map, &open);
+        assert_eq!(ghost.target(), None);
+    }
 }
@@ -0,0 +1,23 @@
+mod auth;
+pub mod behavior;
+mod broadcaster;
+pub mod config;
+pub mod equipment;
+pub mod event_log;
+pub mod ghost;
+mod lobby;
+pub mod map;
+pub mod messages;
+pub mod profile;
+pub mod replay;
+pub mod rewards;
+mod schedule;
+pub mod server;
+pub mod sim;
+mod sim_actor;
+pub mod sounds;
+pub mod storage;
+pub mod tls;
+#[cfg(test)]
+mod test_util;
+mod utils;
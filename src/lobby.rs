@@ -0,0 +1,384 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    broadcaster::{run_broadcaster, Outbound},
+    event_log::RecordLine,
+    map::MapCatalog,
+    messages::Notice,
+    replay::SimInput,
+    sim::{GameUpdate, OptionOverrides, SensorReading, Simulation},
+    sim_actor::{self, spawn_sim, AdminOp, Recorder, SimCommand, SimHandle},
+    server::{Handle, PeerMap},
+    utils,
+};
+
+// Codes are read out loud, so nothing that looks like a digit
+const CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LENGTH: usize = 4;
+
+pub(crate) fn random_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CODE_LENGTH)
+        .map(|_| *utils::rng_select(CODE_LETTERS, &mut rng).unwrap() as char)
+        .collect()
+}
+
+// What every new lobby starts from. A reload changes the tunables.
+pub(crate) struct LobbyTemplate {
+    pub catalog: MapCatalog,
+    pub selected_map: Option<&'static str>,
+    pub tunables: OptionOverrides,
+    pub sim_rate: u32,
+    pub broadcast_rate: u32,
+    pub redact: bool,
+    // every lobby plays out the same from it, for chasing bugs
+    pub seed: Option<u64>,
+    // where every lobby's events go, see --record
+    pub recording: Option<mpsc::UnboundedSender<RecordLine>>,
+}
+
+impl LobbyTemplate {
+    pub fn new_sim(&self) -> Result<Simulation, String> {
+        let mut sim = match self.seed {
+            Some(seed) => Simulation::with_seed(self.catalog.clone(), seed),
+            None => Simulation::new(self.catalog.clone()),
+        };
+        if let Some(id) = self.selected_map {
+            sim.select_map(id)?;
+        }
+        sim.set_tunables(self.tunables.clone())?;
+        sim.set_redact(self.redact);
+        Ok(sim)
+    }
+}
+
+// One game, with a sim, a clock and a broadcaster of its own. Its tasks
+// stop when it's dropped.
+pub(crate) struct Lobby {
+    pub code: String,
+    pub sim: SimHandle,
+    // everyone in the lobby, players, admins and spectators alike
+    pub peer_map: PeerMap,
+    // peers that take incremental lobby events
+    pub lobby_peers: Handle<HashSet<SocketAddr>>,
+    // admins streaming ghost decisions
+    pub debug_peers: Handle<HashSet<SocketAddr>>,
+    pub spectators: Handle<HashSet<SocketAddr>>,
+    // admins and spectators, the only ones who see the whole state
+    pub unredacted: Handle<HashSet<SocketAddr>>,
+    // peers that get SimDelta rather than whole states
+    delta_peers: Handle<HashSet<SocketAddr>>,
+    // there's a recorder for the sim's inputs, see --record
+    recorded: bool,
+    outbound: mpsc::UnboundedSender<Outbound>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl Lobby {
+    pub fn spawn(
+        code: String,
+        sim: Simulation,
+        sim_rate: u32,
+        broadcast_rate: u32,
+        stopping: watch::Receiver<bool>,
+        recorder: Option<Recorder>,
+    ) -> Self {
+        let peer_map: PeerMap = Arc::new(Mutex::new(HashMap::new()));
+        let lobby_peers = Arc::new(Mutex::new(HashSet::new()));
+        let debug_peers = Arc::new(Mutex::new(HashSet::new()));
+        let unredacted = Arc::new(Mutex::new(HashSet::new()));
+        let delta_peers = Arc::new(Mutex::new(HashSet::new()));
+
+        let (outbound, outbound_rx) = mpsc::unbounded_channel();
+        let recorded = recorder.is_some();
+        let (sim, sim_task) = spawn_sim(sim, outbound.clone(), recorder);
+        let broadcaster = tokio::spawn(run_broadcaster(
+            peer_map.clone(),
+            lobby_peers.clone(),
+            debug_peers.clone(),
+            unredacted.clone(),
+            delta_peers.clone(),
+            outbound_rx,
+            broadcast_rate,
+        ));
+        let clock = tokio::spawn(run_simulation(sim.clone(), sim_rate, stopping));
+        let (stop, stopped) = oneshot::channel();
+        tokio::spawn(supervise(
+            code.clone(),
+            peer_map.clone(),
+            stopped,
+            [sim_task, broadcaster, clock],
+        ));
+
+        Lobby {
+            code,
+            sim,
+            peer_map,
+            lobby_peers,
+            debug_peers,
+            spectators: Arc::new(Mutex::new(HashSet::new())),
+            unredacted,
+            delta_peers,
+            recorded,
+            outbound,
+            _stop: stop,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peer_map.lock().unwrap().is_empty()
+    }
+
+    // Drops the peer from everything the lobby keeps, the sim's players are
+    // up to the caller
+    pub async fn forget(&self, addr: SocketAddr) {
+        self.peer_map.lock().unwrap().remove(&addr);
+        self.lobby_peers.lock().unwrap().remove(&addr);
+        self.unredacted.lock().unwrap().remove(&addr);
+        self.delta_peers.lock().unwrap().remove(&addr);
+        self.set_ghost_debug(addr, false).await;
+        if self.spectators.lock().unwrap().remove(&addr) {
+            self.update_spectators().await;
+        }
+    }
+
+    // An input that doesn't do anything to the sim by itself. Only made
+    // when something's recording.
+    pub fn record(&self, input: impl FnOnce() -> SimInput) {
+        if self.recorded {
+            self.sim.record(input());
+        }
+    }
+
+    // What to record f with, none when nothing's recording
+    pub fn input(&self, input: impl FnOnce() -> SimInput) -> Option<SimInput> {
+        self.recorded.then(input)
+    }
+
+    // Runs f, with the input recorded along with the first thing f has the
+    // sim do
+    pub async fn with_input<F: Future>(&self, input: Option<SimInput>, f: F) -> F::Output {
+        sim_actor::with_input(input, f).await
+    }
+
+    pub async fn remove_player(&self, addr: SocketAddr) {
+        let remove = self.sim.query(move |sim| sim.remove_player(addr));
+        let left = self.with_input(self.input(|| SimInput::Left { addr }), remove).await;
+        if let Ok(Some(name)) = left {
+            println!("Player left: {name}");
+            self.sim.broadcast();
+        }
+    }
+
+    // Whatever the peer asked for last wins
+    pub fn set_deltas(&self, addr: SocketAddr, on: bool) {
+        let mut peers = self.delta_peers.lock().unwrap();
+        if on {
+            peers.insert(addr);
+        } else {
+            peers.remove(&addr);
+        }
+    }
+
+    // The lobby shows how many are watching
+    pub async fn update_spectators(&self) {
+        let count = self.spectators.lock().unwrap().len();
+        if self.sim.query(move |sim| sim.set_spectators(count)).await.is_ok() {
+            self.sim.broadcast();
+        }
+    }
+
+    // The sim only tracks decisions while someone is subscribed
+    pub async fn set_ghost_debug(&self, addr: SocketAddr, on: bool) {
+        let any = {
+            let mut peers = self.debug_peers.lock().unwrap();
+            let changed = if on {
+                peers.insert(addr)
+            } else {
+                peers.remove(&addr)
+            };
+            if !changed {
+                return;
+            }
+            !peers.is_empty()
+        };
+        let _ = self.sim.query(move |sim| sim.set_ghost_debug(any)).await;
+    }
+
+    // Runs a sim method that changes what everyone sees
+    pub async fn apply<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Simulation) -> Result<T, String> + Send + 'static,
+    {
+        let result = self.sim.query(f).await?;
+        if result.is_ok() {
+            self.sim.broadcast();
+        }
+        result
+    }
+
+    pub async fn admin_op(&self, op: AdminOp) -> Result<(), String> {
+        self.sim
+            .request(|reply| SimCommand::AdminOp(op, reply))
+            .await?;
+        self.sim.broadcast();
+        Ok(())
+    }
+
+    pub async fn send_gamestate(&self, addr: SocketAddr) {
+        let full = self.unredacted.lock().unwrap().contains(&addr);
+        let update = self
+            .sim
+            .query(move |sim| {
+                if full {
+                    return sim.get_gameupdate();
+                }
+                let name = sim.player_name(addr);
+                sim.get_gameupdate_for(name.as_deref())
+            })
+            .await;
+        if let Ok(update) = update {
+            self.send(addr, update);
+        }
+    }
+
+    pub async fn handle_inventory_result(
+        &self,
+        addr: SocketAddr,
+        result: Result<(), String>,
+    ) -> Result<(), String> {
+        result?;
+        self.send_inventory(addr).await;
+        Ok(())
+    }
+
+    pub fn send_reading(
+        &self,
+        addr: SocketAddr,
+        result: Result<SensorReading, String>,
+    ) -> Result<(), String> {
+        let reading = result?;
+        self.send(addr, GameUpdate::Reading { reading });
+        Ok(())
+    }
+
+    // Inventories only go to their owner
+    pub async fn send_inventory(&self, addr: SocketAddr) {
+        let inventory = self.sim.query(move |sim| sim.get_inventory(addr)).await;
+        if let Ok(Some(inventory)) = inventory {
+            let update = GameUpdate::Inventory { inventory };
+            self.send(addr, update);
+        }
+    }
+
+    pub fn send_notification(&self, addr: SocketAddr, notice: Notice) {
+        self.send(addr, GameUpdate::Notification { notice });
+    }
+
+    // Everything outgoing goes through the broadcaster so ordering holds
+    pub fn send(&self, addr: SocketAddr, update: GameUpdate) {
+        if self.outbound.send(Outbound::Direct(addr, update)).is_err() {
+            println!("Broadcaster for lobby {} is gone", self.code);
+        }
+    }
+}
+
+// Stops the lobby's tasks once it's dropped. If one of them dies first the
+// game can't go on, so everyone in it is let go and the lobby closes when
+// the last of them is cleaned up.
+async fn supervise(
+    code: String,
+    peer_map: PeerMap,
+    stopped: oneshot::Receiver<()>,
+    mut tasks: [JoinHandle<()>; 3],
+) {
+    let [sim_task, broadcaster, clock] = &mut tasks;
+    let ended = tokio::select! {
+        _ = stopped => None,
+        ended = sim_task => Some(utils::task_ended("Sim", ended)),
+        ended = broadcaster => Some(utils::task_ended("Broadcaster", ended)),
+        ended = clock => Some(utils::task_ended("Sim clock", ended)),
+    };
+    if let Some(reason) = ended {
+        println!("Lobby {} failed: {}", code, reason);
+        for peer in peer_map.lock().unwrap().values() {
+            let _ = peer.tx.unbounded_send(Message::Close(None));
+        }
+    }
+    for task in &tasks {
+        task.abort();
+    }
+}
+
+// Drives the sim clock, the sim itself skips ticks until it's started. The
+// clock stops when the server starts shutting down, so the round holds
+// still while everyone's let go.
+async fn run_simulation(sim: SimHandle, rate: u32, mut stopping: watch::Receiver<bool>) {
+    let period = Duration::from_secs_f64(1.0 / rate as f64);
+    let mut last = Instant::now();
+    loop {
+        // a late tick makes up for it, round time keeps up with the wall
+        let now = Instant::now();
+        sim.send(SimCommand::Tick(now - last));
+        last = now;
+        tokio::select! {
+            _ = sleep(period) => {}
+            _ = stopping.wait_for(|stop| *stop) => break,
+        }
+    }
+    // supervise would take a clock that ends for one that died
+    std::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_channel::mpsc::unbounded;
+    use futures_util::StreamExt;
+
+    use crate::server::{Encoding, Peer};
+
+    #[tokio::test]
+    async fn a_sim_panic_lets_everyone_in_the_lobby_go() {
+        let (_stopping, stopping) = watch::channel(false);
+        let sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        let lobby = Lobby::spawn("ABCD".to_owned(), sim, 20, 10, stopping, None);
+        let (tx, mut rx) = unbounded();
+        let peer = Peer {
+            tx,
+            subprotocol: None,
+            encoding: Encoding::Json,
+            protocol: Some(3),
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], 6000));
+        lobby.peer_map.lock().unwrap().insert(addr, peer);
+
+        let _ = lobby.sim.query(|_| panic!("on purpose")).await;
+        let closed = tokio::time::timeout(Duration::from_secs(2), async {
+            while let Some(msg) = rx.next().await {
+                if let Message::Close(_) = msg {
+                    return true;
+                }
+            }
+            false
+        });
+        assert!(closed.await.unwrap());
+        // and the lobby's done for, rather than limping on
+        assert!(lobby.sim.query(|_| ()).await.is_err());
+    }
+}
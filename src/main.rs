@@ -1,21 +1,303 @@
-use std::{io, sync::Arc};
+use std::{fs, io, path::Path, process, str::FromStr, sync::Arc, time::Duration};
 
-mod server;
-mod sim;
-mod map;
-mod ghost;
-mod utils;
+use log::LevelFilter;
+use phasmo_server::{
+    config::{self, ConfigFile},
+    map::Map,
+    replay,
+    server::{self, ServerConfig, DEFAULT_HOST, DEFAULT_PORT},
+    storage::StorageConfig,
+    tls::TlsConfig,
+};
 
 #[tokio::main]
 async fn main() {
     let (tx, rx) = tokio::sync::mpsc::channel(32);
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // the file goes first so the command line can override it
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => args.get(i + 1).cloned(),
+        None => Some(config::CONFIG_PATH.to_owned()).filter(|path| Path::new(path).exists()),
+    };
+    let file = match &config_path {
+        Some(path) => ConfigFile::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+        None => ConfigFile::default(),
+    };
+
+    let mut config = ServerConfig::default();
+    if let Some(bind) = file.bind.clone() {
+        config.addr = bind;
+    }
+    // then the environment, then the flags
+    let mut host = std::env::var("PHASMO_BIND").ok();
+    let mut port = std::env::var("PHASMO_PORT").ok();
+    config.map_path = file.map.clone();
+    config.sim_rate = file.sim_rate.unwrap_or(config.sim_rate);
+    config.broadcast_rate = file.broadcast_rate.unwrap_or(config.broadcast_rate);
+    if file.persist == Some(false) {
+        config.storage = StorageConfig::Memory;
+    }
+    if file.require_subprotocol == Some(true) {
+        config.legacy_clients = false;
+    }
+    config.redact = file.redact.unwrap_or(config.redact);
+    if let Some(secs) = file.peer_timeout {
+        config.peer_timeout = Duration::from_secs(secs);
+    }
+    config.seed = file.seed;
+    config.record = file.record.clone();
+    let mut dump_path = None;
+    let mut replay_path = None;
+    // the environment keeps the password out of ps and the shell history
+    let mut keystore = std::env::var("PHASMO_KEYSTORE").ok().or(file.keystore.clone());
+    let mut keystore_pass = std::env::var("PHASMO_KEYSTORE_PASS")
+        .ok()
+        .or(file.keystore_pass.clone())
+        .unwrap_or_default();
+    let mut cert_cache = file.cert_cache.clone();
+    config.admin_token = std::env::var("PHASMO_ADMIN_TOKEN").ok().or(file.admin_token.clone());
+    config.insecure_admin = file.insecure_admin == Some(true);
+    let mut no_tls = file.tls == Some(false);
+    let mut log_level = file.log_level().unwrap_or_default().unwrap_or(LevelFilter::Warn);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // already loaded
+            "--config" => {
+                args.next();
+            }
+            // interface to listen on, 0.0.0.0 for every one
+            "--bind" => host = args.next(),
+            "--port" => port = args.next(),
+            "--map" => config.map_path = args.next(),
+            // sim tunables, instead of any in the config
+            "--sim-options" => config.sim_options = args.next(),
+            "--dump-map" => dump_path = args.next(),
+            // profiles and round history are thrown away on exit
+            "--no-persist" => config.storage = StorageConfig::Memory,
+            // a PKCS #12 file, otherwise a self-signed certificate is made up
+            "--keystore" => keystore = args.next(),
+            "--keystore-pass" => keystore_pass = args.next().unwrap_or_default(),
+            // keeps the self-signed certificate so its fingerprint doesn't change
+            "--cert-cache" => cert_cache = args.next(),
+            "--no-tls" => no_tls = true,
+            // what ConnectAsAdmin has to send, nobody can be admin without one
+            "--admin-token" => config.admin_token = args.next(),
+            // anyone can be admin without a token, only for a LAN
+            "--insecure-admin" => config.insecure_admin = true,
+            // refuse clients that don't offer a websocket subprotocol
+            "--require-subprotocol" => config.legacy_clients = false,
+            // players see the ghost and everyone's sanity, for debugging
+            "--no-redact" => config.redact = false,
+            // seconds a client can go quiet, pongs included, before it's dropped
+            "--peer-timeout" => {
+                let secs = args.next().unwrap_or_default();
+                config.peer_timeout = secs.parse().map(Duration::from_secs).unwrap_or_else(|_| {
+                    eprintln!("Bad peer timeout {}", secs);
+                    process::exit(1);
+                });
+            }
+            // every lobby's sim plays out the same, the seed's logged otherwise
+            "--seed" => {
+                let seed = args.next().unwrap_or_default();
+                config.seed = Some(seed.parse().unwrap_or_else(|_| {
+                    eprintln!("Bad seed {}", seed);
+                    process::exit(1);
+                }));
+            }
+            // appends every lobby's inputs and events to the file, for replay
+            "--record" => config.record = args.next(),
+            // runs a recording again and checks it plays out the same
+            "--replay" => replay_path = args.next(),
+            "--log-level" => {
+                let level = args.next().unwrap_or_default();
+                log_level = LevelFilter::from_str(&level).unwrap_or_else(|_| {
+                    eprintln!("Unknown log level {}", level);
+                    process::exit(1);
+                });
+            }
+            _ => {
+                eprintln!("Unknown argument {}", arg);
+                process::exit(1);
+            }
+        }
+    }
+
+    config.tls = match keystore {
+        _ if no_tls => TlsConfig::Off,
+        Some(path) => TlsConfig::Keystore {
+            path,
+            password: keystore_pass,
+        },
+        None => TlsConfig::SelfSigned {
+            cache_dir: cert_cache,
+        },
+    };
+
+    if host.is_some() || port.is_some() {
+        config.addr = listen_addr(&config.addr, host, port).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+    }
+
+    // everything gets through the logger, the max level does the filtering
+    // so a reload can change it
+    env_logger::Builder::new().filter_level(LevelFilter::Trace).init();
+    log::set_max_level(log_level);
+    config.config_path = config_path;
+    config.config_file = file;
+
+    if let Some(out) = dump_path {
+        dump_map(config.map_path.as_deref(), &out);
+        return;
+    }
+    if let Some(path) = replay_path {
+        rerun(&path, config.map_path.as_deref());
+        return;
+    }
+
     let rx = Arc::new(tokio::sync::Mutex::new(rx));
-    let handle = tokio::spawn(server::run_server(rx));
+    let mut handle = tokio::spawn(server::run_server(config, rx));
+
+    // enter, Ctrl-C or a SIGTERM quits, unless the server stops first
+    let quit = async {
+        tokio::select! {
+            _ = enter_pressed() => {}
+            _ = shutdown_signal() => {}
+        }
+    };
+    let result = tokio::select! {
+        result = &mut handle => result,
+        _ = quit => {
+            // fails if the server already stopped, its result says why
+            let _ = tx.send(()).await;
+            handle.await
+        }
+    };
+    match result {
+        // stdin might still be read, so the runtime can't wait on it
+        Ok(Ok(())) => process::exit(0),
+        Ok(Err(e)) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Server panicked: {}", e);
+            process::exit(1);
+        }
+    }
+}
 
-    let mut buffer = String::new();
-    io::stdin().read_line(&mut buffer).unwrap();
-    tx.send(()).await.unwrap();
-    handle.await.unwrap();
+// Without a terminal stdin is closed or empty, that's no reason to quit
+async fn enter_pressed() {
+    let read = tokio::task::spawn_blocking(|| io::stdin().read_line(&mut String::new())).await;
+    if !matches!(read, Ok(Ok(n)) if n > 0) {
+        std::future::pending::<()>().await;
+    }
 }
 
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => println!("Can't listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        println!("Can't listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+// Replaces whichever half of host:port was given
+fn listen_addr(addr: &str, host: Option<String>, port: Option<String>) -> Result<String, String> {
+    let (old_host, old_port) = match addr.rsplit_once(':') {
+        Some((host, port)) => (host.to_owned(), port.parse().unwrap_or(DEFAULT_PORT)),
+        None => (addr.to_owned(), DEFAULT_PORT),
+    };
+    let host = host.unwrap_or(old_host);
+    let port = match port {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| format!("Bad port {}, expected 0-65535", port))?,
+        None => old_port,
+    };
+    let host = if host.is_empty() {
+        DEFAULT_HOST.to_owned()
+    } else if host.contains(':') && !host.starts_with('[') {
+        // IPv6 needs brackets to keep the port separate
+        format!("[{}]", host)
+    } else {
+        host
+    };
+    Ok(format!("{}:{}", host, port))
+}
+
+// Exits with an error at the first place the recording doesn't play out
+// the same, on the same maps it was recorded with
+fn rerun(path: &str, map_path: Option<&str>) {
+    let result = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path, e))
+        .and_then(|recording| {
+            let (catalog, _) = server::map_catalog(map_path)?;
+            replay::rerun(&recording, &catalog)
+        });
+    match result {
+        Ok(reruns) => {
+            for rerun in reruns {
+                println!(
+                    "Lobby {}: {} inputs, all {} events match",
+                    rerun.lobby, rerun.inputs, rerun.events
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Writes the configured map as graphviz and exits, failing if the map
+// doesn't validate
+fn dump_map(map_path: Option<&str>, out: &str) {
+    let map = match map_path {
+        Some(path) => {
+            let parsed = fs::read_to_string(path)
+                .map_err(|e| format!("Could not read {}: {}", path, e))
+                .and_then(|json| Map::parse(&json).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(map) => map,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        None => Map::new(),
+    };
+
+    if let Err(e) = fs::write(out, map.to_dot()) {
+        eprintln!("Could not write {}: {}", out, e);
+        process::exit(1);
+    }
+    println!("Wrote {}", out);
+
+    if let Err(e) = map.validate() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -1,128 +1,1321 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BTreeMap, BinaryHeap, HashSet},
+    fmt, fs,
+    hash::{Hash, Hasher},
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
 
 pub type RoomLabel = usize;
 pub type Path = Vec<RoomLabel>;
 
-#[derive(Clone)]
+// How a client can point at a room, by label or by name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum RoomRef {
+    Label(RoomLabel),
+    Name(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Room {
     pub label: RoomLabel,
-    connected_rooms: Vec<RoomLabel>,
+    pub name: String,
+    connections: Vec<Connection>,
+    // 0 is the ground floor, negative is below it
+    #[serde(default)]
+    pub floor: i8,
+    #[serde(default)]
+    pub category: Option<RoomCategory>,
+    #[serde(default)]
+    pub safe_room: bool,
+    // the one the power comes back on from, see Map::breaker_room
+    #[serde(default)]
+    pub breaker: bool,
+    // outside the building, where the ghost never goes
+    #[serde(default)]
+    pub exterior: bool,
+    #[serde(default)]
+    pub hiding_spots: u8,
+    // loose objects the ghost can throw
+    #[serde(default = "default_objects")]
+    pub objects: u32,
+    // where the room sits on the floor plan, for clients that draw it
+    #[serde(default)]
+    pub bounds: Option<Bounds>,
+    #[serde(default)]
+    pub size: RoomSize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum RoomCategory {
+    Bedroom,
+    Bathroom,
+    Kitchen,
+    Living,
+    Hallway,
+    Basement,
+    Attic,
+    Garage,
+    Utility,
+    Storage,
+    Office,
+    Classroom,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum RoomSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl RoomSize {
+    // How fast the room's temperature moves compared to a medium room.
+    // Closets swing quickly, big open rooms take a while.
+    pub fn thermal_rate(&self) -> f64 {
+        match self {
+            RoomSize::Small => 1.5,
+            RoomSize::Medium => 1.0,
+            RoomSize::Large => 0.6,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+pub type DoorId = u32;
+
+// Connections are either a bare label or {"to": label, "weight": n, "stairs": b, "door": id}.
+// Weight is how long the edge takes to cross, in ghost moves. Rooms on
+// different floors can only be joined by stairs. Whether a door is open
+// is up to the sim.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(from = "ConnectionDef")]
+pub struct Connection {
+    pub to: RoomLabel,
+    pub weight: u32,
+    pub stairs: bool,
+    pub door: Option<DoorId>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConnectionDef {
+    Plain(RoomLabel),
+    Weighted {
+        to: RoomLabel,
+        #[serde(default = "default_weight")]
+        weight: u32,
+        #[serde(default)]
+        stairs: bool,
+        #[serde(default)]
+        door: Option<DoorId>,
+    },
+}
+
+impl From<ConnectionDef> for Connection {
+    fn from(def: ConnectionDef) -> Self {
+        match def {
+            ConnectionDef::Plain(to) => Connection {
+                to,
+                weight: default_weight(),
+                stairs: false,
+                door: None,
+            },
+            ConnectionDef::Weighted {
+                to,
+                weight,
+                stairs,
+                door,
+            } => Connection {
+                to,
+                weight,
+                stairs,
+                door,
+            },
+        }
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+// Heaviest a connection can be, so summing a path's weights can't overflow
+pub const MAX_WEIGHT: u32 = 1000;
+
+// What crossing a connection costs, None if it can't be crossed
+pub type EdgeCost<'a> = &'a dyn Fn(&Connection) -> Option<u32>;
+
+fn static_cost(conn: &Connection) -> Option<u32> {
+    Some(conn.weight)
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(String),
+    Parse(String),
+    NoRooms,
+    DuplicateName(String),
+    LabelMismatch {
+        room: String,
+        label: RoomLabel,
+        expected: RoomLabel,
+    },
+    SelfLoop(String),
+    UnknownConnection {
+        room: String,
+        to: RoomLabel,
+    },
+    OneWayConnection {
+        from: String,
+        to: String,
+    },
+    InvalidWeight {
+        from: String,
+        to: String,
+    },
+    InvalidStairs {
+        from: String,
+        to: String,
+    },
+    InvalidDoor {
+        from: String,
+        to: String,
+    },
+    Unreachable(String),
+    InteriorSplit(String),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "{}", e),
+            MapError::Parse(e) => write!(f, "Invalid map: {}", e),
+            MapError::NoRooms => write!(f, "Map has no rooms"),
+            MapError::DuplicateName(name) => {
+                write!(f, "Room name {} is used more than once", name)
+            }
+            MapError::LabelMismatch {
+                room,
+                label,
+                expected,
+            } => write!(f, "Room {} has label {}, expected {}", room, label, expected),
+            MapError::SelfLoop(room) => write!(f, "Room {} connects to itself", room),
+            MapError::UnknownConnection { room, to } => {
+                write!(f, "Room {} connects to unknown room {}", room, to)
+            }
+            MapError::OneWayConnection { from, to } => {
+                write!(f, "Room {} connects to {} but not the other way", from, to)
+            }
+            MapError::InvalidWeight { from, to } => write!(
+                f,
+                "Connection between {} and {} needs the same weight both ways, from 1 to {}",
+                from, to, MAX_WEIGHT
+            ),
+            MapError::InvalidStairs { from, to } => write!(
+                f,
+                "Connection between {} and {} must be stairs both ways if and only if it changes floor",
+                from, to
+            ),
+            MapError::InvalidDoor { from, to } => write!(
+                f,
+                "Connection between {} and {} needs the same door both ways",
+                from, to
+            ),
+            MapError::Unreachable(room) => {
+                write!(f, "Room {} can't be reached from the safe room", room)
+            }
+            MapError::InteriorSplit(room) => write!(
+                f,
+                "Room {} can only be reached from the rest of the building by going outside",
+                room
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+// What clients need to label rooms in their UI
+#[derive(Serialize, Clone)]
+pub struct RoomInfo {
+    pub label: RoomLabel,
+    pub name: String,
+    pub floor: i8,
+    pub category: Option<RoomCategory>,
+    pub hiding_spots: u8,
 }
 
+fn default_objects() -> u32 {
+    3
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Map {
     pub rooms: Vec<Room>,
+    // weighted distance between every pair of rooms, filled in at load
+    #[serde(skip)]
+    distances: Vec<Vec<Option<u32>>>,
+}
+
+pub const DEFAULT_MAP: &str = "default";
+
+// id StartSim uses to ask for a generated map
+pub const RANDOM_MAP: &str = "random";
+
+pub struct GenerateParams {
+    // extra connections on top of the spanning tree, per room
+    pub loop_fraction: f64,
+    pub max_hiding_spots: u8,
+    pub max_objects: u32,
+}
+
+impl Default for GenerateParams {
+    fn default() -> Self {
+        GenerateParams {
+            loop_fraction: 0.25,
+            max_hiding_spots: 2,
+            max_objects: 6,
+        }
+    }
+}
+
+const ROOM_NAMES: &[&str] = &[
+    "Kitchen",
+    "Parlor",
+    "Study",
+    "Library",
+    "Nursery",
+    "Master Bedroom",
+    "Guest Bedroom",
+    "Bathroom",
+    "Laundry Room",
+    "Pantry",
+    "Cellar",
+    "Attic",
+    "Dining Room",
+    "Sitting Room",
+    "Conservatory",
+    "Servants' Quarters",
+    "Chapel",
+    "Hallway",
+    "Sewing Room",
+    "Boiler Room",
+    "Gallery",
+    "Storeroom",
+    "Music Room",
+    "Wine Cellar",
+];
+
+// Maps that can be picked in the lobby, by id
+#[derive(Clone)]
+pub struct MapCatalog {
+    maps: Vec<(String, Map)>,
+}
+
+impl Default for MapCatalog {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl MapCatalog {
+    pub fn builtin() -> Self {
+        let builtin = [
+            (DEFAULT_MAP, include_str!("../maps/default.json")),
+            ("small_house", include_str!("../maps/small_house.json")),
+            ("large_house", include_str!("../maps/large_house.json")),
+            ("school", include_str!("../maps/school.json")),
+        ];
+
+        let maps = builtin
+            .iter()
+            .map(|(id, json)| (id.to_string(), Map::from_json(json).unwrap()))
+            .collect();
+        MapCatalog { maps }
+    }
+
+    // Replaces any map already registered under the id
+    pub fn add(&mut self, id: &str, map: Map) {
+        self.maps.retain(|(map_id, _)| map_id != id);
+        self.maps.push((id.to_owned(), map));
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Map> {
+        self.maps.iter().find(|(map_id, _)| map_id == id).map(|(_, map)| map)
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.maps.iter().map(|(id, _)| id.clone()).collect()
+    }
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Map {
+    // The built-in map, shipped as a file so custom maps share the format
     pub fn new() -> Self {
-        Map {
-            rooms: vec! [
-                Room {
-                    label: 0,
-                    connected_rooms: vec![1, 2, 13],
-                },
-                Room {
-                    label: 1,
-                    connected_rooms: vec![0],
-                },
-                Room {
-                    label: 2,
-                    connected_rooms: vec![0, 3, 4, 6],
-                },
-                Room {
-                    label: 3,
-                    connected_rooms: vec![2, 4],
-                },
-                Room {
-                    label: 4,
-                    connected_rooms: vec![2, 3, 5],
-                },
-                Room {
-                    label: 5,
-                    connected_rooms: vec![4],
-                },
-                Room {
-                    label: 6,
-                    connected_rooms: vec![2,7],
-                },
-                Room {
-                    label: 7,
-                    connected_rooms: vec![6],
-                },
-                Room {
-                    label: 8,
-                    connected_rooms: vec![9],
-                },
-                Room {
-                    label: 9,
-                    connected_rooms: vec![8, 13],
-                },
-                Room {
-                    label: 10,
-                    connected_rooms: vec![11,13,9],
-                },
-                Room {
-                    label: 11,
-                    connected_rooms: vec![10],
-                },
-                Room {
-                    label: 12,
-                    connected_rooms: vec![13],
-                },
+        Map::from_json(include_str!("../maps/default.json")).unwrap()
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, MapError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| MapError::Io(format!("Could not read {}: {}", path, e)))?;
+        Map::from_json(&contents)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, MapError> {
+        Map::parse(json)?.finish()
+    }
+
+    // Reads the map without validating it. Only good for looking at, the
+    // sim and pathfinding need a map from from_json.
+    pub fn parse(json: &str) -> Result<Self, MapError> {
+        serde_json::from_str(json).map_err(|e| MapError::Parse(e.to_string()))
+    }
+
+    fn finish(mut self) -> Result<Self, MapError> {
+        self.validate()?;
+        self.distances = (0..self.rooms.len())
+            .map(|from| self.dijkstra(from, &static_cost).0)
+            .collect();
+        Ok(self)
+    }
+
+    // A random single-floor layout: a spanning tree with a few extra edges
+    // for loops, and the safe room on a dead end so it's at the edge
+    pub fn generate<R: Rng>(rng: &mut R, n_rooms: usize, params: &GenerateParams) -> Self {
+        let n_rooms = n_rooms.max(2);
+
+        let mut edges: Vec<(RoomLabel, RoomLabel)> =
+            (1..n_rooms).map(|room| (rng.gen_range(0..room), room)).collect();
+        let degree = |edges: &[(RoomLabel, RoomLabel)], room| {
+            edges.iter().filter(|(a, b)| *a == room || *b == room).count()
+        };
+
+        let leaves: Vec<RoomLabel> = (0..n_rooms).filter(|r| degree(&edges, *r) == 1).collect();
+        let safe_room = *leaves.choose(rng).unwrap();
+
+        let extra = (n_rooms as f64 * params.loop_fraction).round() as usize;
+        for _ in 0..extra * 4 {
+            if edges.len() >= n_rooms - 1 + extra {
+                break;
+            }
+            let a = rng.gen_range(0..n_rooms);
+            let b = rng.gen_range(0..n_rooms);
+            let taken = edges.contains(&(a, b)) || edges.contains(&(b, a));
+            if a != b && a != safe_room && b != safe_room && !taken {
+                edges.push((a, b));
+            }
+        }
+
+        let mut names: Vec<&str> = ROOM_NAMES.to_vec();
+        names.shuffle(rng);
+        let rooms = (0..n_rooms)
+            .map(|label| {
+                let name = if label == safe_room {
+                    "Entryway".to_owned()
+                } else {
+                    // run out of names on big maps, so start numbering them
+                    let ix = if label > safe_room { label - 1 } else { label };
+                    let base = names[ix % names.len()];
+                    match ix / names.len() {
+                        0 => base.to_owned(),
+                        n => format!("{} {}", base, n + 1),
+                    }
+                };
+                let connections = edges
+                    .iter()
+                    .filter_map(|(a, b)| match label {
+                        l if l == *a => Some(*b),
+                        l if l == *b => Some(*a),
+                        _ => None,
+                    })
+                    .map(|to| Connection {
+                        to,
+                        weight: default_weight(),
+                        stairs: false,
+                        door: None,
+                    })
+                    .collect();
+
                 Room {
-                    label: 13,
-                    connected_rooms: vec![0, 9, 10, 12],
-                },
-            ]
+                    label,
+                    name,
+                    connections,
+                    floor: 0,
+                    category: None,
+                    safe_room: label == safe_room,
+                    breaker: false,
+                    exterior: false,
+                    hiding_spots: rng.gen_range(0..=params.max_hiding_spots),
+                    objects: rng.gen_range(0..=params.max_objects),
+                    bounds: None,
+                    size: RoomSize::default(),
+                }
+            })
+            .collect();
+
+        Map {
+            rooms,
+            distances: Vec::new(),
+        }
+        .finish()
+        .expect("Generated map should always be valid")
+    }
+
+    // Everything else indexes rooms by label and assumes it can path
+    // between any two rooms, so this has to pass before a map is used
+    pub fn validate(&self) -> Result<(), MapError> {
+        if self.rooms.is_empty() {
+            return Err(MapError::NoRooms);
+        }
+
+        let mut names = HashSet::new();
+        for room in &self.rooms {
+            if !names.insert(room.name.as_str()) {
+                return Err(MapError::DuplicateName(room.name.clone()));
+            }
+        }
+
+        for (ix, room) in self.rooms.iter().enumerate() {
+            if room.label != ix {
+                return Err(MapError::LabelMismatch {
+                    room: room.name.clone(),
+                    label: room.label,
+                    expected: ix,
+                });
+            }
+        }
+
+        for room in &self.rooms {
+            for conn in &room.connections {
+                if conn.to == room.label {
+                    return Err(MapError::SelfLoop(room.name.clone()));
+                }
+                let other = match self.rooms.get(conn.to) {
+                    Some(other) => other,
+                    None => {
+                        return Err(MapError::UnknownConnection {
+                            room: room.name.clone(),
+                            to: conn.to,
+                        })
+                    }
+                };
+                let back = match self.connection(other.label, room.label) {
+                    Some(back) => back,
+                    None => {
+                        return Err(MapError::OneWayConnection {
+                            from: room.name.clone(),
+                            to: other.name.clone(),
+                        })
+                    }
+                };
+                if back.weight != conn.weight || !(1..=MAX_WEIGHT).contains(&conn.weight) {
+                    return Err(MapError::InvalidWeight {
+                        from: room.name.clone(),
+                        to: other.name.clone(),
+                    });
+                }
+                let changes_floor = room.floor != other.floor;
+                if back.stairs != conn.stairs || conn.stairs != changes_floor {
+                    return Err(MapError::InvalidStairs {
+                        from: room.name.clone(),
+                        to: other.name.clone(),
+                    });
+                }
+                if back.door != conn.door {
+                    return Err(MapError::InvalidDoor {
+                        from: room.name.clone(),
+                        to: other.name.clone(),
+                    });
+                }
+            }
+        }
+
+        let start = self.safe_room().unwrap_or(0);
+        let (reachable, _) = self.dijkstra(start, &static_cost);
+        for room in &self.rooms {
+            if reachable[room.label].is_none() {
+                return Err(MapError::Unreachable(room.name.clone()));
+            }
+        }
+
+        // the ghost has to get around without stepping outside
+        if let Some(inside) = self.rooms.iter().find(|r| !r.exterior) {
+            let (reachable, _) = self.dijkstra(inside.label, &|c| self.interior_cost(c));
+            for room in self.rooms.iter().filter(|r| !r.exterior) {
+                if reachable[room.label].is_none() {
+                    return Err(MapError::InteriorSplit(room.name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Changes whenever the map does, so clients know to fetch it again
+    pub fn hash(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self).unwrap().hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    // Graphviz description for checking a map by eye. Works on maps that
+    // fail validation too, that's usually when it's needed.
+    pub fn to_dot(&self) -> String {
+        let mut floors: BTreeMap<i8, Vec<&Room>> = BTreeMap::new();
+        for room in &self.rooms {
+            floors.entry(room.floor).or_default().push(room);
+        }
+
+        let mut dot = String::from("graph map {\n");
+        for (floor, rooms) in &floors {
+            let cluster = if *floor < 0 {
+                format!("cluster_floor_m{}", -floor)
+            } else {
+                format!("cluster_floor_{}", floor)
+            };
+            dot += &format!("    subgraph {} {{\n", cluster);
+            dot += &format!("        label=\"Floor {}\";\n", floor);
+            for room in rooms {
+                let mut attrs = vec![format!("label=\"{}\"", room.name.replace('"', "\\\""))];
+                if room.safe_room {
+                    attrs.push("shape=doublecircle".to_owned());
+                }
+                if room.exterior {
+                    attrs.push("style=dashed".to_owned());
+                }
+                dot += &format!("        r{} [{}];\n", room.label, attrs.join(", "));
+            }
+            dot += "    }\n";
+        }
+
+        for room in &self.rooms {
+            for conn in &room.connections {
+                // each edge once, unless it's one-way and needs showing anyway
+                let reverse = self.connection(conn.to, room.label).is_some();
+                if reverse && conn.to < room.label {
+                    continue;
+                }
+
+                let mut labels = Vec::new();
+                if conn.weight != 1 {
+                    labels.push(conn.weight.to_string());
+                }
+                if let Some(door) = conn.door {
+                    labels.push(format!("door {}", door));
+                }
+                let mut attrs = Vec::new();
+                if !labels.is_empty() {
+                    attrs.push(format!("label=\"{}\"", labels.join(", ")));
+                }
+                if conn.stairs {
+                    attrs.push("style=bold".to_owned());
+                }
+                if !reverse {
+                    attrs.push("color=red".to_owned());
+                }
+
+                dot += &format!("    r{} -- r{}", room.label, conn.to);
+                if !attrs.is_empty() {
+                    dot += &format!(" [{}]", attrs.join(", "));
+                }
+                dot += ";\n";
+            }
         }
+        dot += "}\n";
+        dot
+    }
+
+    pub fn safe_room(&self) -> Option<RoomLabel> {
+        self.rooms.iter().find(|r| r.safe_room).map(|r| r.label)
+    }
+
+    // Maps that don't say keep it in the safe room
+    pub fn breaker_room(&self) -> RoomLabel {
+        self.rooms
+            .iter()
+            .find(|r| r.breaker)
+            .map(|r| r.label)
+            .or_else(|| self.safe_room())
+            .unwrap_or(0)
+    }
+
+    pub fn room_name(&self, label: RoomLabel) -> &str {
+        self.rooms
+            .get(label)
+            .map(|r| r.name.as_str())
+            .unwrap_or("Unknown room")
+    }
+
+    // Names match whatever the case
+    pub fn resolve(&self, room: &RoomRef) -> Result<RoomLabel, String> {
+        match room {
+            RoomRef::Label(label) if self.contains(*label) => Ok(*label),
+            RoomRef::Label(label) => Err(format!("No room with label {}", label)),
+            RoomRef::Name(name) => self
+                .rooms
+                .iter()
+                .find(|r| r.name.eq_ignore_ascii_case(name))
+                .map(|r| r.label)
+                .ok_or_else(|| format!("No room named {}", name)),
+        }
+    }
+
+    // Room name with the floor spelled out, for notifications
+    pub fn located_name(&self, label: RoomLabel) -> String {
+        let name = self.room_name(label);
+        match self.rooms.get(label).map(|r| r.floor) {
+            Some(1) => format!("{} (upstairs)", name),
+            Some(-1) => format!("{} (basement)", name),
+            Some(floor) if floor > 1 => format!("{} (floor {})", name, floor),
+            Some(floor) if floor < -1 => format!("{} (basement {})", name, -floor),
+            _ => name.to_owned(),
+        }
+    }
+
+    pub fn directory(&self) -> Vec<RoomInfo> {
+        self.rooms
+            .iter()
+            .map(|r| RoomInfo {
+                label: r.label,
+                name: r.name.clone(),
+                floor: r.floor,
+                category: r.category,
+                hiding_spots: r.hiding_spots,
+            })
+            .collect()
     }
 
-    pub fn get_path(&self, from: RoomLabel, to: RoomLabel) -> Path {
-        let mut path = self._get_path(from, to, Vec::new(), HashSet::new()).unwrap();
-        path.reverse();
-        path
+    // Shortest path from `from` to `to`, excluding `from`. Reversed so the
+    // next room to move into is at the end and can be popped off. None when
+    // there's no way there, or either room isn't on the map.
+    pub fn get_path(&self, from: RoomLabel, to: RoomLabel) -> Option<Path> {
+        self.get_path_with(from, to, &static_cost)
     }
 
-    // basic DFS to get ghost path
-    fn _get_path(
+    // Same as get_path, but asks `cost` what each connection costs right
+    // now. None means it can't be crossed at all.
+    pub fn get_path_with(
         &self,
-        room: RoomLabel,
-        target: RoomLabel,
-        path: Vec<RoomLabel>,
-        visited: HashSet<RoomLabel>,
+        from: RoomLabel,
+        to: RoomLabel,
+        cost: EdgeCost,
     ) -> Option<Path> {
-        if room == target {
-            Some(path.clone())
+        if !self.contains(from) || !self.contains(to) {
+            return None;
+        }
+        self.shortest_path(from, to, cost)
+    }
+
+    pub fn contains(&self, label: RoomLabel) -> bool {
+        label < self.rooms.len()
+    }
+
+    pub fn neighbours(&self, label: RoomLabel) -> Vec<RoomLabel> {
+        self.rooms
+            .get(label)
+            .map(|r| r.connections.iter().map(|c| c.to).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn connections(&self, label: RoomLabel) -> &[Connection] {
+        self.rooms
+            .get(label)
+            .map(|r| r.connections.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn connection(&self, from: RoomLabel, to: RoomLabel) -> Option<&Connection> {
+        self.connections(from).iter().find(|c| c.to == to)
+    }
+
+    pub fn is_exterior(&self, label: RoomLabel) -> bool {
+        self.rooms.get(label).is_some_and(|r| r.exterior)
+    }
+
+    // Everywhere the ghost is allowed to be
+    pub fn interior_rooms(&self) -> Vec<RoomLabel> {
+        self.rooms
+            .iter()
+            .filter(|r| !r.exterior)
+            .map(|r| r.label)
+            .collect()
+    }
+
+    // Static cost with exterior rooms cut out of the graph
+    pub fn interior_cost(&self, conn: &Connection) -> Option<u32> {
+        if self.is_exterior(conn.to) {
+            None
         } else {
-            let room = &self.rooms[room];
-            let mut paths = Vec::new();
-            for adj in &room.connected_rooms {
-                if !visited.contains(&adj) {
-                    let mut new_path = path.clone();
-                    let mut new_visited = visited.clone();
-                    new_path.push(*adj);
-                    new_visited.insert(*adj);
-                    let shortest_path = self._get_path(*adj, target, new_path, new_visited);
-                    paths.push(shortest_path);
+            Some(conn.weight)
+        }
+    }
+
+    pub fn has_door(&self, door: DoorId) -> bool {
+        self.rooms
+            .iter()
+            .any(|r| r.connections.iter().any(|c| c.door == Some(door)))
+    }
+
+    pub fn edge_weight(&self, from: RoomLabel, to: RoomLabel) -> Option<u32> {
+        self.connection(from, to).map(|c| c.weight)
+    }
+
+    // How many floors apart two rooms are
+    pub fn floors_between(&self, a: RoomLabel, b: RoomLabel) -> usize {
+        match (self.rooms.get(a), self.rooms.get(b)) {
+            (Some(a), Some(b)) => (a.floor - b.floor).unsigned_abs() as usize,
+            _ => 0,
+        }
+    }
+
+    // Dijkstra keeping each room's predecessor, then walk back from the target
+    fn shortest_path(&self, from: RoomLabel, to: RoomLabel, cost: EdgeCost) -> Option<Path> {
+        let (dist, prev) = self.dijkstra(from, cost);
+        dist[to]?;
+
+        let mut path = Vec::new();
+        let mut room = to;
+        while room != from {
+            path.push(room);
+            room = prev[room].unwrap();
+        }
+        Some(path)
+    }
+
+    fn dijkstra(
+        &self,
+        from: RoomLabel,
+        cost: EdgeCost,
+    ) -> (Vec<Option<u32>>, Vec<Option<RoomLabel>>) {
+        let mut dist: Vec<Option<u32>> = vec![None; self.rooms.len()];
+        let mut prev: Vec<Option<RoomLabel>> = vec![None; self.rooms.len()];
+        let mut queue = BinaryHeap::new();
+        dist[from] = Some(0);
+        queue.push(Reverse((0, from)));
+
+        while let Some(Reverse((d, room))) = queue.pop() {
+            if dist[room].is_some_and(|best| d > best) {
+                continue;
+            }
+            for conn in &self.rooms[room].connections {
+                let next = match cost(conn) {
+                    // costs can add to a weight, don't let them wrap
+                    Some(weight) => d.saturating_add(weight),
+                    None => continue,
+                };
+                if dist[conn.to].is_none_or(|best| next < best) {
+                    dist[conn.to] = Some(next);
+                    prev[conn.to] = Some(room);
+                    queue.push(Reverse((next, conn.to)));
                 }
             }
+        }
+        (dist, prev)
+    }
 
-            if paths.is_empty() {
-                None
-            } else {
-                let mut shortest_path: Vec<usize> = (0..self.rooms.len() + 1).into_iter().collect();
-                for path in paths {
-                    match path {
-                        None => (),
-                        Some(path) => {
-                            if path.len() < shortest_path.len() {
-                                shortest_path = path;
-                            }
-                        }
-                    }
+    // Weighted distance between two rooms, None if they aren't connected
+    pub fn distance(&self, from: RoomLabel, to: RoomLabel) -> Option<u32> {
+        *self.distances.get(from)?.get(to)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::{json, Value};
+
+    // Rooms in a line, 0 the safe room, each joined to the next
+    fn line(n: usize) -> Value {
+        let rooms: Vec<Value> = (0..n)
+            .map(|label| {
+                let mut connections = Vec::new();
+                if label > 0 {
+                    connections.push(label - 1);
+                }
+                if label + 1 < n {
+                    connections.push(label + 1);
+                }
+                json!({
+                    "label": label,
+                    "name": format!("Room {}", label),
+                    "connections": connections,
+                    "safe_room": label == 0,
+                })
+            })
+            .collect();
+        json!({ "rooms": rooms })
+    }
+
+    // w by h rooms, each joined to the ones beside it
+    fn grid(w: usize, h: usize) -> Value {
+        let rooms: Vec<Value> = (0..w * h)
+            .map(|label| {
+                let (x, y) = (label % w, label / w);
+                let mut connections = Vec::new();
+                if x > 0 {
+                    connections.push(label - 1);
+                }
+                if x + 1 < w {
+                    connections.push(label + 1);
+                }
+                if y > 0 {
+                    connections.push(label - w);
+                }
+                if y + 1 < h {
+                    connections.push(label + w);
+                }
+                json!({
+                    "label": label,
+                    "name": format!("Room {},{}", x, y),
+                    "connections": connections,
+                    "safe_room": label == 0,
+                })
+            })
+            .collect();
+        json!({ "rooms": rooms })
+    }
+
+    fn load(map: &Value) -> Result<Map, MapError> {
+        Map::from_json(&map.to_string())
+    }
+
+    fn error(map: &Value) -> MapError {
+        match load(map) {
+            Ok(_) => panic!("{} loaded", map),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn the_shipped_file_is_the_builtin_map() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/maps/default.json");
+        let map = Map::from_file(path).unwrap();
+        assert_eq!(map.hash(), Map::new().hash());
+        assert_eq!(map.safe_room(), Some(0));
+        assert_eq!(map.rooms[3].objects, 6);
+        // objects default when they're left out
+        assert_eq!(load(&line(2)).unwrap().rooms[1].objects, default_objects());
+    }
+
+    #[test]
+    fn room_sizes_set_how_fast_temperature_moves() {
+        let rates = [
+            (RoomSize::Small, 1.5),
+            (RoomSize::Medium, 1.0),
+            (RoomSize::Large, 0.6),
+        ];
+        for (size, rate) in rates {
+            assert_eq!(size.thermal_rate(), rate);
+        }
+
+        let mut map = line(3);
+        map["rooms"][1]["size"] = json!("Large");
+        let map = load(&map).unwrap();
+        assert!(map.rooms[1].size == RoomSize::Large);
+        assert!(map.rooms[2].size == RoomSize::Medium);
+    }
+
+    #[test]
+    fn malformed_maps_are_rejected() {
+        let without = |key: &str| {
+            let mut map = line(3);
+            map["rooms"][1].as_object_mut().unwrap().remove(key);
+            map
+        };
+        let with = |key: &str, value: Value| {
+            let mut map = line(3);
+            map["rooms"][1][key] = value;
+            map
+        };
+        let parse_errors = [
+            json!({}),
+            json!({"rooms": "Foyer"}),
+            without("name"),
+            without("label"),
+            without("connections"),
+            with("label", json!("one")),
+            with("connections", json!(1)),
+            with("connections", json!([{"weight": 2}])),
+            with("hiding_spots", json!(-1)),
+            with("size", json!("Huge")),
+        ];
+        for map in parse_errors {
+            assert!(matches!(load(&map), Err(MapError::Parse(_))), "{}", map);
+        }
+        assert!(matches!(
+            Map::from_json("{\"rooms\": ["),
+            Err(MapError::Parse(_))
+        ));
+        assert!(matches!(
+            Map::from_file("/nonexistent/map.json"),
+            Err(MapError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn maps_are_validated() {
+        assert!(matches!(error(&json!({"rooms": []})), MapError::NoRooms));
+
+        let mut dupe = line(3);
+        dupe["rooms"][2]["name"] = json!("Room 1");
+        assert!(matches!(error(&dupe), MapError::DuplicateName(n) if n == "Room 1"));
+
+        let mut relabeled = line(3);
+        relabeled["rooms"][1]["label"] = json!(5);
+        assert!(matches!(
+            error(&relabeled),
+            MapError::LabelMismatch { label: 5, expected: 1, .. }
+        ));
+
+        let mut self_loop = line(3);
+        self_loop["rooms"][1]["connections"] = json!([0, 1, 2]);
+        assert!(matches!(error(&self_loop), MapError::SelfLoop(n) if n == "Room 1"));
+
+        let mut unknown = line(3);
+        unknown["rooms"][0]["connections"] = json!([1, 3]);
+        assert!(matches!(error(&unknown), MapError::UnknownConnection { to: 3, .. }));
+
+        let mut one_way = line(3);
+        one_way["rooms"][0]["connections"] = json!([1, 2]);
+        assert!(matches!(
+            error(&one_way),
+            MapError::OneWayConnection { from, to } if from == "Room 0" && to == "Room 2"
+        ));
+
+        let mut cut_off = line(4);
+        cut_off["rooms"][1]["connections"] = json!([0]);
+        cut_off["rooms"][2]["connections"] = json!([3]);
+        assert!(matches!(error(&cut_off), MapError::Unreachable(n) if n == "Room 2"));
+
+        // connected from the safe room wherever it is
+        let mut safe_last = line(3);
+        safe_last["rooms"][0]["safe_room"] = json!(false);
+        safe_last["rooms"][2]["safe_room"] = json!(true);
+        assert_eq!(load(&safe_last).unwrap().safe_room(), Some(2));
+    }
+
+    #[test]
+    fn paths_across_a_big_grid_are_shortest() {
+        let map = load(&grid(20, 20)).unwrap();
+        let (from, to) = (0, 20 * 20 - 1);
+        let path = map.get_path(from, to).unwrap();
+        assert_eq!(path.len(), 19 + 19);
+        assert_eq!(path[0], to);
+        let mut room = from;
+        for next in path.iter().rev() {
+            assert!(map.connection(room, *next).is_some(), "{} -> {}", room, next);
+            room = *next;
+        }
+        assert_eq!(map.distance(from, to), Some(38));
+    }
+
+    #[test]
+    fn the_longest_possible_path_is_still_found() {
+        // every room on the way, as long as a path can get
+        for n in [2, 3, 14, 40] {
+            let map = load(&line(n)).unwrap();
+            let expected: Path = (1..n).rev().collect();
+            assert_eq!(map.get_path(0, n - 1).unwrap(), expected);
+            assert_eq!(map.get_path(n - 1, 0).unwrap().len(), n - 1);
+        }
+    }
+
+    #[test]
+    fn paths_take_the_lighter_route() {
+        // straight across from 0 to 3 is one hop but costs 5
+        let mut map = line(4);
+        map["rooms"][0]["connections"] = json!([1, {"to": 3, "weight": 5}]);
+        map["rooms"][3]["connections"] = json!([2, {"to": 0, "weight": 5}]);
+        let map = load(&map).unwrap();
+        assert_eq!(map.get_path(0, 3).unwrap(), vec![3, 2, 1]);
+        assert_eq!(map.distance(0, 3), Some(3));
+        assert_eq!(map.edge_weight(0, 3), Some(5));
+
+        // unless that's made cheap enough
+        let mut map = line(4);
+        map["rooms"][0]["connections"] = json!([1, {"to": 3, "weight": 2}]);
+        map["rooms"][3]["connections"] = json!([2, {"to": 0, "weight": 2}]);
+        assert_eq!(load(&map).unwrap().get_path(0, 3).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn weights_are_validated() {
+        let mut uneven = line(3);
+        uneven["rooms"][0]["connections"] = json!([{"to": 1, "weight": 2}]);
+        assert!(matches!(error(&uneven), MapError::InvalidWeight { .. }));
+
+        let mut zero = line(3);
+        zero["rooms"][0]["connections"] = json!([{"to": 1, "weight": 0}]);
+        zero["rooms"][1]["connections"] = json!([{"to": 0, "weight": 0}, 2]);
+        assert!(matches!(error(&zero), MapError::InvalidWeight { .. }));
+
+        // two of these in a row would overflow a path's length
+        let mut huge = line(3);
+        huge["rooms"][0]["connections"] = json!([{"to": 1, "weight": 4_000_000_000u32}]);
+        huge["rooms"][1]["connections"] = json!([
+            {"to": 0, "weight": 4_000_000_000u32},
+            {"to": 2, "weight": 4_000_000_000u32}
+        ]);
+        huge["rooms"][2]["connections"] = json!([{"to": 1, "weight": 4_000_000_000u32}]);
+        assert!(matches!(error(&huge), MapError::InvalidWeight { .. }));
+
+        let mut heaviest = line(3);
+        heaviest["rooms"][0]["connections"] = json!([{"to": 1, "weight": MAX_WEIGHT}]);
+        heaviest["rooms"][1]["connections"] = json!([{"to": 0, "weight": MAX_WEIGHT}, 2]);
+        assert_eq!(load(&heaviest).unwrap().distance(0, 2), Some(MAX_WEIGHT + 1));
+    }
+
+    #[test]
+    fn costs_too_big_to_add_up_dont_wrap() {
+        let map = load(&line(4)).unwrap();
+        let huge = |_: &Connection| Some(u32::MAX - 1);
+        assert_eq!(map.get_path_with(0, 3, &huge), Some(vec![3, 2, 1]));
+        let (dist, _) = map.dijkstra(0, &huge);
+        assert_eq!(dist[3], Some(u32::MAX));
+    }
+
+    #[test]
+    fn upstairs_is_only_through_the_stairs() {
+        let map = Map::new();
+        // Foyer to the Bathroom above, up the Stairwell to the landing
+        assert_eq!(map.get_path(0, 3).unwrap(), vec![3, 14, 6, 2]);
+        assert_eq!(map.get_path(3, 0).unwrap(), vec![0, 2, 6, 14]);
+        assert_eq!(map.floors_between(0, 3), 1);
+        assert_eq!(map.floors_between(0, 2), 0);
+        assert!(map.connection(6, 14).unwrap().stairs);
+        assert_eq!(map.located_name(3), "Bathroom (upstairs)");
+        assert_eq!(map.located_name(0), "Foyer");
+    }
+
+    #[test]
+    fn stairs_are_validated() {
+        // floors apart without stairs
+        let mut flat = line(3);
+        flat["rooms"][2]["floor"] = json!(1);
+        assert!(matches!(error(&flat), MapError::InvalidStairs { .. }));
+
+        // stairs on the one floor
+        let mut level = line(3);
+        level["rooms"][1]["connections"] = json!([0, {"to": 2, "stairs": true}]);
+        level["rooms"][2]["connections"] = json!([{"to": 1, "stairs": true}]);
+        assert!(matches!(error(&level), MapError::InvalidStairs { .. }));
+
+        let mut both = level.clone();
+        both["rooms"][2]["floor"] = json!(-1);
+        let map = load(&both).unwrap();
+        assert_eq!(map.located_name(2), "Room 2 (basement)");
+        assert_eq!(map.get_path(0, 2).unwrap(), vec![2, 1]);
+    }
+
+    #[test]
+    fn distances_match_pathing_on_the_fly() {
+        let catalog = MapCatalog::builtin();
+        for id in catalog.ids() {
+            let map = catalog.get(&id).unwrap();
+            for from in 0..map.rooms.len() {
+                for to in 0..map.rooms.len() {
+                    let path = map.get_path(from, to).unwrap();
+                    let walked: u32 = path
+                        .iter()
+                        .rev()
+                        .scan(from, |room, next| {
+                            let weight = map.edge_weight(*room, *next);
+                            *room = *next;
+                            weight
+                        })
+                        .sum();
+                    assert_eq!(map.distance(from, to), Some(walked), "{} {} {}", id, from, to);
                 }
-                Some(shortest_path)
             }
         }
     }
+
+    #[test]
+    fn rooms_that_cant_reach_each_other_have_no_distance() {
+        let mut split = line(4);
+        split["rooms"][1]["connections"] = json!([0]);
+        split["rooms"][2]["connections"] = json!([3]);
+        let mut map = Map::parse(&split.to_string()).unwrap();
+        map.distances = (0..4).map(|from| map.dijkstra(from, &static_cost).0).collect();
+        assert_eq!(map.distance(0, 1), Some(1));
+        assert_eq!(map.distance(0, 3), None);
+        assert_eq!(map.distance(0, 9), None);
+    }
+
+    #[test]
+    fn the_inside_has_to_hold_together() {
+        // 1 is outside, and the only way between 0 and 2
+        let mut map = line(3);
+        map["rooms"][1]["exterior"] = json!(true);
+        assert!(matches!(error(&map), MapError::InteriorSplit(n) if n == "Room 2"));
+
+        let mut map = line(3);
+        map["rooms"][2]["exterior"] = json!(true);
+        let map = load(&map).unwrap();
+        assert_eq!(map.interior_rooms(), vec![0, 1]);
+        let inside = |c: &Connection| map.interior_cost(c);
+        assert!(map.get_path_with(0, 2, &inside).is_none());
+        assert_eq!(map.get_path(0, 2).unwrap(), vec![2, 1]);
+    }
+
+    #[test]
+    fn disconnected_rooms_have_no_path() {
+        // two halves, only parse lets it through
+        let mut split = line(4);
+        split["rooms"][1]["connections"] = json!([0]);
+        split["rooms"][2]["connections"] = json!([3]);
+        let map = Map::parse(&split.to_string()).unwrap();
+        assert_eq!(map.get_path(0, 3), None);
+        assert_eq!(map.get_path(3, 1), None);
+        assert_eq!(map.get_path(0, 1), Some(vec![1]));
+        assert_eq!(map.get_path(3, 2), Some(vec![2]));
+
+        // or cut off by what it costs right now
+        let map = load(&line(4)).unwrap();
+        let closed = |c: &Connection| (c.to != 2).then_some(c.weight);
+        assert_eq!(map.get_path_with(0, 3, &closed), None);
+        assert_eq!(map.get_path_with(0, 1, &closed), Some(vec![1]));
+    }
+
+    #[test]
+    fn labels_off_the_map_have_no_path() {
+        let map = load(&line(4)).unwrap();
+        assert!(map.get_path(2, 2).unwrap().is_empty());
+        assert_eq!(map.get_path(0, 4), None);
+        assert_eq!(map.get_path(4, 0), None);
+        assert_eq!(map.get_path(4, 4), None);
+        assert_eq!(map.get_path(usize::MAX, 0), None);
+        assert_eq!(map.get_path_with(0, usize::MAX, &|_| Some(1)), None);
+    }
+
+    // The recursive search get_path once was, trying every simple path and
+    // keeping the one with the fewest rooms
+    fn fewest_rooms(map: &Map, room: RoomLabel, to: RoomLabel, seen: &mut [bool]) -> Option<usize> {
+        if room == to {
+            return Some(0);
+        }
+        seen[room] = true;
+        let mut best = None;
+        for next in map.neighbours(room) {
+            if !seen[next] {
+                if let Some(n) = fewest_rooms(map, next, to, seen) {
+                    best = Some(best.map_or(n + 1, |b: usize| b.min(n + 1)));
+                }
+            }
+        }
+        seen[room] = false;
+        best
+    }
+
+    #[test]
+    fn paths_are_as_short_as_the_old_search_found() {
+        let map = Map::new();
+        let hops = |_: &Connection| Some(1);
+        for from in 0..map.rooms.len() {
+            for to in 0..map.rooms.len() {
+                let old = fewest_rooms(&map, from, to, &mut vec![false; map.rooms.len()]);
+                let path = map.get_path_with(from, to, &hops).unwrap();
+                assert_eq!(Some(path.len()), old, "{} to {}", from, to);
+                // counting the stairs as heavier never takes a longer way here
+                assert_eq!(map.get_path(from, to).unwrap().len(), path.len(), "{} to {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn generated_maps_are_always_valid() {
+        let params = GenerateParams::default();
+        for seed in 0..300 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let n_rooms = 2 + seed as usize % 40;
+            let map = Map::generate(&mut rng, n_rooms, &params);
+            map.validate().unwrap();
+            assert_eq!(map.rooms.len(), n_rooms);
+            // on the edge, at the end of a dead end
+            let safe = map.safe_room().unwrap();
+            assert_eq!(map.neighbours(safe).len(), 1, "seed {}", seed);
+            assert!(map.rooms.iter().all(|r| r.hiding_spots <= params.max_hiding_spots));
+            assert!(map.rooms.iter().all(|r| r.objects <= params.max_objects));
+        }
+        // too small to have a safe room and somewhere else still gets both
+        let map = Map::generate(&mut StdRng::seed_from_u64(1), 0, &params);
+        assert_eq!(map.rooms.len(), 2);
+    }
+
+    #[test]
+    fn errors_name_the_room() {
+        let mut map = line(3);
+        map["rooms"][2]["connections"] = json!([1, 7]);
+        assert_eq!(error(&map).to_string(), "Room Room 2 connects to unknown room 7");
+    }
+
+    #[test]
+    fn the_builtin_map_dumps_as_it_always_has() {
+        // after a deliberate change, regenerate it with
+        // --dump-map tests/fixtures/default_map.dot
+        let golden = include_str!("../tests/fixtures/default_map.dot");
+        assert_eq!(Map::new().to_dot(), golden);
+    }
+
+    #[test]
+    fn broken_maps_still_dump() {
+        // a one-way door and a room nothing gets to
+        let mut map = line(4);
+        map["rooms"][1]["connections"] = json!([2]);
+        map["rooms"][3]["connections"] = json!([]);
+        map["rooms"][2]["connections"] = json!([1]);
+        let map = Map::parse(&map.to_string()).unwrap();
+        assert!(map.validate().is_err());
+
+        let dot = map.to_dot();
+        assert!(dot.contains("r0 -- r1 [color=red];"), "{}", dot);
+        assert!(dot.contains("r3 [label=\"Room 3\"];"), "{}", dot);
+        assert!(!dot.contains("r3 --"), "{}", dot);
+    }
+
+    #[test]
+    fn every_shipped_map_loads_from_its_file() {
+        let catalog = MapCatalog::builtin();
+        for id in catalog.ids() {
+            let path = format!("{}/maps/{}.json", env!("CARGO_MANIFEST_DIR"), id);
+            let map = Map::from_file(&path).unwrap_or_else(|e| panic!("{}: {}", id, e));
+            map.validate().unwrap();
+            assert_eq!(map.hash(), catalog.get(&id).unwrap().hash(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn custom_maps_join_the_catalog_under_their_id() {
+        let mut catalog = MapCatalog::builtin();
+        let before = catalog.ids().len();
+        let small = load(&line(3)).unwrap();
+        catalog.add("mine", small.clone());
+        assert_eq!(catalog.get("mine").unwrap().hash(), small.hash());
+        // the same id again replaces it
+        catalog.add("mine", load(&line(5)).unwrap());
+        assert_eq!(catalog.ids().len(), before + 1);
+        assert_eq!(catalog.get("mine").unwrap().rooms.len(), 5);
+        catalog.add(DEFAULT_MAP, small);
+        assert_eq!(catalog.get(DEFAULT_MAP).unwrap().rooms.len(), 3);
+        assert!(catalog.get("nowhere").is_none());
+    }
 }
@@ -0,0 +1,204 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{map::RoomLabel, sounds::SoundId};
+
+// Every player-facing message the server can send. Clients translate from
+// the key and its params, the server only ships English as a fallback, so
+// anything new a player reads gets a variant here.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum MessageKey {
+    // params: room
+    #[serde(rename = "interaction.sound")]
+    InteractionSound,
+    #[serde(rename = "interaction.lights_flicker")]
+    LightsFlicker,
+    #[serde(rename = "interaction.door_open")]
+    DoorOpen,
+    // params: room, count, left
+    #[serde(rename = "interaction.throw_object")]
+    ThrowObject,
+    // params: room
+    #[serde(rename = "ghost.manifest")]
+    Manifest,
+    #[serde(rename = "ghost.writing")]
+    GhostWriting,
+    #[serde(rename = "ghost.room_shift")]
+    RoomShift,
+    #[serde(rename = "crucifix.burned")]
+    CrucifixBurned,
+    // params: room, sensor, kind
+    #[serde(rename = "sensor.triggered")]
+    SensorTriggered,
+    #[serde(rename = "ghost.smudged")]
+    Smudged,
+    #[serde(rename = "power.breaker_tripped")]
+    BreakerTripped,
+    #[serde(rename = "round.setup_over")]
+    SetupOver,
+    #[serde(rename = "hunt.start")]
+    HuntStarted,
+    #[serde(rename = "hunt.end")]
+    HuntEnded,
+    // params: name
+    #[serde(rename = "player.death")]
+    PlayerDeath,
+    #[serde(rename = "player.body_found")]
+    BodyFound,
+    // params: seed
+    #[serde(rename = "round.random_map")]
+    RandomMap,
+    // params: overrides
+    #[serde(rename = "round.custom_difficulty")]
+    CustomDifficulty,
+    #[serde(rename = "error.not_allowed")]
+    NotAllowed,
+    #[serde(rename = "error.admin_denied")]
+    AdminDenied,
+    // params: reason, still English until errors get keys of their own
+    #[serde(rename = "error.start_failed")]
+    StartFailed,
+    // params: reason, as above
+    #[serde(rename = "error.item_failed")]
+    ItemFailed,
+    #[serde(rename = "error.guess_rejected")]
+    GuessRejected,
+    #[serde(rename = "error.kick_failed")]
+    KickFailed,
+    // params: reason, joining or making a lobby
+    #[serde(rename = "error.lobby_failed")]
+    LobbyFailed,
+}
+
+// What came of a message, sent back to whoever sent it. request is the
+// message's name, as it was sent.
+#[derive(Serialize, Clone, Debug)]
+pub enum ServerReply {
+    Ack { request: String },
+    Error { request: String, reason: String },
+}
+
+// A line for the players, with a sound to go with it if there is one
+#[derive(Serialize, Clone)]
+pub struct Notice {
+    pub key: MessageKey,
+    pub params: Value,
+    pub fallback_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound_id: Option<SoundId>,
+}
+
+// A notice as states carry it. Ids only go up, so a peer only needs the
+// ones past the last it was sent.
+#[derive(Serialize, Clone)]
+pub struct NoticeEntry {
+    pub id: u64,
+    #[serde(flatten)]
+    pub notice: Notice,
+}
+
+impl Notice {
+    pub fn new(key: MessageKey, params: Value, fallback_text: String) -> Self {
+        Notice {
+            key,
+            params,
+            fallback_text,
+            sound_id: None,
+        }
+    }
+
+    pub fn with_sound(mut self, sound_id: SoundId) -> Self {
+        self.sound_id = Some(sound_id);
+        self
+    }
+
+    // Most ghost activity only needs to say where
+    pub fn in_room(key: MessageKey, room: RoomLabel, fallback_text: String) -> Self {
+        Notice::new(key, json!({ "room": room }), fallback_text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The canonical list. A new key won't compile in `listed` until it's
+    // added here too.
+    const KEYS: [MessageKey; 25] = [
+        MessageKey::InteractionSound,
+        MessageKey::LightsFlicker,
+        MessageKey::DoorOpen,
+        MessageKey::ThrowObject,
+        MessageKey::Manifest,
+        MessageKey::GhostWriting,
+        MessageKey::RoomShift,
+        MessageKey::CrucifixBurned,
+        MessageKey::SensorTriggered,
+        MessageKey::Smudged,
+        MessageKey::BreakerTripped,
+        MessageKey::SetupOver,
+        MessageKey::HuntStarted,
+        MessageKey::HuntEnded,
+        MessageKey::PlayerDeath,
+        MessageKey::BodyFound,
+        MessageKey::RandomMap,
+        MessageKey::CustomDifficulty,
+        MessageKey::NotAllowed,
+        MessageKey::AdminDenied,
+        MessageKey::StartFailed,
+        MessageKey::ItemFailed,
+        MessageKey::GuessRejected,
+        MessageKey::KickFailed,
+        MessageKey::LobbyFailed,
+    ];
+
+    fn listed(key: MessageKey) -> usize {
+        match key {
+            MessageKey::InteractionSound => 0,
+            MessageKey::LightsFlicker => 1,
+            MessageKey::DoorOpen => 2,
+            MessageKey::ThrowObject => 3,
+            MessageKey::Manifest => 4,
+            MessageKey::GhostWriting => 5,
+            MessageKey::RoomShift => 6,
+            MessageKey::CrucifixBurned => 7,
+            MessageKey::SensorTriggered => 8,
+            MessageKey::Smudged => 9,
+            MessageKey::BreakerTripped => 10,
+            MessageKey::SetupOver => 11,
+            MessageKey::HuntStarted => 12,
+            MessageKey::HuntEnded => 13,
+            MessageKey::PlayerDeath => 14,
+            MessageKey::BodyFound => 15,
+            MessageKey::RandomMap => 16,
+            MessageKey::CustomDifficulty => 17,
+            MessageKey::NotAllowed => 18,
+            MessageKey::AdminDenied => 19,
+            MessageKey::StartFailed => 20,
+            MessageKey::ItemFailed => 21,
+            MessageKey::GuessRejected => 22,
+            MessageKey::KickFailed => 23,
+            MessageKey::LobbyFailed => 24,
+        }
+    }
+
+    fn key_name(key: MessageKey) -> String {
+        serde_json::to_value(key).unwrap().as_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn every_key_is_listed_once_under_its_own_name() {
+        let mut names = Vec::new();
+        for (i, key) in KEYS.into_iter().enumerate() {
+            assert_eq!(listed(key), i, "{:?}", key);
+            let name = key_name(key);
+            let (area, what) = name.split_once('.').unwrap();
+            let lower = |s: &str| s.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+            assert!(lower(area) && lower(what), "{}", name);
+            names.push(name);
+        }
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), KEYS.len());
+    }
+}
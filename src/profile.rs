@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{RoundSummary, Storage};
+
+const MONEY_PER_LEVEL: u32 = 100;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub games_played: u32,
+    pub games_survived: u32,
+    pub correct_guesses: u32,
+    pub money: u32,
+    pub total_earned: u32,
+    // times played per map id
+    pub map_plays: HashMap<String, u32>,
+}
+
+impl Profile {
+    fn new(name: &str) -> Self {
+        Profile {
+            name: name.to_owned(),
+            games_played: 0,
+            games_survived: 0,
+            correct_guesses: 0,
+            money: 0,
+            total_earned: 0,
+            map_plays: HashMap::new(),
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        1 + self.total_earned / MONEY_PER_LEVEL
+    }
+
+    pub fn survival_rate(&self) -> f64 {
+        if self.games_played == 0 {
+            return 0.0;
+        }
+        f64::from(self.games_survived) / f64::from(self.games_played)
+    }
+
+    pub fn favorite_map(&self) -> Option<String> {
+        self.map_plays
+            .iter()
+            .max_by_key(|(_, plays)| **plays)
+            .map(|(map, _)| map.clone())
+    }
+
+    pub fn view(&self) -> ProfileView {
+        ProfileView {
+            name: self.name.clone(),
+            level: self.level(),
+            money: self.money,
+            games_played: self.games_played,
+            survival_rate: self.survival_rate(),
+            correct_guesses: self.correct_guesses,
+            favorite_map: self.favorite_map(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ProfileView {
+    name: String,
+    level: u32,
+    money: u32,
+    games_played: u32,
+    survival_rate: f64,
+    correct_guesses: u32,
+    favorite_map: Option<String>,
+}
+
+// Profile bookkeeping on top of whatever storage the server was started
+// with. Callers share one store behind a lock, so writes from different
+// rounds can't interleave. Write errors are logged, a full disk shouldn't
+// take the game down with it.
+pub struct ProfileStore {
+    storage: Box<dyn Storage + Send + Sync>,
+}
+
+impl ProfileStore {
+    pub fn new(storage: Box<dyn Storage + Send + Sync>) -> Self {
+        ProfileStore { storage }
+    }
+
+    pub fn get_or_create(&mut self, name: &str) -> Profile {
+        self.storage
+            .load_profile(name)
+            .unwrap_or_else(|| Profile::new(name))
+    }
+
+    pub fn record_round(
+        &mut self,
+        name: &str,
+        map: &str,
+        survived: bool,
+        correct_guess: bool,
+        earned: u32,
+    ) {
+        let mut profile = self.get_or_create(name);
+
+        profile.games_played += 1;
+        if survived {
+            profile.games_survived += 1;
+        }
+        if correct_guess {
+            profile.correct_guesses += 1;
+        }
+        profile.money += earned;
+        profile.total_earned += earned;
+        *profile.map_plays.entry(map.to_owned()).or_insert(0) += 1;
+
+        if let Err(e) = self.storage.save_profile(&profile) {
+            println!("Could not save profile for {}: {}", name, e);
+        }
+    }
+
+    pub fn record_summary(&mut self, summary: &RoundSummary) {
+        if let Err(e) = self.storage.append_round_summary(summary) {
+            println!("Could not save round summary: {}", e);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::{net::SocketAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event_log::{LoggedEvent, RecordLine},
+    map::MapCatalog,
+    server::{PhasmoMessage, MAX_FAST_FORWARD_SECS},
+    sim::{OptionOverrides, Simulation},
+    sim_actor::{admin_op, AdminOp},
+};
+
+// What went into a lobby's sim, in the order it got there. With the seed,
+// these are all it takes to play the round out again.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum SimInput {
+    // the lobby opened, every other input is on top of this
+    Opened {
+        seed: u64,
+        map: Option<String>,
+        tunables: OptionOverrides,
+    },
+    // only messages that got past auth, which is all the sim ever sees
+    Message {
+        addr: SocketAddr,
+        message: PhasmoMessage,
+    },
+    Left {
+        addr: SocketAddr,
+    },
+    // a config reload
+    Tunables {
+        tunables: OptionOverrides,
+    },
+    // only the ones the sim ran, stopped and paused sims skip theirs
+    Tick {
+        dt: Duration,
+    },
+}
+
+// Where a rerun stopped matching the recording
+#[derive(Debug)]
+pub struct Divergence {
+    pub lobby: String,
+    // of the recording, from 1
+    pub line: usize,
+    pub expected: Option<LoggedEvent>,
+    pub actual: Option<LoggedEvent>,
+    // the last few inputs before it, oldest first
+    pub context: Vec<String>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let show = |event: &Option<LoggedEvent>| match event {
+            Some(event) => serde_json::to_string(event).unwrap(),
+            None => "nothing".to_owned(),
+        };
+        writeln!(f, "Lobby {} diverged at line {}", self.lobby, self.line)?;
+        writeln!(f, "  expected {}", show(&self.expected))?;
+        writeln!(f, "  got      {}", show(&self.actual))?;
+        write!(f, "  after {}", self.context.join("\n        "))
+    }
+}
+
+// How much of a recording a rerun matched
+#[derive(Debug, PartialEq)]
+pub struct Rerun {
+    pub lobby: String,
+    pub inputs: usize,
+    pub events: usize,
+}
+
+// how many inputs a divergence shows what led up to it with
+const CONTEXT: usize = 5;
+
+// Plays every lobby in a recording again from its inputs and checks the
+// sim logs the same events, stopping at the first that doesn't. The
+// catalog has to have the maps the recording was made with.
+pub fn rerun(recording: &str, catalog: &MapCatalog) -> Result<Vec<Rerun>, String> {
+    let mut lines = Vec::new();
+    for (i, line) in recording.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed: RecordLine =
+            serde_json::from_str(line).map_err(|e| format!("Bad line {}: {}", i + 1, e))?;
+        lines.push((i + 1, parsed));
+    }
+
+    let mut lobbies: Vec<String> = Vec::new();
+    for (_, line) in &lines {
+        if !lobbies.contains(&line.lobby().to_owned()) {
+            lobbies.push(line.lobby().to_owned());
+        }
+    }
+    let mut reruns = Vec::new();
+    for lobby in lobbies {
+        let lines = lines.iter().filter(|(_, l)| l.lobby() == lobby);
+        let rerun = rerun_lobby(&lobby, lines, catalog).map_err(|d| d.to_string())?;
+        reruns.push(rerun);
+    }
+    Ok(reruns)
+}
+
+fn rerun_lobby<'a>(
+    lobby: &str,
+    lines: impl Iterator<Item = &'a (usize, RecordLine)>,
+    catalog: &MapCatalog,
+) -> Result<Rerun, Box<Divergence>> {
+    let mut sim: Option<Simulation> = None;
+    let mut inputs = 0;
+    let mut context: Vec<String> = Vec::new();
+    // what the sim logged that the recording hasn't got to yet
+    let mut pending: Vec<LoggedEvent> = Vec::new();
+    let mut last_id = 0;
+    let mut events = 0;
+    let mut last_line = 0;
+
+    let diverged = |line, expected, actual, context: &[String]| {
+        Box::new(Divergence {
+            lobby: lobby.to_owned(),
+            line,
+            expected,
+            actual,
+            context: context.to_vec(),
+        })
+    };
+
+    for (line, entry) in lines {
+        last_line = *line;
+        match entry {
+            RecordLine::Input(recorded) => {
+                // anything the last input made has to have been recorded
+                if let Some(extra) = pending.first() {
+                    return Err(diverged(*line, None, Some(extra.clone()), &context));
+                }
+                let input = &recorded.input;
+                if context.len() == CONTEXT {
+                    context.remove(0);
+                }
+                context.push(format!("line {}: {}", line, serde_json::to_string(input).unwrap()));
+                inputs += 1;
+
+                match (input, sim.as_mut()) {
+                    (SimInput::Opened { seed, map, tunables }, _) => {
+                        let mut new = Simulation::with_seed(catalog.clone(), *seed);
+                        let opened = map
+                            .as_ref()
+                            .map_or(Ok(()), |id| new.select_map(id))
+                            .and_then(|_| new.set_tunables(tunables.clone()).map(|_| ()));
+                        if let Err(e) = opened {
+                            context.push(format!("can't open the sim: {}", e));
+                            return Err(diverged(*line, None, None, &context));
+                        }
+                        sim = Some(new);
+                        last_id = 0;
+                    }
+                    (input, Some(sim)) => apply(sim, input),
+                    // recorded from part way through, nothing to run it on
+                    (_, None) => {
+                        context.push("no Opened before it".to_owned());
+                        return Err(diverged(*line, None, None, &context));
+                    }
+                }
+                if let Some(sim) = &sim {
+                    let (last, new) = sim.events_since(last_id);
+                    last_id = last;
+                    pending = new;
+                }
+            }
+            RecordLine::Event(recorded) => {
+                if pending.is_empty() {
+                    let expected = Some(recorded.entry.clone());
+                    return Err(diverged(*line, expected, None, &context));
+                }
+                let actual = pending.remove(0);
+                if actual != recorded.entry {
+                    let expected = Some(recorded.entry.clone());
+                    return Err(diverged(*line, expected, Some(actual), &context));
+                }
+                events += 1;
+            }
+        }
+    }
+    if let Some(extra) = pending.first() {
+        return Err(diverged(last_line + 1, None, Some(extra.clone()), &context));
+    }
+    Ok(Rerun {
+        lobby: lobby.to_owned(),
+        inputs,
+        events,
+    })
+}
+
+// Does to the sim what the server does for the input. Whether it worked
+// doesn't matter, it'll work or fail the same way it did the first time.
+fn apply(sim: &mut Simulation, input: &SimInput) {
+    match input {
+        SimInput::Opened { .. } => (),
+        SimInput::Message { addr, message } => apply_message(sim, *addr, message.clone()),
+        SimInput::Left { addr } => {
+            sim.remove_player(*addr);
+        }
+        SimInput::Tunables { tunables } => {
+            let _ = sim.set_tunables(tunables.clone());
+        }
+        SimInput::Tick { dt } => {
+            sim.update(*dt);
+        }
+    }
+}
+
+// The sim's half of the server's dispatch, replies and all the rest left out
+fn apply_message(sim: &mut Simulation, addr: SocketAddr, message: PhasmoMessage) {
+    let _ = match message {
+        PhasmoMessage::JoinLobby { name, .. } => sim.add_player(addr, &name),
+        PhasmoMessage::StartSim {
+            map,
+            seed,
+            difficulty,
+            force,
+        } => sim.start(map, seed, difficulty, force),
+        PhasmoMessage::LeaveLobby {} => sim.leave_lobby(addr).map(|_| ()),
+        PhasmoMessage::SetReady { ready } => sim.set_ready(addr, ready),
+        PhasmoMessage::SelectMap { map } => admin_op(sim, AdminOp::SelectMap(map)),
+        PhasmoMessage::LocationUpdate { location } => sim
+            .resolve_room(&location)
+            .and_then(|location| sim.update_player_loc(addr, location))
+            .map(|_| ()),
+        PhasmoMessage::SetFlashlight { on } => {
+            sim.set_flashlight(addr, on);
+            Ok(())
+        }
+        PhasmoMessage::SetHidden { hidden } => sim.set_hidden(addr, hidden),
+        PhasmoMessage::SetLights { location, on } => sim.set_lights(addr, location, on),
+        PhasmoMessage::SetBreaker { on } => sim.set_breaker(addr, on),
+        PhasmoMessage::SetDoorState { from, to, open } => {
+            sim.set_door_between(addr, from, to, open)
+        }
+        PhasmoMessage::SetActiveEquipment { items } => sim.set_active_equipment(addr, items),
+        PhasmoMessage::PlaceCamera { location } => sim.place_camera(addr, location).map(|_| ()),
+        PhasmoMessage::PlaceDots { location } => sim.place_dots(addr, location),
+        PhasmoMessage::PlaceBook { location } => sim.place_book(addr, location),
+        PhasmoMessage::PlaceCrucifix { location } => sim.place_crucifix(addr, location),
+        PhasmoMessage::RemoveBook {} => sim.remove_book(addr),
+        PhasmoMessage::RemoveCamera { id } => sim.remove_camera(addr, id),
+        PhasmoMessage::PlaceSensor { location, kind } => {
+            sim.place_sensor(addr, location, kind).map(|_| ())
+        }
+        PhasmoMessage::RemoveSensor { id } => sim.remove_sensor(addr, id),
+        PhasmoMessage::Equip { item } => sim.equip(addr, item),
+        PhasmoMessage::Drop {} => sim.drop_item(addr),
+        PhasmoMessage::SwapSlot { index } => sim.swap_slot(addr, index),
+        PhasmoMessage::UseSmudge {} => sim.use_smudge(addr),
+        PhasmoMessage::UseSanityPills {} => sim.use_sanity_pills(addr),
+        PhasmoMessage::SubmitGuess { ghost_type } => sim.submit_guess(addr, ghost_type).map(|_| ()),
+        PhasmoMessage::Kick { name } => sim.kick(&name).map(|_| ()),
+        PhasmoMessage::ReadThermometer {} => sim.read_thermometer(addr).map(|_| ()),
+        PhasmoMessage::ReadEmf {} => sim.read_emf(addr).map(|_| ()),
+        PhasmoMessage::UseSpiritBox {} => sim.use_spirit_box(addr).map(|_| ()),
+        PhasmoMessage::UseUvLight {} => sim.use_uv_light(addr).map(|_| ()),
+        PhasmoMessage::UseParabolic { direction_room } => {
+            sim.use_parabolic(addr, direction_room).map(|_| ())
+        }
+        PhasmoMessage::FastForward { secs } => {
+            let dt = Duration::from_secs(secs.min(MAX_FAST_FORWARD_SECS));
+            admin_op(sim, AdminOp::FastForward(dt))
+        }
+        PhasmoMessage::PauseSim {} => admin_op(sim, AdminOp::SetPaused(true)),
+        PhasmoMessage::ResumeSim {} => admin_op(sim, AdminOp::SetPaused(false)),
+        PhasmoMessage::TeleportGhost { location, emf } => {
+            admin_op(sim, AdminOp::TeleportGhost { room: location, emf })
+        }
+        PhasmoMessage::SetGhostRoom { location } => {
+            admin_op(sim, AdminOp::SetGhostRoom(location))
+        }
+        PhasmoMessage::SetGhostType { ghost } => admin_op(sim, AdminOp::SetGhostType(ghost)),
+        PhasmoMessage::ResetSim { clear_players } => {
+            admin_op(sim, AdminOp::Reset { clear_players })
+        }
+        // nothing the sim's events would show
+        PhasmoMessage::Hello { .. }
+        | PhasmoMessage::CreateLobby {}
+        | PhasmoMessage::ConnectAsAdmin { .. }
+        | PhasmoMessage::ConnectAsSpectator { .. }
+        | PhasmoMessage::GetProfile {}
+        | PhasmoMessage::GetMap {}
+        | PhasmoMessage::RequestFullState {}
+        | PhasmoMessage::SubscribeGhostDebug {}
+        | PhasmoMessage::UnsubscribeGhostDebug {}
+        | PhasmoMessage::GetEventLog {}
+        | PhasmoMessage::GetSoundManifest {}
+        | PhasmoMessage::ReloadConfig {} => Ok(()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::{json, Value};
+
+    use crate::{equipment::EquipmentKind, sim::Difficulty};
+
+    fn addr(n: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 8000 + n))
+    }
+
+    fn send(sim: &mut Simulation, n: u16, message: Value) {
+        apply_message(sim, addr(n), serde_json::from_value(message).unwrap());
+    }
+
+    #[test]
+    fn messages_act_for_whoever_sent_them() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        send(&mut sim, 0, json!({"JoinLobby": {"name": "ann", "protocol": 3}}));
+        send(&mut sim, 1, json!({"JoinLobby": {"name": "bob", "protocol": 3}}));
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        sim.update_player_loc(addr(1), 0).unwrap();
+        sim.equip(addr(0), EquipmentKind::ParabolicMicrophone).unwrap();
+        let located = |sim: &Simulation| {
+            let state = serde_json::to_value(sim.get_gameupdate()).unwrap();
+            let players = state["Sim"]["players"].as_array().unwrap().clone();
+            players.iter().map(|p| (p["name"].clone(), p["last_loc"].clone())).collect::<Vec<_>>()
+        };
+
+        // a name left over from older clients doesn't matter, ann's the one who moves
+        send(&mut sim, 0, json!({"LocationUpdate": {"name": "bob", "location": 0}}));
+        send(&mut sim, 0, json!({"LocationUpdate": {"location": 2}}));
+        assert_eq!(located(&sim), [(json!("ann"), json!(2)), (json!("bob"), json!(0))]);
+        send(&mut sim, 0, json!({"PlaceSensor": {"location": 2, "kind": "Motion"}}));
+        send(&mut sim, 0, json!({"UseParabolic": {"direction_room": 0}}));
+        let state = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_eq!(state["Sim"]["sensors"][0]["owner"], "ann");
+        // the parabolic's cooling down for ann, not bob
+        assert!(sim.use_parabolic(addr(0), 0).is_err());
+    }
+}
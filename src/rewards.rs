@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+
+// What the round looked like once it's over, filled in by the sim
+pub struct RoundStats {
+    pub players: Vec<PlayerRoundStats>,
+    pub correct_guess: bool,
+}
+
+pub struct PlayerRoundStats {
+    pub name: String,
+    pub survived: bool,
+    pub objectives_completed: u32,
+    pub photo_score: u32,
+    // evidence the player marked in their journal that the ghost actually has
+    pub journal_evidence: u32,
+}
+
+#[derive(Clone)]
+pub struct Payouts {
+    pub survival: u32,
+    pub correct_guess: u32,
+    pub per_objective: u32,
+    pub per_photo_point: u32,
+    pub per_evidence: u32,
+}
+
+impl Default for Payouts {
+    fn default() -> Self {
+        Payouts {
+            survival: 25,
+            correct_guess: 50,
+            per_objective: 15,
+            per_photo_point: 1,
+            per_evidence: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LineItem {
+    pub reason: String,
+    pub amount: u32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlayerReward {
+    pub name: String,
+    pub items: Vec<LineItem>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RoundRewards {
+    pub players: Vec<PlayerReward>,
+    pub team_total: u32,
+}
+
+pub fn compute_rewards(stats: &RoundStats, payouts: &Payouts) -> RoundRewards {
+    let players: Vec<PlayerReward> = stats
+        .players
+        .iter()
+        .map(|p| player_reward(p, stats.correct_guess, payouts))
+        .collect();
+    let team_total = players.iter().map(|p| p.total).sum();
+
+    RoundRewards {
+        players,
+        team_total,
+    }
+}
+
+fn player_reward(player: &PlayerRoundStats, correct_guess: bool, payouts: &Payouts) -> PlayerReward {
+    let mut items = Vec::new();
+    let mut add = |reason: &str, amount: u32| {
+        if amount > 0 {
+            items.push(LineItem {
+                reason: reason.to_owned(),
+                amount,
+            });
+        }
+    };
+
+    if player.survived {
+        add("Survived", payouts.survival);
+    }
+    if correct_guess {
+        add("Correct ghost type", payouts.correct_guess);
+    }
+    add("Objectives", player.objectives_completed * payouts.per_objective);
+    add("Photos", player.photo_score * payouts.per_photo_point);
+    add("Evidence", player.journal_evidence * payouts.per_evidence);
+
+    let total = items.iter().map(|i| i.amount).sum();
+    PlayerReward {
+        name: player.name.clone(),
+        items,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(survived: &[bool], correct_guess: bool) -> RoundStats {
+        RoundStats {
+            players: survived
+                .iter()
+                .enumerate()
+                .map(|(i, survived)| PlayerRoundStats {
+                    name: format!("p{}", i),
+                    survived: *survived,
+                    objectives_completed: 0,
+                    photo_score: 0,
+                    journal_evidence: 0,
+                })
+                .collect(),
+            correct_guess,
+        }
+    }
+
+    fn reasons(reward: &PlayerReward) -> Vec<&str> {
+        reward.items.iter().map(|i| i.reason.as_str()).collect()
+    }
+
+    #[test]
+    fn survivors_of_a_correct_guess_get_everything() {
+        let rewards = compute_rewards(&stats(&[true], true), &Payouts::default());
+        let player = &rewards.players[0];
+        assert_eq!(reasons(player), ["Survived", "Correct ghost type"]);
+        assert_eq!(player.total, 75);
+        assert_eq!(rewards.team_total, 75);
+    }
+
+    #[test]
+    fn objectives_photos_and_evidence_pay_per_unit() {
+        let mut stats = stats(&[false], false);
+        let player = &mut stats.players[0];
+        player.objectives_completed = 2;
+        player.photo_score = 40;
+        player.journal_evidence = 3;
+        let rewards = compute_rewards(&stats, &Payouts::default());
+        let player = &rewards.players[0];
+        assert_eq!(reasons(player), ["Objectives", "Photos", "Evidence"]);
+        let amounts: Vec<u32> = player.items.iter().map(|i| i.amount).collect();
+        assert_eq!(amounts, [30, 40, 15]);
+        assert_eq!(player.total, 85);
+    }
+
+    #[test]
+    fn nothing_earned_leaves_no_line_items() {
+        let rewards = compute_rewards(&stats(&[false], false), &Payouts::default());
+        assert!(rewards.players[0].items.is_empty());
+        assert_eq!(rewards.players[0].total, 0);
+    }
+
+    #[test]
+    fn team_total_adds_up_everyone() {
+        let payouts = Payouts {
+            survival: 10,
+            correct_guess: 3,
+            ..Payouts::default()
+        };
+        let rewards = compute_rewards(&stats(&[true, false, true], true), &payouts);
+        let totals: Vec<u32> = rewards.players.iter().map(|p| p.total).collect();
+        assert_eq!(totals, [13, 3, 13]);
+        assert_eq!(rewards.team_total, 29);
+        assert_eq!(reasons(&rewards.players[1]), ["Correct ghost type"]);
+    }
+
+    #[test]
+    fn a_zero_payout_is_left_out() {
+        let payouts = Payouts {
+            survival: 0,
+            ..Payouts::default()
+        };
+        let rewards = compute_rewards(&stats(&[true], true), &payouts);
+        assert_eq!(reasons(&rewards.players[0]), ["Correct ghost type"]);
+    }
+}
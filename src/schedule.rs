@@ -0,0 +1,132 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::Duration,
+};
+
+// Handed back when something's scheduled, to call it off later
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TriggerId(u64);
+
+struct Entry<T> {
+    time: Duration,
+    id: u64,
+    trigger: T,
+}
+
+// Soonest first, and in the order they were scheduled when they're due
+// together. Ids only go up, so they settle ties.
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.id).cmp(&(other.time, other.id))
+    }
+}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+// Things waiting on round time. A tick only has to look at the soonest
+// one to know whether anything's due.
+pub struct Schedule<T> {
+    heap: BinaryHeap<Reverse<Entry<T>>>,
+    next_id: u64,
+}
+
+impl<T> Schedule<T> {
+    pub fn new() -> Self {
+        Schedule {
+            heap: BinaryHeap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn push(&mut self, time: Duration, trigger: T) -> TriggerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.heap.push(Reverse(Entry { time, id, trigger }));
+        TriggerId(id)
+    }
+
+    // False if it already went off or was called off
+    pub fn cancel(&mut self, id: TriggerId) -> bool {
+        let before = self.heap.len();
+        self.heap.retain(|Reverse(entry)| entry.id != id.0);
+        self.heap.len() != before
+    }
+
+    pub fn cancel_where(&mut self, f: impl Fn(&T) -> bool) {
+        self.heap.retain(|Reverse(entry)| !f(&entry.trigger));
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+    }
+
+    // Everything due before `now`, soonest first. Whatever gets scheduled
+    // while these are handled waits for the next call, even if it's due.
+    pub fn take_due(&mut self, now: Duration) -> Vec<T> {
+        let mut due = Vec::new();
+        while self.heap.peek().is_some_and(|Reverse(entry)| entry.time < now) {
+            let Reverse(entry) = self.heap.pop().unwrap();
+            due.push(entry.trigger);
+        }
+        due
+    }
+}
+
+impl<T> Default for Schedule<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn due_triggers_come_soonest_first_then_in_order() {
+        let mut schedule = Schedule::new();
+        schedule.push(secs(3.0), "c");
+        schedule.push(secs(1.0), "a");
+        schedule.push(secs(3.0), "d");
+        schedule.push(secs(2.0), "b");
+        assert_eq!(schedule.take_due(secs(2.5)), ["a", "b"]);
+        // only what's due before now
+        assert!(schedule.take_due(secs(3.0)).is_empty());
+        assert_eq!(schedule.take_due(secs(3.1)), ["c", "d"]);
+        assert!(schedule.take_due(secs(60.0)).is_empty());
+    }
+
+    #[test]
+    fn called_off_triggers_never_go_off() {
+        let mut schedule = Schedule::new();
+        let a = schedule.push(secs(1.0), "a");
+        let b = schedule.push(secs(2.0), "b");
+        schedule.push(secs(2.0), "c");
+        schedule.push(secs(3.0), "d");
+        assert!(schedule.cancel(a));
+        assert!(!schedule.cancel(a));
+        schedule.cancel_where(|t| *t == "c");
+        assert_eq!(schedule.take_due(secs(2.5)), ["b"]);
+        // too late once it's gone off
+        assert!(!schedule.cancel(b));
+        schedule.clear();
+        assert!(schedule.take_due(secs(60.0)).is_empty());
+    }
+}
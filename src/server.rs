@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
     sync::{Arc, Mutex},
     time::Duration,
@@ -7,255 +7,1868 @@ use std::{
 
 use futures_channel::mpsc::{unbounded, UnboundedSender};
 use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
-use log::{self, error};
-use native_tls::Identity;
-use serde::{Deserialize, Serialize};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use serde_json::{json, Value};
 // use tokio::prelude::*;
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream},
-    sync::mpsc::{Receiver, Sender},
-    time::sleep,
+    sync::{
+        mpsc::{self, Receiver},
+        watch,
+    },
+    time::{interval, timeout, Instant},
 };
 use tokio_native_tls::TlsAcceptor;
-use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue, StatusCode},
+    Message,
+};
 
 use crate::{
-    map::RoomLabel,
-    sim::{Player, Simulation},
+    auth::{self, PlayerStatus, Sender},
+    config::ConfigFile,
+    broadcaster::{run_broadcaster, Outbound},
+    event_log,
+    lobby::{self, Lobby, LobbyTemplate},
+    equipment::EquipmentKind,
+    ghost::GhostType,
+    map::{Map, MapCatalog, RoomLabel, RoomRef},
+    messages::{MessageKey, Notice, ServerReply},
+    profile::ProfileStore,
+    replay::SimInput,
+    sim::{Difficulty, GameUpdate, SensorKind},
+    sim_actor::{AdminOp, SimCommand},
+    sounds,
+    storage::{RoundSummary, StorageConfig},
+    tls::TlsConfig,
+    utils,
 };
 
+// each is a sim and three tasks, so a stranger can't open them forever
+const MAX_LOBBIES: usize = 16;
+
+// keeps a typo from locking up the sim for hours
+pub(crate) const MAX_FAST_FORWARD_SECS: u64 = 600;
+
+// id the --map file is registered under
+const CUSTOM_MAP: &str = "custom";
+
+// first protocol version with incremental lobby events, older clients get
+// the whole lobby on every change
+pub const LOBBY_EVENTS_PROTOCOL: u32 = 2;
+// the one we speak, the first with Hello
+pub const PROTOCOL: u32 = 3;
+// anything older gets turned away, it wouldn't understand half of what
+// it's sent
+pub const MIN_PROTOCOL: u32 = LOBBY_EVENTS_PROTOCOL;
+// what a client can ask for on top, told in the Welcome
+const FEATURES: &[&str] = &["lobby_events", "deltas", "msgpack"];
+
+// websocket subprotocols we speak, in the order we'd rather use them
+pub const SUBPROTOCOL_JSON: &str = "phasmo.v1.json";
+pub const SUBPROTOCOL_MSGPACK: &str = "phasmo.v1.msgpack";
+const SUBPROTOCOLS: &[&str] = &[SUBPROTOCOL_JSON, SUBPROTOCOL_MSGPACK];
+
 type Tx = UnboundedSender<Message>;
 pub type Handle<T> = Arc<Mutex<T>>;
-type PeerMap = Handle<HashMap<SocketAddr, Tx>>;
-
-#[derive(Serialize, Deserialize)]
-enum PhasmoMessage {
-    JoinLobby { name: String },
-    ConnectAsAdmin {},
-    StartSim {},
-    LocationUpdate { name: String, location: RoomLabel },
+pub(crate) type PeerMap = Handle<HashMap<SocketAddr, Peer>>;
+
+#[derive(Clone)]
+pub(crate) struct Peer {
+    pub tx: Tx,
+    // none for clients from before subprotocols, they get JSON
+    #[allow(dead_code)]
+    pub subprotocol: Option<&'static str>,
+    // what everything sent to the peer is written in
+    pub encoding: Encoding,
+    // none until the handshake
+    pub protocol: Option<u32>,
+}
+
+// JSON in text frames, or MessagePack in binary ones. The subprotocol picks
+// it, a Hello can change it after.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl Encoding {
+    fn of(subprotocol: Option<&str>) -> Self {
+        match subprotocol {
+            Some(SUBPROTOCOL_MSGPACK) => Encoding::Msgpack,
+            _ => Encoding::Json,
+        }
+    }
+
+    pub fn message<T: Serialize>(self, value: &T) -> Message {
+        match self {
+            Encoding::Json => Message::text(serde_json::to_string(value).unwrap()),
+            Encoding::Msgpack => Message::binary(rmp_serde::to_vec_named(value).unwrap()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum PhasmoMessage {
+    // the handshake, nothing else is taken before it. Answered with a
+    // Welcome, or a Rejected and a close if we don't speak the protocol.
+    // An encoding is for clients that can't pick a subprotocol, the reply
+    // comes in the new one.
+    Hello {
+        protocol: u32,
+        #[serde(default)]
+        encoding: Option<Encoding>,
+    },
+    // without a code, the only open lobby, or a new one if there's none
+    JoinLobby {
+        name: String,
+        // the handshake for clients from before Hello, ignored after one
+        #[serde(default)]
+        protocol: u32,
+        #[serde(default)]
+        code: Option<String>,
+        // SimDelta instead of whole Sim states
+        #[serde(default)]
+        deltas: bool,
+    },
+    // the code comes back as LobbyCreated, the sender's already in
+    CreateLobby {},
+    // a lobby code works as for JoinLobby
+    ConnectAsAdmin {
+        #[serde(default)]
+        token: String,
+        #[serde(default)]
+        code: Option<String>,
+        #[serde(default)]
+        deltas: bool,
+    },
+    // sees everything the players do, and more, but can't play
+    ConnectAsSpectator {
+        #[serde(default)]
+        code: Option<String>,
+        #[serde(default)]
+        deltas: bool,
+    },
+    StartSim {
+        #[serde(default)]
+        map: Option<String>,
+        // only used when map is "random"
+        #[serde(default)]
+        seed: Option<u64>,
+        #[serde(default)]
+        difficulty: Difficulty,
+        // for when someone can't get their ready in
+        #[serde(default)]
+        force: bool,
+    },
+    LeaveLobby {},
+    SetReady { ready: bool },
+    SelectMap { map: String },
+    // a room name works as well as a label
+    LocationUpdate { location: RoomRef },
+    SetFlashlight { on: bool },
+    SetLights { location: RoomLabel, on: bool },
+    // only from the breaker's room
+    SetBreaker { on: bool },
+    // crouched into or out of a hiding spot
+    SetHidden { hidden: bool },
+    // a player shut or opened the door between two rooms
+    SetDoorState {
+        from: RoomLabel,
+        to: RoomLabel,
+        open: bool,
+    },
+    SetActiveEquipment { items: Vec<EquipmentKind> },
+    PlaceCamera { location: RoomLabel },
+    PlaceDots { location: RoomLabel },
+    PlaceBook { location: RoomLabel },
+    PlaceCrucifix { location: RoomLabel },
+    // picked the book back up
+    RemoveBook {},
+    RemoveCamera { id: u32 },
+    PlaceSensor { location: RoomLabel, kind: SensorKind },
+    RemoveSensor { id: u32 },
+    Equip { item: EquipmentKind },
+    Drop {},
+    SwapSlot { index: usize },
+    GetProfile {},
+    GetMap {},
+    // for delta clients that missed one, the next state comes whole
+    RequestFullState {},
+    FastForward { secs: u64 },
+    PauseSim {},
+    ResumeSim {},
+    TeleportGhost {
+        location: RoomLabel,
+        // blip the EMF on arrival
+        #[serde(default)]
+        emf: bool,
+    },
+    SetGhostRoom { location: RoomLabel },
+    // null goes back to a random ghost
+    SetGhostType { ghost: Option<GhostType> },
+    Kick { name: String },
+    // back to the lobby from wherever the round is
+    ResetSim {
+        // everyone has to join again
+        #[serde(default)]
+        clear_players: bool,
+    },
+    SubscribeGhostDebug {},
+    UnsubscribeGhostDebug {},
+    // what the sim still keeps of the event log, oldest first
+    GetEventLog {},
+    // every sound id the server can send, so clients can load them up front
+    GetSoundManifest {},
+    // the reading comes back to the sender only
+    ReadThermometer {},
+    // pointed at the sender's room or one next to it
+    UseParabolic { direction_room: RoomLabel },
+    ReadEmf {},
+    UseSpiritBox {},
+    #[serde(rename = "UseUVLight")]
+    UseUvLight {},
+    UseSmudge {},
+    UseSanityPills {},
+    // the journal, ends the round
+    SubmitGuess { ghost_type: GhostType },
+    ReloadConfig {},
+}
+
+// What ConnectAsAdmin lets in
+pub(crate) enum AdminLogin {
+    Token(String),
+    // --insecure-admin without a token, for a LAN
+    Anyone,
+    // no token set, so nobody
+    Refused,
 }
 
-pub struct ServerState {
+pub(crate) struct ServerState {
+    // every connection, in a lobby or not
     peer_map: PeerMap,
-    sim: Handle<Simulation>,
+    lobbies: Handle<HashMap<String, Arc<Lobby>>>,
+    // the code of the lobby each peer is in
+    members: Handle<HashMap<SocketAddr, String>>,
+    template: Mutex<LobbyTemplate>,
+    profiles: Handle<ProfileStore>,
+    admins: Handle<HashSet<SocketAddr>>,
+    // for peers that aren't in a lobby yet
+    outbound: mpsc::UnboundedSender<Outbound>,
+    config_path: Option<String>,
+    // as last loaded, to tell what a reload changed
+    config_file: Mutex<ConfigFile>,
+    sim_options: Option<String>,
+    admin_login: AdminLogin,
+    // flips once, when the server starts shutting down
+    stopping: watch::Sender<bool>,
 }
 
 impl ServerState {
-    fn new() -> Self {
+    fn new(
+        template: LobbyTemplate,
+        profiles: ProfileStore,
+        outbound: mpsc::UnboundedSender<Outbound>,
+        config_path: Option<String>,
+        config_file: ConfigFile,
+        sim_options: Option<String>,
+        admin_login: AdminLogin,
+    ) -> Self {
         ServerState {
+            config_path,
+            sim_options,
+            admin_login,
+            config_file: Mutex::new(config_file),
             peer_map: Arc::new(Mutex::new(HashMap::new())),
-            sim: Arc::new(Mutex::new(Simulation::new())),
+            lobbies: Arc::new(Mutex::new(HashMap::new())),
+            members: Arc::new(Mutex::new(HashMap::new())),
+            template: Mutex::new(template),
+            profiles: Arc::new(Mutex::new(profiles)),
+            admins: Arc::new(Mutex::new(HashSet::new())),
+            outbound,
+            stopping: watch::channel(false).0,
         }
     }
 
-    fn add_peer(&self, addr: SocketAddr, tx: Tx) {
+    fn add_peer(&self, addr: SocketAddr, peer: Peer) {
         let mut peer_map = self.peer_map.lock().unwrap();
-        peer_map.insert(addr, tx);
+        peer_map.insert(addr, peer);
     }
 
-    fn remove_peer(&self, addr: SocketAddr) {
-        let mut peer_map = self.peer_map.lock().unwrap();
-        peer_map.remove(&addr).unwrap();
+    // The lobby has a copy of the peer too
+    fn update_peer(&self, addr: SocketAddr, f: impl Fn(&mut Peer)) {
+        let lobby = self.lobby_of(addr);
+        let maps = std::iter::once(&self.peer_map).chain(lobby.as_ref().map(|l| &l.peer_map));
+        for peer_map in maps {
+            if let Some(peer) = peer_map.lock().unwrap().get_mut(&addr) {
+                f(peer);
+            }
+        }
+    }
+
+    fn protocol_of(&self, addr: SocketAddr) -> Option<u32> {
+        self.peer_map.lock().unwrap().get(&addr)?.protocol
     }
 
-    fn register_player(&self, addr: SocketAddr, name: &str) {
-        let mut sim = self.sim.lock().unwrap();
-        let result = sim.add_player(addr, name);
-        drop(sim);
+    fn lobby_of(&self, addr: SocketAddr) -> Option<Arc<Lobby>> {
+        let code = self.members.lock().unwrap().get(&addr)?.clone();
+        self.lobbies.lock().unwrap().get(&code).cloned()
+    }
 
-        match result {
-            Ok(_) => {
-                self.broadcast_gamestate();
-                println!("Player registered: {name}")
+    // The lobby the peer is in, or the one its code names. Without a code
+    // there's only a choice to make while more than one is open, and if
+    // none is one gets made, so a single group never needs a code.
+    fn enter_lobby(&self, addr: SocketAddr, code: Option<String>) -> Result<Arc<Lobby>, String> {
+        let code = code.map(|code| code.trim().to_uppercase());
+        if let Some(current) = self.lobby_of(addr) {
+            return match code {
+                Some(code) if code != current.code => {
+                    Err(format!("Already in lobby {}", current.code))
+                }
+                _ => Ok(current),
+            };
+        }
+
+        let mut lobbies = self.lobbies.lock().unwrap();
+        let lobby = match code {
+            Some(code) => lobbies
+                .get(&code)
+                .cloned()
+                .ok_or_else(|| format!("No lobby {}", code))?,
+            None if lobbies.len() > 1 => {
+                return Err("More than one lobby is open, which one needs a code".to_owned())
             }
-            Err(e) => println!("{}", e),
+            None => match lobbies.values().next() {
+                Some(lobby) => lobby.clone(),
+                None => return self.open_lobby(addr, &mut lobbies),
+            },
+        };
+        self.join(addr, &lobby);
+        Ok(lobby)
+    }
+
+    // Makes a lobby with the peer in it, the code goes back to them to
+    // hand out
+    fn open_lobby(
+        &self,
+        addr: SocketAddr,
+        lobbies: &mut HashMap<String, Arc<Lobby>>,
+    ) -> Result<Arc<Lobby>, String> {
+        if lobbies.len() >= MAX_LOBBIES {
+            return Err(format!("Already {} lobbies open", MAX_LOBBIES));
         }
+        let code = loop {
+            let code = lobby::random_code();
+            if !lobbies.contains_key(&code) {
+                break code;
+            }
+        };
+        let (lobby, seed) = {
+            let template = self.template.lock().unwrap();
+            let sim = template.new_sim()?;
+            let seed = sim.seed();
+            let lobby = Lobby::spawn(
+                code.clone(),
+                sim,
+                template.sim_rate,
+                template.broadcast_rate,
+                self.stopping.subscribe(),
+                template.recording.clone().map(|tx| (code.clone(), tx)),
+            );
+            lobby.record(|| SimInput::Opened {
+                seed,
+                map: template.selected_map.map(str::to_owned),
+                tunables: template.tunables.clone(),
+            });
+            (Arc::new(lobby), seed)
+        };
+        lobbies.insert(code.clone(), lobby.clone());
+        println!("Lobby {} opened, sim seed {}", code, seed);
+
+        self.join(addr, &lobby);
+        lobby.send(addr, GameUpdate::LobbyCreated { code });
+        Ok(lobby)
     }
 
-    fn handle_message(&self, addr: SocketAddr, msg: Message) {
-        match msg {
-            Message::Text(msg) => {
-                let msg: Result<PhasmoMessage, serde_json::Error> = serde_json::from_str(&msg);
-                match msg {
-                    Ok(PhasmoMessage::ConnectAsAdmin {}) => {
-                        self.send_gamestate(addr);
-                    }
-                    Ok(PhasmoMessage::JoinLobby { name }) => {
-                        self.register_player(addr, &name);
-                    }
-                    Ok(PhasmoMessage::StartSim {}) => {
-                        self.sim.lock().unwrap().start();
-                        self.broadcast_gamestate();
-                    }
-                    Ok(PhasmoMessage::LocationUpdate { name, location }) => {
-                        self.sim.lock().unwrap().update_player_loc(&name, location);
-                        self.broadcast_gamestate();
-                    }
-                    _ => println!("Error parsing"),
-                }
+    // Callers hold the lobbies lock, so the lobby can't close under them
+    fn join(&self, addr: SocketAddr, lobby: &Lobby) {
+        if let Some(peer) = self.peer_map.lock().unwrap().get(&addr).cloned() {
+            lobby.peer_map.lock().unwrap().insert(addr, peer);
+        }
+        self.members.lock().unwrap().insert(addr, lobby.code.clone());
+    }
+
+    // Takes the peer out of its lobby, which closes once nobody's left
+    async fn exit_lobby(&self, addr: SocketAddr) -> Option<Arc<Lobby>> {
+        let lobby = self.lobby_of(addr)?;
+        self.members.lock().unwrap().remove(&addr);
+        lobby.forget(addr).await;
+
+        let mut lobbies = self.lobbies.lock().unwrap();
+        if lobby.is_empty() && lobbies.remove(&lobby.code).is_some() {
+            println!("Lobby {} closed", lobby.code);
+        }
+        Some(lobby)
+    }
+
+    // Closes a peer the sim is already done with. Its connection notices
+    // it's out of the map and doesn't tell the sim again.
+    async fn disconnect(&self, addr: SocketAddr) {
+        if let Some(peer) = self.peer_map.lock().unwrap().remove(&addr) {
+            let _ = peer.tx.unbounded_send(Message::Close(None));
+        }
+        self.admins.lock().unwrap().remove(&addr);
+        self.exit_lobby(addr).await;
+    }
+
+    async fn remove_peer(&self, addr: SocketAddr) {
+        // already gone if its connection panicked while cleaning up
+        if self.peer_map.lock().unwrap().remove(&addr).is_none() {
+            return;
+        }
+        self.admins.lock().unwrap().remove(&addr);
+        if let Some(lobby) = self.exit_lobby(addr).await {
+            lobby.remove_player(addr).await;
+        }
+    }
+
+    // Constant time, so the token can't be guessed a byte at a time
+    fn check_admin_token(&self, token: &str) -> Result<(), String> {
+        match &self.admin_login {
+            AdminLogin::Token(expected)
+                if utils::same_bytes(expected.as_bytes(), token.as_bytes()) =>
+            {
+                Ok(())
             }
-            _ => (),
+            AdminLogin::Token(_) => Err("Wrong admin token".to_owned()),
+            AdminLogin::Anyone => Ok(()),
+            AdminLogin::Refused => Err("No admin token is set on this server".to_owned()),
         }
     }
 
-    fn send_gamestate(&self, addr: SocketAddr) {
-        let mut peer_map = self.peer_map.lock().unwrap();
-        let msg = self.get_gamestate();
-        match peer_map.get_mut(&addr) {
-            Some(sender) => {
-                println!("Sending message");
-                sender.unbounded_send(msg.clone()).unwrap();
+    fn is_admin(&self, addr: SocketAddr) -> bool {
+        self.admins.lock().unwrap().contains(&addr)
+    }
+
+    async fn player_name(&self, lobby: &Lobby, addr: SocketAddr) -> Option<String> {
+        lobby
+            .sim
+            .query(move |sim| sim.player_name(addr))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn sender(&self, addr: SocketAddr) -> Sender {
+        let lobby = self.lobby_of(addr);
+        let (player, in_round) = match &lobby {
+            Some(lobby) => lobby
+                .sim
+                .query(move |sim| {
+                    let status = sim.players.iter().find(|p| p.addr == addr).map(|player| {
+                        if !sim.is_started() {
+                            PlayerStatus::Lobby
+                        } else if player.is_alive() {
+                            PlayerStatus::Alive
+                        } else {
+                            PlayerStatus::Dead
+                        }
+                    });
+                    (status, sim.is_started())
+                })
+                .await
+                .unwrap_or((None, false)),
+            None => (None, false),
+        };
+        let spectator =
+            lobby.is_some_and(|lobby| lobby.spectators.lock().unwrap().contains(&addr));
+        Sender {
+            admin: self.is_admin(addr),
+            spectator,
+            player,
+            in_round,
+        }
+    }
+
+    async fn register_player(
+        &self,
+        lobby: &Lobby,
+        addr: SocketAddr,
+        name: &str,
+        protocol: u32,
+    ) -> Result<(), String> {
+        lobby
+            .sim
+            .request(|reply| SimCommand::AddPlayer {
+                addr,
+                name: name.to_owned(),
+                reply,
+            })
+            .await?;
+
+        let profile = self.profiles.lock().unwrap().get_or_create(name);
+        let owned = name.to_owned();
+        let result = lobby
+            .sim
+            .query(move |sim| sim.set_player_profile(&owned, profile.level(), profile.money))
+            .await;
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+
+        let incremental = protocol >= LOBBY_EVENTS_PROTOCOL;
+        if incremental {
+            lobby.lobby_peers.lock().unwrap().insert(addr);
+        }
+        lobby.sim.broadcast();
+        // everyone else just hears that we joined
+        if incremental {
+            lobby.send_gamestate(addr).await;
+        }
+        println!("Player registered in {}: {name}", lobby.code);
+        Ok(())
+    }
+
+    // Pays everyone out and keeps the round in the history. The lobby shows
+    // levels and money, so the sim gets the new ones too.
+    async fn record_round(&self, lobby: &Lobby, summary: RoundSummary) {
+        let profiles: Vec<_> = {
+            let mut store = self.profiles.lock().unwrap();
+            for player in &summary.players {
+                store.record_round(
+                    &player.name,
+                    &summary.map,
+                    player.survived,
+                    summary.correct_guess,
+                    player.earned,
+                );
             }
-            None => (),
+            store.record_summary(&summary);
+            summary
+                .players
+                .iter()
+                .map(|p| store.get_or_create(&p.name))
+                .collect()
+        };
+        let result = lobby
+            .sim
+            .query(move |sim| {
+                for profile in profiles {
+                    sim.set_player_profile(&profile.name, profile.level(), profile.money);
+                }
+            })
+            .await;
+        if let Err(e) = result {
+            println!("{}", e);
         }
     }
 
-    fn broadcast_gamestate(&self) {
-        println!("Broadcasting gamestate");
-        self.broadcast(self.get_gamestate());
+    async fn send_profile(&self, lobby: &Lobby, addr: SocketAddr) -> Result<(), String> {
+        let name = self.player_name(lobby, addr).await.ok_or("Not registered")?;
+        let profile = self.profiles.lock().unwrap().get_or_create(&name);
+        let update = GameUpdate::Profile {
+            profile: profile.view(),
+        };
+        lobby.send(addr, update);
+        Ok(())
+    }
 
-        self.sim.lock().unwrap().clear_notify_queue();
+    // Tells the player why in a notice as well, for failures they'd
+    // otherwise be left wondering about
+    fn reject<T>(&self, addr: SocketAddr, key: MessageKey, reason: String) -> Result<T, String> {
+        let params = json!({ "reason": reason });
+        self.send_notification(addr, Notice::new(key, params, reason.clone()));
+        Err(reason)
     }
 
-    fn broadcast(&self, msg: Message) {
-        let mut peer_map = self.peer_map.lock().unwrap();
-        for peer in peer_map.values_mut() {
-            peer.unbounded_send(msg.clone()).unwrap();
+    // Every message gets a reply saying whether it worked, named after it
+    // so clients can match it up
+    async fn handle_message(&self, addr: SocketAddr, msg: Message) {
+        // either encoding is read, whichever the peer is sent. The name
+        // comes first, so even a message that doesn't parse gets a reply
+        // it can be matched to.
+        let (request, parsed) = match msg {
+            Message::Text(text) => {
+                let value: Result<Value, String> =
+                    serde_json::from_str(&text).map_err(|e| e.to_string());
+                let request = value
+                    .as_ref()
+                    .ok()
+                    .and_then(Value::as_object)
+                    .and_then(|fields| fields.keys().next())
+                    .cloned();
+                let parsed =
+                    value.and_then(|v| serde_json::from_value(v).map_err(|e| e.to_string()));
+                (request, parsed)
+            }
+            Message::Binary(bytes) => {
+                let fields = rmp_serde::from_slice::<HashMap<String, IgnoredAny>>(&bytes);
+                let request = fields.ok().and_then(|fields| fields.into_keys().next());
+                let parsed = rmp_serde::from_slice(&bytes).map_err(|e| e.to_string());
+                (request, parsed)
+            }
+            _ => return,
+        };
+        let request = request.unwrap_or_default();
+        let result = match parsed {
+            Ok(msg) => self.dispatch(addr, msg).await,
+            Err(e) => Err(format!("Bad message: {}", e)),
+        };
+        let reply = match result {
+            Ok(()) => ServerReply::Ack { request },
+            Err(reason) => {
+                println!("{} from {} failed: {}", request, addr, reason);
+                ServerReply::Error { request, reason }
+            }
+        };
+        self.send(addr, GameUpdate::Reply(reply));
+    }
+
+    // Clients from before Hello shake hands with the protocol in their
+    // JoinLobby, they don't know what a Welcome is
+    async fn handshake(&self, addr: SocketAddr, msg: &PhasmoMessage) -> Result<(), String> {
+        let offered = match msg {
+            PhasmoMessage::Hello { protocol, .. } => *protocol,
+            _ if self.protocol_of(addr).is_some() => return Ok(()),
+            PhasmoMessage::JoinLobby { protocol, .. } => *protocol,
+            _ => return Err("Say Hello first".to_owned()),
+        };
+        if !(MIN_PROTOCOL..=PROTOCOL).contains(&offered) {
+            let reason = format!(
+                "Protocol {} isn't supported, only {} to {}",
+                offered, MIN_PROTOCOL, PROTOCOL
+            );
+            let rejected = GameUpdate::Rejected {
+                protocol: PROTOCOL,
+                min_protocol: MIN_PROTOCOL,
+                reason: reason.clone(),
+            };
+            self.turn_away(addr, rejected).await;
+            return Err(reason);
         }
+        self.update_peer(addr, |peer| peer.protocol = Some(offered));
+        Ok(())
     }
 
-    fn broadcast_close(&self) {
-        let msg = Message::Close(None);
-        self.broadcast(msg);
+    // Straight to the peer rather than through a broadcaster, so the close
+    // can't get there first
+    async fn turn_away(&self, addr: SocketAddr, update: GameUpdate) {
+        let peer = self.peer_map.lock().unwrap().get(&addr).cloned();
+        if let Some(peer) = peer {
+            let _ = peer.tx.unbounded_send(peer.encoding.message(&update));
+        }
+        self.disconnect(addr).await;
     }
 
-    fn get_gamestate(&self) -> Message {
-        let sim = self.sim.lock().unwrap();
+    async fn dispatch(&self, addr: SocketAddr, msg: PhasmoMessage) -> Result<(), String> {
+        self.handshake(addr, &msg).await?;
+        let sender = self.sender(addr).await;
+        let needed = auth::required(&msg);
+        if !needed.allows(sender) {
+            println!("Rejected message from {} ({:?}), needs {:?}", addr, sender, needed);
+            let text = "Not allowed".to_owned();
+            self.send_notification(addr, Notice::new(MessageKey::NotAllowed, json!({}), text));
+            return Err(format!("Not allowed, needs {:?}", needed));
+        }
+        if auth::needs_round(&msg) && !sender.in_round {
+            return Err("No round running".to_owned());
+        }
 
-        let gamestate = sim.get_gameupdate();
-        let gamestate_ser = serde_json::to_string(&gamestate).unwrap();
+        match msg {
+            PhasmoMessage::ConnectAsAdmin {
+                token,
+                code,
+                deltas,
+            } => {
+                if let Err(reason) = self.check_admin_token(&token) {
+                    println!("Failed admin login from {}", addr);
+                    let notice = Notice::new(MessageKey::AdminDenied, json!({}), reason.clone());
+                    self.send_notification(addr, notice);
+                    return Err(reason);
+                }
+                self.admins.lock().unwrap().insert(addr);
+                match self.enter_lobby(addr, code) {
+                    Ok(lobby) => {
+                        lobby.unredacted.lock().unwrap().insert(addr);
+                        lobby.set_deltas(addr, deltas);
+                        lobby.send_gamestate(addr).await;
+                        Ok(())
+                    }
+                    Err(e) => self.reject(addr, MessageKey::LobbyFailed, e),
+                }
+            }
+            PhasmoMessage::ConnectAsSpectator { code, deltas } => {
+                // a player would have to leave first
+                if sender.player.is_some() {
+                    let text = "Not allowed".to_owned();
+                    let notice = Notice::new(MessageKey::NotAllowed, json!({}), text);
+                    self.send_notification(addr, notice);
+                    return Err("Already playing, can't spectate".to_owned());
+                }
+                match self.enter_lobby(addr, code) {
+                    Ok(lobby) => {
+                        lobby.spectators.lock().unwrap().insert(addr);
+                        lobby.unredacted.lock().unwrap().insert(addr);
+                        lobby.set_deltas(addr, deltas);
+                        lobby.update_spectators().await;
+                        lobby.send_gamestate(addr).await;
+                        println!("Spectator connected to {}: {}", lobby.code, addr);
+                        Ok(())
+                    }
+                    Err(e) => self.reject(addr, MessageKey::LobbyFailed, e),
+                }
+            }
+            PhasmoMessage::JoinLobby {
+                name, code, deltas, ..
+            } => match self.enter_lobby(addr, code) {
+                Ok(lobby) => {
+                    lobby.set_deltas(addr, deltas);
+                    // done watching
+                    if lobby.spectators.lock().unwrap().remove(&addr) {
+                        if !self.is_admin(addr) {
+                            lobby.unredacted.lock().unwrap().remove(&addr);
+                        }
+                        lobby.update_spectators().await;
+                    }
+                    let protocol = self.protocol_of(addr).unwrap_or(MIN_PROTOCOL);
+                    let input = lobby.input(|| SimInput::Message {
+                        addr,
+                        message: PhasmoMessage::JoinLobby {
+                            name: name.clone(),
+                            protocol,
+                            code: Some(lobby.code.clone()),
+                            deltas,
+                        },
+                    });
+                    let register = self.register_player(&lobby, addr, &name, protocol);
+                    lobby.with_input(input, register).await
+                }
+                Err(e) => self.reject(addr, MessageKey::LobbyFailed, e),
+            },
+            PhasmoMessage::CreateLobby {} => {
+                let result = match self.lobby_of(addr) {
+                    Some(lobby) => Err(format!("Already in lobby {}", lobby.code)),
+                    None => self.open_lobby(addr, &mut self.lobbies.lock().unwrap()),
+                };
+                match result {
+                    Ok(_) => Ok(()),
+                    Err(e) => self.reject(addr, MessageKey::LobbyFailed, e),
+                }
+            }
+            PhasmoMessage::Hello { encoding, .. } => {
+                if let Some(encoding) = encoding {
+                    self.update_peer(addr, |peer| peer.encoding = encoding);
+                }
+                let features = FEATURES.iter().map(|f| f.to_string()).collect();
+                self.send(addr, GameUpdate::Welcome { protocol: PROTOCOL, features });
+                Ok(())
+            }
+            PhasmoMessage::GetSoundManifest {} => {
+                let sounds = sounds::manifest();
+                self.send(addr, GameUpdate::SoundManifest { sounds });
+                Ok(())
+            }
+            PhasmoMessage::ReloadConfig {} => self.reload_config().await,
+            msg => match self.lobby_of(addr) {
+                Some(lobby) => self.handle_lobby_message(&lobby, addr, msg).await,
+                None => self.reject(addr, MessageKey::LobbyFailed, "Not in a lobby".to_owned()),
+            },
+        }
+    }
 
-        Message::text(gamestate_ser)
+    // Everything that acts on the sender's game
+    async fn handle_lobby_message(
+        &self,
+        lobby: &Lobby,
+        addr: SocketAddr,
+        msg: PhasmoMessage,
+    ) -> Result<(), String> {
+        let input = lobby.input(|| SimInput::Message {
+            addr,
+            message: msg.clone(),
+        });
+        lobby.with_input(input, self.lobby_message(lobby, addr, msg)).await
     }
 
-    fn update_sim(&self, dt: Duration) -> bool {
-        let mut sim = self.sim.lock().unwrap();
-        sim.update(dt)
+    async fn lobby_message(
+        &self,
+        lobby: &Lobby,
+        addr: SocketAddr,
+        msg: PhasmoMessage,
+    ) -> Result<(), String> {
+        match msg {
+            PhasmoMessage::StartSim {
+                map,
+                seed,
+                difficulty,
+                force,
+            } => {
+                let result = lobby
+                    .sim
+                    .request(|reply| SimCommand::Start {
+                        map,
+                        seed,
+                        difficulty,
+                        force,
+                        reply,
+                    })
+                    .await;
+                // bad custom options are the host's to fix
+                result.or_else(|e| self.reject(addr, MessageKey::StartFailed, e))
+            }
+            PhasmoMessage::LeaveLobby {} => {
+                let name = lobby.apply(move |sim| sim.leave_lobby(addr)).await?;
+                self.exit_lobby(addr).await;
+                println!("Player left: {name}");
+                Ok(())
+            }
+            PhasmoMessage::SetReady { ready } => {
+                lobby.apply(move |sim| sim.set_ready(addr, ready)).await
+            }
+            PhasmoMessage::SelectMap { map } => lobby.admin_op(AdminOp::SelectMap(map)).await,
+            PhasmoMessage::LocationUpdate { location } => {
+                let found = lobby
+                    .sim
+                    .request(|reply| SimCommand::UpdateLoc {
+                        addr,
+                        location,
+                        reply,
+                    })
+                    .await?;
+                for notice in found {
+                    lobby.send_notification(addr, notice);
+                }
+                lobby.sim.broadcast();
+                Ok(())
+            }
+            PhasmoMessage::SetFlashlight { on } => {
+                // only affects the sim, clients don't need a fresh gamestate
+                lobby.sim.query(move |sim| sim.set_flashlight(addr, on)).await
+            }
+            PhasmoMessage::SetHidden { hidden } => {
+                lobby.apply(move |sim| sim.set_hidden(addr, hidden)).await
+            }
+            PhasmoMessage::SetLights { location, on } => {
+                lobby.apply(move |sim| sim.set_lights(addr, location, on)).await
+            }
+            PhasmoMessage::SetBreaker { on } => {
+                lobby.apply(move |sim| sim.set_breaker(addr, on)).await
+            }
+            PhasmoMessage::SetDoorState { from, to, open } => {
+                lobby.apply(move |sim| sim.set_door_between(addr, from, to, open)).await
+            }
+            PhasmoMessage::SetActiveEquipment { items } => {
+                lobby.apply(move |sim| sim.set_active_equipment(addr, items)).await
+            }
+            PhasmoMessage::PlaceCamera { location } => {
+                let id = lobby.apply(move |sim| sim.place_camera(addr, location)).await?;
+                println!("Camera {id} placed in room {location}");
+                lobby.send_inventory(addr).await;
+                Ok(())
+            }
+            PhasmoMessage::PlaceDots { location } => {
+                lobby.apply(move |sim| sim.place_dots(addr, location)).await?;
+                println!("Dots projector placed in room {location}");
+                lobby.send_inventory(addr).await;
+                Ok(())
+            }
+            PhasmoMessage::UseSmudge {} => {
+                let result = lobby.apply(move |sim| sim.use_smudge(addr)).await;
+                lobby.handle_inventory_result(addr, result).await
+            }
+            PhasmoMessage::UseSanityPills {} => {
+                let result = lobby.apply(move |sim| sim.use_sanity_pills(addr)).await;
+                // otherwise it looks like the pills did nothing
+                let result = result.or_else(|e| self.reject(addr, MessageKey::ItemFailed, e));
+                lobby.handle_inventory_result(addr, result).await
+            }
+            PhasmoMessage::SubmitGuess { ghost_type } => {
+                let result = lobby.apply(move |sim| sim.submit_guess(addr, ghost_type)).await;
+                match result {
+                    Ok(summary) => {
+                        self.record_round(lobby, summary).await;
+                        Ok(())
+                    }
+                    Err(e) => self.reject(addr, MessageKey::GuessRejected, e),
+                }
+            }
+            PhasmoMessage::PlaceCrucifix { location } => {
+                lobby.apply(move |sim| sim.place_crucifix(addr, location)).await?;
+                println!("Crucifix placed in room {location}");
+                lobby.send_inventory(addr).await;
+                Ok(())
+            }
+            PhasmoMessage::PlaceBook { location } => {
+                lobby.apply(move |sim| sim.place_book(addr, location)).await?;
+                println!("Book placed in room {location}");
+                lobby.send_inventory(addr).await;
+                Ok(())
+            }
+            PhasmoMessage::RemoveBook {} => {
+                let result = lobby.apply(move |sim| sim.remove_book(addr)).await;
+                lobby.handle_inventory_result(addr, result).await
+            }
+            PhasmoMessage::RemoveCamera { id } => {
+                lobby.apply(move |sim| sim.remove_camera(addr, id)).await
+            }
+            PhasmoMessage::PlaceSensor { location, kind } => {
+                let id = lobby.apply(move |sim| sim.place_sensor(addr, location, kind)).await?;
+                println!("{:?} sensor {id} placed in room {location}", kind);
+                Ok(())
+            }
+            PhasmoMessage::RemoveSensor { id } => {
+                lobby.apply(move |sim| sim.remove_sensor(addr, id)).await
+            }
+            PhasmoMessage::Equip { item } => {
+                let result = lobby.sim.query(move |sim| sim.equip(addr, item)).await;
+                lobby.handle_inventory_result(addr, result.and_then(|r| r)).await
+            }
+            PhasmoMessage::Drop {} => {
+                let result = lobby.sim.query(move |sim| sim.drop_item(addr)).await;
+                lobby.handle_inventory_result(addr, result.and_then(|r| r)).await
+            }
+            PhasmoMessage::SwapSlot { index } => {
+                let result = lobby.sim.query(move |sim| sim.swap_slot(addr, index)).await;
+                lobby.handle_inventory_result(addr, result.and_then(|r| r)).await
+            }
+            PhasmoMessage::GetProfile {} => self.send_profile(lobby, addr).await,
+            PhasmoMessage::GetMap {} => {
+                let update = lobby.sim.query(|sim| sim.get_map()).await?;
+                lobby.send(addr, update);
+                Ok(())
+            }
+            PhasmoMessage::RequestFullState {} => {
+                lobby.send_gamestate(addr).await;
+                Ok(())
+            }
+            PhasmoMessage::FastForward { secs } => {
+                let dt = Duration::from_secs(secs.min(MAX_FAST_FORWARD_SECS));
+                lobby.admin_op(AdminOp::FastForward(dt)).await
+            }
+            PhasmoMessage::PauseSim {} => lobby.admin_op(AdminOp::SetPaused(true)).await,
+            PhasmoMessage::ResumeSim {} => lobby.admin_op(AdminOp::SetPaused(false)).await,
+            PhasmoMessage::TeleportGhost { location, emf } => {
+                lobby.admin_op(AdminOp::TeleportGhost { room: location, emf }).await
+            }
+            PhasmoMessage::SetGhostRoom { location } => {
+                lobby.admin_op(AdminOp::SetGhostRoom(location)).await
+            }
+            PhasmoMessage::SetGhostType { ghost } => {
+                lobby.admin_op(AdminOp::SetGhostType(ghost)).await
+            }
+            PhasmoMessage::Kick { name } => {
+                match lobby.apply(move |sim| sim.kick(&name)).await {
+                    Ok(kicked) => {
+                        self.disconnect(kicked).await;
+                        Ok(())
+                    }
+                    Err(e) => self.reject(addr, MessageKey::KickFailed, e),
+                }
+            }
+            PhasmoMessage::ResetSim { clear_players } => {
+                lobby.admin_op(AdminOp::Reset { clear_players }).await
+            }
+            PhasmoMessage::SubscribeGhostDebug {} => {
+                lobby.set_ghost_debug(addr, true).await;
+                Ok(())
+            }
+            PhasmoMessage::UnsubscribeGhostDebug {} => {
+                lobby.set_ghost_debug(addr, false).await;
+                Ok(())
+            }
+            PhasmoMessage::GetEventLog {} => {
+                let events = lobby.sim.query(|sim| sim.event_log()).await?;
+                lobby.send(addr, GameUpdate::EventLog { events });
+                Ok(())
+            }
+            PhasmoMessage::UseParabolic { direction_room } => {
+                // the reading goes to whoever asked
+                let result = lobby
+                    .sim
+                    .query(move |sim| sim.use_parabolic(addr, direction_room))
+                    .await;
+                lobby.send_reading(addr, result.and_then(|r| r))
+            }
+            PhasmoMessage::ReadThermometer {} => {
+                let result = lobby.sim.query(move |sim| sim.read_thermometer(addr)).await;
+                lobby.send_reading(addr, result.and_then(|r| r))
+            }
+            PhasmoMessage::ReadEmf {} => {
+                let result = lobby.sim.query(move |sim| sim.read_emf(addr)).await;
+                lobby.send_reading(addr, result.and_then(|r| r))
+            }
+            PhasmoMessage::UseSpiritBox {} => {
+                let result = lobby.sim.query(move |sim| sim.use_spirit_box(addr)).await;
+                lobby.send_reading(addr, result.and_then(|r| r))
+            }
+            PhasmoMessage::UseUvLight {} => {
+                let result = lobby.sim.query(move |sim| sim.use_uv_light(addr)).await;
+                lobby.send_reading(addr, result.and_then(|r| r))
+            }
+            // handled before there's a lobby to go to
+            PhasmoMessage::Hello { .. }
+            | PhasmoMessage::ConnectAsAdmin { .. }
+            | PhasmoMessage::ConnectAsSpectator { .. }
+            | PhasmoMessage::JoinLobby { .. }
+            | PhasmoMessage::CreateLobby {}
+            | PhasmoMessage::GetSoundManifest {}
+            | PhasmoMessage::ReloadConfig {} => Ok(()),
+        }
     }
 
-    fn is_started(&self) -> bool {
-        self.sim.lock().unwrap().started
+    // Reads the config file again and applies what can change while
+    // running. A bad file changes nothing.
+    async fn reload_config(&self) -> Result<(), String> {
+        // without a config file the sim options can still have changed
+        let loaded = match &self.config_path {
+            Some(path) => ConfigFile::load(path),
+            None => Ok(ConfigFile::default()),
+        };
+        let file = loaded.map_err(|e| format!("Config not reloaded: {}", e))?;
+
+        // tried on a sim of its own first, so it's the same for every lobby
+        // and new ones get it too
+        let result = file.sim_tunables(self.sim_options.as_deref()).and_then(|tunables| {
+            let mut template = self.template.lock().unwrap();
+            let changed = template.new_sim()?.set_tunables(tunables.clone())?;
+            template.tunables = tunables.clone();
+            Ok((tunables, changed))
+        });
+        let (tunables, changed) = result.map_err(|e| format!("Config not reloaded: {}", e))?;
+        let lobbies: Vec<_> = self.lobbies.lock().unwrap().values().cloned().collect();
+        for lobby in lobbies {
+            let input = lobby.input(|| SimInput::Tunables {
+                tunables: tunables.clone(),
+            });
+            let tunables = tunables.clone();
+            let set = lobby.sim.query(move |sim| sim.set_tunables(tunables));
+            let result = lobby.with_input(input, set).await;
+            if let Err(e) = result.and_then(|r| r) {
+                println!("Lobby {} kept its options: {}", lobby.code, e);
+            }
+            // the lobby shows the options
+            lobby.sim.broadcast();
+        }
+        if let Ok(Some(level)) = file.log_level() {
+            if level != log::max_level() {
+                println!("log_level: {} -> {}", log::max_level(), level);
+                log::set_max_level(level);
+            }
+        }
+        for change in &changed {
+            println!("{}", change);
+        }
+
+        let mut old = self.config_file.lock().unwrap();
+        for key in old.restart_only_changes(&file) {
+            println!("{} changed, takes effect on restart", key);
+        }
+        println!("Reloaded config");
+        *old = file;
+        Ok(())
+    }
+
+    fn send_notification(&self, addr: SocketAddr, notice: Notice) {
+        self.send(addr, GameUpdate::Notification { notice });
+    }
+
+    // A peer in a lobby hears everything through its broadcaster, so
+    // ordering holds
+    fn send(&self, addr: SocketAddr, update: GameUpdate) {
+        if let Some(lobby) = self.lobby_of(addr) {
+            return lobby.send(addr, update);
+        }
+        if self.outbound.send(Outbound::Direct(addr, update)).is_err() {
+            println!("Broadcaster is gone");
+        }
+    }
+
+    // Every connection closes itself, the lobbies' clocks stop
+    fn stop(&self) {
+        self.stopping.send_replace(true);
     }
 }
 
 async fn handle_connection(
-    state: Handle<ServerState>,
+    state: Arc<ServerState>,
     raw_stream: TcpStream,
-    acceptor: Arc<tokio::sync::Mutex<TlsAcceptor>>,
+    acceptor: Option<Arc<TlsAcceptor>>,
     addr: SocketAddr,
+    legacy_clients: bool,
+    peer_timeout: Duration,
 ) {
     println!("Incoming TCP connection from: {}", addr);
 
-    let acceptor = acceptor.lock().await;
-    let stream = acceptor.accept(raw_stream).await;
-    drop(acceptor);
-
-    match stream {
-        Ok(stream) => {
-            let ws_stream = tokio_tungstenite::accept_async(stream).await;
-            match ws_stream {
-                Ok(ws_stream) => {
-                    println!("WebSocket connection established: {}", addr);
+    match acceptor {
+        Some(acceptor) => match acceptor.accept(raw_stream).await {
+            Ok(stream) => {
+                serve_websocket(state, stream, addr, legacy_clients, peer_timeout).await
+            }
+            Err(e) => println!("{}", e),
+        },
+        None => serve_websocket(state, raw_stream, addr, legacy_clients, peer_timeout).await,
+    }
+}
 
-                    // Insert the write part of this peer to the peer map.
-                    let (tx, rx) = unbounded();
-                    state.lock().unwrap().add_peer(addr, tx);
+// The first subprotocol the client offers that we speak. Offering none is
+// only fine while legacy clients are.
+fn choose_subprotocol(
+    offered: Option<&str>,
+    legacy_clients: bool,
+) -> Result<Option<&'static str>, String> {
+    let offered: Vec<&str> = offered
+        .map(|header| header.split(',').map(str::trim).filter(|p| !p.is_empty()).collect())
+        .unwrap_or_default();
+    if offered.is_empty() {
+        return if legacy_clients {
+            Ok(None)
+        } else {
+            Err(format!("No subprotocol offered, supported: {}", SUBPROTOCOLS.join(", ")))
+        };
+    }
+    offered
+        .iter()
+        .find_map(|p| SUBPROTOCOLS.iter().find(|s| *s == p).copied())
+        .map(Some)
+        .ok_or_else(|| {
+            format!(
+                "None of {} are supported, supported: {}",
+                offered.join(", "),
+                SUBPROTOCOLS.join(", ")
+            )
+        })
+}
 
-                    let (outgoing, incoming) = ws_stream.split();
+async fn serve_websocket<S>(
+    state: Arc<ServerState>,
+    stream: S,
+    addr: SocketAddr,
+    legacy_clients: bool,
+    peer_timeout: Duration,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut subprotocol = None;
+    // the error type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let negotiate = |request: &Request, mut response: Response| {
+        let offered = request
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|h| h.to_str().ok());
+        match choose_subprotocol(offered, legacy_clients) {
+            Ok(chosen) => {
+                if let Some(chosen) = chosen {
+                    response
+                        .headers_mut()
+                        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(chosen));
+                }
+                subprotocol = chosen;
+                Ok(response)
+            }
+            Err(reason) => {
+                println!("Rejected handshake from {}: {}", addr, reason);
+                let mut response = ErrorResponse::new(Some(reason));
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                Err(response)
+            }
+        }
+    };
+    let ws_stream = tokio_tungstenite::accept_hdr_async(stream, negotiate).await;
+    match ws_stream {
+        Ok(ws_stream) => {
+            let shown = subprotocol.unwrap_or("none");
+            println!("WebSocket connection established: {} ({})", addr, shown);
 
-                    let handle_incoming = incoming.try_for_each(|msg| {
-                        println!(
-                            "Received a message from {}: {}",
-                            addr,
-                            msg.to_text().unwrap()
-                        );
+            // Insert the write part of this peer to the peer map.
+            let (tx, rx) = unbounded();
+            let encoding = Encoding::of(subprotocol);
+            let peer = Peer {
+                tx: tx.clone(),
+                subprotocol,
+                encoding,
+                protocol: None,
+            };
+            state.add_peer(addr, peer);
 
-                        state.lock().unwrap().handle_message(addr, msg);
+            let (outgoing, incoming) = ws_stream.split();
 
-                        future::ok(())
-                    });
-                    let receive_from_others = rx.map(Ok).forward(outgoing);
+            // anything counts as a sign of life, not just pongs
+            let last_seen = Mutex::new(Instant::now());
+            let handle_incoming = incoming.try_for_each(|msg| {
+                *last_seen.lock().unwrap() = Instant::now();
+                if msg.is_pong() {
+                    return future::Either::Left(future::ok(()));
+                }
+                // pings and closes can carry any bytes, they aren't logged
+                match &msg {
+                    Message::Text(text) => println!("Received a message from {}: {}", addr, text),
+                    Message::Binary(bytes) => {
+                        println!("Received {} bytes from {}", bytes.len(), addr)
+                    }
+                    _ => {}
+                }
 
-                    pin_mut!(handle_incoming, receive_from_others);
-                    future::select(handle_incoming, receive_from_others).await;
+                let state = state.clone();
+                future::Either::Right(async move {
+                    state.handle_message(addr, msg).await;
+                    Ok(())
+                })
+            });
+            let receive_from_others = rx.map(Ok).forward(outgoing);
+            let heartbeat = heartbeat(addr, tx.clone(), &last_seen, peer_timeout);
+            let mut stopping = state.stopping.subscribe();
+            let stopped = async move {
+                let _ = stopping.wait_for(|stop| *stop).await;
+            };
 
-                    println!("{} disconnected", &addr);
-                    state.lock().unwrap().remove_peer(addr);
+            pin_mut!(handle_incoming, receive_from_others, heartbeat);
+            let mut connection = future::select(handle_incoming, receive_from_others);
+            tokio::select! {
+                _ = &mut connection => {}
+                _ = heartbeat => {}
+                _ = stopped => {
+                    // the close is done once the peer answers it
+                    let _ = tx.unbounded_send(Message::Close(None));
+                    if timeout(CLOSE_GRACE, connection).await.is_err() {
+                        println!("{} didn't answer the close in time", addr);
+                    }
                 }
-                Err(e) => println!("{}", e),
             }
+
+            println!("{} disconnected", &addr);
+            state.remove_peer(addr).await;
         }
         Err(e) => println!("{}", e),
     }
 }
 
-pub async fn run_server<'a>(rx: Arc<tokio::sync::Mutex<Receiver<()>>>) {
-    let addr = "192.168.1.199:2000";
+// Pings the peer and gives up on it once it's been quiet for `timeout`.
+// Phones that drop off Wi-Fi never send a close, they just go silent.
+async fn heartbeat(
+    addr: SocketAddr,
+    tx: UnboundedSender<Message>,
+    last_seen: &Mutex<Instant>,
+    timeout: Duration,
+) {
+    let mut ticker = interval(timeout / PINGS_PER_TIMEOUT);
+    loop {
+        ticker.tick().await;
+        let silent = last_seen.lock().unwrap().elapsed();
+        if silent > timeout {
+            println!("Evicting {}, silent for {}s", addr, silent.as_secs());
+            return;
+        }
+        if tx.unbounded_send(Message::Ping(Vec::new())).is_err() {
+            return;
+        }
+    }
+}
 
-    let state = Arc::new(Mutex::new(ServerState::new()));
+pub const DEFAULT_HOST: &str = "0.0.0.0";
+pub const DEFAULT_PORT: u16 = 2000;
+const DEFAULT_ADDR: &str = "0.0.0.0:2000";
+// a peer has this many pings to answer before it times out
+const PINGS_PER_TIMEOUT: u32 = 3;
+// how long each peer gets to answer the close on shutdown
+const CLOSE_GRACE: Duration = Duration::from_secs(3);
 
-    // Create the event loop and TCP listener we'll accept connections on.
-    let try_socket = TcpListener::bind(&addr).await;
-    let listener = try_socket.expect("Failed to bind");
-    println!("Listening on: {}", addr);
+pub struct ServerConfig {
+    // host:port
+    pub addr: String,
+    // falls back to the built-in map
+    pub map_path: Option<String>,
+    // sim ticks per second
+    pub sim_rate: u32,
+    // most state broadcasts per second, urgent events skip the wait
+    pub broadcast_rate: u32,
+    pub storage: StorageConfig,
+    pub tls: TlsConfig,
+    // accept clients that don't offer a subprotocol, going away next release
+    pub legacy_clients: bool,
+    // where the config came from, reloads read it again
+    pub config_path: Option<String>,
+    pub config_file: ConfigFile,
+    // --sim-options, beats the config file
+    pub sim_options: Option<String>,
+    // what ConnectAsAdmin has to send, with none nobody can be admin
+    pub admin_token: Option<String>,
+    // without a token, lets any connection be admin. Only for a LAN.
+    pub insecure_admin: bool,
+    // players only get what they could know, off shows them everything
+    pub redact: bool,
+    // how long a peer can go without a word before it's dropped
+    pub peer_timeout: Duration,
+    // for the sims' randomness, none picks one per lobby
+    pub seed: Option<u64>,
+    // a file every lobby's events are appended to, for replaying later
+    pub record: Option<String>,
+}
 
-    // TLS
-    let der = include_bytes!("secrets/keyStore.p12");
-    let cert = Identity::from_pkcs12(der, "pass").unwrap();
-    let native_acceptor = native_tls::TlsAcceptor::builder(cert).build().unwrap();
-    let tls_acceptor = Arc::new(tokio::sync::Mutex::new(
-        tokio_native_tls::TlsAcceptor::from(native_acceptor),
-    ));
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: DEFAULT_ADDR.to_owned(),
+            map_path: None,
+            sim_rate: 10,
+            broadcast_rate: 10,
+            storage: StorageConfig::default(),
+            tls: TlsConfig::default(),
+            legacy_clients: true,
+            config_path: None,
+            config_file: ConfigFile::default(),
+            sim_options: None,
+            admin_token: None,
+            insecure_admin: false,
+            redact: true,
+            peer_timeout: Duration::from_secs(15),
+            seed: None,
+            record: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sim_rate == 0 || self.broadcast_rate == 0 {
+            Err("Sim and broadcast rates must be positive".to_owned())
+        } else if self.peer_timeout.is_zero() {
+            Err("Peer timeout must be positive".to_owned())
+        } else if self.broadcast_rate > self.sim_rate {
+            Err(format!(
+                "Broadcast rate {} is faster than the sim rate {}",
+                self.broadcast_rate, self.sim_rate
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
 
-    let sim_state = state.clone();
+// The built in maps, and the --map file if there is one, which new lobbies
+// start on
+pub fn map_catalog(map_path: Option<&str>) -> Result<(MapCatalog, Option<&'static str>), String> {
+    let mut catalog = MapCatalog::builtin();
+    let selected_map = match map_path {
+        Some(path) => {
+            let map = Map::from_file(path).map_err(|e| e.to_string())?;
+            catalog.add(CUSTOM_MAP, map);
+            Some(CUSTOM_MAP)
+        }
+        None => None,
+    };
+    Ok((catalog, selected_map))
+}
+
+// Runs until told to stop, or until one of the tasks the game can't go on
+// without dies
+pub async fn run_server(
+    config: ServerConfig,
+    rx: Arc<tokio::sync::Mutex<Receiver<()>>>,
+) -> Result<(), String> {
+    config.validate()?;
+    let tls_acceptor = config.tls.acceptor()?.map(Arc::new);
+    let addr = config.addr;
 
+    let (catalog, selected_map) = map_catalog(config.map_path.as_deref())?;
+
+    let recording = match config.record {
+        Some(path) => {
+            let file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .map_err(|e| format!("Could not open {}: {}", path, e))?;
+            println!("Recording events to {}", path);
+            let (tx, rx) = mpsc::unbounded_channel();
+            tokio::spawn(event_log::record(path, file, rx));
+            Some(tx)
+        }
+        None => None,
+    };
+
+    let template = LobbyTemplate {
+        catalog,
+        selected_map,
+        tunables: config.config_file.sim_tunables(config.sim_options.as_deref())?,
+        sim_rate: config.sim_rate,
+        broadcast_rate: config.broadcast_rate,
+        redact: config.redact,
+        seed: config.seed,
+        recording,
+    };
+    // bad tunables would otherwise only show up when the first lobby opens
+    template.new_sim()?;
+
+    let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+    let profiles = ProfileStore::new(config.storage.open()?);
+    let admin_login = match config.admin_token {
+        Some(token) => AdminLogin::Token(token),
+        None if config.insecure_admin => AdminLogin::Anyone,
+        None => AdminLogin::Refused,
+    };
+    let state = Arc::new(ServerState::new(
+        template,
+        profiles,
+        outbound_tx,
+        config.config_path,
+        config.config_file,
+        config.sim_options,
+        admin_login,
+    ));
+    // only replies to peers outside any lobby go through here, the lobbies
+    // have broadcasters of their own
+    let mut broadcaster = tokio::spawn(run_broadcaster(
+        state.peer_map.clone(),
+        Arc::new(Mutex::new(HashSet::new())),
+        Arc::new(Mutex::new(HashSet::new())),
+        Arc::new(Mutex::new(HashSet::new())),
+        Arc::new(Mutex::new(HashSet::new())),
+        outbound_rx,
+        config.broadcast_rate,
+    ));
+
+    // Create the event loop and TCP listener we'll accept connections on.
+    let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+    println!("Listening on: {}", addr);
+    match state.admin_login {
+        AdminLogin::Token(_) => {}
+        AdminLogin::Anyone => println!("No admin token set, anyone who connects can be admin"),
+        AdminLogin::Refused => println!("No admin token set, nobody can connect as admin"),
+    }
+    if !config.redact {
+        println!("Redaction off, players see the whole state");
+    }
 
-    let handle1 = tokio::spawn(run_simulation(sim_state));
     let state2 = state.clone();
-    let handle2 = tokio::spawn(async move {
+    let legacy_clients = config.legacy_clients;
+    let peer_timeout = config.peer_timeout;
+    let reloader = tokio::spawn(reload_on_hangup(state.clone()));
+    // every connection holds a sender, so the receiver hears nothing back
+    // once the last one is done
+    let (alive, mut all_closed) = mpsc::channel::<()>(1);
+    let mut handle2 = tokio::spawn(async move {
         while let Ok((stream, addr)) = listener.accept().await {
             let state = state2.clone();
             let tls_acceptor = tls_acceptor.clone();
-
-            tokio::spawn(handle_connection(state, stream, tls_acceptor, addr));
+            let alive = alive.clone();
+            spawn_connection(state, stream, tls_acceptor, addr, legacy_clients, peer_timeout, alive);
         }
     });
 
-
     let mut rx = rx.lock().await;
-
-    rx.recv().await;
+    // none of these end on their own, so if one does the game is dead
+    let result = tokio::select! {
+        _ = rx.recv() => Ok(()),
+        ended = &mut broadcaster => Err(utils::task_ended("Broadcaster", ended)),
+        ended = &mut handle2 => Err(utils::task_ended("Listener", ended)),
+    };
     println!("Closing connections");
-    state.lock().unwrap().broadcast_close();
-
-    handle1.abort();
+    // no one new, the listener's sender goes with it
     handle2.abort();
+    state.stop();
+    // connections give up on the close by themselves, this is in case
+    // cleaning up after one hangs
+    if timeout(CLOSE_GRACE * 2, all_closed.recv()).await.is_err() {
+        println!("Gave up waiting on connections to close");
+    }
+    // stops every lobby's tasks
+    state.lobbies.lock().unwrap().clear();
+
+    reloader.abort();
+    broadcaster.abort();
+    result
+}
+
+// kill -HUP reloads the config
+async fn reload_on_hangup(state: Arc<ServerState>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                println!("Can't listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if let Err(e) = state.reload_config().await {
+                println!("{}", e);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
 }
 
-pub async fn run_simulation(
-    state: Handle<ServerState>
+// A panic in one connection only loses that peer, but it gets logged with
+// who it was and the peer is cleaned up
+fn spawn_connection(
+    state: Arc<ServerState>,
+    stream: TcpStream,
+    acceptor: Option<Arc<TlsAcceptor>>,
+    addr: SocketAddr,
+    legacy_clients: bool,
+    peer_timeout: Duration,
+    alive: mpsc::Sender<()>,
 ) {
-    let fps = 10;
-    let dt = Duration::from_millis(1000 / fps);
-    loop {
-        if state.lock().unwrap().is_started() {
-            let changed = state.lock().unwrap().update_sim(dt);
-            if changed {
-                state.lock().unwrap().broadcast_gamestate();
+    let task = tokio::spawn(handle_connection(
+        state.clone(),
+        stream,
+        acceptor,
+        addr,
+        legacy_clients,
+        peer_timeout,
+    ));
+    tokio::spawn(async move {
+        if let Err(e) = task.await {
+            if e.is_panic() {
+                let msg = utils::panic_message(e.into_panic().as_ref());
+                println!("Connection {} panicked: {}", addr, msg);
+                state.remove_peer(addr).await;
             }
+        }
+        drop(alive);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            sleep(dt).await;
+    use std::collections::BTreeSet;
+
+    use serde::de::DeserializeOwned;
+
+    use crate::{
+        event_log::ReviewState,
+        profile::Profile,
+        sim::{OptionOverrides, Simulation},
+        test_util::TempPath,
+    };
+
+    #[test]
+    fn broadcasts_cant_outpace_the_sim() {
+        let rates = |sim_rate, broadcast_rate| ServerConfig {
+            sim_rate,
+            broadcast_rate,
+            ..Default::default()
+        };
+        rates(60, 5).validate().unwrap();
+        rates(10, 10).validate().unwrap();
+        let e = rates(10, 20).validate().unwrap_err();
+        assert_eq!(e, "Broadcast rate 20 is faster than the sim rate 10");
+        assert!(rates(0, 0).validate().is_err());
+        assert!(rates(10, 0).validate().is_err());
+    }
+
+    #[test]
+    fn the_first_subprotocol_we_speak_wins() {
+        let choose = |offered, legacy| choose_subprotocol(offered, legacy);
+        let offered = Some("chat, phasmo.v1.msgpack, phasmo.v1.json");
+        assert_eq!(choose(offered, false), Ok(Some(SUBPROTOCOL_MSGPACK)));
+        assert_eq!(choose(Some(" phasmo.v1.json "), false), Ok(Some(SUBPROTOCOL_JSON)));
+        assert!(choose(Some("chat, phasmo.v2.json"), true).unwrap_err().contains("chat"));
+        // saying nothing is only fine for legacy clients
+        assert_eq!(choose(None, true), Ok(None));
+        assert_eq!(choose(Some(""), true), Ok(None));
+        assert!(choose(None, false).unwrap_err().contains(SUBPROTOCOL_JSON));
+    }
+
+    #[tokio::test]
+    async fn a_reload_reaches_a_round_already_running() {
+        let path = TempPath::with_text("reload.toml", "[sim]\nsanity_drain_rate = 0.1\n");
+        let file = ConfigFile::load(path.as_str()).unwrap();
+        let template = LobbyTemplate {
+            catalog: MapCatalog::builtin(),
+            selected_map: None,
+            tunables: file.sim_tunables(None).unwrap(),
+            sim_rate: 20,
+            broadcast_rate: 10,
+            redact: true,
+            seed: Some(1),
+            recording: None,
+        };
+        let profiles = ProfileStore::new(StorageConfig::Memory.open().unwrap());
+        let (outbound, _outbound) = mpsc::unbounded_channel();
+        let login = AdminLogin::Refused;
+        let config_path = Some(path.as_str().to_owned());
+        let state =
+            ServerState::new(template, profiles, outbound, config_path, file, None, login);
+        let ann = SocketAddr::from(([127, 0, 0, 1], 6100));
+        let lobby = state.enter_lobby(ann, None).unwrap();
+        let started = lobby.sim.query(move |sim| {
+            sim.add_player(ann, "ann")?;
+            sim.start(None, None, Difficulty::default(), true)
+        });
+        started.await.unwrap().unwrap();
+        let drain = || lobby.sim.query(|sim| sim.options().sanity_drain_rate.unwrap());
+        let before = drain().await.unwrap();
+
+        std::fs::write(&path, "[sim]\nsanity_drain_rate = 0.3\n").unwrap();
+        state.reload_config().await.unwrap();
+        // the difficulty still scales it
+        let after = drain().await.unwrap();
+        assert!((after - before * 3.0).abs() < 1e-9, "{} then {}", before, after);
+
+        // a bad file leaves everything as it was
+        std::fs::write(&path, "[sim]\nsanity_drain_rate = \"fast\"\n").unwrap();
+        let e = state.reload_config().await.unwrap_err();
+        assert!(e.contains("sanity_drain_rate"), "{}", e);
+        std::fs::write(&path, "[sim]\nsanity_drain_rate = 9.0\n").unwrap();
+        let e = state.reload_config().await.unwrap_err();
+        assert!(e.contains("sanity_drain_rate must be between"), "{}", e);
+        assert_eq!(drain().await.unwrap(), after);
+        let tunables = state.config_file.lock().unwrap().sim.clone().unwrap();
+        assert_eq!(tunables.sanity_drain_rate, Some(0.3));
+    }
+
+    fn addr(n: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 7000 + n))
+    }
+
+    // Every variant's name, from what serde says when it's sent one it
+    // doesn't know
+    fn variants<T: DeserializeOwned>() -> BTreeSet<String> {
+        let e = serde_json::from_value::<T>(json!({"NoSuchVariant": {}})).err().unwrap();
+        let e = e.to_string();
+        let (_, expected) = e.split_once("expected one of ").unwrap();
+        expected.split(", ").map(|v| v.trim_matches('`').to_owned()).collect()
+    }
+
+    // as the server writes it to a peer that asked for msgpack
+    fn msgpack<T: Serialize>(value: &T) -> Vec<u8> {
+        Encoding::Msgpack.message(value).into_data()
+    }
+
+    fn tag(value: &Value) -> String {
+        value.as_object().unwrap().keys().next().unwrap().clone()
+    }
+
+    // one of each, with every optional field set
+    fn messages() -> Vec<Value> {
+        vec![
+            json!({"Hello": {"protocol": 3, "encoding": "msgpack"}}),
+            json!({"JoinLobby": {"name": "ann", "protocol": 3, "code": "ABCD", "deltas": true}}),
+            json!({"CreateLobby": {}}),
+            json!({"ConnectAsAdmin": {"token": "secret", "code": "ABCD", "deltas": true}}),
+            json!({"ConnectAsSpectator": {"code": "ABCD", "deltas": false}}),
+            json!({"StartSim": {
+                "map": "school",
+                "seed": u64::MAX,
+                "difficulty": {"Custom": {"base": "Nightmare", "overrides": {
+                    "sanity_drain_rate": 0.25, "flicker_radius": 2
+                }}},
+                "force": true
+            }}),
+            json!({"LeaveLobby": {}}),
+            json!({"SetReady": {"ready": true}}),
+            json!({"SelectMap": {"map": "default"}}),
+            json!({"LocationUpdate": {"location": 3}}),
+            json!({"SetFlashlight": {"on": true}}),
+            json!({"SetLights": {"location": 2, "on": false}}),
+            json!({"SetBreaker": {"on": false}}),
+            json!({"SetHidden": {"hidden": true}}),
+            json!({"SetDoorState": {"from": 0, "to": 2, "open": false}}),
+            json!({"SetActiveEquipment": {"items": ["Flashlight", "EmfReader"]}}),
+            json!({"PlaceCamera": {"location": 4}}),
+            json!({"PlaceDots": {"location": 4}}),
+            json!({"PlaceBook": {"location": 4}}),
+            json!({"PlaceCrucifix": {"location": 4}}),
+            json!({"RemoveBook": {}}),
+            json!({"RemoveCamera": {"id": 70000}}),
+            json!({"PlaceSensor": {"location": 4, "kind": "Sound"}}),
+            json!({"RemoveSensor": {"id": 1}}),
+            json!({"Equip": {"item": "Thermometer"}}),
+            json!({"Drop": {}}),
+            json!({"SwapSlot": {"index": 2}}),
+            json!({"GetProfile": {}}),
+            json!({"GetMap": {}}),
+            json!({"RequestFullState": {}}),
+            json!({"FastForward": {"secs": 300}}),
+            json!({"PauseSim": {}}),
+            json!({"ResumeSim": {}}),
+            json!({"TeleportGhost": {"location": 6, "emf": true}}),
+            json!({"SetGhostRoom": {"location": 6}}),
+            json!({"SetGhostType": {"ghost": "Demon"}}),
+            json!({"Kick": {"name": "bob"}}),
+            json!({"ResetSim": {"clear_players": true}}),
+            json!({"SubscribeGhostDebug": {}}),
+            json!({"UnsubscribeGhostDebug": {}}),
+            json!({"GetEventLog": {}}),
+            json!({"GetSoundManifest": {}}),
+            json!({"ReadThermometer": {}}),
+            json!({"UseParabolic": {"direction_room": 13}}),
+            json!({"ReadEmf": {}}),
+            json!({"UseSpiritBox": {}}),
+            json!({"UseUVLight": {}}),
+            json!({"UseSmudge": {}}),
+            json!({"UseSanityPills": {}}),
+            json!({"SubmitGuess": {"ghost_type": "Spirit"}}),
+            json!({"ReloadConfig": {}}),
+        ]
+    }
+
+    #[test]
+    fn every_message_reads_the_same_in_either_encoding() {
+        let samples = messages();
+        let tags: BTreeSet<_> = samples.iter().map(tag).collect();
+        assert_eq!(tags, variants::<PhasmoMessage>());
+        for sample in samples {
+            // as the client wrote it, then as we'd write it back
+            let from_json: PhasmoMessage = serde_json::from_value(sample.clone()).unwrap();
+            let from_msgpack: PhasmoMessage = rmp_serde::from_slice(&msgpack(&sample)).unwrap();
+            let again: PhasmoMessage = rmp_serde::from_slice(&msgpack(&from_json)).unwrap();
+            let expected = serde_json::to_value(&from_json).unwrap();
+            assert_eq!(serde_json::to_value(&from_msgpack).unwrap(), expected);
+            assert_eq!(serde_json::to_value(&again).unwrap(), expected);
+        }
+    }
+
+    // Adding a variant breaks this until it's given a sample in updates()
+    fn kind(update: &GameUpdate) -> usize {
+        match update {
+            GameUpdate::Lobby { .. } => 0,
+            GameUpdate::Reply(_) => 1,
+            GameUpdate::EventLog { .. } => 2,
+            GameUpdate::Review { .. } => 3,
+            GameUpdate::Welcome { .. } => 4,
+            GameUpdate::Rejected { .. } => 5,
+            GameUpdate::LobbyCreated { .. } => 6,
+            GameUpdate::PlayerJoined { .. } => 7,
+            GameUpdate::PlayerLeft { .. } => 8,
+            GameUpdate::PlayerUpdated { .. } => 9,
+            GameUpdate::Sim { .. } => 10,
+            GameUpdate::SimDelta { .. } => 11,
+            GameUpdate::Results(_) => 12,
+            GameUpdate::Map { .. } => 13,
+            GameUpdate::Inventory { .. } => 14,
+            GameUpdate::Notification { .. } => 15,
+            GameUpdate::Profile { .. } => 16,
+            GameUpdate::GhostDebug { .. } => 17,
+            GameUpdate::Audio { .. } => 18,
+            GameUpdate::SoundManifest { .. } => 19,
+            GameUpdate::Reading { .. } => 20,
+        }
+    }
+    const KINDS: usize = 21;
+
+    // Whatever a seeded round sends along the way, with the rest made up
+    fn updates() -> Vec<GameUpdate> {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        let tunables = OptionOverrides {
+            setup_duration: Some(0.0),
+            ghost_interaction_rate: Some(30.0),
+            ..Default::default()
+        };
+        sim.set_tunables(tunables).unwrap();
+        sim.add_player(addr(0), "ann").unwrap();
+        sim.add_player(addr(1), "bob").unwrap();
+        // once the joins are out, or they'd carry the rest
+        let mut updates: Vec<_> = sim.take_lobby_events().into_iter().map(|(e, _)| e).collect();
+        sim.set_ready(addr(0), true).unwrap();
+        sim.leave_lobby(addr(1)).unwrap();
+        updates.extend(sim.take_lobby_events().into_iter().map(|(e, _)| e));
+        updates.push(sim.get_gameupdate());
+
+        sim.set_ghost_debug(true);
+        sim.start(None, None, Difficulty::Professional, true).unwrap();
+        sim.update_player_loc(addr(0), 0).unwrap();
+        sim.equip(addr(0), EquipmentKind::Thermometer).unwrap();
+        let reading = sim.read_thermometer(addr(0)).unwrap();
+        // close enough to hear it
+        sim.teleport_ghost(2, false).unwrap();
+        sim.advance(Duration::from_secs(60));
+        updates.push(sim.get_gameupdate());
+        updates.push(sim.get_gameupdate_for(Some("ann")));
+        updates.push(sim.get_map());
+        let inventory = sim.get_inventory(addr(0)).unwrap();
+        updates.push(GameUpdate::Inventory { inventory });
+        updates.push(GameUpdate::Reading { reading });
+        let decisions = sim.take_ghost_debug();
+        updates.push(GameUpdate::GhostDebug { decisions });
+        updates.extend(sim.take_audio_queue().into_iter().map(|(_, cue)| GameUpdate::Audio { cue }));
+        let events = sim.event_log();
+        updates.push(GameUpdate::Review {
+            state: ReviewState::default(),
+            event: events.last().unwrap().clone(),
+        });
+        updates.push(GameUpdate::EventLog { events });
+        sim.submit_guess(addr(0), GhostType::Spirit).unwrap();
+        updates.push(sim.get_gameupdate());
+
+        let profile: Profile = serde_json::from_value(json!({
+            "name": "ann", "games_played": 3, "games_survived": 1, "correct_guesses": 2,
+            "money": 150, "total_earned": 250, "map_plays": {"default": 3}
+        }))
+        .unwrap();
+        let notice = Notice::new(MessageKey::HuntStarted, json!({"room": "Foyer"}), "Run".into());
+        let mut changes = serde_json::Map::new();
+        changes.insert("hunting".to_owned(), json!(true));
+        changes.insert("lights".to_owned(), json!([true, false]));
+        updates.extend([
+            GameUpdate::Reply(ServerReply::Ack {
+                request: "JoinLobby".to_owned(),
+            }),
+            GameUpdate::Reply(ServerReply::Error {
+                request: "Kick".to_owned(),
+                reason: "No player named zed".to_owned(),
+            }),
+            GameUpdate::Welcome {
+                protocol: 3,
+                features: vec!["deltas".to_owned(), "msgpack".to_owned()],
+            },
+            GameUpdate::Rejected {
+                protocol: 1,
+                min_protocol: 2,
+                reason: "Too old".to_owned(),
+            },
+            GameUpdate::LobbyCreated {
+                code: "ABCD".to_owned(),
+            },
+            GameUpdate::SimDelta {
+                seq: 7,
+                full: false,
+                changes,
+            },
+            GameUpdate::Notification { notice },
+            GameUpdate::Profile {
+                profile: profile.view(),
+            },
+            GameUpdate::SoundManifest {
+                sounds: sounds::manifest(),
+            },
+        ]);
+        updates
+    }
+
+    #[test]
+    fn every_update_reads_the_same_in_either_encoding() {
+        let updates = updates();
+        let kinds: BTreeSet<_> = updates.iter().map(kind).collect();
+        assert_eq!(kinds, (0..KINDS).collect());
+        for update in &updates {
+            let expected = serde_json::to_value(update).unwrap();
+            let read: Value = rmp_serde::from_slice(&msgpack(update)).unwrap();
+            assert_eq!(read, expected, "{}", tag(&expected));
         }
     }
+
+    #[test]
+    fn bad_bytes_are_errors() {
+        let read = |bytes: &[u8]| rmp_serde::from_slice::<PhasmoMessage>(bytes);
+        assert!(read(&[0xc1]).is_err());
+        assert!(read(&[0x81, 0xa8]).is_err());
+        assert!(read(&msgpack(&json!({"NoSuchMessage": {}}))).is_err());
+        assert!(read(&msgpack(&json!({"SetReady": {"ready": "yes"}}))).is_err());
+    }
 }
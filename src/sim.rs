@@ -1,465 +1,6583 @@
 use crate::utils;
-use crate::{ghost::*, map::*, server::Handle};
+use crate::{
+    behavior::*,
+    equipment::*,
+    event_log::{EventLog, LoggedEvent, ReviewState, SimEvent},
+    ghost::*,
+    map::*,
+    messages::{MessageKey, Notice, NoticeEntry, ServerReply},
+    schedule::{Schedule, TriggerId},
+    profile::ProfileView,
+    rewards::{compute_rewards, Payouts, PlayerRoundStats, RoundRewards, RoundStats},
+    storage::{PlayerSummary, RoundSummary},
+    sounds::{SoundId, SoundInfo},
+};
 use log::info;
-use rand::Rng;
-use serde::Serialize;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::{sync::mpsc::Sender, time::Duration};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+};
+use tokio::time::Duration;
 
 #[derive(Serialize, Clone)]
 pub struct Player {
     pub name: String,
+    // what names are matched on, see name_key
+    #[serde(skip)]
+    key: String,
+    #[serde(serialize_with = "utils::serialize_addr")]
     pub addr: SocketAddr,
     pub last_loc: Option<RoomLabel>,
+    level: u32,
+    money: u32,
     sanity: f64,
+    alive: bool,
+    flashlight_on: bool,
+    active_equipment: Vec<EquipmentKind>,
+    #[serde(skip)]
+    inventory: Inventory,
+    // sim time of the last accepted location update
+    #[serde(skip)]
+    last_loc_time: Duration,
+    #[serde(skip)]
+    movement_violations: u32,
+    // too many impossible moves, worth an admin's attention
+    flagged: bool,
+    // gone mid round, the player stays until the round ends
+    connected: bool,
+    #[serde(skip)]
+    last_thermometer_read: Option<Duration>,
+    #[serde(skip)]
+    last_spirit_box: Option<Duration>,
+    #[serde(skip)]
+    last_parabolic: Option<Duration>,
+    // newest last, only kept as long as the reader's tier allows
+    #[serde(skip)]
+    emf_history: Vec<u32>,
+    // in a hiding spot, only admins watching the ghost get told
+    #[serde(skip)]
+    hidden: bool,
+    // said they're good to go, only matters in the lobby
+    #[serde(skip)]
+    ready: bool,
 }
 
 impl Player {
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    pub fn sanity(&self) -> f64 {
+        self.sanity
+    }
+
+    // Anything a ghost could sense, the flashlight included
+    fn electronics_on(&self) -> bool {
+        self.flashlight_on || !self.active_equipment.is_empty()
+    }
+
     fn drain_sanity(&mut self, amt: f64) {
         let new_amt = self.sanity - amt;
         self.sanity = if new_amt < 0.0 { 0.0 } else { new_amt };
     }
+
+    fn restore_sanity(&mut self, amt: f64) {
+        self.sanity = (self.sanity + amt).min(100.0);
+    }
+
+    // Back to how they joined, ready for another round
+    fn reset_for_round(&mut self) {
+        self.last_loc = None;
+        self.sanity = 100.0;
+        self.alive = true;
+        self.flashlight_on = false;
+        self.active_equipment.clear();
+        self.inventory = Inventory::new();
+        self.movement_violations = 0;
+        self.last_thermometer_read = None;
+        self.last_spirit_box = None;
+        self.last_parabolic = None;
+        self.emf_history.clear();
+        self.hidden = false;
+        self.ready = false;
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Loudness {
+    Loud,
+    Muffled,
+    Faint,
+}
+
+impl Loudness {
+    fn from_distance(distance: u32) -> Option<Self> {
+        match distance {
+            0 => Some(Loudness::Loud),
+            1 => Some(Loudness::Muffled),
+            2 => Some(Loudness::Faint),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct AudioCue {
+    kind: InteractionType,
+    sound_id: SoundId,
+    loudness: Loudness,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum GamePhase {
+    Lobby,
+    Running,
+    // the journal's in, results are up until the next round
+    Finished,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlayerResult {
+    name: String,
+    sanity: f64,
+    survived: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RoundResults {
+    guess: GhostType,
+    correct_guess: bool,
+    // the true ghost type, with what it showed and what it kept back
+    ghost: EvidenceReveal,
+    players: Vec<PlayerResult>,
+    // where everyone who died was left
+    bodies: Vec<Body>,
+    rewards: RoundRewards,
+}
+
+// A player as they go out in a state. Sanity is only filled in for the
+// player themselves and flagged for admins.
+#[derive(Serialize, Clone)]
+pub struct PlayerView {
+    name: String,
+    #[serde(serialize_with = "utils::serialize_addr")]
+    addr: SocketAddr,
+    last_loc: Option<RoomLabel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_loc_name: Option<String>,
+    level: u32,
+    money: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sanity: Option<f64>,
+    // what a thermometer would read where they are, for them and admins
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temp: Option<i32>,
+    alive: bool,
+    flashlight_on: bool,
+    // the ghost is hunting close by, for them and admins
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flashlight_flicker: Option<bool>,
+    active_equipment: Vec<EquipmentKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flagged: Option<bool>,
+    connected: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LobbyPlayer {
+    name: String,
+    level: u32,
+    money: u32,
+    ready: bool,
+}
+
+// Only the fields that changed are sent
+#[derive(Serialize, Clone, Default)]
+pub struct PlayerChanges {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    money: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ready: Option<bool>,
+}
+
+// Player list changes since the last lobby broadcast. Joins are resolved
+// when they're sent so they carry the latest profile.
+enum LobbyChange {
+    Joined(String),
+    Left(String),
+    Updated(String, PlayerChanges),
+}
+
+// A blast that reached EMF 5, which only ghosts with the EMF evidence can
+// do. Only counts as found if someone was in the room while it lasted.
+#[derive(Serialize, Clone)]
+pub struct EmfEvent {
+    pub time: Duration,
+    pub room: RoomLabel,
+    pub observed_by: Vec<String>,
+}
+
+// What an interaction left on the EMF readers, in the room the ghost was
+// in at the time. The rooms next to it read a level lower.
+#[derive(Serialize, Clone)]
+pub struct EmfBlast {
+    id: u64,
+    room: RoomLabel,
+    level: u32,
+    until: Duration,
+    // its entry in emf_events, if it's an EMF 5
+    #[serde(skip)]
+    event: Option<usize>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Body {
+    name: String,
+    room: RoomLabel,
+    #[serde(skip)]
+    found_by: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Camera {
+    id: u32,
+    room: RoomLabel,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SensorKind {
+    // goes off when something walks into its room
+    Motion,
+    // picks up sounds in its room and the ones next to it
+    Sound,
+}
+
+// For the truck's sensor board. Everyone sees where they are.
+#[derive(Serialize, Clone)]
+pub struct Sensor {
+    id: u32,
+    kind: SensorKind,
+    room: RoomLabel,
+    owner: String,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum TrippedBy {
+    Ghost,
+    Player,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SensorEvent {
+    sensor: u32,
+    kind: SensorKind,
+    room: RoomLabel,
+    by: TrippedBy,
+    time: Duration,
+}
+
+// Burns a charge each time it stops a hunt, then sits there doing nothing
+#[derive(Serialize, Clone, Copy)]
+pub struct Crucifix {
+    room: RoomLabel,
+    charges: u32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CameraFeed {
+    id: u32,
+    room: RoomLabel,
+    orbs_visible: bool,
+    dots_visible: bool,
+    light_on: bool,
+    ghost_motion: bool,
+    player_motion: bool,
 }
 
-#[derive(Serialize)]
+// Sim is the biggest by far, but it's also what goes out every tick, so
+// boxing it would only add an allocation to the common case
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Clone)]
 pub enum GameUpdate {
     Lobby {
-        players: Vec<String>,
+        players: Vec<LobbyPlayer>,
+        maps: Vec<String>,
+        selected_map: String,
+        map_hash: String,
+        rooms: Vec<RoomInfo>,
+        // what the next round plays with, boxed as it's much bigger than the
+        // other variants
+        options: Box<OptionOverrides>,
+        // so players know someone's watching
+        spectators: usize,
+    },
+    // how the sender's last message went
+    Reply(ServerReply),
+    // admins only, what's still kept of it
+    EventLog {
+        events: Vec<LoggedEvent>,
+    },
+    // never sent, replay builds these from a recording
+    Review {
+        state: ReviewState,
+        event: LoggedEvent,
+    },
+    // the answer to a Hello, with what the client can ask for
+    Welcome {
+        protocol: u32,
+        features: Vec<String>,
+    },
+    // right before the close, for a protocol we don't speak
+    Rejected {
+        protocol: u32,
+        min_protocol: u32,
+        reason: String,
     },
+    // the code to hand out to whoever else should join
+    LobbyCreated {
+        code: String,
+    },
+    // incremental lobby events, for clients that asked for them
+    PlayerJoined {
+        player: LobbyPlayer,
+    },
+    PlayerLeft {
+        name: String,
+    },
+    PlayerUpdated {
+        name: String,
+        changes: PlayerChanges,
+    },
+    // Players get their own copy with what they couldn't know left out,
+    // see get_gameupdate_for
     Sim {
-        players: Vec<Player>,
-        ghost_location: RoomLabel,
-        favorite_room: RoomLabel,
-        ghost_orbs_visible: bool,
-        ambient_temp: i32,
-        ghost_room_temp: i32,
-        emf_level: u32,
-        notifications: Vec<String>,
+        players: Vec<PlayerView>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ghost_location: Option<RoomLabel>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ghost_location_name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        favorite_room: Option<RoomLabel>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        favorite_room_name: Option<String>,
+        cameras: Vec<CameraFeed>,
+        sensors: Vec<Sensor>,
+        // the latest firings and what set them off, only for admins
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sensor_events: Option<Vec<SensorEvent>>,
+        dropped_items: Vec<(RoomLabel, EquipmentKind)>,
+        room_objects: Vec<u32>,
+        hiding_spots: Vec<u8>,
+        bodies: Vec<Body>,
+        sanity_pills_used: u32,
+        // seconds left to set up before the ghost gets going, none after
+        setup_remaining: Option<f64>,
+        // none while interference scrambles the equipment. Players only get
+        // the ghost room's temperature while they're standing in it.
+        ambient_temp: Option<i32>,
+        ghost_room_temp: Option<i32>,
+        // every room by label, only for admins
+        #[serde(skip_serializing_if = "Option::is_none")]
+        room_temps: Option<Vec<i32>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        room_sounds: Option<Vec<u32>>,
+        // by label, on only while the breaker is too
+        lights: Vec<bool>,
+        breaker_on: bool,
+        breaker_room: RoomLabel,
+        emf_level: Option<u32>,
+        // every blast still going, only for admins
+        #[serde(skip_serializing_if = "Option::is_none")]
+        emf_blasts: Option<Vec<EmfBlast>>,
+        interference: Interference,
+        // the latest few, the broadcaster trims them to what each peer
+        // hasn't had
+        notifications: Vec<NoticeEntry>,
+        // the newest notice there's been, even if it's no longer carried
+        #[serde(skip)]
+        last_notice: u64,
+        book_location: Option<RoomLabel>,
         ghost_writing_visible: bool,
+        dots_location: Option<RoomLabel>,
+        // the ghost is walking through the projector's dots
+        dots_visible: bool,
+        // the ghost is showing itself in its current room
+        ghost_manifested: bool,
+        hunting: bool,
+        // an admin's holding the round, clients show it
+        paused: bool,
+        map_id: String,
+        map_hash: String,
+        difficulty: Difficulty,
+    },
+    // Sim for clients that asked for deltas, with only the fields that
+    // changed since the last one. seq goes up by one each time, a client
+    // that sees a gap sends RequestFullState. A full one replaces whatever
+    // the client had. Notifications are only there when there are new ones.
+    SimDelta {
+        seq: u64,
+        full: bool,
+        changes: serde_json::Map<String, serde_json::Value>,
+    },
+    // boxed as it's much bigger than the other variants
+    Results(Box<RoundResults>),
+    Map {
+        map_id: String,
+        map_hash: String,
+        map: Map,
+    },
+    Inventory {
+        inventory: Inventory,
+    },
+    Notification {
+        #[serde(flatten)]
+        notice: Notice,
     },
+    Profile {
+        profile: ProfileView,
+    },
+    GhostDebug {
+        decisions: Vec<GhostDecision>,
+    },
+    Audio {
+        cue: AudioCue,
+    },
+    SoundManifest {
+        sounds: Vec<SoundInfo>,
+    },
+    // only to the player holding the sensor
+    Reading {
+        reading: SensorReading,
+    },
+}
+
+// How badly a hunt is messing with electronics
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Interference {
+    None,
+    // the warning before a hunt
+    Flicker,
+    Full,
+}
+
+// What a sensor showed, at the team's tier for it so clients can draw the
+// right readout
+#[derive(Serialize, Clone)]
+pub enum SensorReading {
+    Thermometer {
+        tier: Tier,
+        temp: i32,
+    },
+    Emf {
+        tier: Tier,
+        active: bool,
+        // none below the tier that shows exact levels
+        level: Option<u32>,
+        history: Vec<u32>,
+    },
+    // whether there are prints in the player's room
+    Ultraviolet {
+        tier: Tier,
+        fingerprints: bool,
+    },
+    // none is static
+    SpiritBox {
+        tier: Tier,
+        response: Option<SpiritBoxPhrase>,
+    },
+    // how loud the room it's pointed at is, 0 to 100
+    Parabolic {
+        tier: Tier,
+        room: RoomLabel,
+        level: u32,
+    },
+}
+
+// What the ghost can say, clients turn it into words
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum SpiritBoxPhrase {
+    Here,
+    Close,
+    Behind,
+    Away,
+    Kill,
+    Death,
+    Young,
+    Old,
+}
+
+impl SpiritBoxPhrase {
+    pub const ALL: [SpiritBoxPhrase; 8] = [
+        SpiritBoxPhrase::Here,
+        SpiritBoxPhrase::Close,
+        SpiritBoxPhrase::Behind,
+        SpiritBoxPhrase::Away,
+        SpiritBoxPhrase::Kill,
+        SpiritBoxPhrase::Death,
+        SpiritBoxPhrase::Young,
+        SpiritBoxPhrase::Old,
+    ];
 }
 
 #[derive(Clone)]
 pub enum EventTrigger {
     RemoveGhostOrbs,
     UpdateThermometer,
-    EndEMF,
+    EndEMF(u64),
+    // the warning's over, the hunt proper begins
+    StartHunt,
+    ClearFingerprints(RoomLabel),
+    // the ghost stops holding back
+    EndSetup,
+    HideDots,
     EndHunt,
+    GhostInteraction,
+    EndManifestation,
+    // nobody came back in time
+    Abandon,
 }
 
+const ADVANCE_STEP: Duration = Duration::from_millis(100);
+// Notices kept for states to carry. A peer further behind than this only
+// gets the newest.
+const NOTICE_BACKLOG: usize = 64;
+
+// how far the interaction rate has to drift, as a fraction, before the
+// pending interaction is drawn again
+const INTERACTION_RESAMPLE_CHANGE: f64 = 0.25;
+
+// only ghosts with the EMF evidence can push the reader this high
+const EMF_EVIDENCE_LEVEL: u32 = 5;
+
 pub struct Simulation {
     pub players: Vec<Player>,
-    pub started: bool,
-    event_triggers: Vec<(Duration, EventTrigger)>,
+    // watching without playing, the server keeps track of who
+    spectators: usize,
+    phase: GamePhase,
+    // the last round's outcome, shown until the next one starts
+    results: Option<RoundResults>,
+    event_triggers: Schedule<EventTrigger>,
     ghost: Ghost,
+    behavior: Box<dyn GhostBehavior>,
+    catalog: MapCatalog,
+    map_id: String,
     map: Map,
+    map_hash: String,
     cur_time: Duration,
     flags: SimFlags,
     options: SimOptions,
-    notify_queue: Vec<String>,
+    // what the current or last round was started with
+    difficulty: Difficulty,
+    // the server's own tweaks from its config, under every difficulty
+    tunables: OptionOverrides,
+    // set by an admin, otherwise each round's ghost is random
+    preset_ghost: Option<GhostType>,
+    // the newest NOTICE_BACKLOG notices, kept after they're sent
+    notices: VecDeque<NoticeEntry>,
+    last_notice: u64,
+    // per-player audio, sent alongside the next broadcast
+    audio_queue: Vec<(SocketAddr, AudioCue)>,
+    // set when the next state shouldn't wait for the broadcast rate
+    urgent: bool,
+    lobby_changes: Vec<LobbyChange>,
+    ghost_debug: bool,
+    debug_queue: Vec<GhostDecision>,
+    // players' states leave out what they couldn't know, off for debugging
+    redact: bool,
+    // held by an admin, no round time passes
+    paused: bool,
+    // the only source of randomness, see with_seed
+    seed: u64,
+    rng: StdRng,
+    // kept across rounds, RoundStarted marks where each one begins
+    events: EventLog,
+}
+
+// Who a state is put together for
+#[derive(Clone, Copy)]
+enum Viewer<'a> {
+    Everything,
+    Player(&'a Player),
+    // in the server but not in the round
+    Nobody,
+}
+
+impl Viewer<'_> {
+    fn sees(&self, room: RoomLabel) -> bool {
+        match self {
+            Viewer::Everything => true,
+            Viewer::Player(player) => player.last_loc == Some(room),
+            Viewer::Nobody => false,
+        }
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new(MapCatalog::builtin())
+    }
 }
 
 impl Simulation {
-    pub fn new() -> Self {
-        let mut event_triggers = Vec::new();
-        event_triggers.push((Duration::from_secs(0), EventTrigger::UpdateThermometer));
+    pub fn new(catalog: MapCatalog) -> Self {
+        Self::with_seed(catalog, rand::thread_rng().gen())
+    }
+
+    // Everything random in the sim comes from the seed, so the same seed
+    // and the same messages at the same round times play out the same
+    pub fn with_seed(catalog: MapCatalog, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut event_triggers = Schedule::new();
+        event_triggers.push(Duration::from_secs(0), EventTrigger::UpdateThermometer);
+        let options = SimOptions::new();
+        let mut flags = SimFlags::new(&mut rng);
+        flags.equipment_pool = options.starting_equipment.clone();
+
+        let map_id = if catalog.get(DEFAULT_MAP).is_some() {
+            DEFAULT_MAP.to_owned()
+        } else {
+            catalog.ids().first().cloned().expect("Map catalog is empty")
+        };
+        let map = catalog.get(&map_id).unwrap().clone();
+        let map_hash = map.hash();
+
         Simulation {
             players: Vec::new(),
-            started: false,
+            spectators: 0,
+            phase: GamePhase::Lobby,
+            results: None,
             event_triggers,
-            ghost: Ghost::new(),
-            map: Map::new(),
+            ghost: Ghost::new(&map, &options.room_category_weights, &mut rng),
+            behavior: behavior_for(flags.ghost_type),
+            catalog,
+            map_id,
+            map,
+            map_hash,
             cur_time: Duration::from_secs(0),
-            flags: SimFlags::new(),
-            options: SimOptions::new(),
-            notify_queue: Vec::new(),
+            flags,
+            options,
+            difficulty: Difficulty::default(),
+            tunables: OptionOverrides::default(),
+            preset_ghost: None,
+            notices: VecDeque::new(),
+            last_notice: 0,
+            audio_queue: Vec::new(),
+            urgent: false,
+            lobby_changes: Vec::new(),
+            ghost_debug: false,
+            debug_queue: Vec::new(),
+            redact: true,
+            paused: false,
+            seed,
+            rng,
+            events: EventLog::new(),
+        }
+    }
+
+    // For bug reports, it takes the same messages to play out the same
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn event_log(&self) -> Vec<LoggedEvent> {
+        self.events.entries()
+    }
+
+    // Up to the newest id and what came after `id`, for a recording
+    pub(crate) fn events_since(&self, id: u64) -> (u64, Vec<LoggedEvent>) {
+        (self.events.last_id(), self.events.since(id).cloned().collect())
+    }
+
+    fn log(&mut self, event: SimEvent) {
+        self.events.push(self.cur_time, event);
+    }
+
+    // Logged once per threshold on the way down, again if pills bring
+    // someone back over it
+    fn log_sanity_marks(&mut self) {
+        for player in &self.players {
+            let band = SANITY_MARKS.iter().filter(|m| player.sanity < **m as f64).count();
+            let last = self.flags.sanity_bands.insert(player.key.clone(), band).unwrap_or(0);
+            for threshold in SANITY_MARKS.get(last..band).unwrap_or_default() {
+                let name = player.name.clone();
+                let event = SimEvent::SanityBelow { name, threshold: *threshold };
+                self.events.push(self.cur_time, event);
+            }
         }
     }
 
     pub fn add_player(&mut self, addr: SocketAddr, name: &str) -> Result<(), String> {
+        let key = name_key(name);
+        if self.phase != GamePhase::Lobby {
+            // picking their player back up mid round, or to see the results
+            let dropped = self.players.iter_mut().find(|p| p.key == key && !p.connected);
+            if let Some(player) = dropped {
+                info!("Player {} reconnected", player.name);
+                player.addr = addr;
+                player.connected = true;
+                let name = player.name.clone();
+                self.log(SimEvent::PlayerJoined { name });
+                self.cancel_abandon();
+                return Ok(());
+            }
+        }
         let players = &mut self.players;
         if players.iter().any(|p| p.addr == addr) {
             Err("Already connected".to_owned())
-        } else if players.iter().any(|p| p.name == name) {
-            Err("Name taken".to_owned())
+        } else if key.is_empty() {
+            Err("Name is empty".to_owned())
+        } else if let Some(taken) = players.iter().find(|p| p.key == key) {
+            Err(format!("Name taken by {}", taken.name))
         } else {
             info!("Adding player {} to lobby", name);
             let player = Player {
                 name: name.to_owned(),
+                key,
                 addr,
                 last_loc: None,
+                level: 1,
+                money: 0,
                 sanity: 100.0,
+                alive: true,
+                flashlight_on: false,
+                active_equipment: Vec::new(),
+                inventory: Inventory::new(),
+                last_loc_time: Duration::from_secs(0),
+                movement_violations: 0,
+                flagged: false,
+                connected: true,
+                last_thermometer_read: None,
+                last_spirit_box: None,
+                last_parabolic: None,
+                emf_history: Vec::new(),
+                hidden: false,
+                ready: false,
             };
             players.push(player);
+            self.log(SimEvent::PlayerJoined { name: name.to_owned() });
+            if self.is_started() {
+                self.cancel_abandon();
+            } else {
+                self.lobby_changes.push(LobbyChange::Joined(name.to_owned()));
+            }
             Ok(())
         }
     }
 
-    pub fn update_player_loc(&mut self, name: &str, location: RoomLabel) {
-        let mut player = self.players.iter_mut().find(|p| p.name == name);
-        if let Some(player) = player.as_mut() {
-            player.last_loc = Some(location);
+    // Players only leave while in the lobby, mid round they stay in the
+    // game so their body and journal still count
+    pub fn remove_player(&mut self, addr: SocketAddr) -> Option<String> {
+        if self.is_started() {
+            let player = self
+                .players
+                .iter_mut()
+                .find(|p| p.addr == addr && p.connected)?;
+            info!("Player {} disconnected", player.name);
+            player.connected = false;
+            let name = player.name.clone();
+            self.log(SimEvent::PlayerLeft { name: name.clone() });
+            // admins watching don't keep a game alive
+            if !self.players.iter().any(|p| p.connected) {
+                let grace = self.options.abandon_grace;
+                println!("Everyone left, back to the lobby in {}s", grace.as_secs());
+                self.event_triggers
+                    .push(self.cur_time + grace, EventTrigger::Abandon);
+            }
+            return Some(name);
         }
+        let ix = self.players.iter().position(|p| p.addr == addr)?;
+        let player = self.players.remove(ix);
+        info!("Removing player {} from lobby", player.name);
+        self.log(SimEvent::PlayerLeft { name: player.name.clone() });
+        self.lobby_changes
+            .push(LobbyChange::Left(player.name.clone()));
+        Some(player.name)
     }
 
-    pub fn start(&mut self) {
-        self.started = true;
-    }
-
-    // REAL TIME UPDATES
-    pub fn update(&mut self, dt: Duration) -> bool {
-        self.cur_time += dt;
-        // if self.flags.is_hunting {
-        //     self.check_triggers();
-        // }
-
-        // Drain everyone's sanity
-        let millis: u32 = dt.as_millis().try_into().unwrap();
-        let millis_f: f64 = millis.try_into().unwrap();
-        let seconds = millis_f / 1000.0;
-        let sanity_drain = self.options.sanity_drain_rate * seconds;
-
-        for player in self.players.iter_mut() {
-            player.drain_sanity(sanity_drain);
+    // Returns notifications meant only for the player that moved. The dead
+    // stay where they fell.
+    pub fn update_player_loc(
+        &mut self,
+        addr: SocketAddr,
+        location: RoomLabel,
+    ) -> Result<Vec<Notice>, String> {
+        if !self.map.contains(location) {
+            return Err(format!("No room with label {}", location));
         }
 
-        let mut changed = false;
-        let move_elapse = self.cur_time - self.flags.last_ghost_move;
-        if move_elapse > self.options.ghost_move_interval {
-            self.flags.last_ghost_move = self.cur_time;
-            self.move_ghost();
-            changed = true;
+        let mut found = Vec::new();
+        let player = find_player_mut(&mut self.players, addr)?;
+        if !player.alive {
+            return Err(format!("{} is dead", player.name));
         }
+        // people still walk around during a pause, the round's clock doesn't
+        if self.phase == GamePhase::Running && !self.paused {
+            check_movement(player, location, &self.map, &self.options, self.cur_time)?;
+        }
+        // hiding spots don't come along
+        if player.hidden && player.last_loc != Some(location) {
+            player.hidden = false;
+        }
+        let entered = player.last_loc != Some(location);
+        player.last_loc = Some(location);
+        player.last_loc_time = self.cur_time;
+        let walked_into_hunt = self.flags.is_hunting && location == self.ghost.current_room;
 
-        let pulse_elapse = self.cur_time - self.flags.last_event_pulse;
-        if pulse_elapse > self.options.event_pulse_interval {
-            self.event_pulse(self.cur_time);
-            self.flags.last_event_pulse = self.cur_time;
-            changed = true;
+        let bodies = self.flags.bodies.iter_mut().filter(|b| b.room == location);
+        for body in bodies {
+            if !body.found_by.contains(&player.name) {
+                body.found_by.push(player.name.clone());
+                player.drain_sanity(self.options.body_discovery_sanity_loss);
+                let notice = Notice::new(
+                    MessageKey::BodyFound,
+                    json!({ "name": body.name }),
+                    format!("You found {}'s body", body.name),
+                );
+                found.push(notice.with_sound(SoundId::BodyFound));
+            }
         }
 
-        let changed = self.check_triggers() || changed;
-        return changed;
+        // walking in on a blast still counts
+        let blasts = self.flags.emf_blasts.iter().filter(|b| b.room == location);
+        for index in blasts.filter_map(|b| b.event) {
+            let event = &mut self.flags.emf_events[index];
+            if !event.observed_by.contains(&player.name) {
+                event.observed_by.push(player.name.clone());
+            }
+        }
+        if entered {
+            self.trip_sensors(SensorKind::Motion, location, TrippedBy::Player);
+        }
+        if walked_into_hunt {
+            self.catch_players();
+        }
+        Ok(found)
     }
-    
-    fn check_triggers(&mut self) -> bool {
-        let mut changed = false;
 
-        let (triggered, remaining): (Vec<_>, Vec<_>) = self
-            .event_triggers
-            .clone()
-            .into_iter()
-            .partition(|(time, _)| *time < self.cur_time);
+    // Hunts are the only thing that should be calling this
+    fn kill_player(&mut self, name: &str) {
+        let key = name_key(name);
+        let mut player = self.players.iter_mut().find(|p| p.key == key && p.alive);
+        if let Some(player) = player.as_mut() {
+            player.alive = false;
+            let died = SimEvent::PlayerDied {
+                name: player.name.clone(),
+                room: player.last_loc,
+            };
+            self.events.push(self.cur_time, died);
 
-        self.event_triggers = remaining;
-        for (_, trigger) in triggered {
-            changed = true;
-            match trigger {
-                EventTrigger::RemoveGhostOrbs => {
-                    println!("Orbs no longer visible");
-                    self.flags.orbs_visible = false;
-                }
-                EventTrigger::UpdateThermometer => {
-                    println!("Current time: {}", self.cur_time.as_secs());
+            if let Some(room) = player.last_loc {
+                self.flags.bodies.push(Body {
+                    name: player.name.clone(),
+                    room,
+                    found_by: Vec::new(),
+                });
+            }
+            let notice = Notice::new(
+                MessageKey::PlayerDeath,
+                json!({ "name": player.name }),
+                format!("{} has died", name),
+            );
+            self.notify(notice.with_sound(SoundId::PlayerDeath));
+            self.urgent = true;
+        }
+    }
 
-                    // only exists to force a periodic update
-                    let event_time = self.cur_time + self.options.thermometer_update_interval;
-                    self.event_triggers
-                        .push((event_time, EventTrigger::UpdateThermometer));
-                }
-                EventTrigger::EndEMF => self.flags.emf_level = 0,
-                EventTrigger::EndHunt => {
-                    self.flags.is_hunting = false;
-                },
+    pub fn set_player_profile(&mut self, name: &str, level: u32, money: u32) {
+        let key = name_key(name);
+        let mut player = self.players.iter_mut().find(|p| p.key == key);
+        if let Some(player) = player.as_mut() {
+            let changes = PlayerChanges {
+                level: Some(level).filter(|l| *l != player.level),
+                money: Some(money).filter(|m| *m != player.money),
+                ..Default::default()
+            };
+            player.level = level;
+            player.money = money;
+
+            let changed = changes.level.is_some() || changes.money.is_some();
+            // a join that hasn't gone out yet will carry the new values
+            let joining = self
+                .lobby_changes
+                .iter()
+                .any(|c| matches!(c, LobbyChange::Joined(n) if name_key(n) == key));
+            if changed && !joining && !self.is_started() {
+                self.lobby_changes
+                    .push(LobbyChange::Updated(name.to_owned(), changes));
             }
         }
-        return changed;
+    }
+
+    pub fn set_ready(&mut self, addr: SocketAddr, ready: bool) -> Result<(), String> {
+        if self.phase != GamePhase::Lobby {
+            return Err("Not in the lobby".to_owned());
+        }
+        let player = find_player_mut(&mut self.players, addr)?;
+        if player.ready == ready {
+            return Ok(());
+        }
+        player.ready = ready;
+        let changes = PlayerChanges {
+            ready: Some(ready),
+            ..Default::default()
+        };
+        let name = player.name.clone();
+        let joining = self
+            .lobby_changes
+            .iter()
+            .any(|c| matches!(c, LobbyChange::Joined(n) if *n == name));
+        if !joining {
+            self.lobby_changes.push(LobbyChange::Updated(name, changes));
+        }
+        Ok(())
+    }
 
+    // Admin only. Goes the same way as a dropped connection, so mid round
+    // they're only marked as gone and can still come back.
+    pub fn kick(&mut self, name: &str) -> Result<SocketAddr, String> {
+        let key = name_key(name);
+        let player = self
+            .players
+            .iter()
+            .find(|p| p.key == key)
+            .ok_or_else(|| format!("No player named {}", name))?;
+        if !player.connected {
+            return Err(format!("{} is already gone", player.name));
+        }
+        let addr = player.addr;
+        println!("Admin kicked {}", player.name);
+        self.remove_player(addr);
+        Ok(addr)
     }
 
-    fn move_ghost(&mut self) {
-        // chance to just stay in ghost room
-        // TODO parameterize tendency to stay in ghost room
-        let stay = self.ghost.current_room == self.ghost.ghost_room && utils::roll(0.5);
-        if !stay {
-            self.ghost.move_room(&self.map)
+    // Backing out before the round starts, the name's free again after
+    pub fn leave_lobby(&mut self, addr: SocketAddr) -> Result<String, String> {
+        if self.is_started() {
+            return Err("Game already started".to_owned());
         }
+        self.remove_player(addr)
+            .ok_or_else(|| "Not registered".to_owned())
+    }
 
-        if let Some(book_room) = self.flags.book_location {
-            if !self.flags.ghost_writing_visible
-                && self.ghost.current_room == book_room
-                && utils::roll(self.options.ghost_interaction_frequency)
-                {
-                    self.flags.ghost_writing_visible = true
+    // Lobby events since the last call, each with the peers that shouldn't
+    // get it. Those joined since, their snapshot has it already.
+    pub fn take_lobby_events(&mut self) -> Vec<(GameUpdate, Vec<SocketAddr>)> {
+        let changes = std::mem::take(&mut self.lobby_changes);
+        // joins and leaves between rounds show up in the next full state
+        if self.phase != GamePhase::Lobby {
+            return Vec::new();
+        }
+        let mut joiners = Vec::new();
+        let mut events = Vec::new();
+        for change in changes.into_iter().rev() {
+            let event = match change {
+                LobbyChange::Joined(name) => {
+                    let Some(player) = self.players.iter().find(|p| p.name == name) else {
+                        continue;
+                    };
+                    joiners.push(player.addr);
+                    GameUpdate::PlayerJoined {
+                        player: lobby_player(player),
+                    }
                 }
+                LobbyChange::Left(name) => GameUpdate::PlayerLeft { name },
+                LobbyChange::Updated(name, changes) => GameUpdate::PlayerUpdated { name, changes },
+            };
+            events.push((event, joiners.clone()));
         }
+        events.reverse();
+        events
     }
 
-    fn event_pulse(&mut self, cur_time: Duration) {
-        println!("Event pulse");
-
-        // Chance for hunt
-        // let hunt_chance = self.options.ghost_hunt_frequency + self.average_sanity_drain();
-        // if utils::roll(hunt_chance) {
-        //     self.flags.is_hunting = true;
-        // 
-        //     let time = self.cur_time + self.options.ghost_hunt_duration;
-        //     self.event_triggers.push((time, EventTrigger::EndHunt));
-        //     // if hunt occurs, no other events need to occur
-        //     return;
-        // }
-        
+    pub fn set_redact(&mut self, on: bool) {
+        self.redact = on;
+    }
 
-        // Chance for orbs
-        if !self.flags.orbs_visible {
-            if utils::roll(self.options.ghost_orbs_frequency) {
-                println!("Orbs now visible");
-                self.flags.orbs_visible = true;
+    // Whether players need states of their own right now: during a round,
+    // and after it while the results are up
+    pub fn redacts(&self) -> bool {
+        self.redact && self.phase != GamePhase::Lobby
+    }
 
-                let trigger_time = cur_time + self.options.ghost_orbs_duration;
-                self.event_triggers
-                    .push((trigger_time, EventTrigger::RemoveGhostOrbs));
-            }
+    // Admin only. The clock keeps ticking but nothing in the round moves,
+    // scheduled events are on round time and keep their place.
+    pub fn set_paused(&mut self, paused: bool) -> Result<(), String> {
+        if !self.is_started() {
+            return Err("Sim not started".to_owned());
         }
+        if self.paused == paused {
+            let state = if paused { "paused" } else { "running" };
+            return Err(format!("Sim already {}", state));
+        }
+        self.paused = paused;
+        println!("Admin {} the sim", if paused { "paused" } else { "resumed" });
+        Ok(())
+    }
 
-        // Chance for ghost interaction
-        // Todo: multiplier
-        let interaction_chance =
-            self.options.ghost_interaction_frequency + (self.average_sanity_drain());
-        let interaction_chance = 1.0;
-        if true {
-            println!("Interaction");
-            // && book is in ghost current room
-            let interaction = InteractionType::generate_interaction();
-
-            // drain player's sanity
-            for player in self.players.iter_mut() {
-                // TODO parameterize
-                let sanity_loss = if player.last_loc == Some(self.ghost.current_room) {
-                    15.0
-                } else {
-                    5.0
-                };
-                player.drain_sanity(sanity_loss);
-            }
-
-            let min_emf = 2;
-            let max_emf = if self.ghost.has_evidence_type(EvidenceType::Emf) {
-                5
-            } else {
-                3
-            };
-            self.blast_emf(min_emf, max_emf);
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
 
-            let room = self.ghost.current_room;
-            let msg = interaction.interaction_msg();
-            self.notify(&msg);
-        }
+    pub fn set_spectators(&mut self, count: usize) {
+        self.spectators = count;
     }
 
-    pub fn get_gameupdate(&self) -> GameUpdate {
-        let player_names = self.players.iter().map(|p| p.name.clone()).collect();
+    pub fn player_name(&self, addr: SocketAddr) -> Option<String> {
+        self.players
+            .iter()
+            .find(|p| p.addr == addr)
+            .map(|p| p.name.clone())
+    }
 
-        if !self.started {
-            GameUpdate::Lobby {
-                players: player_names,
+    // Into or out of a hiding spot in the player's room, if one's free
+    pub fn set_hidden(&mut self, addr: SocketAddr, hidden: bool) -> Result<(), String> {
+        if !self.is_started() {
+            return Err("Sim not started".to_owned());
+        }
+        let player = find_player_mut(&mut self.players, addr)?;
+        if !player.alive {
+            return Err(format!("{} is dead", player.name));
+        }
+        let room = player.last_loc;
+        if hidden && !player.hidden {
+            let room = room.ok_or_else(|| format!("{} hasn't said where they are", player.name))?;
+            let taken = self
+                .players
+                .iter()
+                .filter(|p| p.hidden && p.last_loc == Some(room))
+                .count();
+            if taken >= self.hiding_capacity(room).into() {
+                return Err(format!("Nowhere left to hide in {}", self.map.room_name(room)));
             }
-        } else {
-            let mut rng = rand::thread_rng();
-
-            let v = self.options.temperature_variability;
-            let amb_noise = Rng::gen_range(&mut rng, -v..v);
-            let gr_noise = Rng::gen_range(&mut rng, -v..v);
+        }
 
-            // TODO magic number
-            let ambient_temp = std::cmp::max(self.flags.ambient_temp + amb_noise, 40);
+        let player = find_player_mut(&mut self.players, addr)?;
+        player.hidden = hidden;
+        let name = player.name.clone();
+        self.debug(GhostDecision::Hiding { name, room, hidden });
+        Ok(())
+    }
 
-            let ghost_room_temp = self.ghost_room_temp() + gr_noise;
-            let ghost_room_temp = if self.ghost.has_evidence_type(EvidenceType::Freezing) {
-                ghost_room_temp
-            } else {
-                std::cmp::max(ghost_room_temp, self.flags.ghost_room_min_temp)
-            };
+    pub fn set_flashlight(&mut self, addr: SocketAddr, on: bool) {
+        let mut player = self.players.iter_mut().find(|p| p.addr == addr);
+        if let Some(player) = player.as_mut() {
+            player.flashlight_on = on;
+        }
+    }
 
-            GameUpdate::Sim {
-                players: self.players.clone(),
-                ghost_location: self.ghost.current_room,
-                favorite_room: self.ghost.ghost_room,
-                ghost_orbs_visible: self.flags.orbs_visible,
+    pub fn set_active_equipment(
+        &mut self,
+        addr: SocketAddr,
+        items: Vec<EquipmentKind>,
+    ) -> Result<(), String> {
+        if items.len() > MAX_ACTIVE_EQUIPMENT {
+            return Err("Too many active items".to_owned());
+        }
+        if items.iter().any(|item| !item.is_electronic()) {
+            return Err("Only electronics can be switched on".to_owned());
+        }
 
+        let player = find_player_mut(&mut self.players, addr)?;
+        // the flashlight is always on hand, everything else has to be carried
+        let carried = |item: &EquipmentKind| {
+            *item == EquipmentKind::Flashlight || player.inventory.contains(*item)
+        };
+        if !items.iter().all(carried) {
+            return Err("Item not in inventory".to_owned());
+        }
+        let outside = player.last_loc.is_some_and(|loc| self.map.is_exterior(loc));
+        if outside && items.iter().any(|item| *item != EquipmentKind::Flashlight) {
+            return Err("Only the flashlight works outside".to_owned());
+        }
 
-                emf_level: self.flags.emf_level,
-                ghost_room_temp,
-                ambient_temp,
-                notifications: self.notify_queue.clone(),
-                ghost_writing_visible: self.flags.ghost_writing_visible
+        let mut loadout = Vec::new();
+        for item in items {
+            if !loadout.contains(&item) {
+                loadout.push(item);
             }
         }
+
+        player.active_equipment = loadout;
+        Ok(())
     }
 
-    fn average_sanity_drain(&self) -> f64 {
-        if self.players.is_empty() {
-            return 0.0;
-        }
+    // Picks the item up off the floor if it's lying in the player's room,
+    // otherwise takes it from the team's equipment pool
+    pub fn equip(&mut self, addr: SocketAddr, item: EquipmentKind) -> Result<(), String> {
+        let player = find_player_mut(&mut self.players, addr)?;
+        let room = player.last_loc;
 
-        let players: u32 = self.players.len().try_into().unwrap();
-        let players: f64 = players.into();
+        let dropped = &mut self.flags.dropped_items;
+        let pool = &mut self.flags.equipment_pool;
+        if let Some(ix) = dropped.iter().position(|d| Some(d.0) == room && d.1 == item) {
+            player.inventory.add(item)?;
+            dropped.remove(ix);
+        } else if let Some(ix) = pool.iter().position(|i| *i == item) {
+            player.inventory.add(item)?;
+            pool.remove(ix);
+        } else {
+            return Err("Item not available".to_owned());
+        }
+        Ok(())
+    }
 
-        let total: f64 = self.players.clone().into_iter().map(|p| p.sanity).sum();
+    pub fn drop_item(&mut self, addr: SocketAddr) -> Result<(), String> {
+        let player = find_player_mut(&mut self.players, addr)?;
+        let room = player.last_loc.ok_or("Location unknown")?;
+        let item = player.inventory.take_held().ok_or("Not holding anything")?;
 
-        return total / players;
+        player.active_equipment.retain(|i| *i != item);
+        self.flags.dropped_items.push((room, item));
+        Ok(())
     }
 
-    fn ghost_room_temp(&self) -> i32 {
-        let mins = self.cur_time.as_secs() / 5;
-        let gr_temp =
-            self.flags.ambient_temp + (self.flags.delta_temp * mins.try_into().unwrap_or(0));
-        std::cmp::max(gr_temp, self.flags.ghost_room_min_temp)
+    pub fn swap_slot(&mut self, addr: SocketAddr, index: usize) -> Result<(), String> {
+        find_player_mut(&mut self.players, addr)?
+            .inventory
+            .swap_to(index)
     }
 
-    fn blast_emf(&mut self, min_amount: u32, max_amount: u32) {
-        if self.flags.emf_level != 0 {
+    // Whatever the ghost touched might keep its prints for a while
+    fn leave_fingerprints(&mut self) {
+        if !self.shows_evidence(EvidenceType::Ultraviolet) {
+            return;
+        }
+        let chance = self.options.fingerprint_chance;
+        let left = utils::roll(chance, &mut self.rng);
+        self.debug(GhostDecision::Roll {
+            kind: "fingerprints".to_owned(),
+            chance,
+            hit: left,
+        });
+        if !left {
             return;
         }
 
-        let mut rng = rand::thread_rng();
-        self.flags.emf_level = Rng::gen_range(&mut rng, min_amount..=max_amount);
-
-        let event_time = self.cur_time + self.options.emf_blast_duration;
-        self.event_triggers.push((event_time, EventTrigger::EndEMF));
+        let room = self.ghost.current_room;
+        // fresh prints over old ones start the clock again
+        self.event_triggers
+            .cancel_where(|t| matches!(t, EventTrigger::ClearFingerprints(r) if *r == room));
+        if !self.flags.fingerprints.contains(&room) {
+            self.flags.fingerprints.push(room);
+        }
+        let time = self.cur_time + self.options.fingerprint_duration;
+        self.event_triggers
+            .push(time, EventTrigger::ClearFingerprints(room));
+        self.debug(GhostDecision::Fingerprints { room });
     }
 
-    fn notify(&mut self, msg: &str) {
-        self.notify_queue.push(msg.to_owned());
+    pub fn use_uv_light(&mut self, addr: SocketAddr) -> Result<SensorReading, String> {
+        let tier = self.options.tier(EquipmentKind::UvLight);
+        let room = self.sensor_room(addr, EquipmentKind::UvLight)?;
+        self.check_interference()?;
+        let fingerprints = self.flags.fingerprints.contains(&room);
+        Ok(SensorReading::Ultraviolet { tier, fingerprints })
     }
 
-    pub fn clear_notify_queue(&mut self) {
-        self.notify_queue.clear();
+    // Asks the ghost a question. It answers if it's close enough, has the
+    // evidence, and feels like it; shy ones only talk to someone alone.
+    pub fn use_spirit_box(&mut self, addr: SocketAddr) -> Result<SensorReading, String> {
+        let tier = self.options.tier(EquipmentKind::SpiritBox);
+        let mods = tier.modifiers();
+        let room = self.sensor_room(addr, EquipmentKind::SpiritBox)?;
+        self.check_interference()?;
+
+        let cur_time = self.cur_time;
+        let cooldown = self.options.spirit_box_cooldown;
+        let player = self.players.iter_mut().find(|p| p.addr == addr).unwrap();
+        let ready = player
+            .last_spirit_box
+            .is_none_or(|last| cur_time - last >= cooldown);
+        if !ready {
+            return Err("Spirit box needs a moment between questions".to_owned());
+        }
+        player.last_spirit_box = Some(cur_time);
+
+        let in_range = self
+            .map
+            .distance(room, self.ghost.current_room)
+            .is_some_and(|d| d <= mods.spirit_box_radius);
+        let company = self
+            .players
+            .iter()
+            .filter(|p| p.alive && p.addr != addr && p.last_loc == Some(room))
+            .count();
+        let willing = !self.behavior.spirit_box_alone() || company == 0;
+        let chance = self.options.spirit_box_response_chance;
+        let responds = in_range
+            && willing
+            && self.shows_evidence(EvidenceType::SpiritBox)
+            && utils::roll(chance, &mut self.rng);
+        self.debug(GhostDecision::Roll {
+            kind: "spirit box".to_owned(),
+            chance,
+            hit: responds,
+        });
+
+        let response = if responds {
+            utils::rng_select(&SpiritBoxPhrase::ALL, &mut self.rng).copied()
+        } else {
+            None
+        };
+        Ok(SensorReading::SpiritBox { tier, response })
     }
-}
 
-pub struct SimFlags {
-    last_event_pulse: Duration,
-    last_ghost_move: Duration,
+    // Pointed at the player's own room or one next to it. The rooms past
+    // that come through fainter.
+    pub fn use_parabolic(
+        &mut self,
+        addr: SocketAddr,
+        target: RoomLabel,
+    ) -> Result<SensorReading, String> {
+        let tier = self.options.tier(EquipmentKind::ParabolicMicrophone);
+        let room = self.sensor_room(addr, EquipmentKind::ParabolicMicrophone)?;
+        let player = self.players.iter().find(|p| p.addr == addr).unwrap();
+        if !player.alive {
+            return Err(format!("{} is dead", player.name));
+        }
+        if target != room && self.map.connection(room, target).is_none() {
+            return Err(format!("Can't point it at {} from here", self.map.room_name(target)));
+        }
+        self.check_interference()?;
 
-    emf_level: u32,
-    ghost_type: GhostType,
+        let cur_time = self.cur_time;
+        let cooldown = self.options.parabolic_cooldown;
+        let player = self.players.iter_mut().find(|p| p.addr == addr).unwrap();
+        let ready = player.last_parabolic.is_none_or(|last| cur_time - last >= cooldown);
+        if !ready {
+            return Err("Parabolic microphone is still listening".to_owned());
+        }
+        player.last_parabolic = Some(cur_time);
 
-    // Temp
-    ghost_room_min_temp: i32,
-    delta_temp: i32,
-    ambient_temp: i32,
+        let beyond = self
+            .map
+            .neighbours(target)
+            .into_iter()
+            .map(|n| self.room_sound(n) * PARABOLIC_FALLOFF)
+            .fold(0.0, f64::max);
+        let level = self.room_sound(target).max(beyond).round() as u32;
+        Ok(SensorReading::Parabolic {
+            tier,
+            room: target,
+            level,
+        })
+    }
 
-    // Ghost orbs
-    orbs_visible: bool,
-    book_location: Option<RoomLabel>,
-    ghost_writing_visible: bool,
+    fn check_interference(&self) -> Result<(), String> {
+        match self.interference() {
+            Interference::None => Ok(()),
+            _ => Err("Too much interference to get a reading".to_owned()),
+        }
+    }
 
-    is_hunting: bool,
-}
+    pub fn read_thermometer(&mut self, addr: SocketAddr) -> Result<SensorReading, String> {
+        let tier = self.options.tier(EquipmentKind::Thermometer);
+        let mods = tier.modifiers();
+        let room = self.sensor_room(addr, EquipmentKind::Thermometer)?;
+        self.check_interference()?;
+        let actual = self.room_temp(room);
 
-impl SimFlags {
-    fn new() -> Self {
-        let mut rng = rand::thread_rng();
+        let cur_time = self.cur_time;
+        let player = self.players.iter_mut().find(|p| p.addr == addr).unwrap();
+        let ready = player
+            .last_thermometer_read
+            .is_none_or(|last| cur_time - last >= mods.thermometer_cooldown);
+        if !ready {
+            return Err("Thermometer is still sampling".to_owned());
+        }
+        player.last_thermometer_read = Some(cur_time);
 
-        let ghost_type = GhostType::Spirit;
+        let noise = mods.thermometer_noise;
+        let temp = actual + self.rng.gen_range(-noise..=noise);
+        let temp = self.clamp_temp(room, temp);
+        Ok(SensorReading::Thermometer { tier, temp })
+    }
 
-        let ambient_temp = 50;
-        let ghost_room_min_temp = if ghost_type.has_evidence_type(EvidenceType::Freezing) {
-            28
+    pub fn read_emf(&mut self, addr: SocketAddr) -> Result<SensorReading, String> {
+        let tier = self.options.tier(EquipmentKind::EmfReader);
+        let mods = tier.modifiers();
+        let room = self.sensor_room(addr, EquipmentKind::EmfReader)?;
+        self.check_interference()?;
+        let level = self.emf_level_at(room);
+
+        let player = self.players.iter_mut().find(|p| p.addr == addr).unwrap();
+        // reading it counts as seeing it, the next room over doesn't
+        let blasts = self.flags.emf_blasts.iter().filter(|b| b.room == room);
+        for index in blasts.filter_map(|b| b.event) {
+            let event = &mut self.flags.emf_events[index];
+            if !event.observed_by.contains(&player.name) {
+                event.observed_by.push(player.name.clone());
+            }
+        }
+
+        player.emf_history.push(level);
+        let keep = mods.emf_history;
+        let excess = player.emf_history.len().saturating_sub(keep);
+        player.emf_history.drain(..excess);
+
+        Ok(SensorReading::Emf {
+            tier,
+            active: level > 0,
+            level: mods.emf_exact.then_some(level),
+            history: player.emf_history.clone(),
+        })
+    }
+
+    // Where a player is reading a sensor from, if they're able to
+    fn sensor_room(&self, addr: SocketAddr, item: EquipmentKind) -> Result<RoomLabel, String> {
+        if !self.is_started() {
+            return Err("Sim not started".to_owned());
+        }
+        let player = self
+            .players
+            .iter()
+            .find(|p| p.addr == addr)
+            .ok_or("Not a player")?;
+        if !player.inventory.contains(item) {
+            return Err("Item not in inventory".to_owned());
+        }
+        player.last_loc.ok_or_else(|| "Location unknown".to_owned())
+    }
+
+    pub fn get_inventory(&self, addr: SocketAddr) -> Option<Inventory> {
+        self.players
+            .iter()
+            .find(|p| p.addr == addr)
+            .map(|p| p.inventory.clone())
+    }
+
+    // The player at `addr`, if they're alive and `room` is where they are
+    // or a room next to it
+    fn in_reach(&self, addr: SocketAddr, room: RoomLabel) -> Result<&Player, String> {
+        let player = self
+            .players
+            .iter()
+            .find(|p| p.addr == addr)
+            .ok_or("Not registered")?;
+        if !player.alive {
+            return Err(format!("{} is dead", player.name));
+        }
+        let loc = player
+            .last_loc
+            .ok_or_else(|| format!("{} hasn't said where they are", player.name))?;
+        if loc != room && self.map.connection(loc, room).is_none() {
+            return Err(format!("{} is too far from {}", player.name, self.room_name(room)));
+        }
+        Ok(player)
+    }
+
+    // Inside, where the player is or a room next to it
+    pub fn place_camera(&mut self, addr: SocketAddr, room: RoomLabel) -> Result<u32, String> {
+        if room >= self.map.rooms.len() {
+            return Err("No such room".to_owned());
+        }
+        if self.map.is_exterior(room) {
+            return Err("Cameras have to go inside".to_owned());
+        }
+        if self.flags.cameras.len() >= self.options.camera_limit {
+            return Err("Camera limit reached".to_owned());
+        }
+        self.in_reach(addr, room)?;
+
+        let player = find_player_mut(&mut self.players, addr)?;
+        player.inventory.remove(EquipmentKind::VideoCamera)?;
+
+        let id = self.flags.next_camera_id;
+        self.flags.next_camera_id += 1;
+        self.flags.cameras.push(Camera { id, room });
+        Ok(id)
+    }
+
+    // Inside, where the player is or a room next to it
+    pub fn place_sensor(
+        &mut self,
+        addr: SocketAddr,
+        room: RoomLabel,
+        kind: SensorKind,
+    ) -> Result<u32, String> {
+        if !self.is_started() {
+            return Err("Sim not started".to_owned());
+        }
+        if !self.map.contains(room) {
+            return Err("No such room".to_owned());
+        }
+        if self.map.is_exterior(room) {
+            return Err("Sensors have to go inside".to_owned());
+        }
+        if self.flags.sensors.len() >= self.options.sensor_limit {
+            return Err("Sensor limit reached".to_owned());
+        }
+        let owner = self.in_reach(addr, room)?.name.clone();
+
+        let id = self.flags.next_sensor_id;
+        self.flags.next_sensor_id += 1;
+        self.flags.sensors.push(Sensor {
+            id,
+            kind,
+            room,
+            owner,
+        });
+        Ok(id)
+    }
+
+    // Whoever put it down can take it back from anywhere, anyone else has
+    // to be within reach of it
+    pub fn remove_sensor(&mut self, addr: SocketAddr, id: u32) -> Result<(), String> {
+        let ix = self
+            .flags
+            .sensors
+            .iter()
+            .position(|s| s.id == id)
+            .ok_or("No such sensor")?;
+        let sensor = &self.flags.sensors[ix];
+        let player = self
+            .players
+            .iter()
+            .find(|p| p.addr == addr)
+            .ok_or("Not registered")?;
+        if !player.alive {
+            return Err(format!("{} is dead", player.name));
+        }
+        if player.name != sensor.owner {
+            self.in_reach(addr, sensor.room)?;
+        }
+        self.flags.sensors.remove(ix);
+        Ok(())
+    }
+
+    // Every sensor of the kind that hears what happened in `room`
+    fn trip_sensors(&mut self, kind: SensorKind, room: RoomLabel, by: TrippedBy) {
+        let tripped: Vec<Sensor> = self
+            .flags
+            .sensors
+            .iter()
+            .filter(|s| s.kind == kind)
+            .filter(|s| match kind {
+                SensorKind::Motion => s.room == room,
+                SensorKind::Sound => s.room == room || self.map.connection(s.room, room).is_some(),
+            })
+            .cloned()
+            .collect();
+        for sensor in tripped {
+            let room_name = self.map.located_name(sensor.room);
+            let text = match kind {
+                SensorKind::Motion => format!("Motion sensor in the {} went off", room_name),
+                SensorKind::Sound => {
+                    format!("Sound sensor in the {} picked something up", room_name)
+                }
+            };
+            let params = json!({ "room": sensor.room, "sensor": sensor.id, "kind": kind });
+            self.notify(Notice::new(MessageKey::SensorTriggered, params, text));
+            self.flags.sensor_events.push_back(SensorEvent {
+                sensor: sensor.id,
+                kind,
+                room: sensor.room,
+                by,
+                time: self.cur_time,
+            });
+            if self.flags.sensor_events.len() > SENSOR_EVENTS_KEPT {
+                self.flags.sensor_events.pop_front();
+            }
+        }
+    }
+
+    // Lit where the ghost is, or next door, it calls off whatever hunt is
+    // coming or going on and keeps it from hunting for a while
+    pub fn use_smudge(&mut self, addr: SocketAddr) -> Result<(), String> {
+        let room = self.sensor_room(addr, EquipmentKind::Smudge)?;
+        if self.flags.smudges_used >= self.options.smudge_limit {
+            return Err("No smudge sticks left".to_owned());
+        }
+        find_player_mut(&mut self.players, addr)?
+            .inventory
+            .remove(EquipmentKind::Smudge)?;
+        self.flags.smudges_used += 1;
+
+        let ghost_room = self.ghost.current_room;
+        if room != ghost_room && !self.map.neighbours(ghost_room).contains(&room) {
+            return Ok(());
+        }
+
+        println!("Ghost smudged");
+        let duration = self
+            .options
+            .smudge_duration
+            .mul_f64(self.behavior.smudge_multiplier());
+        self.flags.smudged_until = self.cur_time + duration;
+        // whichever of the hunt's start or end is coming, it isn't
+        if let Some(id) = self.flags.hunt_timer.take() {
+            self.event_triggers.cancel(id);
+        }
+        if self.flags.is_hunting {
+            self.end_hunt();
         } else {
-            35
+            self.flags.hunt_warning = false;
+        }
+
+        let msg = format!(
+            "The ghost recoiled from the smudge in the {}",
+            self.map.located_name(room)
+        );
+        self.notify(Notice::in_room(MessageKey::Smudged, room, msg));
+        self.blast_emf(2, self.behavior.max_emf(&self.flags.shown_evidence));
+        self.urgent = true;
+        Ok(())
+    }
+
+    // The team shares a supply for the round, however many bottles turn up
+    pub fn use_sanity_pills(&mut self, addr: SocketAddr) -> Result<(), String> {
+        if !self.is_started() {
+            return Err("Sim not started".to_owned());
+        }
+        if self.flags.pills_used >= self.options.sanity_pills_limit {
+            return Err("No sanity pills left".to_owned());
+        }
+        let amount = self.options.sanity_pills_amount;
+        let player = find_player_mut(&mut self.players, addr)?;
+        if !player.alive {
+            return Err("Dead players can't take pills".to_owned());
+        }
+        player.inventory.remove(EquipmentKind::SanityPills)?;
+        player.restore_sanity(amount);
+        println!("{} took sanity pills, now at {:.0}", player.name, player.sanity);
+        self.flags.pills_used += 1;
+        Ok(())
+    }
+
+    // Inside, where the player is or a room next to it
+    pub fn place_crucifix(&mut self, addr: SocketAddr, room: RoomLabel) -> Result<(), String> {
+        if room >= self.map.rooms.len() {
+            return Err("No such room".to_owned());
+        }
+        if self.map.is_exterior(room) {
+            return Err("The crucifix has to go inside".to_owned());
+        }
+        if self.flags.crucifixes.len() >= self.options.crucifix_limit {
+            return Err("Crucifix limit reached".to_owned());
+        }
+        self.in_reach(addr, room)?;
+
+        find_player_mut(&mut self.players, addr)?
+            .inventory
+            .remove(EquipmentKind::Crucifix)?;
+        let crucifix = Crucifix {
+            room,
+            charges: self.options.crucifix_charges,
         };
+        self.flags.crucifixes.push(crucifix);
+        self.debug(GhostDecision::Crucifix {
+            room,
+            charges: crucifix.charges,
+        });
+        Ok(())
+    }
 
-        let mins_to_min_temp = rand::Rng::gen_range(&mut rng, 4..10);
+    // Like the projector: out of the player's hands the first time, moved by
+    // someone near it after that. Either way it's a fresh page, so any
+    // writing is gone.
+    pub fn place_book(&mut self, addr: SocketAddr, room: RoomLabel) -> Result<(), String> {
+        if room >= self.map.rooms.len() {
+            return Err("No such room".to_owned());
+        }
+        if self.map.is_exterior(room) {
+            return Err("The book has to go inside".to_owned());
+        }
+        self.in_reach(addr, room)?;
 
-        // change in temperature per minute
-        let delta_temp = (ghost_room_min_temp - ambient_temp) / mins_to_min_temp;
+        match self.flags.book_location {
+            Some(placed) => {
+                self.in_reach(addr, placed)?;
+            }
+            None => {
+                find_player_mut(&mut self.players, addr)?
+                    .inventory
+                    .remove(EquipmentKind::Book)?;
+            }
+        }
+        self.flags.book_location = Some(room);
+        self.flags.ghost_writing_visible = false;
+        Ok(())
+    }
 
-        SimFlags {
-            last_ghost_move: Duration::from_secs(0),
-            last_event_pulse: Duration::from_secs(0),
-            emf_level: 0,
-            ghost_type,
+    pub fn remove_book(&mut self, addr: SocketAddr) -> Result<(), String> {
+        let room = self.flags.book_location.ok_or("The book isn't down")?;
+        self.in_reach(addr, room)?;
+        find_player_mut(&mut self.players, addr)?
+            .inventory
+            .add(EquipmentKind::Book)?;
+        self.flags.book_location = None;
+        self.flags.ghost_writing_visible = false;
+        Ok(())
+    }
 
-            ambient_temp,
-            ghost_room_min_temp,
-            delta_temp,
+    // There's only one projector. The first placement takes it from the
+    // player, after that it's moved by someone near it, and whatever it was
+    // showing goes. Either way it goes where the player can reach.
+    pub fn place_dots(&mut self, addr: SocketAddr, room: RoomLabel) -> Result<(), String> {
+        if room >= self.map.rooms.len() {
+            return Err("No such room".to_owned());
+        }
+        if self.map.is_exterior(room) {
+            return Err("The projector has to go inside".to_owned());
+        }
+        self.in_reach(addr, room)?;
 
-            orbs_visible: false,
-            book_location: None,
-            ghost_writing_visible: false,
-            is_hunting: false
+        match self.flags.dots_location {
+            Some(placed) => {
+                self.in_reach(addr, placed)?;
+            }
+            None => {
+                find_player_mut(&mut self.players, addr)?
+                    .inventory
+                    .remove(EquipmentKind::DotsProjector)?;
+            }
         }
+        self.flags.dots_location = Some(room);
+        self.flags.dots_visible = false;
+        if let Some(id) = self.flags.dots_timer.take() {
+            self.event_triggers.cancel(id);
+        }
+        Ok(())
     }
-}
 
-pub struct SimOptions {
-    ghost_move_interval: Duration,
-    event_pulse_interval: Duration,
-    ghost_orbs_duration: Duration,
-    ghost_orbs_frequency: f64,
-    temperature_variability: i32,
-    thermometer_update_interval: Duration,
+    // From the camera's room or one next to it
+    pub fn remove_camera(&mut self, addr: SocketAddr, id: u32) -> Result<(), String> {
+        let ix = self
+            .flags
+            .cameras
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or("No such camera")?;
+        self.in_reach(addr, self.flags.cameras[ix].room)?;
+        // left on the floor for someone to pick up
+        let camera = self.flags.cameras.remove(ix);
+        self.flags
+            .dropped_items
+            .push((camera.room, EquipmentKind::VideoCamera));
+        Ok(())
+    }
 
-    ghost_interaction_frequency: f64,
-    ghost_event_frequency: f64,
+    // How many players can hide in a room this round
+    pub fn hiding_capacity(&self, room: RoomLabel) -> u8 {
+        self.flags.hiding_spots.get(room).copied().unwrap_or(0)
+    }
 
-    emf_blast_duration: Duration,
+    pub fn set_door(&mut self, door: DoorId, open: bool) -> Result<(), String> {
+        if !self.map.has_door(door) {
+            return Err(format!("No door {}", door));
+        }
+        if open {
+            self.flags.closed_doors.remove(&door);
+        } else {
+            self.flags.closed_doors.insert(door);
+        }
+        Ok(())
+    }
 
-    ghost_hunt_frequency: f64,
-    ghost_hunt_duration: Duration,
+    // For clients that only know the rooms either side. From either of
+    // them or a room next to one.
+    pub fn set_door_between(
+        &mut self,
+        addr: SocketAddr,
+        from: RoomLabel,
+        to: RoomLabel,
+        open: bool,
+    ) -> Result<(), String> {
+        let door = self.map.connection(from, to).and_then(|c| c.door).ok_or_else(|| {
+            format!(
+                "No door between {} and {}",
+                self.map.room_name(from),
+                self.map.room_name(to)
+            )
+        })?;
+        if self.in_reach(addr, from).is_err() {
+            self.in_reach(addr, to)?;
+        }
+        self.set_door(door, open)
+    }
 
-    sanity_drain_rate: f64,
-}
+    // From the room or one next to it. Switches can be flipped with the
+    // breaker off, they just don't do anything until it's back on
+    pub fn set_lights(
+        &mut self,
+        addr: SocketAddr,
+        room: RoomLabel,
+        on: bool,
+    ) -> Result<(), String> {
+        if room >= self.flags.lights_on.len() {
+            return Err("No such room".to_owned());
+        }
+        self.in_reach(addr, room)?;
+        self.flags.lights_on[room] = on;
+        if on && self.overloaded() && self.flags.breaker_on {
+            self.trip_breaker();
+        }
+        Ok(())
+    }
 
-impl SimOptions {
-    fn new() -> Self {
-        SimOptions {
-            ghost_move_interval: Duration::from_secs(10),
-            event_pulse_interval: Duration::from_secs(10),
+    // Only from the breaker's room, and it won't stay on with too many
+    // lights on
+    pub fn set_breaker(&mut self, addr: SocketAddr, on: bool) -> Result<(), String> {
+        let breaker_room = self.map.breaker_room();
+        let player = self
+            .players
+            .iter()
+            .find(|p| p.addr == addr)
+            .ok_or("Not a player")?;
+        if player.last_loc != Some(breaker_room) {
+            return Err(format!("The breaker is in the {}", self.map.room_name(breaker_room)));
+        }
+        if on && self.overloaded() {
+            return Err("Too many lights are on for the breaker".to_owned());
+        }
+        if self.flags.breaker_on == on {
+            return Ok(());
+        }
+        let name = player.name.clone();
+        self.flags.breaker_on = on;
+        println!("{} turned the breaker {}", name, if on { "on" } else { "off" });
+        self.log(SimEvent::Breaker { on, by: Some(name) });
+        Ok(())
+    }
 
-            ghost_orbs_duration: Duration::from_secs(20),
-            ghost_orbs_frequency: 1.0,
+    fn overloaded(&self) -> bool {
+        let limit = self.options.breaker_light_limit;
+        limit > 0 && self.flags.lights_on.iter().filter(|on| **on).count() > limit
+    }
 
-            temperature_variability: 5,
-            thermometer_update_interval: Duration::from_secs(2),
+    fn trip_breaker(&mut self) {
+        self.flags.breaker_on = false;
+        println!("Breaker tripped");
+        self.log(SimEvent::Breaker { on: false, by: None });
+        let text = "The power went out".to_owned();
+        self.notify(Notice::new(MessageKey::BreakerTripped, json!({}), text));
+    }
 
-            ghost_interaction_frequency: 1.25,
-            ghost_event_frequency: 1.25,
-            ghost_hunt_frequency: 0.0,
-            ghost_hunt_duration: Duration::from_secs(30),
-            emf_blast_duration: Duration::from_secs(3),
+    // Flickering sometimes leaves the room dark, and now and then takes
+    // the whole breaker with it
+    fn ghost_lights_out(&mut self) {
+        let room = self.ghost.current_room;
+        if self.flags.breaker_on && self.rng.gen_bool(self.options.breaker_trip_chance) {
+            self.trip_breaker();
+        } else if self.rng.gen_bool(self.options.lights_out_chance) {
+            if let Some(lights) = self.flags.lights_on.get_mut(room) {
+                *lights = false;
+            }
+        }
+    }
 
+    // Same seed, same map, so a good one can be played again
+    fn generate_map(&mut self, seed: Option<u64>) {
+        let seed = seed.unwrap_or_else(|| self.rng.gen());
+        let mut rng = StdRng::seed_from_u64(seed);
+        let n_rooms = rng.gen_range(8..=16);
+        let map = Map::generate(&mut rng, n_rooms, &GenerateParams::default());
 
-            sanity_drain_rate: 0.05, // %/s
+        println!("Generated {} room map from seed {}", n_rooms, seed);
+        let text = format!("Random map seed: {}", seed);
+        self.notify(Notice::new(MessageKey::RandomMap, json!({ "seed": seed }), text));
+        self.catalog.add(RANDOM_MAP, map);
+        self.select_map(RANDOM_MAP).unwrap();
+    }
+
+    // Admin only, picks the ghost for the coming rounds instead of a random
+    // one. None goes back to random.
+    pub fn set_ghost_type(&mut self, ghost_type: Option<GhostType>) -> Result<(), String> {
+        if self.is_started() {
+            return Err("Game already started".to_owned());
+        }
+        println!("Admin preset ghost: {:?}", ghost_type);
+        self.preset_ghost = ghost_type;
+        if let Some(ghost_type) = ghost_type {
+            self.apply_ghost_type(ghost_type);
         }
+        Ok(())
     }
 
-    // Load from admin options
-    fn load() -> Self {
-        todo!()
+    pub fn preset_ghost(&self) -> Option<GhostType> {
+        self.preset_ghost
     }
-}
 
-#[derive(Clone)]
-enum InteractionType {
-    Sound,
-    LightsFlicker,
-    // GhostWriting,
-}
+    pub fn personality(&self) -> Personality {
+        self.flags.personality
+    }
 
-impl InteractionType {
-    fn generate_interaction() -> Self {
-        let list = vec![InteractionType::Sound, InteractionType::LightsFlicker];
-        utils::rng_select(&list)
+    // Everything that follows from the ghost type
+    fn apply_ghost_type(&mut self, ghost_type: GhostType) {
+        self.flags.ghost_type = ghost_type;
+        self.behavior = behavior_for(ghost_type);
+        self.flags.shown_evidence = choose_shown_evidence(
+            ghost_type,
+            self.options.evidence_given,
+            &mut self.rng,
+        );
+        // a ghost hiding its freezing temps won't go below the normal floor
+        let min_temp = self.behavior.ghost_room_min_temp(&self.flags.shown_evidence);
+        self.flags.set_ghost_room_min_temp(min_temp, &mut self.rng);
+
+        self.debug(self.ghost_identity());
+
+        let personality = roll_personality(self.behavior.as_ref(), &mut self.rng);
+        self.flags.personality = personality;
+        self.debug(GhostDecision::Personality(personality));
+    }
+
+    // The answer to the round, for admins only
+    fn ghost_identity(&self) -> GhostDecision {
+        GhostDecision::Identity {
+            ghost_type: self.flags.ghost_type,
+            shown_evidence: self.flags.shown_evidence.clone(),
+        }
+    }
+
+    pub fn select_map(&mut self, id: &str) -> Result<(), String> {
+        if self.is_started() {
+            return Err("Game already started".to_owned());
+        }
+        match self.catalog.get(id) {
+            Some(map) => {
+                self.map = map.clone();
+                self.map_id = id.to_owned();
+                self.map_hash = map.hash();
+                Ok(())
+            }
+            None => Err(format!("Unknown map {}", id)),
+        }
+    }
+
+    pub fn start(
+        &mut self,
+        map: Option<String>,
+        seed: Option<u64>,
+        difficulty: Difficulty,
+        force: bool,
+    ) -> Result<(), String> {
+        if self.is_started() {
+            return Err("Game already started".to_owned());
+        }
+        // results count as everyone having been ready, there's no lobby
+        // in between to ready up in
+        if !force && self.phase == GamePhase::Lobby {
+            let waiting: Vec<&str> = self
+                .players
+                .iter()
+                .filter(|p| !p.ready)
+                .map(|p| p.name.as_str())
+                .collect();
+            if !waiting.is_empty() {
+                return Err(format!("Waiting on {}", waiting.join(", ")));
+            }
+        }
+        // nothing changes if the overrides are bad
+        let options = self.round_options(&self.tunables, &difficulty)?;
+        if self.phase == GamePhase::Finished {
+            self.reset_round();
+        }
+        match map {
+            Some(id) if id == RANDOM_MAP => self.generate_map(seed),
+            Some(id) => self.select_map(&id)?,
+            None => (),
+        }
+
+        println!("Starting on map {}", self.map_id);
+        println!("Difficulty {:?}", difficulty.preset());
+        if let Difficulty::Custom { overrides, .. } = &difficulty {
+            let changed = serde_json::to_string(overrides).unwrap();
+            let text = format!("Custom difficulty: {}", changed);
+            let params = json!({ "overrides": overrides });
+            self.notify(Notice::new(MessageKey::CustomDifficulty, params, text));
+        }
+        self.options = options;
+        self.difficulty = difficulty;
+        self.ghost = Ghost::new(&self.map, &self.options.room_category_weights, &mut self.rng);
+        let ghost_type = self.preset_ghost.unwrap_or_else(|| {
+            *utils::rng_select(&GhostType::ALL, &mut self.rng).unwrap()
+        });
+        self.apply_ghost_type(ghost_type);
+        self.log(SimEvent::RoundStarted {
+            map: self.map_id.clone(),
+            ghost_type,
+        });
+        self.flags.room_objects = self.map.rooms.iter().map(|r| r.objects).collect();
+        self.flags.room_temps = vec![self.flags.ambient_temp as f64; self.map.rooms.len()];
+        self.flags.room_sounds = vec![0.0; self.map.rooms.len()];
+        self.flags.lights_on = vec![false; self.map.rooms.len()];
+        self.flags.hiding_spots = self
+            .map
+            .rooms
+            .iter()
+            .map(|r| scale_hiding_spots(r.hiding_spots, self.options.hiding_spot_multiplier))
+            .collect();
+        self.phase = GamePhase::Running;
+        self.paused = false;
+        // everyone gets full Sim states from here on
+        self.lobby_changes.clear();
+        if !self.options.setup_duration.is_zero() {
+            let end = self.cur_time + self.options.setup_duration;
+            self.flags.setup_until = Some(end);
+            self.event_triggers.push(end, EventTrigger::EndSetup);
+        }
+        self.schedule_interaction();
+        Ok(())
+    }
+
+    // Runs the sim forward without waiting, in steps no bigger than a
+    // normal tick so intervals and triggers happen in the same order
+    pub fn advance(&mut self, duration: Duration) -> bool {
+        let mut changed = false;
+        let mut remaining = duration;
+        // the round can end part way through
+        while !remaining.is_zero() && self.is_started() {
+            let dt = std::cmp::min(remaining, ADVANCE_STEP);
+            changed = self.update(dt) || changed;
+            remaining -= dt;
+        }
+        changed
     }
 
-    fn interaction_msg(&self) -> String {
-        let str = match self {
-            InteractionType::Sound => "Sound",
-            InteractionType::LightsFlicker => "Lights",
+    // REAL TIME UPDATES
+    pub fn update(&mut self, dt: Duration) -> bool {
+        self.cur_time += dt;
+
+        let v = self.options.temperature_variability;
+        // inclusive, an empty range panics when there's no variability
+        self.flags.temp_noise = (self.rng.gen_range(-v..=v), self.rng.gen_range(-v..=v));
+
+        let quieter = self.options.sound_decay_rate * dt.as_secs_f64();
+        for level in self.flags.room_sounds.iter_mut() {
+            *level = (*level - quieter).max(0.0);
+        }
+
+        // Drain everyone's sanity
+        let millis: u32 = dt.as_millis().try_into().unwrap();
+        let millis_f: f64 = millis.into();
+        let seconds = millis_f / 1000.0;
+        let setup = if self.flags.setup_until.is_some() {
+            self.options.setup_drain_multiplier
+        } else {
+            1.0
+        };
+        let sanity_drain = self.options.sanity_drain_rate
+            * self.behavior.sanity_drain_multiplier()
+            * setup
+            * seconds;
+        let flashlight_drain = sanity_drain * self.options.flashlight_drain_multiplier;
+        let hidden_drain = sanity_drain * self.options.hidden_drain_multiplier;
+        let lit_drain = sanity_drain * self.options.lit_drain_multiplier;
+        let hunting = self.flags.is_hunting;
+        let dark: Vec<bool> = (0..self.map.rooms.len()).map(|r| self.is_room_dark(r)).collect();
+
+        // nobody's scared while they're away
+        let map = &self.map;
+        let inside = |p: &&mut Player| !p.last_loc.is_some_and(|loc| map.is_exterior(loc));
+        let here = |p: &&mut Player| p.alive && p.connected;
+        for player in self.players.iter_mut().filter(here).filter(inside) {
+            // a flashlight only helps when the room is dark
+            let in_dark = player.last_loc.is_none_or(|room| dark[room]);
+            if player.hidden && hunting {
+                // hearing it go past is worse
+                player.drain_sanity(hidden_drain);
+            } else if !in_dark {
+                player.drain_sanity(lit_drain);
+            } else if player.flashlight_on {
+                player.drain_sanity(flashlight_drain);
+            } else {
+                player.drain_sanity(sanity_drain);
+            }
+        }
+
+        let mut changed = false;
+        // longer edges take proportionally longer to cross
+        let steps = {
+            let cost = ghost_cost(
+                &self.map,
+                &self.flags.closed_doors,
+                self.ghost_avoids_doors(),
+                self.options.door_open_cost,
+            );
+            self.ghost.next_step_weight(&self.map, &cost)
+        };
+        let move_interval = if self.flags.is_hunting {
+            let speed = self.behavior.hunt_speed() * self.electronics_speed();
+            (self.options.hunt_move_interval * steps).div_f64(speed)
+        } else {
+            (self.options.ghost_move_interval * steps)
+                .mul_f64(self.behavior.move_interval_multiplier())
         };
-        str.to_owned()
+        let move_elapse = self.cur_time - self.flags.last_ghost_move;
+        if move_elapse > move_interval {
+            self.flags.last_ghost_move = self.cur_time;
+            self.move_ghost();
+            if self.flags.is_hunting {
+                self.search_hiding_spots();
+                self.catch_players();
+            }
+            changed = true;
+        }
+
+        let pulse_elapse = self.cur_time - self.flags.last_event_pulse;
+        if pulse_elapse > self.options.event_pulse_interval {
+            self.event_pulse(self.cur_time);
+            self.flags.last_event_pulse = self.cur_time;
+            changed = true;
+        }
+
+        let changed = self.check_triggers() || changed;
+        self.log_sanity_marks();
+        changed
+    }
+    
+    // Whether anything that went off needs a state sent. Several can come
+    // due in one step when fast forwarding, they go off in order.
+    fn check_triggers(&mut self) -> bool {
+        let mut changed = false;
+        for trigger in self.event_triggers.take_due(self.cur_time) {
+            let broadcast = match trigger {
+                EventTrigger::RemoveGhostOrbs => {
+                    println!("Orbs no longer visible");
+                    self.flags.orbs_visible = false;
+                    self.log(SimEvent::OrbsGone);
+                    true
+                }
+                EventTrigger::UpdateThermometer => {
+                    println!("Current time: {}", self.cur_time.as_secs());
+                    self.settle_temps(self.options.thermometer_update_interval);
+
+                    // catches what changes without sending a state itself
+                    let event_time = self.cur_time + self.options.thermometer_update_interval;
+                    self.event_triggers
+                        .push(event_time, EventTrigger::UpdateThermometer);
+                    self.drifted()
+                }
+                EventTrigger::EndEMF(id) => {
+                    let ix = self.flags.emf_blasts.iter().position(|b| b.id == id);
+                    if let Some(blast) = ix.map(|ix| self.flags.emf_blasts.remove(ix)) {
+                        if let Some(index) = blast.event {
+                            self.log_emf_event(&self.flags.emf_events[index]);
+                        }
+                        self.log(SimEvent::EmfEnded {
+                            room: blast.room,
+                            level: blast.level,
+                        });
+                    }
+                    true
+                }
+                EventTrigger::GhostInteraction => {
+                    // no interacting while hunting, on the way home, or right
+                    // after an event
+                    let suppressed = self.cur_time < self.flags.interactions_suppressed_until
+                        || self.flags.is_hunting
+                        || self.flags.hunt_warning;
+                    if self.flags.returning_since.is_none() && !suppressed {
+                        self.ghost_interaction();
+                    }
+                    self.schedule_interaction();
+                    true
+                }
+                EventTrigger::EndManifestation => {
+                    self.flags.manifested = false;
+                    true
+                }
+                EventTrigger::Abandon => {
+                    self.abandon();
+                    // the round is gone, and so is everything else it scheduled
+                    return true;
+                }
+                EventTrigger::ClearFingerprints(room) => {
+                    self.flags.fingerprints.retain(|r| *r != room);
+                    true
+                }
+                EventTrigger::EndSetup => {
+                    println!("Setup over");
+                    self.flags.setup_until = None;
+                    let text = "The ghost is becoming restless...".to_owned();
+                    self.notify(Notice::new(MessageKey::SetupOver, json!({}), text));
+                    // the rate goes back up
+                    self.schedule_interaction();
+                    true
+                }
+                EventTrigger::HideDots => {
+                    self.flags.dots_timer = None;
+                    self.flags.dots_visible = false;
+                    true
+                }
+                EventTrigger::StartHunt => {
+                    self.flags.hunt_timer = None;
+                    self.flags.hunt_warning = false;
+                    self.start_hunt();
+                    true
+                }
+                EventTrigger::EndHunt => {
+                    self.flags.hunt_timer = None;
+                    self.end_hunt();
+                    true
+                }
+            };
+            changed = broadcast || changed;
+        }
+        changed
+    }
+
+    // What the periodic refresh is for. Temperatures settling and sanity
+    // draining don't send states on their own, so it only needs to when
+    // either has moved far enough for clients to show.
+    fn drifted(&mut self) -> bool {
+        let sanity = self.players.iter().map(|p| p.sanity.round() as u32).collect();
+        let readings = (self.room_temps(), sanity);
+        let changed = self.flags.last_readings.as_ref() != Some(&readings);
+        self.flags.last_readings = Some(readings);
+        changed
+    }
+
+    fn move_ghost(&mut self) {
+        if let Some(since) = self.flags.returning_since {
+            let timed_out = self.cur_time - since > self.options.ghost_return_timeout;
+            if self.ghost.current_room == self.ghost.ghost_room || timed_out {
+                println!("Ghost done returning");
+                self.flags.returning_since = None;
+            } else {
+                // no lingering or interacting on the way back
+                self.step_ghost();
+                return;
+            }
+        }
+
+        // chance to just stay in ghost room, less the more it likes to roam
+        let stay_chance = (1.0 - 0.5 * self.flags.personality.wanderlust).clamp(0.0, 1.0);
+        let stay = self.ghost.current_room == self.ghost.ghost_room
+            && utils::roll(stay_chance, &mut self.rng);
+        if !stay {
+            self.step_ghost()
+        }
+
+        if let Some(book_room) = self.flags.book_location {
+            if self.shows_evidence(EvidenceType::Writing)
+                && !self.flags.ghost_writing_visible
+                && self.ghost.current_room == book_room
+                && utils::roll(
+                    utils::prob_from_rate(
+                        self.options.ghost_interaction_rate,
+                        self.options.ghost_move_interval,
+                    ),
+                    &mut self.rng,
+                )
+                {
+                    self.flags.ghost_writing_visible = true;
+                    let msg =
+                        format!("You hear scribbling in the {}", self.map.located_name(book_room));
+                    self.notify(
+                        Notice::in_room(MessageKey::GhostWriting, book_room, msg)
+                            .with_sound(SoundId::GhostWriting),
+                    );
+                }
+        }
+
+        self.pass_dots();
+    }
+
+    // Walking through the projector's dots shows the ghost for a moment
+    fn pass_dots(&mut self) {
+        if self.flags.dots_location != Some(self.ghost.current_room)
+            || self.flags.dots_visible
+            || !self.shows_evidence(EvidenceType::Dots)
+        {
+            return;
+        }
+        let chance = self.options.dots_chance;
+        let seen = utils::roll(chance, &mut self.rng);
+        self.debug(GhostDecision::Roll {
+            kind: "dots".to_owned(),
+            chance,
+            hit: seen,
+        });
+        if seen {
+            println!("Dots now visible");
+            self.flags.dots_visible = true;
+            let time = self.cur_time + self.options.dots_duration;
+            self.flags.dots_timer = Some(self.event_triggers.push(time, EventTrigger::HideDots));
+        }
+    }
+
+    // Moves the ghost one step, opening any closed door on the way. Hunting
+    // it goes for the nearest player it knows the whereabouts of.
+    fn step_ghost(&mut self) {
+        let from = self.ghost.current_room;
+        let old_target = self.ghost.target();
+        let prey = if self.flags.is_hunting {
+            self.nearest_prey()
+        } else {
+            None
+        };
+        {
+            let cost = ghost_cost(
+                &self.map,
+                &self.flags.closed_doors,
+                self.ghost_avoids_doors(),
+                self.options.door_open_cost,
+            );
+            match prey {
+                Some(room) => self.ghost.chase(&self.map, &cost, room, &mut self.rng),
+                None => self.ghost.move_room(&self.map, &cost, &mut self.rng),
+            }
+        }
+
+        match self.ghost.target() {
+            Some(room) if Some(room) != old_target => {
+                self.debug(GhostDecision::Target { room })
+            }
+            _ => (),
+        }
+        if self.ghost.current_room != from {
+            self.chill_room(self.ghost.current_room);
+            self.trip_sensors(SensorKind::Motion, self.ghost.current_room, TrippedBy::Ghost);
+            // hunting, it stomps
+            let steps = if self.flags.is_hunting {
+                self.options.ghost_event_sound
+            } else {
+                self.options.footstep_sound
+            };
+            self.make_sound(self.ghost.current_room, steps);
+            self.log(SimEvent::GhostMoved {
+                from,
+                to: self.ghost.current_room,
+            });
+            self.debug(GhostDecision::Move {
+                from,
+                to: self.ghost.current_room,
+                path: self.ghost.planned_path(),
+            });
+        }
+
+        let door = self
+            .map
+            .connection(from, self.ghost.current_room)
+            .and_then(|c| c.door);
+        if let Some(door) = door {
+            if self.flags.closed_doors.remove(&door) {
+                println!("Ghost opened door {}", door);
+                self.queue_audio(&InteractionType::DoorOpen);
+                let room_name = self.map.located_name(self.ghost.current_room);
+                let room = self.ghost.current_room;
+                self.notify(InteractionType::DoorOpen.notice(room, &room_name));
+            }
+        }
+    }
+
+    // Where the living player the ghost is most drawn to was last seen,
+    // never outside since the ghost can't follow them there. Closer draws
+    // it more, and so does a flashlight; ties go to whoever joined first.
+    // Beyond its detection radius it can't sense them at all.
+    fn nearest_prey(&self) -> Option<RoomLabel> {
+        let from = self.ghost.current_room;
+        self.players
+            .iter()
+            .filter(|p| p.alive && p.connected && !p.hidden)
+            .filter_map(|p| {
+                let loc = p.last_loc.filter(|loc| !self.map.is_exterior(*loc))?;
+                let distance = self.map.distance(from, loc)?;
+                if distance > self.detection_radius(p) {
+                    return None;
+                }
+                Some((self.prey_weight(p) / (distance as f64 + 1.0), loc))
+            })
+            .min_by(|(a, _), (b, _)| b.total_cmp(a))
+            .map(|(_, loc)| loc)
+    }
+
+    fn prey_weight(&self, player: &Player) -> f64 {
+        let mut weight = 1.0;
+        if player.flashlight_on {
+            weight *= self.options.flashlight_target_weight;
+        }
+        if player.electronics_on() {
+            weight *= self.behavior.electronics_attraction();
+        }
+        weight
+    }
+
+    // Switching everything off is the way to stay unnoticed
+    fn detection_radius(&self, player: &Player) -> u32 {
+        if player.electronics_on() {
+            self.options.detection_radius
+        } else {
+            self.options.dark_detection_radius
+        }
+    }
+
+    // A Raiju speeds up with anything switched on in or next to its room
+    fn electronics_speed(&self) -> f64 {
+        let here = self.ghost.current_room;
+        let near = |room: RoomLabel| room == here || self.map.connection(here, room).is_some();
+        let powered = self
+            .players
+            .iter()
+            .filter(|p| p.alive && p.electronics_on())
+            .any(|p| p.last_loc.is_some_and(near));
+        if powered {
+            self.behavior.electronics_hunt_speed()
+        } else {
+            1.0
+        }
+    }
+
+    // A flashlight near a hunting, or about to hunt, ghost flickers
+    fn flashlight_flickers(&self, player: &Player) -> bool {
+        let hunting = self.flags.is_hunting || self.flags.hunt_warning;
+        let near = player
+            .last_loc
+            .and_then(|room| self.map.distance(room, self.ghost.current_room))
+            .is_some_and(|d| d <= self.options.flicker_radius);
+        hunting && player.flashlight_on && near
+    }
+
+    // Each move, anyone hiding in the hunting ghost's room might be found
+    fn search_hiding_spots(&mut self) {
+        let room = self.ghost.current_room;
+        let chance = self.options.hide_discovery_chance;
+        let hiding: Vec<usize> = (0..self.players.len())
+            .filter(|i| {
+                let p = &self.players[*i];
+                p.alive && p.hidden && p.last_loc == Some(room)
+            })
+            .collect();
+        for i in hiding {
+            let found = utils::roll(chance, &mut self.rng);
+            self.debug(GhostDecision::Roll {
+                kind: format!("discover {}", self.players[i].name),
+                chance,
+                hit: found,
+            });
+            if found {
+                println!("Ghost found {} hiding", self.players[i].name);
+                self.players[i].hidden = false;
+            }
+        }
+    }
+
+    // Anyone alive and not hidden in the hunting ghost's room dies
+    fn catch_players(&mut self) {
+        let room = self.ghost.current_room;
+        let caught: Vec<String> = self
+            .players
+            .iter()
+            .filter(|p| p.alive && p.connected && !p.hidden && p.last_loc == Some(room))
+            .map(|p| p.name.clone())
+            .collect();
+        for name in caught {
+            println!("Ghost caught {} in {}", name, self.map.room_name(room));
+            self.kill_player(&name);
+        }
+    }
+
+    fn cancel_abandon(&mut self) {
+        self.event_triggers
+            .cancel_where(|trigger| matches!(trigger, EventTrigger::Abandon));
+    }
+
+    // Ends the round with nobody in it and goes back to the lobby
+    fn abandon(&mut self) {
+        println!("Round abandoned on map {}", self.map_id);
+        self.log(SimEvent::RoundEnded);
+        self.reset_round();
+        self.phase = GamePhase::Lobby;
+    }
+
+    // Admin only. Drops whatever round is going on or just finished, the
+    // players stay on in the lobby unless they're cleared too.
+    pub fn reset(&mut self, clear_players: bool) {
+        println!("Admin reset the sim");
+        if self.is_started() {
+            self.log(SimEvent::RoundEnded);
+        }
+        self.reset_round();
+        if clear_players {
+            self.players.clear();
+        }
+        self.phase = GamePhase::Lobby;
+        self.notices.clear();
+        self.audio_queue.clear();
+        self.lobby_changes.clear();
+    }
+
+    // Clears out the last round, whoever left during it goes with it
+    fn reset_round(&mut self) {
+        self.players.retain(|p| p.connected);
+        for player in self.players.iter_mut() {
+            player.reset_for_round();
+        }
+        self.results = None;
+        self.event_triggers.clear();
+        self.event_triggers.push(self.cur_time, EventTrigger::UpdateThermometer);
+        self.flags = SimFlags::new(&mut self.rng);
+        self.flags.equipment_pool = self.options.starting_equipment.clone();
+        self.behavior = behavior_for(self.flags.ghost_type);
+        self.ghost = Ghost::new(&self.map, &self.options.room_category_weights, &mut self.rng);
+        self.urgent = true;
+    }
+
+    // Admin only. Mid hunt the ghost just hunts on from the new room.
+    pub fn teleport_ghost(&mut self, room: RoomLabel, emf: bool) -> Result<(), String> {
+        self.check_ghost_room(room)?;
+        println!(
+            "Admin forced ghost into {} (was in {})",
+            self.map.room_name(room),
+            self.map.room_name(self.ghost.current_room)
+        );
+        let from = self.ghost.current_room;
+        self.ghost.teleport(room);
+        self.chill_room(room);
+        self.log(SimEvent::GhostMoved { from, to: room });
+        if emf {
+            self.blast_emf(2, self.behavior.max_emf(&self.flags.shown_evidence));
+        }
+        Ok(())
+    }
+
+    // Admin only. The new room starts cooling from scratch and any orbs
+    // showing in the old one go away.
+    pub fn set_ghost_room(&mut self, room: RoomLabel) -> Result<(), String> {
+        self.check_ghost_room(room)?;
+        println!(
+            "Admin forced ghost room to {} (was {})",
+            self.map.room_name(room),
+            self.map.room_name(self.ghost.ghost_room)
+        );
+        self.move_favorite_room(room);
+        Ok(())
+    }
+
+    // The old room warms back up and the new one starts cooling, since
+    // both follow ghost_room_since
+    fn move_favorite_room(&mut self, room: RoomLabel) {
+        self.log(SimEvent::FavoriteRoomChanged {
+            from: self.ghost.ghost_room,
+            to: room,
+        });
+        self.ghost.ghost_room = room;
+        self.flags.ghost_room_since = self.cur_time;
+        if self.flags.orbs_visible {
+            self.log(SimEvent::OrbsGone);
+        }
+        self.flags.orbs_visible = false;
+        self.event_triggers.cancel_where(|t| matches!(t, EventTrigger::RemoveGhostOrbs));
+    }
+
+    // Now and then the ghost takes to a new room, so finding the cold one
+    // isn't the end of it. Players only get a noise where it is now.
+    fn shift_favorite_room(&mut self) {
+        let except = Some(self.ghost.ghost_room);
+        let weights = &self.options.room_category_weights;
+        let room = match pick_ghost_room(&self.map, weights, except, &mut self.rng) {
+            Some(room) => room,
+            None => return,
+        };
+        println!(
+            "Ghost moved its room to {} (was {})",
+            self.map.room_name(room),
+            self.map.room_name(self.ghost.ghost_room)
+        );
+        self.move_favorite_room(room);
+
+        if self.options.room_shift_hint {
+            self.blast_emf(2, 3);
+            self.make_sound(self.ghost.current_room, self.options.interaction_sound);
+            let room_name = self.map.located_name(self.ghost.current_room);
+            let params = json!({ "room": self.ghost.current_room });
+            let text = format!("You hear furniture dragging in the {}", room_name);
+            self.notify(Notice::new(MessageKey::RoomShift, params, text));
+        }
+    }
+
+    fn check_ghost_room(&self, room: RoomLabel) -> Result<(), String> {
+        if !self.is_started() {
+            Err("Sim not started".to_owned())
+        } else if !self.map.contains(room) {
+            Err(format!("No room with label {}", room))
+        } else if self.map.is_exterior(room) {
+            Err(format!("{} is outside", self.map.room_name(room)))
+        } else if self.map.rooms[room].safe_room {
+            Err(format!("{} is the safe room", self.map.room_name(room)))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn shows_evidence(&self, evidence: EvidenceType) -> bool {
+        self.flags.shown_evidence.contains(&evidence)
+    }
+
+    // For the end of round: what the ghost was and what it kept hidden
+    fn evidence_reveal(&self) -> EvidenceReveal {
+        let evidence = self.behavior.evidence().to_vec();
+        let hidden = evidence
+            .iter()
+            .filter(|e| !self.flags.shown_evidence.contains(e))
+            .copied()
+            .collect();
+        EvidenceReveal {
+            ghost_type: self.flags.ghost_type,
+            evidence,
+            hidden,
+        }
+    }
+
+    // The journal's guess ends the round. Anyone still in it can send it,
+    // but only the first one counts. The summary is for the server's
+    // records, the players get the results in the next state.
+    pub fn submit_guess(
+        &mut self,
+        addr: SocketAddr,
+        guess: GhostType,
+    ) -> Result<RoundSummary, String> {
+        match self.phase {
+            GamePhase::Lobby => return Err("Sim not started".to_owned()),
+            GamePhase::Finished => return Err("A guess was already submitted".to_owned()),
+            GamePhase::Running => (),
+        }
+        let name = self
+            .players
+            .iter()
+            .find(|p| p.addr == addr)
+            .map(|p| p.name.clone())
+            .ok_or("Not a player")?;
+
+        let actual = self.flags.ghost_type;
+        let correct_guess = guess_accepted(
+            guess,
+            actual,
+            &self.flags.shown_evidence,
+            self.options.evidence_given,
+        );
+        println!("{} guessed {:?}, it was {:?}", name, guess, actual);
+
+        let stats = RoundStats {
+            players: self
+                .players
+                .iter()
+                .map(|p| PlayerRoundStats {
+                    name: p.name.clone(),
+                    survived: p.alive,
+                    // nothing tracks these yet
+                    objectives_completed: 0,
+                    photo_score: 0,
+                    journal_evidence: 0,
+                })
+                .collect(),
+            correct_guess,
+        };
+        let rewards = compute_rewards(&stats, &self.options.payouts);
+        let summary = RoundSummary {
+            map: self.map_id.clone(),
+            difficulty: self.difficulty.clone(),
+            preset_ghost: self.preset_ghost.is_some(),
+            personality: self.flags.personality,
+            correct_guess,
+            players: rewards
+                .players
+                .iter()
+                .zip(&stats.players)
+                .map(|(reward, p)| PlayerSummary {
+                    name: reward.name.clone(),
+                    survived: p.survived,
+                    earned: reward.total,
+                })
+                .collect(),
+        };
+
+        for reward in &rewards.players {
+            self.log(SimEvent::Rewarded {
+                name: reward.name.clone(),
+                items: reward.items.clone(),
+                total: reward.total,
+            });
+        }
+        self.results = Some(RoundResults {
+            guess,
+            correct_guess,
+            ghost: self.evidence_reveal(),
+            players: self
+                .players
+                .iter()
+                .map(|p| PlayerResult {
+                    name: p.name.clone(),
+                    sanity: p.sanity,
+                    survived: p.alive,
+                })
+                .collect(),
+            bodies: self.flags.bodies.clone(),
+            rewards,
+        });
+        self.phase = GamePhase::Finished;
+        self.log(SimEvent::RoundEnded);
+        // nothing left to happen this round
+        self.event_triggers.clear();
+        self.flags.is_hunting = false;
+        self.flags.hunt_warning = false;
+        self.urgent = true;
+        Ok(summary)
+    }
+
+    // Shy ghosts won't open doors unless they're hunting
+    fn ghost_avoids_doors(&self) -> bool {
+        self.behavior.avoids_closed_doors() && !self.flags.is_hunting
+    }
+
+    fn event_pulse(&mut self, cur_time: Duration) {
+        println!("Event pulse");
+
+        // nothing else goes on while it hunts or heads home after
+        if self.flags.is_hunting || self.flags.hunt_warning || self.flags.returning_since.is_some()
+        {
+            return;
+        }
+
+        // Chance for hunt, only once the team is low enough
+        let average_sanity = self.average_sanity();
+        let hunt_chance = utils::prob_from_rate(self.hunt_rate(), self.options.event_pulse_interval);
+        let hunt = utils::roll(hunt_chance, &mut self.rng);
+        self.debug(GhostDecision::HuntCheck {
+            average_sanity,
+            threshold: self.hunt_threshold(),
+            chance: hunt_chance,
+            hit: hunt,
+        });
+        if hunt && !self.crucifix_stops_hunt() {
+            self.warn_hunt();
+            // if hunt occurs, no other events need to occur
+            return;
+        }
+
+        // Chance for a ghost event, which takes the place of any interaction
+        // until the next pulse
+        let event_chance = utils::prob_from_rate(
+            self.options.ghost_event_rate * self.aggression_multiplier(),
+            self.options.event_pulse_interval,
+        );
+        let event = !self.flags.manifested && utils::roll(event_chance, &mut self.rng);
+        self.debug(GhostDecision::Roll {
+            kind: "event".to_owned(),
+            chance: event_chance,
+            hit: event,
+        });
+        if event {
+            self.ghost_event();
+            self.flags.interactions_suppressed_until = cur_time + self.options.event_pulse_interval;
+        }
+
+        // Chance for orbs
+        let orbs_chance =
+            utils::prob_from_rate(self.options.ghost_orbs_rate, self.options.event_pulse_interval);
+        let shows_orbs = self.shows_evidence(EvidenceType::GhostOrbs);
+        let orbs =
+            shows_orbs && !self.flags.orbs_visible && utils::roll(orbs_chance, &mut self.rng);
+        self.debug(GhostDecision::Roll {
+            kind: "orbs".to_owned(),
+            chance: orbs_chance,
+            hit: orbs,
+        });
+        if orbs {
+            println!("Orbs now visible");
+            self.flags.orbs_visible = true;
+            self.log(SimEvent::OrbsShown {
+                room: self.ghost.ghost_room,
+            });
+
+            let trigger_time = cur_time + self.options.ghost_orbs_duration;
+            self.event_triggers
+                .push(trigger_time, EventTrigger::RemoveGhostOrbs);
+        }
+
+        if self.options.room_shifts {
+            let shift_chance = utils::prob_from_rate(
+                self.options.room_shift_rate * self.behavior.room_shift_multiplier(),
+                self.options.event_pulse_interval,
+            );
+            let shift = utils::roll(shift_chance, &mut self.rng);
+            self.debug(GhostDecision::Roll {
+                kind: "room_shift".to_owned(),
+                chance: shift_chance,
+                hit: shift,
+            });
+            if shift {
+                self.shift_favorite_room();
+            }
+        }
+
+        // Interactions get scheduled ahead of time. If sanity has moved
+        // enough to change the rate, draw the next one again; the wait is
+        // memoryless so nothing is lost by throwing the old one away.
+        let rate = self.interaction_rate();
+        let scheduled = self.flags.scheduled_interaction_rate;
+        if (rate - scheduled).abs() > scheduled * INTERACTION_RESAMPLE_CHANGE {
+            self.schedule_interaction();
+        }
+    }
+
+    // Zero above the threshold, then climbs the further the team's average
+    // drops below it, to 1 + low_sanity_hunts times the base rate at nothing
+    // left
+    fn hunt_rate(&self) -> f64 {
+        let threshold = self.hunt_threshold();
+        let average = self.average_sanity();
+        let held_back = self.flags.setup_until.is_some() || self.cur_time < self.flags.smudged_until;
+        if threshold <= 0.0 || average >= threshold || held_back {
+            return 0.0;
+        }
+        let depth = (threshold - average) / threshold;
+        let boost = 1.0 + self.options.low_sanity_hunts * depth;
+        self.options.ghost_hunt_rate * self.aggression_multiplier() * boost
+    }
+
+    // A charged crucifix in the ghost's room or next door uses itself up
+    // instead of letting it hunt
+    fn crucifix_stops_hunt(&mut self) -> bool {
+        let here = self.ghost.current_room;
+        let near = self.map.neighbours(here);
+        let crucifix = match self
+            .flags
+            .crucifixes
+            .iter_mut()
+            .find(|c| c.charges > 0 && (c.room == here || near.contains(&c.room)))
+        {
+            Some(crucifix) => {
+                crucifix.charges -= 1;
+                *crucifix
+            }
+            None => return false,
+        };
+
+        println!("Crucifix stopped a hunt");
+        self.debug(GhostDecision::Crucifix {
+            room: crucifix.room,
+            charges: crucifix.charges,
+        });
+        let msg = format!("The crucifix in the {} burned", self.map.located_name(crucifix.room));
+        self.notify(Notice::in_room(MessageKey::CrucifixBurned, crucifix.room, msg));
+        true
+    }
+
+    // Electronics flicker for a few seconds before the hunt starts
+    fn warn_hunt(&mut self) {
+        println!("Hunt coming");
+        self.flags.hunt_warning = true;
+        let time = self.cur_time + self.options.hunt_warning;
+        self.flags.hunt_timer = Some(self.event_triggers.push(time, EventTrigger::StartHunt));
+        self.urgent = true;
+    }
+
+    fn interference(&self) -> Interference {
+        if self.flags.is_hunting {
+            Interference::Full
+        } else if self.flags.hunt_warning {
+            Interference::Flicker
+        } else {
+            Interference::None
+        }
+    }
+
+    // Some ghosts hunt sooner than others
+    fn hunt_threshold(&self) -> f64 {
+        let bonus = self.behavior.hunt_threshold_bonus();
+        (self.options.hunt_sanity_threshold + bonus).clamp(0.0, 100.0)
+    }
+
+    fn start_hunt(&mut self) {
+        println!("Hunt started from {}", self.map.room_name(self.ghost.current_room));
+        self.flags.is_hunting = true;
+        self.log(SimEvent::HuntStarted {
+            room: self.ghost.current_room,
+        });
+        self.make_sound(self.ghost.current_room, self.options.ghost_event_sound);
+        // anything that was showing stops for the hunt
+        self.flags.manifested = false;
+        self.event_triggers.cancel_where(|t| matches!(t, EventTrigger::EndManifestation));
+
+        let time = self.cur_time + self.options.ghost_hunt_duration;
+        self.flags.hunt_timer = Some(self.event_triggers.push(time, EventTrigger::EndHunt));
+        let text = "The ghost is hunting".to_owned();
+        let notice = Notice::new(MessageKey::HuntStarted, json!({}), text);
+        self.notify(notice.with_sound(SoundId::HuntStart));
+        self.urgent = true;
+    }
+
+    fn end_hunt(&mut self) {
+        println!("Hunt over");
+        self.flags.is_hunting = false;
+        self.log(SimEvent::HuntEnded);
+        for player in self.players.iter_mut() {
+            player.hidden = false;
+        }
+        let text = "The hunt is over".to_owned();
+        self.notify(Notice::new(MessageKey::HuntEnded, json!({}), text));
+        self.urgent = true;
+
+        // head home before doing anything else
+        let cost = ghost_cost(
+            &self.map,
+            &self.flags.closed_doors,
+            self.ghost_avoids_doors(),
+            self.options.door_open_cost,
+        );
+        self.ghost.return_home(&self.map, &cost);
+        self.flags.returning_since = Some(self.cur_time);
+    }
+
+    // More likely as the team loses it
+    fn interaction_rate(&self) -> f64 {
+        let rate = self.options.ghost_interaction_rate * self.behavior.interaction_multiplier();
+        let setup = if self.flags.setup_until.is_some() {
+            self.options.setup_interaction_multiplier
+        } else {
+            1.0
+        };
+        rate * setup * self.aggression_multiplier()
+    }
+
+    // What every activity roll is scaled by: the team's sanity and this
+    // round's personality
+    fn aggression_multiplier(&self) -> f64 {
+        let personality = &self.flags.personality;
+        let room = self.ghost.current_room;
+        let watched = self.players.iter().any(|p| p.alive && p.last_loc == Some(room));
+        let shy = if watched { 1.0 - personality.shyness } else { 1.0 };
+        self.activity_multiplier() * personality.aggression * shy
+    }
+
+    // Replaces any pending interaction with one an exponentially
+    // distributed wait away, so they don't line up with the pulse
+    fn schedule_interaction(&mut self) {
+        self.event_triggers.cancel_where(|t| matches!(t, EventTrigger::GhostInteraction));
+
+        let rate = self.interaction_rate();
+        self.flags.scheduled_interaction_rate = rate;
+        let wait = utils::sample_exponential(rate, &mut self.rng);
+        if let Some(wait) = wait {
+            self.event_triggers
+                .push(self.cur_time + wait, EventTrigger::GhostInteraction);
+        }
+        self.debug(GhostDecision::InteractionScheduled {
+            rate,
+            wait_secs: wait.map(|w| w.as_secs_f64()),
+        });
+    }
+
+    fn ghost_interaction(&mut self) {
+        self.flags.activity.interactions += 1;
+        println!("Interaction ({} so far)", self.flags.activity.interactions);
+        // && book is in ghost current room
+        let can_throw = self.flags.room_objects[self.ghost.current_room] > 0;
+        let interaction = InteractionType::generate_interaction(can_throw, &mut self.rng);
+        self.debug(GhostDecision::Interaction {
+            kind: interaction.clone(),
+            room: self.ghost.current_room,
+        });
+        self.log(SimEvent::Interaction {
+            kind: interaction.clone(),
+            room: self.ghost.current_room,
+        });
+
+        // drain player's sanity, nobody outside notices
+        let map = &self.map;
+        let inside = |p: &&mut Player| !p.last_loc.is_some_and(|loc| map.is_exterior(loc));
+        let here = |p: &&mut Player| p.alive && p.connected;
+        for player in self.players.iter_mut().filter(here).filter(inside) {
+            let sanity_loss = if player.last_loc == Some(self.ghost.current_room) {
+                self.options.interaction_sanity_loss
+            } else {
+                self.options.interaction_sanity_loss_elsewhere
+            };
+            player.drain_sanity(sanity_loss);
+        }
+
+        let min_emf = 2;
+        self.blast_emf(min_emf, self.behavior.max_emf(&self.flags.shown_evidence));
+        self.leave_fingerprints();
+
+        self.queue_audio(&interaction);
+        if interaction == InteractionType::Sound {
+            self.trip_sensors(SensorKind::Sound, self.ghost.current_room, TrippedBy::Ghost);
+            self.make_sound(self.ghost.current_room, self.options.interaction_sound);
+        }
+        if interaction == InteractionType::LightsFlicker {
+            self.ghost_lights_out();
+        }
+
+        let room_name = self.map.located_name(self.ghost.current_room);
+        let msg = match interaction {
+            InteractionType::ThrowObject => self.throw_objects(&room_name),
+            _ => interaction.notice(self.ghost.current_room, &room_name),
+        };
+        self.notify(msg);
+    }
+
+    // The ghost shows itself for a moment. Rarer and scarier than an
+    // interaction, it only hits the players who see it.
+    fn ghost_event(&mut self) {
+        self.flags.activity.events += 1;
+        println!("Ghost event ({} so far)", self.flags.activity.events);
+
+        let room = self.ghost.current_room;
+        self.make_sound(room, self.options.ghost_event_sound);
+        for player in self.players.iter_mut().filter(|p| p.alive && p.last_loc == Some(room)) {
+            player.drain_sanity(self.options.ghost_event_sanity_loss);
+        }
+
+        self.flags.manifested = true;
+        let end_time = self.cur_time + self.options.ghost_event_duration;
+        self.event_triggers
+            .push(end_time, EventTrigger::EndManifestation);
+
+        let msg = format!("The ghost manifested in the {}", self.map.located_name(room));
+        self.notify(Notice::in_room(MessageKey::Manifest, room, msg).with_sound(SoundId::Manifest));
+    }
+
+    // How often the ghost has done things this round, for checking tuning
+    pub fn activity(&self) -> ActivityCounts {
+        self.flags.activity
+    }
+
+    fn throw_objects(&mut self, room_name: &str) -> Notice {
+        let room = self.ghost.current_room;
+        let objects = &mut self.flags.room_objects[room];
+        let thrown = std::cmp::min(*objects, self.behavior.max_thrown());
+        *objects -= thrown;
+
+        let text = if thrown == 1 {
+            format!("Object thrown in the {} ({} left)", room_name, objects)
+        } else {
+            format!("{} objects thrown in the {} ({} left)", thrown, room_name, objects)
+        };
+        let params = json!({ "room": room, "count": thrown, "left": *objects });
+        Notice::new(MessageKey::ThrowObject, params, text).with_sound(SoundId::ThrowObject)
+    }
+
+    // Everything, for admins and spectators
+    pub fn get_gameupdate(&self) -> GameUpdate {
+        self.gameupdate(Viewer::Everything)
+    }
+
+    // What the named player gets to see, or anyone not playing if there's
+    // no name. The lobby has nothing to hide.
+    pub fn get_gameupdate_for(&self, name: Option<&str>) -> GameUpdate {
+        if !self.redact {
+            return self.get_gameupdate();
+        }
+        let player = name.and_then(|name| self.players.iter().find(|p| p.name == name));
+        match player {
+            Some(player) => self.gameupdate(Viewer::Player(player)),
+            None => self.gameupdate(Viewer::Nobody),
+        }
+    }
+
+    fn gameupdate(&self, viewer: Viewer) -> GameUpdate {
+        if let (GamePhase::Finished, Some(results)) = (self.phase, &self.results) {
+            // a player's own breakdown and the team's total, the rest is
+            // for admins
+            let mut results = results.clone();
+            match viewer {
+                Viewer::Everything => (),
+                Viewer::Player(me) => results.rewards.players.retain(|r| r.name == me.name),
+                Viewer::Nobody => results.rewards.players.clear(),
+            }
+            GameUpdate::Results(Box::new(results))
+        } else if !self.is_started() {
+            let players = self
+                .players
+                .iter()
+                .map(lobby_player)
+                .collect();
+
+            GameUpdate::Lobby {
+                players,
+                maps: self.catalog.ids(),
+                selected_map: self.map_id.clone(),
+                map_hash: self.map_hash.clone(),
+                rooms: self.map.directory(),
+                options: Box::new(self.options.effective()),
+                spectators: self.spectators,
+            }
+        } else {
+            let (amb_noise, _) = self.flags.temp_noise;
+
+            // TODO magic number
+            let ambient_temp = std::cmp::max(self.flags.ambient_temp + amb_noise, 40);
+
+            let ghost_room_temp = self.noisy_temp(self.ghost.ghost_room);
+            // admins get the strongest reading anywhere
+            let emf_level = match viewer {
+                Viewer::Everything => self.flags.emf_blasts.iter().map(|b| b.level).max(),
+                Viewer::Player(player) => player.last_loc.map(|room| self.emf_level_at(room)),
+                Viewer::Nobody => None,
+            };
+
+            let interference = self.interference();
+            let working = interference == Interference::None;
+            let everything = matches!(viewer, Viewer::Everything);
+
+            GameUpdate::Sim {
+                players: self
+                    .players
+                    .iter()
+                    .map(|p| {
+                        let temp = p.last_loc.map(|room| self.noisy_temp(room));
+                        let flicker = self.flashlight_flickers(p);
+                        let temp = temp.filter(|_| working);
+                        player_view(p, viewer, &self.map, temp, flicker)
+                    })
+                    .collect(),
+                ghost_location: Some(self.ghost.current_room).filter(|_| everything),
+                ghost_location_name: Some(self.room_name(self.ghost.current_room))
+                    .filter(|_| everything),
+                favorite_room: Some(self.ghost.ghost_room).filter(|_| everything),
+                favorite_room_name: Some(self.room_name(self.ghost.ghost_room))
+                    .filter(|_| everything),
+                cameras: self.camera_feeds(),
+                sensors: self.flags.sensors.clone(),
+                sensor_events: Some(self.flags.sensor_events.iter().cloned().collect())
+                    .filter(|_| everything),
+                dropped_items: self.flags.dropped_items.clone(),
+                room_objects: self.flags.room_objects.clone(),
+                hiding_spots: self.flags.hiding_spots.clone(),
+                bodies: self.flags.bodies.clone(),
+                sanity_pills_used: self.flags.pills_used,
+                setup_remaining: self
+                    .flags
+                    .setup_until
+                    .map(|end| end.saturating_sub(self.cur_time).as_secs_f64()),
+
+                emf_level: Some(emf_level.unwrap_or(0)).filter(|_| working),
+                emf_blasts: Some(self.flags.emf_blasts.clone()).filter(|_| everything),
+                ghost_room_temp: Some(ghost_room_temp)
+                    .filter(|_| working && viewer.sees(self.ghost.ghost_room)),
+                ambient_temp: Some(ambient_temp).filter(|_| working),
+                room_temps: Some(self.room_temps()).filter(|_| everything),
+                room_sounds: Some(self.flags.room_sounds.iter().map(|s| s.round() as u32).collect())
+                    .filter(|_| everything),
+                lights: (0..self.map.rooms.len()).map(|r| !self.is_room_dark(r)).collect(),
+                breaker_on: self.flags.breaker_on,
+                breaker_room: self.map.breaker_room(),
+                interference,
+                notifications: self.notices.iter().cloned().collect(),
+                last_notice: self.last_notice,
+                book_location: self.flags.book_location,
+                ghost_writing_visible: self.flags.ghost_writing_visible
+                    && self.flags.book_location.is_some_and(|room| viewer.sees(room)),
+                dots_location: self.flags.dots_location,
+                dots_visible: self.flags.dots_visible
+                    && self.flags.dots_location.is_some_and(|room| viewer.sees(room)),
+                ghost_manifested: self.flags.manifested
+                    && viewer.sees(self.ghost.current_room),
+                hunting: self.flags.is_hunting,
+                paused: self.paused,
+                map_id: self.map_id.clone(),
+                map_hash: self.map_hash.clone(),
+                difficulty: self.difficulty.clone(),
+            }
+        }
+    }
+
+    pub fn room_name(&self, label: RoomLabel) -> String {
+        self.map.room_name(label).to_owned()
+    }
+
+    pub fn resolve_room(&self, room: &RoomRef) -> Result<RoomLabel, String> {
+        self.map.resolve(room)
+    }
+
+    // The whole map is static, so it's only sent when asked for
+    pub fn get_map(&self) -> GameUpdate {
+        GameUpdate::Map {
+            map_id: self.map_id.clone(),
+            map_hash: self.map_hash.clone(),
+            map: self.map.clone(),
+        }
+    }
+
+    // Orbs only ever show up on camera, never to someone standing in the room
+    fn camera_feeds(&self) -> Vec<CameraFeed> {
+        self.flags
+            .cameras
+            .iter()
+            .map(|camera| CameraFeed {
+                id: camera.id,
+                room: camera.room,
+                orbs_visible: self.flags.orbs_visible && camera.room == self.ghost.ghost_room,
+                dots_visible: self.flags.dots_visible
+                    && self.flags.dots_location == Some(camera.room),
+                light_on: !self.is_room_dark(camera.room),
+                ghost_motion: camera.room == self.ghost.current_room,
+                player_motion: self
+                    .players
+                    .iter()
+                    .any(|p| p.last_loc == Some(camera.room)),
+            })
+            .collect()
+    }
+
+    // 1 with everyone at full sanity, climbing by low_sanity_activity as the
+    // team's sanity runs out
+    fn activity_multiplier(&self) -> f64 {
+        let lost = (100.0 - self.average_sanity()).clamp(0.0, 100.0) / 100.0;
+        1.0 + self.options.low_sanity_activity * lost
+    }
+
+    // Of the living, the dead don't count for anything
+    fn average_sanity(&self) -> f64 {
+        let alive: Vec<&Player> = self.players.iter().filter(|p| p.alive).collect();
+        if alive.is_empty() {
+            return 100.0;
+        }
+
+        let players: u32 = alive.len().try_into().unwrap();
+        let players: f64 = players.into();
+
+        let total: f64 = alive.iter().map(|p| p.sanity).sum();
+
+        total / players
+    }
+
+    fn is_room_dark(&self, room: RoomLabel) -> bool {
+        !(self.flags.breaker_on && self.flags.lights_on.get(room).copied().unwrap_or(false))
+    }
+
+    fn ghost_room_temp(&self) -> i32 {
+        let mins = (self.cur_time - self.flags.ghost_room_since).as_secs() / 5;
+        let room_rate = self.map.rooms[self.ghost.ghost_room].size.thermal_rate();
+        let rate = if self.behavior.fast_cooling() {
+            room_rate * self.options.hantu_cooling_multiplier
+        } else {
+            room_rate
+        };
+        let cooled = (self.flags.delta_temp as f64 * mins as f64 * rate) as i32;
+        std::cmp::max(self.flags.ambient_temp + cooled, self.flags.ghost_room_min_temp)
+    }
+
+    // Moves every room toward where it's headed: the ghost room along its
+    // cooling curve, wherever the ghost is a little under ambient, the rest
+    // back to ambient. Getting colder happens at once, warming back up takes
+    // a while, quicker in small rooms.
+    fn settle_temps(&mut self, dt: Duration) {
+        let ambient = self.flags.ambient_temp as f64;
+        for room in 0..self.flags.room_temps.len() {
+            let mut target = if room == self.ghost.ghost_room {
+                self.ghost_room_temp() as f64
+            } else {
+                ambient
+            };
+            if room == self.ghost.current_room {
+                target = target.min(ambient - GHOST_CHILL);
+            }
+            let rate = self.map.rooms[room].size.thermal_rate();
+            let temp = &mut self.flags.room_temps[room];
+            if target < *temp {
+                *temp = target;
+            } else {
+                let share = 1.0 - (-TEMP_RECOVERY_RATE * rate * dt.as_secs_f64()).exp();
+                *temp += (target - *temp) * share;
+            }
+        }
+    }
+
+    // Louder on top of whatever's still echoing, up to 100
+    fn make_sound(&mut self, room: RoomLabel, amount: f64) {
+        if let Some(level) = self.flags.room_sounds.get_mut(room) {
+            *level = (*level + amount).min(100.0);
+        }
+    }
+
+    fn room_sound(&self, room: RoomLabel) -> f64 {
+        self.flags.room_sounds.get(room).copied().unwrap_or(0.0)
+    }
+
+    // The ghost leaves a room colder just passing through
+    fn chill_room(&mut self, room: RoomLabel) {
+        let chilled = self.flags.ambient_temp as f64 - GHOST_CHILL;
+        if let Some(temp) = self.flags.room_temps.get_mut(room) {
+            *temp = temp.min(chilled);
+        }
+    }
+
+    fn room_temp(&self, room: RoomLabel) -> i32 {
+        self.flags
+            .room_temps
+            .get(room)
+            .map_or(self.flags.ambient_temp, |temp| temp.round() as i32)
+    }
+
+    fn room_temps(&self) -> Vec<i32> {
+        (0..self.flags.room_temps.len()).map(|room| self.room_temp(room)).collect()
+    }
+
+    // Only a freezing ghost's own room reads under the floor any other
+    // ghost cools its room to, noise or not
+    fn clamp_temp(&self, room: RoomLabel, temp: i32) -> i32 {
+        if room == self.ghost.ghost_room && self.shows_evidence(EvidenceType::Freezing) {
+            temp
+        } else {
+            std::cmp::max(temp, self.behavior.ghost_room_min_temp(&[]))
+        }
+    }
+
+    // As the state shows it, with this tick's jitter
+    fn noisy_temp(&self, room: RoomLabel) -> i32 {
+        let (amb_noise, gr_noise) = self.flags.temp_noise;
+        let noise = if room == self.ghost.ghost_room {
+            gr_noise
+        } else {
+            amb_noise
+        };
+        self.clamp_temp(room, self.room_temp(room) + noise)
+    }
+
+    // Blasts in different rooms, or the same one, all stand until their
+    // own time's up
+    fn blast_emf(&mut self, min_amount: u32, max_amount: u32) {
+        let level = self.rng.gen_range(min_amount..=max_amount);
+        let room = self.ghost.current_room;
+        self.log(SimEvent::EmfBlast { room, level });
+
+        let event = (level == EMF_EVIDENCE_LEVEL).then(|| {
+            let observed_by = self
+                .players
+                .iter()
+                .filter(|p| p.alive && p.last_loc == Some(room))
+                .map(|p| p.name.clone())
+                .collect();
+            self.flags.emf_events.push(EmfEvent {
+                time: self.cur_time,
+                room,
+                observed_by,
+            });
+            self.flags.emf_events.len() - 1
+        });
+
+        let id = self.flags.next_emf_id;
+        self.flags.next_emf_id += 1;
+        let until = self.cur_time + self.options.emf_blast_duration;
+        self.flags.emf_blasts.push(EmfBlast {
+            id,
+            room,
+            level,
+            until,
+            event,
+        });
+        self.event_triggers.push(until, EventTrigger::EndEMF(id));
+    }
+
+    // The strongest blast in the room, or one next door a level down, so
+    // a reader can be swept toward the source
+    fn emf_level_at(&self, room: RoomLabel) -> u32 {
+        self.flags
+            .emf_blasts
+            .iter()
+            .map(|blast| {
+                if blast.room == room {
+                    blast.level
+                } else if self.map.connection(blast.room, room).is_some() {
+                    blast.level.saturating_sub(1)
+                } else {
+                    0
+                }
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn log_emf_event(&self, event: &EmfEvent) {
+        let room = self.map.room_name(event.room);
+        if event.observed_by.is_empty() {
+            println!("EMF 5 in {} at {}s, nobody saw it", room, event.time.as_secs());
+        } else {
+            println!(
+                "EMF 5 in {} at {}s, seen by {}",
+                room,
+                event.time.as_secs(),
+                event.observed_by.join(", ")
+            );
+        }
+    }
+
+    fn round_options(
+        &self,
+        tunables: &OptionOverrides,
+        difficulty: &Difficulty,
+    ) -> Result<SimOptions, String> {
+        let options = SimOptions::new().with_overrides(tunables)?;
+        SimOptions::from_difficulty(&options, difficulty)
+    }
+
+    // Takes effect right away, a running round keeps its difficulty on top.
+    // Returns what changed, as "key: old -> new".
+    pub fn set_tunables(&mut self, tunables: OptionOverrides) -> Result<Vec<String>, String> {
+        let options = self.round_options(&tunables, &self.difficulty)?;
+        let old = serde_json::to_value(self.options.effective()).unwrap();
+        let new = serde_json::to_value(options.effective()).unwrap();
+
+        let mut changed = Vec::new();
+        if let (Some(old), Some(new)) = (old.as_object(), new.as_object()) {
+            for (key, value) in new {
+                match old.get(key) {
+                    Some(old_value) if old_value == value => (),
+                    Some(old_value) => changed.push(format!("{}: {} -> {}", key, old_value, value)),
+                    None => changed.push(format!("{}: {}", key, value)),
+                }
+            }
+        }
+        self.tunables = tunables;
+        self.options = options;
+        Ok(changed)
+    }
+
+    pub fn time(&self) -> Duration {
+        self.cur_time
+    }
+
+    // What the round's running with, difficulty and all
+    pub fn options(&self) -> OptionOverrides {
+        self.options.effective()
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        self.phase
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.phase == GamePhase::Running
+    }
+
+    pub fn difficulty(&self) -> &Difficulty {
+        &self.difficulty
+    }
+
+    // Every EMF 5 this round, in order
+    pub fn emf_events(&self) -> &[EmfEvent] {
+        &self.flags.emf_events
+    }
+
+    // Each player hears an interaction depending on how far away they are
+    fn queue_audio(&mut self, kind: &InteractionType) {
+        let room = self.ghost.current_room;
+        for player in self.players.iter().filter(|p| p.alive) {
+            // floors and ceilings muffle more than walls do
+            let loudness = player
+                .last_loc
+                .and_then(|loc| {
+                    let floors = self.map.floors_between(loc, room) as u32;
+                    let distance = self.map.distance(loc, room)?;
+                    Some(distance + floors * self.options.floor_sound_penalty)
+                })
+                .and_then(Loudness::from_distance);
+
+            if let Some(loudness) = loudness {
+                let cue = AudioCue {
+                    kind: kind.clone(),
+                    sound_id: kind.sound_id(),
+                    loudness,
+                };
+                self.audio_queue.push((player.addr, cue));
+            }
+        }
+    }
+
+    pub fn take_audio_queue(&mut self) -> Vec<(SocketAddr, AudioCue)> {
+        std::mem::take(&mut self.audio_queue)
+    }
+
+    fn notify(&mut self, notice: Notice) {
+        self.last_notice += 1;
+        let id = self.last_notice;
+        self.notices.push_back(NoticeEntry { id, notice });
+        if self.notices.len() > NOTICE_BACKLOG {
+            self.notices.pop_front();
+        }
+    }
+
+    // Decisions are only kept while an admin is watching
+    pub fn set_ghost_debug(&mut self, on: bool) {
+        self.ghost_debug = on;
+        if !on {
+            self.debug_queue.clear();
+        } else if self.is_started() {
+            // rolled before anyone was listening
+            self.debug(self.ghost_identity());
+            self.debug(GhostDecision::Personality(self.flags.personality));
+        }
+    }
+
+    fn debug(&mut self, decision: GhostDecision) {
+        if self.ghost_debug {
+            self.debug_queue.push(decision);
+        }
+    }
+
+    pub fn take_ghost_debug(&mut self) -> Vec<GhostDecision> {
+        std::mem::take(&mut self.debug_queue)
+    }
+
+    pub fn take_urgent(&mut self) -> bool {
+        std::mem::take(&mut self.urgent)
+    }
+}
+
+// Names match ignoring case and extra whitespace, so "Alice" and "alice "
+// can't both join. The name as typed is still what gets shown.
+pub fn name_key(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn player_view(
+    player: &Player,
+    viewer: Viewer,
+    map: &Map,
+    temp: Option<i32>,
+    flicker: bool,
+) -> PlayerView {
+    let (own, everything) = match viewer {
+        Viewer::Everything => (true, true),
+        Viewer::Player(me) => (me.addr == player.addr, false),
+        Viewer::Nobody => (false, false),
+    };
+    PlayerView {
+        name: player.name.clone(),
+        addr: player.addr,
+        last_loc: player.last_loc,
+        last_loc_name: player.last_loc.map(|room| map.room_name(room).to_owned()),
+        level: player.level,
+        money: player.money,
+        sanity: Some(player.sanity).filter(|_| own),
+        temp: temp.filter(|_| own),
+        alive: player.alive,
+        flashlight_on: player.flashlight_on,
+        flashlight_flicker: Some(flicker).filter(|_| own),
+        active_equipment: player.active_equipment.clone(),
+        flagged: Some(player.flagged).filter(|_| everything),
+        connected: player.connected,
+    }
+}
+
+fn lobby_player(player: &Player) -> LobbyPlayer {
+    LobbyPlayer {
+        name: player.name.clone(),
+        level: player.level,
+        money: player.money,
+        ready: player.ready,
+    }
+}
+
+// Catches players reporting rooms they couldn't have walked to since
+// their last update. Only an error when the check is enforced.
+fn check_movement(
+    player: &mut Player,
+    location: RoomLabel,
+    map: &Map,
+    options: &SimOptions,
+    now: Duration,
+) -> Result<(), String> {
+    let from = match player.last_loc {
+        Some(from) if options.movement_check != MovementCheck::Off => from,
+        _ => return Ok(()),
+    };
+    let distance = map.distance(from, location).unwrap_or(u32::MAX);
+    let too_fast = now - player.last_loc_time < options.movement_window;
+    if distance <= options.max_distance_per_window || !too_fast {
+        return Ok(());
+    }
+
+    player.movement_violations += 1;
+    if player.movement_violations >= options.movement_flag_threshold {
+        player.flagged = true;
+    }
+    let msg = format!(
+        "{} moved {} rooms from {} to {} too quickly",
+        player.name,
+        distance,
+        map.room_name(from),
+        map.room_name(location)
+    );
+    println!("{}", msg);
+
+    match options.movement_check {
+        MovementCheck::Enforce => Err(msg),
+        _ => Ok(()),
+    }
+}
+
+fn scale_hiding_spots(spots: u8, multiplier: f64) -> u8 {
+    (spots as f64 * multiplier.max(0.0)).floor().min(u8::MAX as f64) as u8
+}
+
+// The ghost stays indoors, and closed doors slow it down by `open_cost`
+// or stop it entirely
+fn ghost_cost<'a>(
+    map: &'a Map,
+    closed: &'a HashSet<DoorId>,
+    avoid_closed: bool,
+    open_cost: u32,
+) -> impl Fn(&Connection) -> Option<u32> + 'a {
+    move |conn| {
+        let weight = map.interior_cost(conn)?;
+        match conn.door {
+            Some(door) if closed.contains(&door) => {
+                if avoid_closed {
+                    None
+                } else {
+                    Some(weight.saturating_add(open_cost))
+                }
+            }
+            _ => Some(weight),
+        }
+    }
+}
+
+fn find_player_mut(players: &mut [Player], addr: SocketAddr) -> Result<&mut Player, String> {
+    players
+        .iter_mut()
+        .find(|p| p.addr == addr)
+        .ok_or_else(|| "Not registered".to_owned())
+}
+
+// What the ghost just decided and why, streamed to admins that ask
+#[derive(Serialize, Clone)]
+pub enum GhostDecision {
+    Move {
+        from: RoomLabel,
+        to: RoomLabel,
+        // what's left of the way to its target
+        path: Vec<RoomLabel>,
+    },
+    Target {
+        room: RoomLabel,
+    },
+    Roll {
+        kind: String,
+        chance: f64,
+        hit: bool,
+    },
+    InteractionScheduled {
+        rate: f64,
+        // none when the rate is zero
+        wait_secs: Option<f64>,
+    },
+    Interaction {
+        kind: InteractionType,
+        room: RoomLabel,
+    },
+    // only admins know where they all are
+    Fingerprints {
+        room: RoomLabel,
+    },
+    // placed, or burned stopping a hunt
+    Crucifix {
+        room: RoomLabel,
+        charges: u32,
+    },
+    Hiding {
+        name: String,
+        room: Option<RoomLabel>,
+        hidden: bool,
+    },
+    // every pulse, whether or not the team is low enough to be hunted
+    HuntCheck {
+        average_sanity: f64,
+        threshold: f64,
+        chance: f64,
+        hit: bool,
+    },
+    // picked at round start, never in the players' state
+    Identity {
+        ghost_type: GhostType,
+        shown_evidence: Vec<EvidenceType>,
+    },
+    // rolled at round start
+    Personality(Personality),
+}
+
+#[derive(Serialize, Clone)]
+pub struct EvidenceReveal {
+    ghost_type: GhostType,
+    evidence: Vec<EvidenceType>,
+    hidden: Vec<EvidenceType>,
+}
+
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct ActivityCounts {
+    pub interactions: u32,
+    pub events: u32,
+}
+
+pub struct SimFlags {
+    last_event_pulse: Duration,
+    last_ghost_move: Duration,
+
+    // still going, the ghost may have moved on from them since
+    emf_blasts: Vec<EmfBlast>,
+    next_emf_id: u64,
+    ghost_type: GhostType,
+    // the part of the ghost's evidence that shows up this round
+    shown_evidence: Vec<EvidenceType>,
+    personality: Personality,
+
+    // Temp
+    ghost_room_min_temp: i32,
+    delta_temp: i32,
+    ambient_temp: i32,
+    // the thermometers' jitter, ambient then ghost room. Rolled each tick
+    // so every view of a state agrees.
+    temp_noise: (i32, i32),
+    // by player key, how many of SANITY_MARKS they're under
+    sanity_bands: HashMap<String, usize>,
+
+    // when the ghost settled in its room, the room cools from then on
+    ghost_room_since: Duration,
+    // by label, see settle_temps
+    room_temps: Vec<f64>,
+    // by label, 0 to 100, dying down every tick
+    room_sounds: Vec<f64>,
+
+    // Ghost orbs
+    orbs_visible: bool,
+    book_location: Option<RoomLabel>,
+    ghost_writing_visible: bool,
+    // only the one projector, wherever it was put last
+    dots_location: Option<RoomLabel>,
+    dots_visible: bool,
+
+    // every one placed this round, burned out or not
+    crucifixes: Vec<Crucifix>,
+    smudges_used: u32,
+    pills_used: u32,
+    // no hunts until then
+    smudged_until: Duration,
+
+    // Video cameras
+    cameras: Vec<Camera>,
+    next_camera_id: u32,
+
+    sensors: Vec<Sensor>,
+    next_sensor_id: u32,
+    // the newest SENSOR_EVENTS_KEPT
+    sensor_events: VecDeque<SensorEvent>,
+
+    // Equipment left in the truck and lying around the map
+    equipment_pool: Vec<EquipmentKind>,
+    dropped_items: Vec<(RoomLabel, EquipmentKind)>,
+
+    // Throwables left in each room, indexed by label
+    room_objects: Vec<u32>,
+    // hiding spots this round, after the difficulty multiplier
+    hiding_spots: Vec<u8>,
+
+    // Everyone who died this round, several can share a room
+    bodies: Vec<Body>,
+
+    emf_events: Vec<EmfEvent>,
+
+    // doors are open unless someone shut them
+    closed_doors: HashSet<DoorId>,
+    // by label, switches stay where they were when the breaker goes
+    lights_on: Vec<bool>,
+    breaker_on: bool,
+
+    // rate the pending GhostInteraction was drawn with
+    scheduled_interaction_rate: f64,
+    // an event drowns out interactions until the next pulse
+    interactions_suppressed_until: Duration,
+    manifested: bool,
+    activity: ActivityCounts,
+
+    // while the team is still setting up, when that ends
+    setup_until: Option<Duration>,
+    is_hunting: bool,
+    // rolled a hunt, flickering until it starts
+    hunt_warning: bool,
+    // when the warning turns into a hunt, or the hunt ends
+    hunt_timer: Option<TriggerId>,
+    // when the dots stop showing
+    dots_timer: Option<TriggerId>,
+    // every room's temperature and everyone's sanity as of the last
+    // periodic refresh
+    last_readings: Option<(Vec<i32>, Vec<u32>)>,
+    // rooms with prints fresh enough to see under UV
+    fingerprints: Vec<RoomLabel>,
+    // set while the ghost walks back to its room after a hunt
+    returning_since: Option<Duration>,
+}
+
+// sanity levels that go in the event log when someone drops under them,
+// highest first
+const SANITY_MARKS: [u32; 3] = [75, 50, 25];
+
+// how much of a room's sound a parabolic microphone picks up from the
+// rooms past the one it's pointed at
+const PARABOLIC_FALLOFF: f64 = 0.5;
+
+// how many sensor firings a state carries
+const SENSOR_EVENTS_KEPT: usize = 32;
+
+// how far under ambient a room drops with the ghost in it
+const GHOST_CHILL: f64 = 6.0;
+// how fast a medium room warms back up, the share of the way left it
+// covers each second, roughly
+const TEMP_RECOVERY_RATE: f64 = 0.05;
+
+// how long the ghost room takes to get as cold as it will get
+const MINS_TO_MIN_TEMP: std::ops::Range<i32> = 4..10;
+
+impl SimFlags {
+    fn new(rng: &mut StdRng) -> Self {
+        let ghost_type = GhostType::Spirit;
+
+        let ambient_temp = 50;
+        let shown_evidence = behavior_for(ghost_type).evidence().to_vec();
+        let ghost_room_min_temp = behavior_for(ghost_type).ghost_room_min_temp(&shown_evidence);
+
+        let mins_to_min_temp = rng.gen_range(MINS_TO_MIN_TEMP);
+
+        // change in temperature per minute
+        let delta_temp = (ghost_room_min_temp - ambient_temp) / mins_to_min_temp;
+
+        SimFlags {
+            last_ghost_move: Duration::from_secs(0),
+            last_event_pulse: Duration::from_secs(0),
+            emf_blasts: Vec::new(),
+            next_emf_id: 0,
+            ghost_type,
+            shown_evidence,
+            personality: Personality::default(),
+
+            ambient_temp,
+            ghost_room_min_temp,
+            delta_temp,
+            temp_noise: (0, 0),
+            sanity_bands: HashMap::new(),
+
+            ghost_room_since: Duration::from_secs(0),
+            room_temps: Vec::new(),
+            room_sounds: Vec::new(),
+            orbs_visible: false,
+            book_location: None,
+            ghost_writing_visible: false,
+            dots_location: None,
+            dots_visible: false,
+            crucifixes: Vec::new(),
+            smudges_used: 0,
+            pills_used: 0,
+            smudged_until: Duration::from_secs(0),
+            cameras: Vec::new(),
+            next_camera_id: 0,
+            sensors: Vec::new(),
+            next_sensor_id: 0,
+            sensor_events: VecDeque::new(),
+            equipment_pool: Vec::new(),
+            dropped_items: Vec::new(),
+            room_objects: Vec::new(),
+            hiding_spots: Vec::new(),
+            bodies: Vec::new(),
+            emf_events: Vec::new(),
+            closed_doors: HashSet::new(),
+            lights_on: Vec::new(),
+            breaker_on: true,
+            scheduled_interaction_rate: 0.0,
+            interactions_suppressed_until: Duration::from_secs(0),
+            manifested: false,
+            activity: ActivityCounts::default(),
+            setup_until: None,
+            is_hunting: false,
+            hunt_warning: false,
+            hunt_timer: None,
+            dots_timer: None,
+            last_readings: None,
+            fingerprints: Vec::new(),
+            returning_since: None,
+        }
+    }
+    fn set_ghost_room_min_temp(&mut self, min_temp: i32, rng: &mut StdRng) {
+        let mins_to_min_temp = rng.gen_range(MINS_TO_MIN_TEMP);
+        self.ghost_room_min_temp = min_temp;
+        self.delta_temp = (min_temp - self.ambient_temp) / mins_to_min_temp;
+    }
+
+}
+
+#[derive(Clone)]
+pub struct SimOptions {
+    ghost_move_interval: Duration,
+    event_pulse_interval: Duration,
+    ghost_orbs_duration: Duration,
+    // rates are in events per minute
+    ghost_orbs_rate: f64,
+    temperature_variability: i32,
+    thermometer_update_interval: Duration,
+    hantu_cooling_multiplier: f64,
+
+    ghost_interaction_rate: f64,
+    ghost_event_rate: f64,
+    // off keeps the ghost in the room it started in
+    room_shifts: bool,
+    room_shift_rate: f64,
+    // an EMF blast and a noise where the ghost is when it moves rooms
+    room_shift_hint: bool,
+    ghost_event_duration: Duration,
+    ghost_event_sanity_loss: f64,
+    // what an interaction costs everyone inside, more in the ghost's room
+    interaction_sanity_loss: f64,
+    interaction_sanity_loss_elsewhere: f64,
+    // how much of its evidence the ghost shows, 3 is all of it
+    evidence_given: u8,
+
+    emf_blast_duration: Duration,
+
+    ghost_hunt_rate: f64,
+    ghost_hunt_duration: Duration,
+    // no hunts while the team's average sanity is above this
+    hunt_sanity_threshold: f64,
+    // how much more the ghost does with the team's sanity gone, on top of
+    // its rate at full sanity
+    low_sanity_activity: f64,
+    // the same for hunts, counted down from the threshold
+    low_sanity_hunts: f64,
+    // how often the ghost moves while hunting
+    hunt_move_interval: Duration,
+    // flickering between rolling a hunt and starting it
+    hunt_warning: Duration,
+    // per interaction, for a ghost showing ultraviolet
+    fingerprint_chance: f64,
+    fingerprint_duration: Duration,
+    // per ghost move through the projector's room, for a ghost showing dots
+    dots_chance: f64,
+    dots_duration: Duration,
+    // per question, when everything else lines up
+    spirit_box_response_chance: f64,
+    spirit_box_cooldown: Duration,
+    parabolic_cooldown: Duration,
+    // how loud the ghost gets, 0 to 100, and how fast it dies down per second
+    footstep_sound: f64,
+    interaction_sound: f64,
+    // ghost events and hunts
+    ghost_event_sound: f64,
+    sound_decay_rate: f64,
+    // per ghost move, for each player hiding in its room
+    hide_discovery_chance: f64,
+    // how much more a hunting ghost goes for someone with a flashlight on
+    flashlight_target_weight: f64,
+    // how close a hunting ghost makes flashlights flicker
+    flicker_radius: u32,
+    // how far a hunting ghost senses players, with and without electronics on
+    detection_radius: u32,
+    dark_detection_radius: u32,
+    // on top of the normal drain, while hidden from a hunt
+    hidden_drain_multiplier: f64,
+    ghost_return_timeout: Duration,
+    // how long a round keeps going once every player has disconnected
+    abandon_grace: Duration,
+    // no hunts at the start of a round, and less of everything else
+    setup_duration: Duration,
+    setup_drain_multiplier: f64,
+    setup_interaction_multiplier: f64,
+
+    // extra move intervals it takes the ghost to get through a closed door
+    door_open_cost: u32,
+    // extra distance per floor between a sound and the listener
+    floor_sound_penalty: u32,
+
+    sanity_drain_rate: f64,
+    flashlight_drain_multiplier: f64,
+    // for a room with its lights on, whatever the flashlight's doing
+    lit_drain_multiplier: f64,
+    body_discovery_sanity_loss: f64,
+
+    camera_limit: usize,
+    sensor_limit: usize,
+    // how many rooms can be lit before the breaker trips, 0 for no limit
+    breaker_light_limit: usize,
+    // per lights flicker interaction
+    lights_out_chance: f64,
+    breaker_trip_chance: f64,
+    crucifix_limit: usize,
+    // hunts each crucifix can stop
+    crucifix_charges: u32,
+    smudge_limit: u32,
+    // how long a smudged ghost can't hunt
+    smudge_duration: Duration,
+    // doses for the whole team
+    sanity_pills_limit: u32,
+    sanity_pills_amount: f64,
+    // scales every room's hiding spots at round start, harder games use less
+    hiding_spot_multiplier: f64,
+    starting_equipment: Vec<EquipmentKind>,
+    // anything not listed is Tier I
+    equipment_tiers: Vec<(EquipmentKind, Tier)>,
+
+    // how hard to be on players whose position jumps around
+    movement_check: MovementCheck,
+    max_distance_per_window: u32,
+    movement_window: Duration,
+    movement_flag_threshold: u32,
+
+    // how likely the ghost is to pick each kind of room as its favorite
+    room_category_weights: Vec<(RoomCategory, f64)>,
+
+    payouts: Payouts,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MovementCheck {
+    Off,
+    // log impossible moves but accept them
+    Warn,
+    Enforce,
+}
+
+impl SimOptions {
+    fn new() -> Self {
+        SimOptions {
+            ghost_move_interval: Duration::from_secs(10),
+            event_pulse_interval: Duration::from_secs(10),
+
+            ghost_orbs_duration: Duration::from_secs(20),
+            ghost_orbs_rate: 1.0, // per minute
+
+            temperature_variability: 5,
+            thermometer_update_interval: Duration::from_secs(2),
+            hantu_cooling_multiplier: 2.0,
+
+            ghost_interaction_rate: 3.0, // per minute
+            ghost_event_rate: 1.0,       // per minute
+            room_shifts: true,
+            room_shift_rate: 0.1, // per minute
+            room_shift_hint: true,
+            ghost_event_duration: Duration::from_secs(3),
+            ghost_event_sanity_loss: 10.0,
+            interaction_sanity_loss: 15.0,
+            interaction_sanity_loss_elsewhere: 5.0,
+            evidence_given: 3,
+            ghost_hunt_rate: 1.0,        // per minute
+            ghost_hunt_duration: Duration::from_secs(30),
+            hunt_sanity_threshold: 50.0,
+            low_sanity_activity: 1.0,
+            low_sanity_hunts: 1.0,
+            hunt_move_interval: Duration::from_secs(4),
+            hunt_warning: Duration::from_secs(3),
+            fingerprint_chance: 0.5,
+            fingerprint_duration: Duration::from_secs(120),
+            dots_chance: 0.5,
+            dots_duration: Duration::from_secs(3),
+            spirit_box_response_chance: 0.33,
+            spirit_box_cooldown: Duration::from_secs(5),
+            parabolic_cooldown: Duration::from_secs(1),
+            footstep_sound: 20.0,
+            interaction_sound: 50.0,
+            ghost_event_sound: 90.0,
+            sound_decay_rate: 25.0,
+            hide_discovery_chance: 0.25,
+            flashlight_target_weight: 2.0,
+            flicker_radius: 2,
+            detection_radius: 8,
+            dark_detection_radius: 2,
+            hidden_drain_multiplier: 2.0,
+            ghost_return_timeout: Duration::from_secs(60),
+            abandon_grace: Duration::from_secs(5 * 60),
+            setup_duration: Duration::from_secs(2 * 60),
+            setup_drain_multiplier: 0.0,
+            setup_interaction_multiplier: 0.5,
+            emf_blast_duration: Duration::from_secs(3),
+            door_open_cost: 1,
+            floor_sound_penalty: 1,
+
+            sanity_drain_rate: 0.05, // %/s
+            flashlight_drain_multiplier: 0.8,
+            lit_drain_multiplier: 0.5,
+            body_discovery_sanity_loss: 10.0,
+
+            camera_limit: 6,
+            sensor_limit: 4,
+            breaker_light_limit: 0,
+            lights_out_chance: 0.3,
+            breaker_trip_chance: 0.05,
+            crucifix_limit: 2,
+            crucifix_charges: 2,
+            smudge_limit: 2,
+            smudge_duration: Duration::from_secs(90),
+            sanity_pills_limit: 4,
+            sanity_pills_amount: 40.0,
+            hiding_spot_multiplier: 1.0,
+            starting_equipment: vec![
+                EquipmentKind::EmfReader,
+                EquipmentKind::EmfReader,
+                EquipmentKind::Thermometer,
+                EquipmentKind::Thermometer,
+                EquipmentKind::SpiritBox,
+                EquipmentKind::UvLight,
+                EquipmentKind::VideoCamera,
+                EquipmentKind::VideoCamera,
+                EquipmentKind::VideoCamera,
+                EquipmentKind::DotsProjector,
+                EquipmentKind::ParabolicMicrophone,
+                EquipmentKind::Book,
+                EquipmentKind::Crucifix,
+                EquipmentKind::Smudge,
+                EquipmentKind::Salt,
+                EquipmentKind::SanityPills,
+            ],
+
+            equipment_tiers: vec![
+                (EquipmentKind::EmfReader, Tier::I),
+                (EquipmentKind::Thermometer, Tier::I),
+                (EquipmentKind::SpiritBox, Tier::I),
+            ],
+
+            movement_check: MovementCheck::Enforce,
+            max_distance_per_window: 2,
+            movement_window: Duration::from_secs(1),
+            movement_flag_threshold: 3,
+
+            room_category_weights: vec![
+                (RoomCategory::Basement, 3.0),
+                (RoomCategory::Attic, 2.5),
+                (RoomCategory::Bedroom, 2.0),
+                (RoomCategory::Bathroom, 1.5),
+                (RoomCategory::Hallway, 0.0),
+            ],
+
+            payouts: Payouts::default(),
+        }
+    }
+
+    // The base options with the overrides on top, or every field that's
+    // out of range
+    fn with_overrides(&self, overrides: &OptionOverrides) -> Result<Self, String> {
+        let mut options = self.clone();
+        let o = overrides;
+        let secs = Duration::from_secs_f64;
+        if let Some(v) = o.ghost_move_interval {
+            options.ghost_move_interval = secs(check(v, 0.5, 600.0, "ghost_move_interval")?);
+        }
+        if let Some(v) = o.event_pulse_interval {
+            options.event_pulse_interval = secs(check(v, 0.5, 600.0, "event_pulse_interval")?);
+        }
+        if let Some(v) = o.ghost_hunt_duration {
+            options.ghost_hunt_duration = secs(check(v, 1.0, 300.0, "ghost_hunt_duration")?);
+        }
+        if let Some(v) = o.hunt_move_interval {
+            options.hunt_move_interval = secs(check(v, 0.5, 600.0, "hunt_move_interval")?);
+        }
+        if let Some(v) = o.hunt_sanity_threshold {
+            options.hunt_sanity_threshold = check(v, 0.0, 100.0, "hunt_sanity_threshold")?;
+        }
+        if let Some(v) = o.low_sanity_activity {
+            options.low_sanity_activity = check(v, 0.0, 10.0, "low_sanity_activity")?;
+        }
+        if let Some(v) = o.low_sanity_hunts {
+            options.low_sanity_hunts = check(v, 0.0, 10.0, "low_sanity_hunts")?;
+        }
+        if let Some(v) = o.interaction_sanity_loss {
+            options.interaction_sanity_loss = check(v, 0.0, 100.0, "interaction_sanity_loss")?;
+        }
+        if let Some(v) = o.interaction_sanity_loss_elsewhere {
+            options.interaction_sanity_loss_elsewhere =
+                check(v, 0.0, 100.0, "interaction_sanity_loss_elsewhere")?;
+        }
+        if let Some(v) = o.hunt_warning {
+            options.hunt_warning = secs(check(v, 0.0, 30.0, "hunt_warning")?);
+        }
+        if let Some(v) = o.fingerprint_chance {
+            options.fingerprint_chance = check(v, 0.0, 1.0, "fingerprint_chance")?;
+        }
+        if let Some(v) = o.fingerprint_duration {
+            options.fingerprint_duration = secs(check(v, 1.0, 900.0, "fingerprint_duration")?);
+        }
+        if let Some(v) = o.dots_chance {
+            options.dots_chance = check(v, 0.0, 1.0, "dots_chance")?;
+        }
+        if let Some(v) = o.dots_duration {
+            options.dots_duration = secs(check(v, 0.5, 60.0, "dots_duration")?);
+        }
+        if let Some(v) = o.spirit_box_response_chance {
+            options.spirit_box_response_chance =
+                check(v, 0.0, 1.0, "spirit_box_response_chance")?;
+        }
+        if let Some(v) = o.spirit_box_cooldown {
+            options.spirit_box_cooldown = secs(check(v, 0.0, 60.0, "spirit_box_cooldown")?);
+        }
+        if let Some(v) = o.parabolic_cooldown {
+            options.parabolic_cooldown = secs(check(v, 0.0, 60.0, "parabolic_cooldown")?);
+        }
+        if let Some(v) = o.footstep_sound {
+            options.footstep_sound = check(v, 0.0, 100.0, "footstep_sound")?;
+        }
+        if let Some(v) = o.interaction_sound {
+            options.interaction_sound = check(v, 0.0, 100.0, "interaction_sound")?;
+        }
+        if let Some(v) = o.ghost_event_sound {
+            options.ghost_event_sound = check(v, 0.0, 100.0, "ghost_event_sound")?;
+        }
+        if let Some(v) = o.sound_decay_rate {
+            options.sound_decay_rate = check(v, 1.0, 100.0, "sound_decay_rate")?;
+        }
+        if let Some(v) = o.hide_discovery_chance {
+            options.hide_discovery_chance = check(v, 0.0, 1.0, "hide_discovery_chance")?;
+        }
+        if let Some(v) = o.flashlight_target_weight {
+            options.flashlight_target_weight = check(v, 0.1, 10.0, "flashlight_target_weight")?;
+        }
+        if let Some(v) = o.flicker_radius {
+            options.flicker_radius = check(v as f64, 0.0, 20.0, "flicker_radius")? as u32;
+        }
+        if let Some(v) = o.detection_radius {
+            options.detection_radius = check(v as f64, 0.0, 50.0, "detection_radius")? as u32;
+        }
+        if let Some(v) = o.dark_detection_radius {
+            let v = check(v as f64, 0.0, 50.0, "dark_detection_radius")? as u32;
+            options.dark_detection_radius = v;
+        }
+        if options.dark_detection_radius > options.detection_radius {
+            return Err("dark_detection_radius must not exceed detection_radius".to_owned());
+        }
+        if let Some(v) = o.hidden_drain_multiplier {
+            options.hidden_drain_multiplier = check(v, 0.0, 10.0, "hidden_drain_multiplier")?;
+        }
+        if let Some(v) = o.ghost_orbs_duration {
+            options.ghost_orbs_duration = secs(check(v, 1.0, 300.0, "ghost_orbs_duration")?);
+        }
+        if let Some(v) = o.emf_blast_duration {
+            options.emf_blast_duration = secs(check(v, 0.5, 60.0, "emf_blast_duration")?);
+        }
+        if let Some(v) = o.abandon_grace {
+            options.abandon_grace = secs(check(v, 0.0, 3600.0, "abandon_grace")?);
+        }
+        if let Some(v) = o.setup_duration {
+            options.setup_duration = secs(check(v, 0.0, 900.0, "setup_duration")?);
+        }
+        if let Some(v) = o.setup_drain_multiplier {
+            options.setup_drain_multiplier = check(v, 0.0, 1.0, "setup_drain_multiplier")?;
+        }
+        if let Some(v) = o.setup_interaction_multiplier {
+            options.setup_interaction_multiplier =
+                check(v, 0.0, 1.0, "setup_interaction_multiplier")?;
+        }
+        if let Some(v) = o.temperature_variability {
+            options.temperature_variability =
+                check(v as f64, 0.0, 20.0, "temperature_variability")? as i32;
+        }
+        if let Some(v) = o.ghost_orbs_rate {
+            options.ghost_orbs_rate = check(v, 0.0, MAX_RATE, "ghost_orbs_rate")?;
+        }
+        if let Some(v) = o.ghost_interaction_rate {
+            options.ghost_interaction_rate = check(v, 0.0, MAX_RATE, "ghost_interaction_rate")?;
+        }
+        if let Some(v) = o.ghost_event_rate {
+            options.ghost_event_rate = check(v, 0.0, MAX_RATE, "ghost_event_rate")?;
+        }
+        if let Some(v) = o.room_shifts {
+            options.room_shifts = v;
+        }
+        if let Some(v) = o.room_shift_rate {
+            options.room_shift_rate = check(v, 0.0, MAX_RATE, "room_shift_rate")?;
+        }
+        if let Some(v) = o.room_shift_hint {
+            options.room_shift_hint = v;
+        }
+        if let Some(v) = o.ghost_hunt_rate {
+            options.ghost_hunt_rate = check(v, 0.0, MAX_RATE, "ghost_hunt_rate")?;
+        }
+        if let Some(v) = o.sanity_drain_rate {
+            options.sanity_drain_rate = check(v, 0.0, 5.0, "sanity_drain_rate")?;
+        }
+        if let Some(v) = o.flashlight_drain_multiplier {
+            options.flashlight_drain_multiplier =
+                check(v, 0.0, 2.0, "flashlight_drain_multiplier")?;
+        }
+        if let Some(v) = o.lit_drain_multiplier {
+            options.lit_drain_multiplier = check(v, 0.0, 2.0, "lit_drain_multiplier")?;
+        }
+        if let Some(v) = o.hiding_spot_multiplier {
+            options.hiding_spot_multiplier = check(v, 0.0, 4.0, "hiding_spot_multiplier")?;
+        }
+        if let Some(v) = o.camera_limit {
+            options.camera_limit = check(v as f64, 0.0, 20.0, "camera_limit")? as usize;
+        }
+        if let Some(v) = o.sensor_limit {
+            options.sensor_limit = check(v as f64, 0.0, 20.0, "sensor_limit")? as usize;
+        }
+        if let Some(v) = o.breaker_light_limit {
+            options.breaker_light_limit =
+                check(v as f64, 0.0, 100.0, "breaker_light_limit")? as usize;
+        }
+        if let Some(v) = o.lights_out_chance {
+            options.lights_out_chance = check(v, 0.0, 1.0, "lights_out_chance")?;
+        }
+        if let Some(v) = o.breaker_trip_chance {
+            options.breaker_trip_chance = check(v, 0.0, 1.0, "breaker_trip_chance")?;
+        }
+        if let Some(v) = o.crucifix_limit {
+            options.crucifix_limit = check(v as f64, 0.0, 10.0, "crucifix_limit")? as usize;
+        }
+        if let Some(v) = o.crucifix_charges {
+            options.crucifix_charges = check(v as f64, 1.0, 10.0, "crucifix_charges")? as u32;
+        }
+        if let Some(v) = o.smudge_limit {
+            options.smudge_limit = check(v as f64, 0.0, 10.0, "smudge_limit")? as u32;
+        }
+        if let Some(v) = o.smudge_duration {
+            options.smudge_duration = secs(check(v, 1.0, 600.0, "smudge_duration")?);
+        }
+        if let Some(v) = o.sanity_pills_limit {
+            options.sanity_pills_limit = check(v as f64, 0.0, 20.0, "sanity_pills_limit")? as u32;
+        }
+        if let Some(v) = o.sanity_pills_amount {
+            options.sanity_pills_amount = check(v, 0.0, 100.0, "sanity_pills_amount")?;
+        }
+        if let Some(v) = o.evidence_given {
+            options.evidence_given = check(v as f64, 0.0, 3.0, "evidence_given")? as u8;
+        }
+        for (kind, tier) in o.equipment_tiers.iter().flatten() {
+            options.equipment_tiers.retain(|(k, _)| k != kind);
+            options.equipment_tiers.push((*kind, *tier));
+        }
+        Ok(options)
+    }
+
+    // The preset scaled onto these options, then any custom overrides
+    fn from_difficulty(base: &SimOptions, difficulty: &Difficulty) -> Result<Self, String> {
+        let scale = difficulty.preset().scale();
+        let mut options = base.clone();
+        options.sanity_drain_rate *= scale.sanity_drain;
+        options.ghost_hunt_rate *= scale.hunt_rate;
+        options.ghost_interaction_rate *= scale.interaction_rate;
+        options.ghost_orbs_duration = options.ghost_orbs_duration.mul_f64(scale.orbs_duration);
+        options.hiding_spot_multiplier *= scale.hiding_spots;
+        options.setup_duration = options.setup_duration.mul_f64(scale.setup);
+        options.evidence_given = options.evidence_given.saturating_sub(scale.evidence_hidden);
+        match difficulty {
+            Difficulty::Custom { overrides, .. } => options.with_overrides(overrides),
+            _ => Ok(options),
+        }
+    }
+
+    fn tier(&self, kind: EquipmentKind) -> Tier {
+        self.equipment_tiers
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, tier)| *tier)
+            .unwrap_or_default()
+    }
+
+    // Every overridable option as it stands, for showing players what
+    // they're in for
+    fn effective(&self) -> OptionOverrides {
+        OptionOverrides {
+            ghost_move_interval: Some(self.ghost_move_interval.as_secs_f64()),
+            event_pulse_interval: Some(self.event_pulse_interval.as_secs_f64()),
+            ghost_hunt_duration: Some(self.ghost_hunt_duration.as_secs_f64()),
+            hunt_move_interval: Some(self.hunt_move_interval.as_secs_f64()),
+            hunt_sanity_threshold: Some(self.hunt_sanity_threshold),
+            low_sanity_activity: Some(self.low_sanity_activity),
+            low_sanity_hunts: Some(self.low_sanity_hunts),
+            interaction_sanity_loss: Some(self.interaction_sanity_loss),
+            interaction_sanity_loss_elsewhere: Some(self.interaction_sanity_loss_elsewhere),
+            hunt_warning: Some(self.hunt_warning.as_secs_f64()),
+            fingerprint_chance: Some(self.fingerprint_chance),
+            fingerprint_duration: Some(self.fingerprint_duration.as_secs_f64()),
+            dots_chance: Some(self.dots_chance),
+            dots_duration: Some(self.dots_duration.as_secs_f64()),
+            spirit_box_response_chance: Some(self.spirit_box_response_chance),
+            spirit_box_cooldown: Some(self.spirit_box_cooldown.as_secs_f64()),
+            parabolic_cooldown: Some(self.parabolic_cooldown.as_secs_f64()),
+            footstep_sound: Some(self.footstep_sound),
+            interaction_sound: Some(self.interaction_sound),
+            ghost_event_sound: Some(self.ghost_event_sound),
+            sound_decay_rate: Some(self.sound_decay_rate),
+            hide_discovery_chance: Some(self.hide_discovery_chance),
+            flashlight_target_weight: Some(self.flashlight_target_weight),
+            flicker_radius: Some(self.flicker_radius),
+            detection_radius: Some(self.detection_radius),
+            dark_detection_radius: Some(self.dark_detection_radius),
+            hidden_drain_multiplier: Some(self.hidden_drain_multiplier),
+            ghost_orbs_duration: Some(self.ghost_orbs_duration.as_secs_f64()),
+            emf_blast_duration: Some(self.emf_blast_duration.as_secs_f64()),
+            abandon_grace: Some(self.abandon_grace.as_secs_f64()),
+            setup_duration: Some(self.setup_duration.as_secs_f64()),
+            setup_drain_multiplier: Some(self.setup_drain_multiplier),
+            setup_interaction_multiplier: Some(self.setup_interaction_multiplier),
+            temperature_variability: Some(self.temperature_variability),
+            ghost_orbs_rate: Some(self.ghost_orbs_rate),
+            ghost_interaction_rate: Some(self.ghost_interaction_rate),
+            ghost_event_rate: Some(self.ghost_event_rate),
+            room_shifts: Some(self.room_shifts),
+            room_shift_rate: Some(self.room_shift_rate),
+            room_shift_hint: Some(self.room_shift_hint),
+            ghost_hunt_rate: Some(self.ghost_hunt_rate),
+            sanity_drain_rate: Some(self.sanity_drain_rate),
+            flashlight_drain_multiplier: Some(self.flashlight_drain_multiplier),
+            lit_drain_multiplier: Some(self.lit_drain_multiplier),
+            hiding_spot_multiplier: Some(self.hiding_spot_multiplier),
+            camera_limit: Some(self.camera_limit),
+            sensor_limit: Some(self.sensor_limit),
+            breaker_light_limit: Some(self.breaker_light_limit),
+            lights_out_chance: Some(self.lights_out_chance),
+            breaker_trip_chance: Some(self.breaker_trip_chance),
+            crucifix_limit: Some(self.crucifix_limit),
+            crucifix_charges: Some(self.crucifix_charges),
+            smudge_limit: Some(self.smudge_limit),
+            smudge_duration: Some(self.smudge_duration.as_secs_f64()),
+            sanity_pills_limit: Some(self.sanity_pills_limit),
+            sanity_pills_amount: Some(self.sanity_pills_amount),
+            evidence_given: Some(self.evidence_given),
+            equipment_tiers: Some(self.equipment_tiers.clone()),
+        }
+    }
+}
+
+// per minute, anything above this is a typo
+const MAX_RATE: f64 = 60.0;
+
+fn check(value: f64, min: f64, max: f64, field: &str) -> Result<f64, String> {
+    if value.is_finite() && (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} must be between {} and {}, got {}", field, min, max, value))
+    }
+}
+
+// Options a host may change for a custom round, anything left out keeps
+// the preset's value. Durations are in seconds, rates per minute.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OptionOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_move_interval: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_pulse_interval: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_hunt_duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hunt_move_interval: Option<f64>,
+    // average sanity, in percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hunt_sanity_threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_sanity_activity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_sanity_hunts: Option<f64>,
+    // per interaction, in the ghost's room and anywhere else inside
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interaction_sanity_loss: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interaction_sanity_loss_elsewhere: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hunt_warning: Option<f64>,
+    // 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint_chance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint_duration: Option<f64>,
+    // 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dots_chance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dots_duration: Option<f64>,
+    // 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spirit_box_response_chance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spirit_box_cooldown: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parabolic_cooldown: Option<f64>,
+    // 0 to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub footstep_sound: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interaction_sound: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_event_sound: Option<f64>,
+    // per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sound_decay_rate: Option<f64>,
+    // 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hide_discovery_chance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flashlight_target_weight: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flicker_radius: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection_radius: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark_detection_radius: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hidden_drain_multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_orbs_duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emf_blast_duration: Option<f64>,
+    // how long a round waits for someone to reconnect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abandon_grace: Option<f64>,
+    // 0 turns setup off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup_duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup_drain_multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup_interaction_multiplier: Option<f64>,
+    // degrees either way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_variability: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_orbs_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_interaction_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_event_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_shifts: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_shift_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_shift_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ghost_hunt_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sanity_drain_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flashlight_drain_multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lit_drain_multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hiding_spot_multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_limit: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor_limit: Option<usize>,
+    // 0 for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breaker_light_limit: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lights_out_chance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breaker_trip_chance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crucifix_limit: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crucifix_charges: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smudge_limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smudge_duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sanity_pills_limit: Option<u32>,
+    // sanity per dose, in percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sanity_pills_amount: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evidence_given: Option<u8>,
+    // only the items listed change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equipment_tiers: Option<Vec<(EquipmentKind, Tier)>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum Preset {
+    Amateur,
+    // the options as configured, untouched
+    #[default]
+    #[serde(alias = "Standard")]
+    Intermediate,
+    Professional,
+    Nightmare,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub enum Difficulty {
+    Amateur,
+    #[default]
+    #[serde(alias = "Standard")]
+    Intermediate,
+    Professional,
+    Nightmare,
+    Custom {
+        #[serde(default)]
+        base: Preset,
+        // boxed, it's much bigger than the rest of the enum
+        overrides: Box<OptionOverrides>,
+    },
+}
+
+impl Difficulty {
+    pub fn preset(&self) -> Preset {
+        match self {
+            Difficulty::Amateur => Preset::Amateur,
+            Difficulty::Intermediate => Preset::Intermediate,
+            Difficulty::Professional => Preset::Professional,
+            Difficulty::Nightmare => Preset::Nightmare,
+            Difficulty::Custom { base, .. } => *base,
+        }
+    }
+}
+
+// How a preset bends the configured options, as multipliers so the host's
+// own tuning still counts
+struct PresetScale {
+    sanity_drain: f64,
+    hunt_rate: f64,
+    interaction_rate: f64,
+    orbs_duration: f64,
+    hiding_spots: f64,
+    setup: f64,
+    // pieces of evidence the ghost keeps to itself
+    evidence_hidden: u8,
+}
+
+impl Preset {
+    // Every preset's numbers, rebalance here
+    fn scale(&self) -> PresetScale {
+        match self {
+            Preset::Amateur => PresetScale {
+                sanity_drain: 0.5,
+                hunt_rate: 0.5,
+                interaction_rate: 1.25,
+                orbs_duration: 1.5,
+                hiding_spots: 1.5,
+                setup: 1.5,
+                evidence_hidden: 0,
+            },
+            Preset::Intermediate => PresetScale {
+                sanity_drain: 1.0,
+                hunt_rate: 1.0,
+                interaction_rate: 1.0,
+                orbs_duration: 1.0,
+                hiding_spots: 1.0,
+                setup: 1.0,
+                evidence_hidden: 0,
+            },
+            Preset::Professional => PresetScale {
+                sanity_drain: 1.5,
+                hunt_rate: 1.5,
+                interaction_rate: 0.8,
+                orbs_duration: 0.75,
+                hiding_spots: 0.75,
+                setup: 0.5,
+                evidence_hidden: 0,
+            },
+            Preset::Nightmare => PresetScale {
+                sanity_drain: 2.0,
+                hunt_rate: 2.0,
+                interaction_rate: 0.6,
+                orbs_duration: 0.5,
+                hiding_spots: 0.5,
+                setup: 0.0,
+                evidence_hidden: 1,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum InteractionType {
+    Sound,
+    LightsFlicker,
+    ThrowObject,
+    DoorOpen,
+    // GhostWriting,
+}
+
+impl InteractionType {
+    fn generate_interaction(can_throw: bool, rng: &mut StdRng) -> Self {
+        let throw_weight = if can_throw { 1.0 } else { 0.0 };
+        let weights = [
+            (InteractionType::Sound, 1.0),
+            (InteractionType::LightsFlicker, 1.0),
+            (InteractionType::ThrowObject, throw_weight),
+        ];
+        utils::weighted_select(&weights, rng)
+            .cloned()
+            .unwrap_or(InteractionType::Sound)
+    }
+
+    fn notice(&self, room: RoomLabel, room_name: &str) -> Notice {
+        let (key, str) = match self {
+            InteractionType::Sound => (MessageKey::InteractionSound, "Sound"),
+            InteractionType::LightsFlicker => (MessageKey::LightsFlicker, "Lights"),
+            InteractionType::ThrowObject => (MessageKey::ThrowObject, "Object thrown"),
+            InteractionType::DoorOpen => (MessageKey::DoorOpen, "Door opened"),
+        };
+        let text = format!("{} in the {}", str, room_name);
+        Notice::in_room(key, room, text).with_sound(self.sound_id())
+    }
+
+    fn sound_id(&self) -> SoundId {
+        match self {
+            InteractionType::Sound => SoundId::InteractionSound,
+            InteractionType::LightsFlicker => SoundId::LightsFlicker,
+            InteractionType::ThrowObject => SoundId::ThrowObject,
+            InteractionType::DoorOpen => SoundId::DoorOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Nothing goes on by itself, tests turn back on what they look at
+    const QUIET: &str = r#"{
+        "setup_duration": 0, "ghost_interaction_rate": 0, "ghost_event_rate": 0,
+        "ghost_hunt_rate": 0, "ghost_orbs_rate": 0, "room_shift_rate": 0
+    }"#;
+
+    fn addr(n: usize) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 4000 + n as u16))
+    }
+
+    fn tunables(json: &str) -> OptionOverrides {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // A round on the default map with the players standing in the foyer
+    fn started(seed: u64, overrides: &str, names: &[&str]) -> Simulation {
+        haunted_by(None, seed, overrides, names)
+    }
+
+    fn haunted_by(
+        ghost_type: Option<GhostType>,
+        seed: u64,
+        overrides: &str,
+        names: &[&str],
+    ) -> Simulation {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), seed);
+        sim.set_tunables(tunables(overrides)).unwrap();
+        sim.set_ghost_type(ghost_type).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            sim.add_player(addr(i), name).unwrap();
+        }
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        for i in 0..names.len() {
+            sim.update_player_loc(addr(i), 0).unwrap();
+        }
+        sim
+    }
+
+    fn player<'a>(sim: &'a Simulation, name: &str) -> &'a Player {
+        sim.players.iter().find(|p| p.name == name).unwrap()
+    }
+
+    fn player_mut<'a>(sim: &'a mut Simulation, name: &str) -> &'a mut Player {
+        sim.players.iter_mut().find(|p| p.name == name).unwrap()
+    }
+
+    // An inside room that far from `from`, the first by label
+    fn room_at(sim: &Simulation, from: RoomLabel, distance: u32) -> RoomLabel {
+        (0..sim.map.rooms.len())
+            .find(|r| !sim.map.is_exterior(*r) && sim.map.distance(from, *r) == Some(distance))
+            .unwrap()
+    }
+
+    fn place(sim: &mut Simulation, name: &str, room: RoomLabel) {
+        player_mut(sim, name).last_loc = Some(room);
+    }
+
+    #[test]
+    fn flashlight_slows_drain_in_the_dark() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.set_flashlight(addr(0), true);
+        sim.advance(Duration::from_secs(10));
+        let lost = |name| 100.0 - player(&sim, name).sanity();
+        let ratio = lost("ann") / lost("bob");
+        assert!((ratio - sim.options.flashlight_drain_multiplier).abs() < 1e-9, "{ratio}");
+    }
+
+    #[test]
+    fn advancing_is_ticking_without_the_wait() {
+        let mut ticked = started(3, "{}", &["ann"]);
+        let mut advanced = started(3, "{}", &["ann"]);
+        for _ in 0..3000 {
+            ticked.update(ADVANCE_STEP);
+        }
+        advanced.advance(ADVANCE_STEP * 3000);
+        assert_eq!(ticked.cur_time, advanced.cur_time);
+        assert!(ticked.event_log() == advanced.event_log());
+        assert!(advanced.event_log().len() > 10);
+    }
+
+    #[test]
+    fn advancing_fires_triggers_in_order() {
+        let mut sim = started(4, r#"{"setup_duration": 30}"#, &["ann"]);
+        assert!(sim.flags.setup_until.is_some());
+        sim.advance(Duration::from_secs(600));
+        assert!(sim.flags.setup_until.is_none());
+        let log = sim.event_log();
+        assert!(log.windows(2).all(|w| w[0].id < w[1].id && w[0].at <= w[1].at));
+        // nothing from after the window
+        assert!(log.iter().all(|e| e.at <= sim.cur_time.as_secs_f64()));
+    }
+
+    #[test]
+    fn advancing_stops_when_the_round_ends() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.remove_player(addr(0));
+        let left = sim.cur_time;
+        sim.advance(Duration::from_secs(3600));
+        assert!(!sim.is_started());
+        let grace = sim.options.abandon_grace;
+        assert!(sim.cur_time >= left + grace && sim.cur_time <= left + grace + ADVANCE_STEP);
+        assert!(!sim.advance(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn heavier_edges_take_longer_to_cross() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        // Hallway to Stairwell is 1, Stairwell up to the landing is 2
+        sim.set_ghost_room(14).unwrap();
+        sim.teleport_ghost(2, false).unwrap();
+        let since = sim.event_log().last().unwrap().id;
+        sim.advance(Duration::from_secs(60));
+
+        let moved_at = |from, to| {
+            let moved = |e: &&LoggedEvent| e.event == SimEvent::GhostMoved { from, to };
+            sim.events_since(since).1.iter().find(moved).map(|e| e.at).unwrap()
+        };
+        let interval = sim.options.ghost_move_interval.as_secs_f64()
+            * sim.behavior.move_interval_multiplier();
+        let crossing = moved_at(6, 14) - moved_at(2, 6);
+        assert!(crossing > 2.0 * interval && crossing < 2.0 * interval + 0.2, "{crossing}");
+    }
+
+    #[test]
+    fn the_map_comes_whole_and_updates_carry_its_hash() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        let map = serde_json::to_value(sim.get_map()).unwrap();
+        let foyer = &map["Map"]["map"]["rooms"][0];
+        assert_eq!(foyer["name"], "Foyer");
+        assert_eq!(foyer["safe_room"], true);
+        assert_eq!(foyer["bounds"]["width"], 4.0);
+        assert_eq!(foyer["connections"][0]["to"], 1);
+
+        let hash = map["Map"]["map_hash"].clone();
+        let lobby = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_eq!(lobby["Lobby"]["map_hash"], hash);
+        // and it's only in the Map reply, not every state
+        assert!(lobby["Lobby"].get("map").is_none());
+
+        sim.select_map("school").unwrap();
+        let lobby = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_ne!(lobby["Lobby"]["map_hash"], hash);
+        sim.add_player(addr(0), "ann").unwrap();
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        let state = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_eq!(state["Sim"]["map_hash"], lobby["Lobby"]["map_hash"]);
+    }
+
+    #[test]
+    fn a_shy_ghost_stays_behind_a_closed_door() {
+        // the Nursery's only way out is door 1
+        let mut sim = haunted_by(Some(GhostType::Shade), 1, QUIET, &["ann"]);
+        sim.set_door(1, false).unwrap();
+        sim.set_ghost_room(7).unwrap();
+        sim.teleport_ghost(7, false).unwrap();
+        sim.advance(Duration::from_secs(300));
+        assert_eq!(sim.ghost.current_room, 7);
+        assert!(sim.flags.closed_doors.contains(&1));
+    }
+
+    #[test]
+    fn doors_are_shut_from_either_side_or_next_door() {
+        // door 1 is between the Nursery and the Upstairs Hallway
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        let e = sim.set_door_between(addr(0), 7, 14, false).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(14)));
+        assert!(!sim.flags.closed_doors.contains(&1));
+
+        place(&mut sim, "ann", 7);
+        sim.set_door_between(addr(0), 7, 14, false).unwrap();
+        assert!(sim.flags.closed_doors.contains(&1));
+        // the Bathroom is next to the Upstairs Hallway
+        place(&mut sim, "bob", 3);
+        sim.set_door_between(addr(1), 14, 7, true).unwrap();
+        assert!(!sim.flags.closed_doors.contains(&1));
+
+        let e = sim.set_door_between(addr(1), 3, 14, false).unwrap_err();
+        assert_eq!(e, format!("No door between {} and {}", sim.room_name(3), sim.room_name(14)));
+        sim.kill_player("bob");
+        assert_eq!(sim.set_door_between(addr(1), 14, 7, false).unwrap_err(), "bob is dead");
+    }
+
+    #[test]
+    fn a_hunting_ghost_opens_a_closed_door() {
+        let mut sim = haunted_by(Some(GhostType::Revenant), 1, QUIET, &["ann"]);
+        sim.set_door(1, false).unwrap();
+        sim.set_ghost_room(7).unwrap();
+        sim.teleport_ghost(7, false).unwrap();
+        place(&mut sim, "ann", 14);
+        sim.start_hunt();
+        while sim.ghost.current_room == 7 {
+            sim.update(ADVANCE_STEP);
+        }
+        assert_eq!(sim.ghost.current_room, 14);
+        assert!(!sim.flags.closed_doors.contains(&1));
+        assert!(sim.notices.iter().any(|n| n.notice.key == MessageKey::DoorOpen));
+    }
+
+    #[test]
+    fn hiding_spots_run_out() {
+        // the Master Bedroom has two
+        let mut sim = started(1, QUIET, &["ann", "bob", "cat"]);
+        for name in ["ann", "bob", "cat"] {
+            place(&mut sim, name, 4);
+        }
+        assert_eq!(sim.hiding_capacity(4), 2);
+        sim.set_hidden(addr(0), true).unwrap();
+        sim.set_hidden(addr(1), true).unwrap();
+        assert!(sim.set_hidden(addr(2), true).is_err());
+        // a spot comes free when someone comes out
+        sim.set_hidden(addr(0), false).unwrap();
+        sim.set_hidden(addr(2), true).unwrap();
+        assert_eq!(sim.map.directory()[4].hiding_spots, 2);
+    }
+
+    #[test]
+    fn harder_rounds_have_fewer_hiding_spots() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.add_player(addr(0), "ann").unwrap();
+        sim.start(None, None, Difficulty::Nightmare, true).unwrap();
+        // one in the Coat Closet halves to none, the map's left alone
+        assert_eq!(sim.hiding_capacity(1), 0);
+        assert_eq!(sim.hiding_capacity(4), 1);
+        assert_eq!(sim.map.rooms[1].hiding_spots, 1);
+        place(&mut sim, "ann", 1);
+        assert!(sim.set_hidden(addr(0), true).is_err());
+
+        let sim = started(1, r#"{"hiding_spot_multiplier": 0}"#, &["ann"]);
+        assert!((0..sim.map.rooms.len()).all(|r| sim.hiding_capacity(r) == 0));
+    }
+
+    #[test]
+    fn small_rooms_swing_faster_than_big_ones() {
+        // the Coat Closet's small, the Hallway medium, the Garage large
+        let (small, medium, large) = (1, 2, 12);
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.set_ghost_room(14).unwrap();
+        sim.teleport_ghost(14, false).unwrap();
+        for room in [small, medium, large] {
+            sim.chill_room(room);
+        }
+        sim.settle_temps(Duration::from_secs(5));
+        let temps = &sim.flags.room_temps;
+        assert!(temps[small] > temps[medium] && temps[medium] > temps[large], "{temps:?}");
+
+        let cooled_after = |sim: &mut Simulation, room, secs| {
+            sim.ghost.ghost_room = room;
+            sim.flags.ghost_room_since = sim.cur_time.saturating_sub(Duration::from_secs(secs));
+            sim.ghost_room_temp()
+        };
+        sim.cur_time = Duration::from_secs(600);
+        assert!(cooled_after(&mut sim, small, 20) < cooled_after(&mut sim, large, 20));
+    }
+
+    #[test]
+    fn hantu_cooling_goes_on_top_of_room_size() {
+        let mut sim = haunted_by(Some(GhostType::Hantu), 1, QUIET, &["ann"]);
+        sim.cur_time = Duration::from_secs(600);
+        sim.ghost.ghost_room = 12;
+        sim.flags.ghost_room_since = Duration::from_secs(580);
+        let fast = sim.ghost_room_temp();
+        sim.options.hantu_cooling_multiplier = 1.0;
+        let normal = sim.ghost_room_temp();
+        let ambient = sim.flags.ambient_temp;
+        assert!(fast < normal && normal < ambient, "{fast} {normal} {ambient}");
+    }
+
+    #[test]
+    fn players_cant_skip_across_the_map() {
+        // the Bathroom's four rooms from the Foyer, the Coat Closet one
+        let mut sim = started(1, QUIET, &["ann"]);
+        assert!(sim.update_player_loc(addr(0), 3).is_err());
+        assert_eq!(player(&sim, "ann").last_loc, Some(0));
+        sim.update_player_loc(addr(0), 1).unwrap();
+        // fine given the time to walk it
+        sim.advance(Duration::from_secs(2));
+        sim.update_player_loc(addr(0), 3).unwrap();
+    }
+
+    #[test]
+    fn warned_moves_go_through_and_repeats_get_flagged() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.options.movement_check = MovementCheck::Warn;
+        for room in [3, 0, 3] {
+            assert!(!player(&sim, "ann").flagged);
+            sim.update_player_loc(addr(0), room).unwrap();
+            assert_eq!(player(&sim, "ann").last_loc, Some(room));
+        }
+        assert_eq!(player(&sim, "ann").movement_violations, 3);
+        assert!(player(&sim, "ann").flagged);
+
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.options.movement_check = MovementCheck::Off;
+        for room in [3, 0, 3] {
+            sim.update_player_loc(addr(0), room).unwrap();
+        }
+        assert_eq!(player(&sim, "ann").movement_violations, 0);
+    }
+
+    #[test]
+    fn a_random_map_seed_can_be_played_again() {
+        let play = |seed| {
+            let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+            sim.add_player(addr(0), "ann").unwrap();
+            let random = Some(RANDOM_MAP.to_owned());
+            sim.start(random, seed, Difficulty::default(), true).unwrap();
+            assert_eq!(sim.map_id, RANDOM_MAP);
+            sim.map_hash.clone()
+        };
+        assert_eq!(play(Some(42)), play(Some(42)));
+        assert_ne!(play(Some(42)), play(Some(43)));
+        assert_ne!(play(None), Simulation::new(MapCatalog::builtin()).map_hash);
+    }
+
+    #[test]
+    fn the_ghost_stops_at_the_front_door() {
+        // the Driveway's outside, just past the Foyer
+        let mut sim = haunted_by(Some(GhostType::Revenant), 1, QUIET, &["ann"]);
+        sim.update_player_loc(addr(0), 15).unwrap();
+        sim.teleport_ghost(2, false).unwrap();
+        let since = sim.event_log().last().unwrap().id;
+        sim.start_hunt();
+        sim.advance(Duration::from_secs(120));
+
+        let went_outside = |e: &LoggedEvent| match e.event {
+            SimEvent::GhostMoved { to, .. } => sim.map.is_exterior(to),
+            _ => false,
+        };
+        assert!(!sim.events_since(since).1.iter().any(went_outside));
+        assert!(player(&sim, "ann").alive);
+        assert_eq!(player(&sim, "ann").sanity(), 100.0);
+        assert!(sim.teleport_ghost(15, false).is_err());
+        assert!(sim.set_ghost_room(15).is_err());
+    }
+
+    #[test]
+    fn hunting_ghost_goes_for_the_closest_player() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        let ghost = sim.ghost.current_room;
+        let (near, far) = (room_at(&sim, ghost, 1), room_at(&sim, ghost, 2));
+        place(&mut sim, "ann", far);
+        place(&mut sim, "bob", near);
+        assert_eq!(sim.nearest_prey(), Some(near));
+    }
+
+    #[test]
+    fn flashlight_draws_a_hunting_ghost() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        let ghost = sim.ghost.current_room;
+        let (near, far) = (room_at(&sim, ghost, 1), room_at(&sim, ghost, 2));
+        place(&mut sim, "ann", far);
+        place(&mut sim, "bob", near);
+        // 2 / 3 beats 1 / 2 at the default weight
+        sim.set_flashlight(addr(0), true);
+        assert_eq!(sim.nearest_prey(), Some(far));
+    }
+
+    #[test]
+    fn raiju_is_drawn_further_by_a_flashlight() {
+        let setup = |ghost_type| {
+            let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+            sim.set_tunables(tunables(QUIET)).unwrap();
+            sim.set_ghost_type(Some(ghost_type)).unwrap();
+            sim.add_player(addr(0), "ann").unwrap();
+            sim.add_player(addr(1), "bob").unwrap();
+            sim.start(None, None, Difficulty::default(), true).unwrap();
+            let ghost = sim.ghost.current_room;
+            let (near, far) = (room_at(&sim, ghost, 1), room_at(&sim, ghost, 4));
+            place(&mut sim, "ann", far);
+            place(&mut sim, "bob", near);
+            sim.set_flashlight(addr(0), true);
+            (sim, near, far)
+        };
+        // 2 / 5 doesn't beat 1 / 2, 4 / 5 does
+        let (sim, near, _) = setup(GhostType::Spirit);
+        assert_eq!(sim.nearest_prey(), Some(near));
+        let (sim, _, far) = setup(GhostType::Raiju);
+        assert_eq!(sim.nearest_prey(), Some(far));
+    }
+
+    #[test]
+    fn raiju_hunts_faster_near_a_flashlight() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.set_tunables(tunables(QUIET)).unwrap();
+        sim.set_ghost_type(Some(GhostType::Raiju)).unwrap();
+        sim.add_player(addr(0), "ann").unwrap();
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        let ghost = sim.ghost.current_room;
+        let (near, far) = (room_at(&sim, ghost, 1), room_at(&sim, ghost, 3));
+        place(&mut sim, "ann", near);
+        assert_eq!(sim.electronics_speed(), 1.0);
+        sim.set_flashlight(addr(0), true);
+        assert_eq!(sim.electronics_speed(), 1.5);
+        place(&mut sim, "ann", far);
+        assert_eq!(sim.electronics_speed(), 1.0);
+    }
+
+    #[test]
+    fn flashlight_flickers_only_near_a_hunt() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let ghost = sim.ghost.current_room;
+        place(&mut sim, "ann", ghost);
+        sim.set_flashlight(addr(0), true);
+        let flicker = |sim: &Simulation| {
+            let update = serde_json::to_value(sim.get_gameupdate_for(Some("ann"))).unwrap();
+            update["Sim"]["players"][0]["flashlight_flicker"].as_bool().unwrap()
+        };
+        assert!(!flicker(&sim));
+        sim.flags.is_hunting = true;
+        assert!(flicker(&sim));
+        let far = room_at(&sim, ghost, 3);
+        place(&mut sim, "ann", far);
+        assert!(!flicker(&sim));
+    }
+
+    #[test]
+    fn active_equipment_is_validated() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let too_many = vec![EquipmentKind::Flashlight; MAX_ACTIVE_EQUIPMENT + 1];
+        assert!(sim.set_active_equipment(addr(0), too_many).is_err());
+        let salt = vec![EquipmentKind::Salt];
+        assert!(sim.set_active_equipment(addr(0), salt).is_err());
+        let emf = vec![EquipmentKind::EmfReader];
+        assert!(sim.set_active_equipment(addr(0), emf.clone()).is_err());
+
+        sim.equip(addr(0), EquipmentKind::EmfReader).unwrap();
+        let twice = vec![EquipmentKind::EmfReader, EquipmentKind::EmfReader];
+        sim.set_active_equipment(addr(0), twice).unwrap();
+        assert!(player(&sim, "ann").active_equipment == emf);
+    }
+
+    #[test]
+    fn players_with_everything_off_are_sensed_from_closer() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let ghost = sim.ghost.current_room;
+        let dark = sim.options.dark_detection_radius;
+        let far = room_at(&sim, ghost, dark + 1);
+        place(&mut sim, "ann", far);
+        assert_eq!(sim.nearest_prey(), None);
+
+        sim.equip(addr(0), EquipmentKind::EmfReader).unwrap();
+        sim.set_active_equipment(addr(0), vec![EquipmentKind::EmfReader]).unwrap();
+        assert_eq!(sim.nearest_prey(), Some(far));
+
+        let near = room_at(&sim, ghost, dark);
+        sim.set_active_equipment(addr(0), Vec::new()).unwrap();
+        place(&mut sim, "ann", near);
+        assert_eq!(sim.nearest_prey(), Some(near));
+    }
+
+    #[test]
+    fn raiju_senses_any_active_electronics() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.set_tunables(tunables(QUIET)).unwrap();
+        sim.set_ghost_type(Some(GhostType::Raiju)).unwrap();
+        sim.add_player(addr(0), "ann").unwrap();
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        let near = room_at(&sim, sim.ghost.current_room, 1);
+        place(&mut sim, "ann", near);
+        sim.equip(addr(0), EquipmentKind::Thermometer).unwrap();
+        sim.set_active_equipment(addr(0), vec![EquipmentKind::Thermometer]).unwrap();
+        assert_eq!(sim.electronics_speed(), 1.5);
+    }
+
+    #[test]
+    fn detection_radius_options_are_checked() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        let wider = r#"{"detection_radius": 2, "dark_detection_radius": 3}"#;
+        assert!(sim.set_tunables(tunables(wider)).is_err());
+        assert!(sim.set_tunables(tunables(r#"{"detection_radius": 51}"#)).is_err());
+        let ok = r#"{"detection_radius": 4, "dark_detection_radius": 0}"#;
+        assert!(sim.set_tunables(tunables(ok)).is_ok());
+    }
+
+    fn throws_in(sim: &Simulation, room: RoomLabel) -> usize {
+        let throw = |e: &LoggedEvent| {
+            e.event
+                == SimEvent::Interaction {
+                    kind: InteractionType::ThrowObject,
+                    room,
+                }
+        };
+        sim.event_log().iter().filter(|e| throw(e)).count()
+    }
+
+    fn drain_objects(ghost_type: GhostType) -> Simulation {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.set_tunables(tunables(QUIET)).unwrap();
+        sim.set_ghost_type(Some(ghost_type)).unwrap();
+        sim.add_player(addr(0), "ann").unwrap();
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        sim.teleport_ghost(3, false).unwrap();
+        for _ in 0..200 {
+            sim.ghost_interaction();
+        }
+        sim
+    }
+
+    #[test]
+    fn throws_stop_once_a_room_is_empty() {
+        let sim = drain_objects(GhostType::Spirit);
+        assert_eq!(sim.flags.room_objects[3], 0);
+        // the bathroom starts with 6, one a throw
+        assert_eq!(throws_in(&sim, 3), 6);
+    }
+
+    #[test]
+    fn poltergeist_throws_several_at_once() {
+        let sim = drain_objects(GhostType::Poltergeist);
+        assert_eq!(sim.flags.room_objects[3], 0);
+        // 3 at a time
+        assert_eq!(throws_in(&sim, 3), 2);
+    }
+
+    #[test]
+    fn players_see_what_is_left_to_throw() {
+        let mut sim = drain_objects(GhostType::Spirit);
+        sim.update_player_loc(addr(0), 0).unwrap();
+        let update = serde_json::to_value(sim.get_gameupdate_for(Some("ann"))).unwrap();
+        assert_eq!(update["Sim"]["room_objects"][3], 0);
+        assert_eq!(update["Sim"]["room_objects"][7], 5);
+    }
+
+    #[test]
+    fn ghost_heads_home_quietly_after_a_hunt() {
+        let busy = r#"{
+            "setup_duration": 0, "ghost_interaction_rate": 60, "ghost_event_rate": 0,
+            "ghost_hunt_rate": 0, "ghost_orbs_rate": 0, "room_shift_rate": 0,
+            "ghost_hunt_duration": 5
+        }"#;
+        // out on the driveway, where a hunt can't reach
+        let mut sim = started(7, busy, &["ann"]);
+        sim.update_player_loc(addr(0), 15).unwrap();
+        let home = sim.ghost.ghost_room;
+        let away = (0..sim.map.rooms.len())
+            .filter(|r| sim.check_ghost_room(*r).is_ok())
+            .max_by_key(|r| sim.map.distance(home, *r))
+            .unwrap();
+        sim.teleport_ghost(away, false).unwrap();
+        sim.start_hunt();
+
+        let step = Duration::from_millis(100);
+        while sim.flags.is_hunting {
+            sim.update(step);
+        }
+        let hunt_over = sim.event_log().last().unwrap().id;
+        assert!(sim.flags.returning_since.is_some());
+        while sim.flags.returning_since.is_some() {
+            sim.update(step);
+        }
+        assert_eq!(sim.ghost.current_room, home);
+        let interacted = |e: &LoggedEvent| matches!(e.event, SimEvent::Interaction { .. });
+        assert!(!sim.events_since(hunt_over).1.iter().any(interacted));
+
+        // and it's back to normal once it's there
+        let back = sim.event_log().last().unwrap().id;
+        sim.advance(Duration::from_secs(60));
+        assert!(sim.events_since(back).1.iter().any(interacted));
+    }
+
+    #[test]
+    fn bodies_stay_where_players_died() {
+        let mut sim = started(1, QUIET, &["ann", "bob", "cat"]);
+        sim.update_player_loc(addr(0), 2).unwrap();
+        sim.update_player_loc(addr(1), 2).unwrap();
+        sim.kill_player("ann");
+        sim.kill_player("bob");
+        assert_eq!(sim.flags.bodies.len(), 2);
+
+        let before = player(&sim, "cat").sanity();
+        let found = sim.update_player_loc(addr(2), 2).unwrap();
+        assert_eq!(found.len(), 2);
+        let loss = 2.0 * sim.options.body_discovery_sanity_loss;
+        assert_eq!(before - player(&sim, "cat").sanity(), loss);
+
+        // only the first time in
+        sim.update_player_loc(addr(2), 0).unwrap();
+        assert!(sim.update_player_loc(addr(2), 2).unwrap().is_empty());
+
+        sim.submit_guess(addr(2), GhostType::Spirit).unwrap();
+        let results = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        let bodies = &results["Results"]["bodies"];
+        assert_eq!(bodies[0]["name"], "ann");
+        assert_eq!(bodies[1]["room"], 2);
+    }
+
+    #[test]
+    fn rewards_go_in_the_event_log() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.kill_player("bob");
+        let actual = sim.flags.ghost_type;
+        sim.submit_guess(addr(0), actual).unwrap();
+
+        let log = sim.event_log();
+        let rewarded: Vec<(&str, u32)> = log
+            .iter()
+            .filter_map(|e| match &e.event {
+                SimEvent::Rewarded { name, total, .. } => Some((name.as_str(), *total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(rewarded, [("ann", 75), ("bob", 50)]);
+        assert_eq!(log.last().unwrap().event, SimEvent::RoundEnded);
+    }
+
+    #[test]
+    fn players_only_see_their_own_rewards() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.kill_player("bob");
+        let actual = sim.flags.ghost_type;
+        sim.submit_guess(addr(0), actual).unwrap();
+        assert!(sim.redacts());
+
+        let rewards = |update: GameUpdate| {
+            let results = serde_json::to_value(update).unwrap();
+            let rewards = &results["Results"]["rewards"];
+            let names: Vec<String> = rewards["players"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["name"].as_str().unwrap().to_owned())
+                .collect();
+            (names, rewards["team_total"].as_u64().unwrap())
+        };
+        assert_eq!(rewards(sim.get_gameupdate()), (vec!["ann".into(), "bob".into()], 125));
+        assert_eq!(rewards(sim.get_gameupdate_for(Some("bob"))), (vec!["bob".into()], 125));
+        assert_eq!(rewards(sim.get_gameupdate_for(None)), (vec![], 125));
+    }
+
+    fn heard(sim: &mut Simulation) -> Vec<(SocketAddr, Loudness)> {
+        sim.queue_audio(&InteractionType::DoorOpen);
+        let cues = sim.take_audio_queue();
+        cues.into_iter().map(|(addr, cue)| (addr, cue.loudness)).collect()
+    }
+
+    #[test]
+    fn interactions_get_quieter_further_away() {
+        let mut sim = started(1, QUIET, &["ann", "bob", "cat", "dan"]);
+        // kitchen, pantry, foyer and coat closet are all downstairs
+        sim.teleport_ghost(9, false).unwrap();
+        for (name, room) in ["ann", "bob", "cat", "dan"].iter().zip([9, 8, 0, 1]) {
+            place(&mut sim, name, room);
+        }
+        let expected = [
+            (addr(0), Loudness::Loud),
+            (addr(1), Loudness::Muffled),
+            (addr(2), Loudness::Faint),
+        ];
+        assert_eq!(heard(&mut sim), expected);
+    }
+
+    #[test]
+    fn floors_muffle_interactions() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        // the stairs are as far as 2 rooms, a floor away makes it 3
+        sim.teleport_ghost(6, false).unwrap();
+        place(&mut sim, "ann", 2);
+        place(&mut sim, "bob", 14);
+        assert_eq!(sim.map.distance(6, 14), Some(2));
+        assert_eq!(heard(&mut sim), [(addr(0), Loudness::Muffled)]);
+    }
+
+    #[test]
+    fn the_dead_hear_nothing() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let ghost = sim.ghost.current_room;
+        place(&mut sim, "ann", ghost);
+        sim.kill_player("ann");
+        assert!(heard(&mut sim).is_empty());
+    }
+
+    #[test]
+    fn interactions_come_at_random_not_on_the_pulse() {
+        // about six a minute, with ann outside so no sanity moves the rate
+        let mut sim = started(5, QUIET, &["ann"]);
+        place(&mut sim, "ann", 15);
+        sim.options.ghost_interaction_rate = 6.0;
+        sim.schedule_interaction();
+        sim.advance(Duration::from_secs(3600));
+
+        let at: Vec<f64> = sim
+            .event_log()
+            .iter()
+            .filter(|e| matches!(e.event, SimEvent::Interaction { .. }))
+            .map(|e| e.at)
+            .collect();
+        assert_eq!(player(&sim, "ann").sanity(), 100.0);
+        let gaps: Vec<f64> = at.windows(2).map(|w| w[1] - w[0]).collect();
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        let expected = 60.0 / sim.interaction_rate();
+        assert!((mean / expected - 1.0).abs() < 0.15, "{mean}s apart, not {expected}s");
+        // the pulse is every 10s, most gaps aren't a multiple of it
+        let pulse = sim.options.event_pulse_interval.as_secs_f64();
+        let on_pulse = gaps.iter().filter(|g| (*g / pulse - (*g / pulse).round()).abs() < 0.02);
+        assert!(on_pulse.count() < gaps.len() / 5);
+    }
+
+    #[test]
+    fn rounds_start_with_rates_too_slow_to_ever_come_round() {
+        for tiny in [
+            r#"{"ghost_interaction_rate": 1e-18}"#,
+            r#"{"setup_interaction_multiplier": 1e-18}"#,
+        ] {
+            let mut sim = started(5, tiny, &["ann"]);
+            sim.advance(Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    fn names_clash_ignoring_case_and_spacing() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.add_player(addr(0), "Alice").unwrap();
+        assert_eq!(sim.add_player(addr(1), "alice "), Err("Name taken by Alice".to_owned()));
+        assert!(sim.add_player(addr(1), " ALICE").is_err());
+        assert!(sim.add_player(addr(1), "  ").is_err());
+        sim.add_player(addr(1), "Alice2").unwrap();
+
+        sim.add_player(addr(2), "Mary  Ann").unwrap();
+        assert!(sim.add_player(addr(3), "mary ann").is_err());
+        // shown the way it was typed
+        assert_eq!(sim.player_name(addr(2)).as_deref(), Some("Mary  Ann"));
+        assert_eq!(sim.kick(" MARY ANN "), Ok(addr(2)));
+    }
+
+    #[test]
+    fn a_dropped_player_picks_up_under_any_spelling() {
+        let mut sim = started(1, QUIET, &["Alice"]);
+        sim.remove_player(addr(0));
+        sim.add_player(addr(5), " alice").unwrap();
+        assert_eq!(player(&sim, "Alice").addr, addr(5));
+        assert!(player(&sim, "Alice").connected);
+    }
+
+    fn emf_seen_by(sim: &Simulation, event: usize) -> Vec<String> {
+        sim.emf_events()[event].observed_by.clone()
+    }
+
+    #[test]
+    fn emf_fives_count_for_whoever_was_there_while_it_lasted() {
+        let mut sim = started(1, QUIET, &["ann", "bob", "cat"]);
+        sim.ghost.teleport(13);
+        sim.blast_emf(5, 5);
+        assert!(emf_seen_by(&sim, 0).is_empty());
+
+        place(&mut sim, "bob", 2);
+        sim.ghost.teleport(2);
+        sim.blast_emf(5, 5);
+        assert_eq!(emf_seen_by(&sim, 1), ["bob"]);
+        // walking in on it counts
+        sim.update_player_loc(addr(0), 2).unwrap();
+        assert_eq!(emf_seen_by(&sim, 1), ["bob", "ann"]);
+        // once it's died down it doesn't
+        sim.advance(sim.options.emf_blast_duration + ADVANCE_STEP);
+        sim.update_player_loc(addr(2), 2).unwrap();
+        assert_eq!(emf_seen_by(&sim, 1), ["bob", "ann"]);
+        // and lower levels aren't evidence at all
+        sim.blast_emf(2, 4);
+        assert_eq!(sim.emf_events().len(), 2);
+    }
+
+    #[test]
+    fn reading_an_emf_five_counts_as_seeing_it() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.equip(addr(0), EquipmentKind::EmfReader).unwrap();
+        sim.ghost.teleport(2);
+        sim.blast_emf(5, 5);
+        // the room next door reads a level lower, that's not it
+        sim.read_emf(addr(0)).unwrap();
+        assert!(emf_seen_by(&sim, 0).is_empty());
+
+        place(&mut sim, "ann", 2);
+        sim.read_emf(addr(0)).unwrap();
+        assert_eq!(emf_seen_by(&sim, 0), ["ann"]);
+    }
+
+    #[test]
+    fn events_and_interactions_keep_to_their_own_rates() {
+        let mut sim = started(9, QUIET, &["ann"]);
+        // outside, so nothing about ann changes the rates
+        place(&mut sim, "ann", 15);
+        sim.options.ghost_event_rate = 1.0;
+        sim.options.ghost_interaction_rate = 3.0;
+        sim.schedule_interaction();
+        let hour = Duration::from_secs(3600);
+        sim.advance(hour);
+
+        let pulse = sim.options.event_pulse_interval;
+        let pulses = hour.as_secs_f64() / pulse.as_secs_f64();
+        let event_rate = sim.options.ghost_event_rate * sim.aggression_multiplier();
+        let events = pulses * utils::prob_from_rate(event_rate, pulse);
+        // an event keeps interactions away until the next pulse
+        let open = hour.as_secs_f64() - sim.activity().events as f64 * pulse.as_secs_f64();
+        let interactions = sim.interaction_rate() * open / 60.0;
+
+        let activity = sim.activity();
+        let near = |got: u32, expected: f64| (got as f64 / expected - 1.0).abs() < 0.25;
+        assert!(near(activity.events, events), "{} events, not {events}", activity.events);
+        assert!(
+            near(activity.interactions, interactions),
+            "{} interactions, not {interactions}",
+            activity.interactions
+        );
+        let log = sim.event_log();
+        let logged = log.iter().filter(|e| matches!(e.event, SimEvent::Interaction { .. }));
+        assert_eq!(logged.count() as u32, activity.interactions);
+    }
+
+    #[test]
+    fn the_sim_asks_the_ghost_behavior() {
+        struct Calming;
+
+        impl GhostBehavior for Calming {
+            fn evidence(&self) -> &'static [EvidenceType] {
+                &[EvidenceType::Emf, EvidenceType::Freezing, EvidenceType::GhostOrbs]
+            }
+
+            fn sanity_drain_multiplier(&self) -> f64 {
+                0.0
+            }
+        }
+
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.behavior = Box::new(Calming);
+        sim.advance(Duration::from_secs(60));
+        assert_eq!(player(&sim, "ann").sanity(), 100.0);
+    }
+
+    fn giving(evidence: u8) -> String {
+        let mut quiet: serde_json::Value = serde_json::from_str(QUIET).unwrap();
+        quiet["evidence_given"] = evidence.into();
+        quiet.to_string()
+    }
+
+    #[test]
+    fn harder_rounds_show_less_of_the_evidence() {
+        for given in 0..=3 {
+            let sim = haunted_by(Some(GhostType::Spirit), 1, &giving(given), &["ann"]);
+            let reveal = sim.evidence_reveal();
+            assert_eq!(sim.flags.shown_evidence.len(), given as usize);
+            assert_eq!(reveal.evidence, behavior_for(GhostType::Spirit).evidence());
+            assert_eq!(reveal.hidden.len(), 3 - given as usize);
+            assert!(reveal.hidden.iter().all(|e| !sim.shows_evidence(*e)));
+        }
+    }
+
+    #[test]
+    fn hidden_evidence_never_turns_up() {
+        let strongest = |given| {
+            let mut sim = haunted_by(Some(GhostType::Spirit), 1, &giving(given), &["ann"]);
+            for _ in 0..50 {
+                sim.teleport_ghost(2, true).unwrap();
+            }
+            let log = sim.event_log();
+            let levels = log.iter().filter_map(|e| match e.event {
+                SimEvent::EmfBlast { level, .. } => Some(level),
+                _ => None,
+            });
+            (levels.max().unwrap(), sim.emf_events().len())
+        };
+        // the Spirit's EMF 5 is one of the three it has to hide
+        assert_eq!(strongest(0), (3, 0));
+        let (level, fives) = strongest(3);
+        assert!(level == 5 && fives > 0);
+    }
+
+    #[test]
+    fn with_no_evidence_any_ghost_that_fits_is_right() {
+        let guess = |given, ghost| {
+            let mut sim = haunted_by(Some(GhostType::Spirit), 1, &giving(given), &["ann"]);
+            sim.submit_guess(addr(0), ghost).unwrap().correct_guess
+        };
+        assert!(guess(0, GhostType::Demon));
+        assert!(!guess(3, GhostType::Demon));
+        assert!(guess(3, GhostType::Spirit));
+    }
+
+    fn custom(json: &str) -> Difficulty {
+        Difficulty::Custom {
+            base: Preset::Intermediate,
+            overrides: Box::new(tunables(json)),
+        }
+    }
+
+    #[test]
+    fn a_bad_custom_difficulty_names_the_field_and_starts_nothing() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.add_player(addr(0), "ann").unwrap();
+        let before = sim.options.effective();
+        for (json, field) in [
+            (r#"{"ghost_move_interval": 0}"#, "ghost_move_interval"),
+            (r#"{"sanity_drain_rate": -1}"#, "sanity_drain_rate"),
+            (r#"{"fingerprint_chance": 2}"#, "fingerprint_chance"),
+        ] {
+            let e = sim.start(None, None, custom(json), true).unwrap_err();
+            assert!(e.starts_with(field), "{}", e);
+            // the admin's tunables go through the very same checks
+            assert_eq!(sim.set_tunables(tunables(json)).unwrap_err(), e);
+            assert_eq!(sim.phase(), GamePhase::Lobby);
+            assert!(sim.options.effective() == before);
+        }
+    }
+
+    #[test]
+    fn a_custom_round_plays_and_is_remembered_as_custom() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.add_player(addr(0), "ann").unwrap();
+        let difficulty = custom(r#"{"ghost_move_interval": 42, "evidence_given": 1}"#);
+        sim.start(None, None, difficulty, true).unwrap();
+        assert_eq!(sim.options.ghost_move_interval, Duration::from_secs(42));
+        assert_eq!(sim.options.evidence_given, 1);
+        // what's not overridden is the preset's
+        assert_eq!(sim.options.hunt_warning, SimOptions::new().hunt_warning);
+
+        let summary = sim.submit_guess(addr(0), GhostType::Spirit).unwrap();
+        match summary.difficulty {
+            Difficulty::Custom { overrides, .. } => {
+                assert_eq!(overrides.ghost_move_interval, Some(42.0));
+            }
+            _ => panic!("Not remembered as custom"),
+        }
+    }
+
+    #[test]
+    fn ghost_decisions_are_only_kept_while_someone_watches() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.advance(Duration::from_secs(60));
+        assert!(sim.take_ghost_debug().is_empty());
+
+        sim.set_ghost_debug(true);
+        sim.advance(Duration::from_secs(60));
+        let decisions = sim.take_ghost_debug();
+        assert!(decisions.iter().any(|d| matches!(d, GhostDecision::Move { .. })));
+        assert!(decisions.iter().any(|d| matches!(d, GhostDecision::Roll { .. })));
+
+        // whatever wasn't sent yet goes with the last watcher
+        sim.advance(Duration::from_secs(60));
+        sim.set_ghost_debug(false);
+        assert!(sim.take_ghost_debug().is_empty());
+    }
+
+    #[test]
+    fn a_teleported_hunt_carries_on_from_the_new_room() {
+        let mut sim = haunted_by(Some(GhostType::Revenant), 1, QUIET, &["ann"]);
+        sim.teleport_ghost(7, false).unwrap();
+        place(&mut sim, "ann", 14);
+        sim.start_hunt();
+        sim.teleport_ghost(13, false).unwrap();
+        assert!(sim.ghost.planned_path().is_empty());
+        assert!(sim.flags.is_hunting);
+        while sim.ghost.current_room == 13 {
+            sim.update(ADVANCE_STEP);
+        }
+        assert!(sim.map.connection(13, sim.ghost.current_room).is_some());
+        assert!(sim.flags.is_hunting);
+    }
+
+    #[test]
+    fn a_preset_ghost_holds_until_the_round_starts() {
+        let mut sim = haunted_by(Some(GhostType::Demon), 1, QUIET, &["ann"]);
+        assert_eq!(sim.flags.ghost_type, GhostType::Demon);
+        let demon = behavior_for(GhostType::Demon).evidence();
+        assert!(sim.flags.shown_evidence.iter().all(|e| demon.contains(e)));
+        assert!(sim.set_ghost_type(Some(GhostType::Spirit)).is_err());
+        assert!(sim.set_ghost_type(None).is_err());
+        assert_eq!(sim.flags.ghost_type, GhostType::Demon);
+        let summary = sim.submit_guess(addr(0), GhostType::Demon).unwrap();
+        assert!(summary.preset_ghost);
+
+        // without one, each round gets whatever the rng picks
+        let mut ghosts: Vec<_> = (0..20)
+            .map(|seed| haunted_by(None, seed, QUIET, &["ann"]).flags.ghost_type)
+            .collect();
+        ghosts.dedup();
+        assert!(ghosts.len() > 1);
+    }
+
+    #[test]
+    fn players_only_act_for_themselves_and_the_dead_dont() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        // whoever sent it is who acts, a stranger is nobody
+        assert!(sim.update_player_loc(addr(9), 0).is_err());
+        sim.update_player_loc(addr(0), 2).unwrap();
+        assert_eq!(player(&sim, "bob").last_loc, Some(0));
+
+        place(&mut sim, "bob", 2);
+        sim.kill_player("bob");
+        assert!(sim.update_player_loc(addr(1), 0).is_err());
+        assert_eq!(player(&sim, "bob").last_loc, Some(2));
+    }
+
+    #[test]
+    fn coming_back_in_time_keeps_the_round_going() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.remove_player(addr(0));
+        sim.remove_player(addr(1));
+        sim.advance(sim.options.abandon_grace / 2);
+        // on a new connection, under a different spelling
+        sim.add_player(addr(5), "Bob").unwrap();
+        sim.advance(sim.options.abandon_grace);
+        assert_eq!(sim.phase(), GamePhase::Running);
+
+        // once they go again, so does the round
+        sim.remove_player(addr(5));
+        sim.advance(sim.options.abandon_grace + ADVANCE_STEP);
+        assert_eq!(sim.phase(), GamePhase::Lobby);
+        assert_eq!(sim.event_log().last().unwrap().event, SimEvent::RoundEnded);
+    }
+
+    #[test]
+    fn the_personality_reaches_the_rolls() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.options.ghost_interaction_rate = 1.0;
+        sim.ghost.teleport(13);
+        sim.flags.personality = Personality {
+            aggression: 1.0,
+            wanderlust: 1.0,
+            shyness: 0.5,
+        };
+        let calm = sim.interaction_rate();
+        sim.flags.personality.aggression = 2.0;
+        assert!((sim.interaction_rate() - 2.0 * calm).abs() < 1e-9);
+        // it's quieter with someone in the room
+        place(&mut sim, "ann", 13);
+        assert!((sim.interaction_rate() - calm).abs() < 1e-9);
+
+        // a homebody hardly leaves its favorite room, a wanderer can't stay
+        let moves = |wanderlust| {
+            let mut sim = started(1, QUIET, &["ann"]);
+            sim.flags.personality.wanderlust = wanderlust;
+            let since = sim.event_log().last().unwrap().id;
+            sim.advance(Duration::from_secs(600));
+            let moved = |e: &&LoggedEvent| matches!(e.event, SimEvent::GhostMoved { .. });
+            sim.events_since(since).1.iter().filter(moved).count()
+        };
+        assert!(moves(0.0) < moves(2.0), "{} {}", moves(0.0), moves(2.0));
+
+        let summary = sim.submit_guess(addr(0), GhostType::Spirit).unwrap();
+        assert_eq!(summary.personality.aggression, 2.0);
+    }
+
+    #[test]
+    fn every_interaction_comes_with_its_sound() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        for interaction in [
+            InteractionType::Sound,
+            InteractionType::LightsFlicker,
+            InteractionType::ThrowObject,
+            InteractionType::DoorOpen,
+        ] {
+            let notice = interaction.notice(2, "Hallway");
+            assert_eq!(notice.sound_id, Some(interaction.sound_id()));
+            assert!(SoundId::ALL.contains(&interaction.sound_id()));
+        }
+        // and a death's heard by everyone
+        sim.kill_player("ann");
+        let death = sim.notices.iter().find(|n| n.notice.key == MessageKey::PlayerDeath);
+        assert_eq!(death.unwrap().notice.sound_id, Some(SoundId::PlayerDeath));
+    }
+
+    #[test]
+    fn a_busy_round_only_says_what_the_keys_cover() {
+        // everything on, and long enough for hunts, deaths and the rest
+        let mut sim = started(3, "{}", &["ann", "bob"]);
+        sim.advance(Duration::from_secs(1800));
+        assert!(sim.notices.len() > 10, "{}", sim.notices.len());
+        for entry in &sim.notices {
+            let value = serde_json::to_value(entry).unwrap();
+            assert!(value["key"].as_str().is_some_and(|k| k.contains('.')), "{}", value);
+            assert!(value["params"].is_object(), "{}", value);
+            assert!(!entry.notice.fallback_text.is_empty());
+        }
+    }
+
+    // Ann in the Dining Room with a thermometer and an EMF reader of the tier
+    fn equipped(tier: Tier) -> Simulation {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.options.equipment_tiers = vec![
+            (EquipmentKind::Thermometer, tier),
+            (EquipmentKind::EmfReader, tier),
+        ];
+        let ann = player_mut(&mut sim, "ann");
+        ann.inventory.add(EquipmentKind::Thermometer).unwrap();
+        ann.inventory.add(EquipmentKind::EmfReader).unwrap();
+        place(&mut sim, "ann", 13);
+        sim
+    }
+
+    #[test]
+    fn thermometer_tiers_set_the_noise_and_the_wait() {
+        for (tier, noise, cooldown) in [
+            (Tier::I, 4, Duration::from_secs(3)),
+            (Tier::II, 2, Duration::from_secs(1)),
+            (Tier::III, 0, Duration::from_millis(200)),
+        ] {
+            let mut sim = equipped(tier);
+            let mut spread = Vec::new();
+            for _ in 0..50 {
+                let actual = sim.room_temp(13);
+                match sim.read_thermometer(addr(0)).unwrap() {
+                    SensorReading::Thermometer { tier: read, temp } => {
+                        assert_eq!(read, tier);
+                        spread.push(temp - actual);
+                    }
+                    _ => panic!("Not a thermometer reading"),
+                }
+                assert!(sim.read_thermometer(addr(0)).is_err());
+                sim.advance(cooldown);
+            }
+            assert!(spread.iter().all(|d| d.abs() <= noise), "{:?} {:?}", tier, spread);
+            // the full range of it, not just the edges
+            let widest = spread.iter().map(|d| d.abs()).max().unwrap();
+            assert_eq!(widest, noise, "{:?}", tier);
+        }
+    }
+
+    #[test]
+    fn emf_tiers_set_how_much_the_reader_tells() {
+        for (tier, exact, history) in [
+            (Tier::I, false, 0),
+            (Tier::II, true, 0),
+            (Tier::III, true, 5),
+        ] {
+            let mut sim = equipped(tier);
+            sim.ghost.teleport(13);
+            sim.blast_emf(4, 4);
+            for _ in 0..8 {
+                sim.read_emf(addr(0)).unwrap();
+            }
+            match sim.read_emf(addr(0)).unwrap() {
+                SensorReading::Emf {
+                    active,
+                    level,
+                    history: kept,
+                    ..
+                } => {
+                    assert!(active);
+                    assert_eq!(level, exact.then_some(4), "{:?}", tier);
+                    assert_eq!(kept.len(), history, "{:?}", tier);
+                }
+                _ => panic!("Not an EMF reading"),
+            }
+        }
+    }
+
+    #[test]
+    fn spirit_box_tiers_set_how_far_it_hears() {
+        for (tier, radius) in [(Tier::I, 1), (Tier::II, 2), (Tier::III, 3)] {
+            let mut sim = haunted_by(Some(GhostType::Spirit), 1, &giving(3), &["ann"]);
+            sim.options.equipment_tiers = vec![(EquipmentKind::SpiritBox, tier)];
+            sim.options.spirit_box_response_chance = 1.0;
+            sim.options.spirit_box_cooldown = Duration::ZERO;
+            player_mut(&mut sim, "ann").inventory.add(EquipmentKind::SpiritBox).unwrap();
+            place(&mut sim, "ann", 13);
+            for distance in 1..=3 {
+                let room = room_at(&sim, 13, distance);
+                sim.ghost.teleport(room);
+                let heard = match sim.use_spirit_box(addr(0)).unwrap() {
+                    SensorReading::SpiritBox { response, .. } => response.is_some(),
+                    _ => panic!("Not a spirit box reading"),
+                };
+                assert_eq!(heard, distance <= radius, "{:?} at {}", tier, distance);
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_living_hide_and_only_themselves() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        place(&mut sim, "ann", 4);
+        place(&mut sim, "bob", 4);
+        sim.set_hidden(addr(0), true).unwrap();
+        assert!(player(&sim, "ann").hidden && !player(&sim, "bob").hidden);
+
+        sim.kill_player("bob");
+        assert_eq!(sim.set_hidden(addr(1), true).unwrap_err(), "bob is dead");
+        assert!(sim.set_hidden(addr(1), false).is_err());
+        assert!(sim.set_hidden(addr(9), true).is_err());
+    }
+
+    // When the ghost crossed from the Hallway to the Stairwell, then from
+    // there up to the landing, which is twice as long
+    fn landing_crossing(ghost_type: GhostType, hunting: bool) -> f64 {
+        let mut sim = haunted_by(Some(ghost_type), 1, QUIET, &["ann"]);
+        sim.set_ghost_room(14).unwrap();
+        sim.teleport_ghost(2, false).unwrap();
+        place(&mut sim, "ann", 14);
+        if hunting {
+            sim.start_hunt();
+        }
+        let since = sim.event_log().last().unwrap().id;
+        sim.advance(Duration::from_secs(90));
+        let moved_at = |from, to| {
+            let moved = |e: &&LoggedEvent| e.event == SimEvent::GhostMoved { from, to };
+            sim.events_since(since).1.iter().find(moved).map(|e| e.at).unwrap()
+        };
+        moved_at(6, 14) - moved_at(2, 6)
+    }
+
+    #[test]
+    fn ghost_types_keep_their_own_pace() {
+        let options = SimOptions::new();
+        let roaming = 2.0 * options.ghost_move_interval.as_secs_f64();
+        let hunting = 2.0 * options.hunt_move_interval.as_secs_f64();
+        let cases = [
+            (GhostType::Spirit, false, roaming),
+            (GhostType::Jinn, false, roaming * 0.7),
+            (GhostType::Revenant, false, roaming * 1.5),
+            (GhostType::Spirit, true, hunting),
+            (GhostType::Revenant, true, hunting / 2.0),
+        ];
+        for (ghost_type, hunt, expected) in cases {
+            let crossing = landing_crossing(ghost_type, hunt);
+            let close = crossing > expected && crossing < expected + 0.2;
+            assert!(close, "{:?} hunting {}: {} not {}", ghost_type, hunt, crossing, expected);
+        }
+    }
+
+    #[test]
+    fn demons_hunt_sooner_and_shades_later() {
+        let threshold =
+            |ghost_type| haunted_by(Some(ghost_type), 1, QUIET, &["ann"]).hunt_threshold();
+        let base = SimOptions::new().hunt_sanity_threshold;
+        assert_eq!(threshold(GhostType::Spirit), base);
+        assert_eq!(threshold(GhostType::Demon), base + 20.0);
+        assert_eq!(threshold(GhostType::Shade), base - 15.0);
+    }
+
+    #[test]
+    fn a_jinn_drains_everyone_faster() {
+        let lost = |ghost_type| {
+            let mut sim = haunted_by(Some(ghost_type), 1, QUIET, &["ann"]);
+            sim.advance(Duration::from_secs(30));
+            100.0 - player(&sim, "ann").sanity()
+        };
+        let ratio = lost(GhostType::Jinn) / lost(GhostType::Spirit);
+        assert!((ratio - 1.5).abs() < 1e-9, "{ratio}");
+    }
+
+    #[test]
+    fn a_shade_keeps_quiet_while_its_watched() {
+        let active = r#"{"setup_duration": 0, "ghost_interaction_rate": 2}"#;
+        let rates = |ghost_type| {
+            let mut sim = haunted_by(Some(ghost_type), 1, active, &["ann"]);
+            let ghost = sim.ghost.current_room;
+            let away = room_at(&sim, ghost, 2);
+            place(&mut sim, "ann", away);
+            // without what this round's personality rolled
+            let aggression = sim.flags.personality.aggression;
+            let alone = sim.interaction_rate() / aggression;
+            place(&mut sim, "ann", ghost);
+            (alone, sim.interaction_rate() / aggression)
+        };
+        let (spirit, _) = rates(GhostType::Spirit);
+        let (alone, watched) = rates(GhostType::Shade);
+        assert!((alone / spirit - 0.6).abs() < 1e-9, "{} against {}", alone, spirit);
+        // a shade's shyness is at least a half
+        assert!(watched <= alone * 0.5, "{} watched, {} alone", watched, alone);
+    }
+
+    // Ann with a UV light in the ghost's room, and every interaction
+    // leaving prints for a minute if the ghost can
+    fn printing(ghost_type: GhostType, evidence: u8) -> Simulation {
+        let mut tunables: serde_json::Value = serde_json::from_str(&giving(evidence)).unwrap();
+        tunables["fingerprint_chance"] = 1.0.into();
+        tunables["fingerprint_duration"] = 60.0.into();
+        let mut sim = haunted_by(Some(ghost_type), 1, &tunables.to_string(), &["ann"]);
+        player_mut(&mut sim, "ann").inventory.add(EquipmentKind::UvLight).unwrap();
+        let ghost = sim.ghost.current_room;
+        place(&mut sim, "ann", ghost);
+        sim
+    }
+
+    fn sees_prints(sim: &mut Simulation) -> bool {
+        match sim.use_uv_light(addr(0)).unwrap() {
+            SensorReading::Ultraviolet { fingerprints, .. } => fingerprints,
+            _ => panic!("Not a UV reading"),
+        }
+    }
+
+    #[test]
+    fn fingerprints_fade_unless_theyre_left_again() {
+        let mut sim = printing(GhostType::Jinn, 3);
+        let room = sim.ghost.current_room;
+        assert!(!sees_prints(&mut sim));
+        sim.ghost_interaction();
+        assert!(sees_prints(&mut sim));
+        assert_eq!(sim.flags.fingerprints, vec![room]);
+
+        // fresh ones start the minute again
+        sim.advance(Duration::from_secs(40));
+        sim.teleport_ghost(room, false).unwrap();
+        sim.ghost_interaction();
+        sim.advance(Duration::from_secs(40));
+        assert!(sees_prints(&mut sim));
+        sim.advance(Duration::from_secs(30));
+        assert!(!sees_prints(&mut sim));
+        assert!(sim.flags.fingerprints.is_empty());
+    }
+
+    #[test]
+    fn only_ghosts_showing_ultraviolet_leave_prints() {
+        // the Spirit never has it, the Jinn's can be among what's hidden
+        for (ghost_type, evidence) in [(GhostType::Spirit, 3), (GhostType::Jinn, 0)] {
+            let mut sim = printing(ghost_type, evidence);
+            for _ in 0..10 {
+                sim.ghost_interaction();
+            }
+            assert!(!sees_prints(&mut sim), "{:?}", ghost_type);
+            assert!(sim.flags.fingerprints.is_empty());
+        }
+    }
+
+    #[test]
+    fn pills_give_sanity_back_until_theyre_gone() {
+        let mut pills: serde_json::Value = serde_json::from_str(QUIET).unwrap();
+        pills["sanity_pills_limit"] = 2.into();
+        pills["sanity_pills_amount"] = 40.0.into();
+        let mut sim = started(1, &pills.to_string(), &["ann", "bob", "cat"]);
+        let dose = |sim: &mut Simulation, name| {
+            player_mut(sim, name).inventory.add(EquipmentKind::SanityPills).unwrap()
+        };
+        assert_eq!(sim.use_sanity_pills(addr(0)).unwrap_err(), "Item not in inventory");
+        sim.kill_player("cat");
+        dose(&mut sim, "cat");
+        assert_eq!(sim.use_sanity_pills(addr(2)).unwrap_err(), "Dead players can't take pills");
+
+        player_mut(&mut sim, "ann").sanity = 30.0;
+        dose(&mut sim, "ann");
+        sim.use_sanity_pills(addr(0)).unwrap();
+        assert_eq!(player(&sim, "ann").sanity(), 70.0);
+        dose(&mut sim, "ann");
+        sim.use_sanity_pills(addr(0)).unwrap();
+        assert_eq!(player(&sim, "ann").sanity(), 100.0);
+        assert!(!player(&sim, "ann").inventory.contains(EquipmentKind::SanityPills));
+
+        // the round's supply is shared, and failing doesn't use up bob's
+        dose(&mut sim, "bob");
+        assert_eq!(sim.use_sanity_pills(addr(1)).unwrap_err(), "No sanity pills left");
+        assert!(player(&sim, "bob").inventory.contains(EquipmentKind::SanityPills));
+        let update = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_eq!(update["Sim"]["sanity_pills_used"], 2);
+    }
+
+    #[test]
+    fn only_the_newest_notices_are_kept() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let before = sim.last_notice;
+        for n in 0..NOTICE_BACKLOG + 10 {
+            sim.notify(Notice::new(MessageKey::HuntStarted, json!({}), n.to_string()));
+        }
+        let last = before + NOTICE_BACKLOG as u64 + 10;
+        assert_eq!(sim.last_notice, last);
+        let ids: Vec<u64> = sim.notices.iter().map(|n| n.id).collect();
+        let newest: Vec<u64> = (last + 1 - NOTICE_BACKLOG as u64..=last).collect();
+        assert_eq!(ids, newest);
+    }
+
+    // When the first of each event came, from one after `since`
+    fn first_at(sim: &Simulation, since: u64, event: impl Fn(&SimEvent) -> bool) -> f64 {
+        let log = sim.events_since(since).1;
+        log.iter().find(|e| event(&e.event)).map(|e| e.at).unwrap()
+    }
+
+    #[test]
+    fn orbs_and_emf_clear_when_theyre_due() {
+        let mut tunables: serde_json::Value = serde_json::from_str(&giving(3)).unwrap();
+        tunables["ghost_orbs_rate"] = 60.0.into();
+        tunables["ghost_orbs_duration"] = 20.0.into();
+        tunables["emf_blast_duration"] = 5.0.into();
+        let mut sim = haunted_by(Some(GhostType::Banshee), 1, &tunables.to_string(), &["ann"]);
+        let since = sim.event_log().last().unwrap().id;
+        let blasted = sim.time().as_secs_f64();
+        sim.blast_emf(2, 2);
+        sim.advance(Duration::from_secs(120));
+
+        // within a tick of when they were due
+        let step = ADVANCE_STEP.as_secs_f64() + 1e-9;
+        let ended = first_at(&sim, since, |e| matches!(e, SimEvent::EmfEnded { .. }));
+        assert!(ended - blasted > 5.0 && ended - blasted <= 5.0 + step, "{}", ended);
+        let shown = first_at(&sim, since, |e| matches!(e, SimEvent::OrbsShown { .. }));
+        let gone = first_at(&sim, since, |e| *e == SimEvent::OrbsGone);
+        assert!(gone - shown > 20.0 && gone - shown <= 20.0 + step, "{} to {}", shown, gone);
+    }
+
+    #[test]
+    fn a_refresh_with_nothing_new_sends_no_state() {
+        let mut still: serde_json::Value = serde_json::from_str(QUIET).unwrap();
+        still["sanity_drain_rate"] = 0.into();
+        still["temperature_variability"] = 0.into();
+        still["ghost_move_interval"] = 600.into();
+        still["event_pulse_interval"] = 600.into();
+        let mut sim = started(1, &still.to_string(), &["ann"]);
+        // long enough for every room to settle
+        sim.advance(Duration::from_secs(300));
+        let refresh = sim.options.thermometer_update_interval;
+        let ticks = 3 * refresh.as_millis() / ADVANCE_STEP.as_millis();
+        for _ in 0..ticks {
+            assert!(!sim.update(ADVANCE_STEP), "at {:?}", sim.time());
+        }
+        // the next one after sanity moves does
+        player_mut(&mut sim, "ann").sanity = 50.0;
+        assert!(sim.advance(refresh + ADVANCE_STEP));
+    }
+
+    // everything else at its defaults
+    const WANDERING: &str = r#"{"setup_duration": 0, "ghost_orbs_rate": 60, "evidence_given": 3}"#;
+
+    // What the sim sent every tick of a minute, stepped the way the server
+    // steps it
+    fn a_minute_of(seed: u64) -> Vec<String> {
+        let mut sim = haunted_by(Some(GhostType::Banshee), seed, WANDERING, &["ann", "bob"]);
+        sim.update_player_loc(addr(1), 2).unwrap();
+        (0..600)
+            .map(|_| {
+                sim.update(ADVANCE_STEP);
+                serde_json::to_string(&sim.get_gameupdate()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_seed_sends_the_same_updates_tick_for_tick() {
+        let first = a_minute_of(7);
+        assert_eq!(first, a_minute_of(7));
+        assert_ne!(first, a_minute_of(8));
+    }
+
+    #[test]
+    fn a_seeded_minute_has_the_ghost_wandering_and_showing_orbs() {
+        let run = || {
+            let mut sim = haunted_by(Some(GhostType::Banshee), 7, WANDERING, &["ann"]);
+            for _ in 0..600 {
+                sim.update(ADVANCE_STEP);
+            }
+            sim
+        };
+        let sim = run();
+        let log: Vec<_> = sim.event_log().into_iter().map(|e| e.event).collect();
+        let moves: Vec<_> = log
+            .iter()
+            .filter_map(|e| match e {
+                SimEvent::GhostMoved { from, to } => Some((*from, *to)),
+                _ => None,
+            })
+            .collect();
+        assert!(!moves.is_empty());
+        // room to room, never skipping one
+        for (from, to) in &moves {
+            assert!(sim.map.neighbours(*from).contains(to), "{} to {}", from, to);
+        }
+        assert_eq!(moves.last().unwrap().1, sim.ghost.current_room);
+        assert!(log.iter().any(|e| matches!(e, SimEvent::OrbsShown { .. })));
+        assert_eq!(sim.ghost.current_room, run().ghost.current_room);
+    }
+
+    #[test]
+    fn every_view_of_a_tick_has_the_same_noise() {
+        let noisy = r#"{"setup_duration": 0, "temperature_variability": 5}"#;
+        let mut sim = started(3, noisy, &["ann"]);
+        sim.update(ADVANCE_STEP);
+        let once = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_eq!(once, serde_json::to_value(sim.get_gameupdate()).unwrap());
+        let ann = serde_json::to_value(sim.get_gameupdate_for(Some("ann"))).unwrap();
+        assert_eq!(ann, serde_json::to_value(sim.get_gameupdate_for(Some("ann"))).unwrap());
+    }
+
+    #[test]
+    fn sanity_marks_are_logged_once_on_the_way_down() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let since = sim.event_log().last().unwrap().id;
+        let marks = |sim: &Simulation| -> Vec<u32> {
+            let log = sim.events_since(since).1;
+            log.iter()
+                .filter_map(|e| match e.event {
+                    SimEvent::SanityBelow { threshold, .. } => Some(threshold),
+                    _ => None,
+                })
+                .collect()
+        };
+        // straight past two at once
+        player_mut(&mut sim, "ann").sanity = 45.0;
+        sim.update(ADVANCE_STEP);
+        sim.update(ADVANCE_STEP);
+        assert_eq!(marks(&sim), [75, 50]);
+        // back over one and down again
+        player_mut(&mut sim, "ann").sanity = 60.0;
+        sim.update(ADVANCE_STEP);
+        player_mut(&mut sim, "ann").sanity = 20.0;
+        sim.update(ADVANCE_STEP);
+        assert_eq!(marks(&sim), [75, 50, 50, 25]);
+    }
+
+    #[test]
+    fn comings_goings_and_deaths_are_logged() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.kill_player("ann");
+        sim.remove_player(addr(1));
+        let log: Vec<_> = sim.event_log().into_iter().map(|e| e.event).collect();
+        assert!(matches!(&log[0], SimEvent::PlayerJoined { name } if name == "ann"));
+        assert!(matches!(&log[1], SimEvent::PlayerJoined { name } if name == "bob"));
+        assert!(matches!(log[2], SimEvent::RoundStarted { .. }));
+        let tail = &log[log.len() - 2..];
+        assert!(matches!(&tail[0], SimEvent::PlayerDied { name, room: Some(0) } if name == "ann"));
+        assert!(matches!(&tail[1], SimEvent::PlayerLeft { name } if name == "bob"));
+    }
+
+    #[test]
+    fn a_lobby_haunts_the_map_it_picked() {
+        // 0 - 1 - 2 - 3 - 4, the foyer at one end
+        let rooms: Vec<_> = (0..5)
+            .map(|r: usize| {
+                let connections: Vec<_> = [r.wrapping_sub(1), r + 1]
+                    .into_iter()
+                    .filter(|c| *c < 5)
+                    .collect();
+                serde_json::json!({
+                    "label": r, "name": format!("Room {}", r),
+                    "connections": connections, "safe_room": r == 0
+                })
+            })
+            .collect();
+        let map = Map::from_json(&serde_json::json!({ "rooms": rooms }).to_string()).unwrap();
+        let mut catalog = MapCatalog::builtin();
+        catalog.add("corridor", map);
+
+        let mut homes = std::collections::BTreeSet::new();
+        for seed in 0..40 {
+            let mut sim = Simulation::with_seed(catalog.clone(), seed);
+            sim.set_tunables(tunables(QUIET)).unwrap();
+            sim.add_player(addr(0), "ann").unwrap();
+            assert!(sim.select_map("nowhere").is_err());
+            sim.select_map("corridor").unwrap();
+            sim.start(None, None, Difficulty::default(), true).unwrap();
+            assert_eq!(sim.map.rooms.len(), 5);
+            homes.insert(sim.ghost.ghost_room);
+            assert!(sim.select_map(DEFAULT_MAP).is_err());
+        }
+        // anywhere but the safe room
+        assert_eq!(homes.into_iter().collect::<Vec<_>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn a_chilled_room_warms_back_along_the_curve() {
+        // the Hallway's medium, the ghost's upstairs out of the way
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.set_ghost_room(14).unwrap();
+        sim.teleport_ghost(14, false).unwrap();
+        let ambient = sim.flags.ambient_temp as f64;
+        sim.chill_room(2);
+        assert_eq!(sim.flags.room_temps[2], ambient - GHOST_CHILL);
+
+        let mut last = ambient - GHOST_CHILL;
+        for secs in 1..=60 {
+            sim.settle_temps(Duration::from_secs(1));
+            let temp = sim.flags.room_temps[2];
+            let left = GHOST_CHILL * (-TEMP_RECOVERY_RATE * secs as f64).exp();
+            assert!((ambient - temp - left).abs() < 1e-9, "{} at {}s", temp, secs);
+            assert!(temp > last && temp < ambient);
+            last = temp;
+        }
+        // however it's stepped
+        let mut once = started(1, QUIET, &["ann"]);
+        once.set_ghost_room(14).unwrap();
+        once.teleport_ghost(14, false).unwrap();
+        once.chill_room(2);
+        once.settle_temps(Duration::from_secs(60));
+        assert!((once.flags.room_temps[2] - last).abs() < 1e-9);
+        // chilling again drops it straight back
+        sim.teleport_ghost(2, false).unwrap();
+        assert_eq!(sim.room_temp(2), (ambient - GHOST_CHILL) as i32);
+    }
+
+    #[test]
+    fn only_a_freezing_ghosts_room_reads_under_the_floor() {
+        let floor = 35;
+        for (ghost_type, freezing) in [(GhostType::Jinn, true), (GhostType::Banshee, false)] {
+            let mut sim = haunted_by(Some(ghost_type), 1, &giving(3), &["ann"]);
+            sim.set_ghost_room(13).unwrap();
+            sim.teleport_ghost(13, false).unwrap();
+            sim.advance(Duration::from_secs(900));
+            let home = sim.ghost.ghost_room;
+            let coldest = sim.flags.room_temps[home].round() as i32;
+            assert_eq!(coldest, sim.flags.ghost_room_min_temp, "{:?}", ghost_type);
+            assert_eq!(coldest < floor, freezing, "{:?}", ghost_type);
+
+            // readings are held to it too, wherever they're taken
+            let ann = player_mut(&mut sim, "ann");
+            ann.inventory.add(EquipmentKind::Thermometer).unwrap();
+            for room in [home, 2] {
+                place(&mut sim, "ann", room);
+                let temp = match sim.read_thermometer(addr(0)).unwrap() {
+                    SensorReading::Thermometer { temp, .. } => temp,
+                    _ => panic!("Not a thermometer reading"),
+                };
+                let frozen = freezing && room == home;
+                assert_eq!(temp < floor, frozen, "{:?} in {}: {}", ghost_type, room, temp);
+                assert_eq!(sim.clamp_temp(room, 0), if frozen { 0 } else { floor });
+                sim.advance(Duration::from_secs(5));
+            }
+        }
+    }
+
+    #[test]
+    fn players_see_their_own_rooms_temperature() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        place(&mut sim, "bob", 13);
+        let admin = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        let temps = admin["Sim"]["room_temps"].as_array().unwrap();
+        assert_eq!(temps.len(), sim.map.rooms.len());
+        assert!(admin["Sim"]["players"].as_array().unwrap().iter().all(|p| p["temp"].is_i64()));
+
+        let ann = serde_json::to_value(sim.get_gameupdate_for(Some("ann"))).unwrap();
+        assert!(ann["Sim"].get("room_temps").is_none());
+        let players = ann["Sim"]["players"].as_array().unwrap();
+        let temp = |name: &str| players.iter().find(|p| p["name"] == name).unwrap().get("temp");
+        assert_eq!(temp("ann").unwrap().as_i64(), Some(sim.noisy_temp(0) as i64));
+        assert!(temp("bob").is_none());
+    }
+
+    #[test]
+    fn sensors_go_inside_within_reach_of_someone_alive() {
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        sim.add_player(addr(0), "ann").unwrap();
+        let e = sim.place_sensor(addr(0), 0, SensorKind::Motion).unwrap_err();
+        assert_eq!(e, "Sim not started");
+
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        // the Foyer, the Hallway off it, and the Driveway out front
+        sim.place_sensor(addr(0), 0, SensorKind::Motion).unwrap();
+        sim.place_sensor(addr(0), 2, SensorKind::Sound).unwrap();
+        let too_far = sim.place_sensor(addr(0), 6, SensorKind::Motion).unwrap_err();
+        assert_eq!(too_far, format!("ann is too far from {}", sim.room_name(6)));
+        let outside = sim.place_sensor(addr(0), 15, SensorKind::Motion).unwrap_err();
+        assert_eq!(outside, "Sensors have to go inside");
+        assert!(sim.place_sensor(addr(0), 99, SensorKind::Motion).is_err());
+        assert_eq!(sim.place_sensor(addr(9), 0, SensorKind::Motion).unwrap_err(), "Not registered");
+
+        player_mut(&mut sim, "bob").last_loc = None;
+        let e = sim.place_sensor(addr(1), 0, SensorKind::Motion).unwrap_err();
+        assert_eq!(e, "bob hasn't said where they are");
+        place(&mut sim, "bob", 0);
+        sim.kill_player("bob");
+        assert_eq!(sim.place_sensor(addr(1), 0, SensorKind::Motion).unwrap_err(), "bob is dead");
+
+        let owners: Vec<_> = sim.flags.sensors.iter().map(|s| (s.owner.as_str(), s.room)).collect();
+        assert_eq!(owners, [("ann", 0), ("ann", 2)]);
+    }
+
+    #[test]
+    fn sensors_run_out_until_one_is_taken_back() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        let ids: Vec<_> = (0..sim.options.sensor_limit)
+            .map(|_| sim.place_sensor(addr(0), 0, SensorKind::Motion).unwrap())
+            .collect();
+        let e = sim.place_sensor(addr(0), 0, SensorKind::Motion).unwrap_err();
+        assert_eq!(e, "Sensor limit reached");
+        sim.remove_sensor(addr(0), ids[1]).unwrap();
+        assert!(sim.remove_sensor(addr(0), ids[1]).is_err());
+        let id = sim.place_sensor(addr(0), 0, SensorKind::Motion).unwrap();
+        assert!(!ids.contains(&id));
+    }
+
+    #[test]
+    fn sensors_come_up_for_their_owner_or_whoever_is_near() {
+        let mut sim = started(1, QUIET, &["ann", "bob", "cat"]);
+        let hallway = sim.place_sensor(addr(0), 2, SensorKind::Motion).unwrap();
+        let foyer = sim.place_sensor(addr(0), 0, SensorKind::Sound).unwrap();
+        assert_eq!(sim.remove_sensor(addr(0), 99).unwrap_err(), "No such sensor");
+        assert_eq!(sim.remove_sensor(addr(9), hallway).unwrap_err(), "Not registered");
+
+        // bob's upstairs, out of reach of ann's sensor
+        place(&mut sim, "bob", 14);
+        let e = sim.remove_sensor(addr(1), hallway).unwrap_err();
+        assert_eq!(e, format!("bob is too far from {}", sim.room_name(2)));
+        player_mut(&mut sim, "bob").last_loc = None;
+        let e = sim.remove_sensor(addr(1), hallway).unwrap_err();
+        assert_eq!(e, "bob hasn't said where they are");
+        sim.kill_player("cat");
+        assert_eq!(sim.remove_sensor(addr(2), foyer).unwrap_err(), "cat is dead");
+
+        // ann takes hers back from upstairs, bob his from the stairwell
+        place(&mut sim, "ann", 14);
+        sim.remove_sensor(addr(0), foyer).unwrap();
+        place(&mut sim, "bob", 6);
+        sim.remove_sensor(addr(1), hallway).unwrap();
+        assert!(sim.flags.sensors.is_empty());
+    }
+
+    #[test]
+    fn sensors_trip_on_what_they_watch_for() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.set_ghost_room(14).unwrap();
+        sim.teleport_ghost(14, false).unwrap();
+        place(&mut sim, "ann", 2);
+        let motion = sim.place_sensor(addr(0), 6, SensorKind::Motion).unwrap();
+        let near = sim.place_sensor(addr(0), 2, SensorKind::Sound).unwrap();
+        place(&mut sim, "ann", 0);
+        let far = sim.place_sensor(addr(0), 1, SensorKind::Sound).unwrap();
+
+        // sound carries a room over, motion has to be in it
+        sim.trip_sensors(SensorKind::Sound, 6, TrippedBy::Ghost);
+        sim.teleport_ghost(2, false).unwrap();
+        sim.update_player_loc(addr(0), 2).unwrap();
+        sim.update_player_loc(addr(0), 6).unwrap();
+        let fired: Vec<_> = sim.flags.sensor_events.iter().map(|e| (e.sensor, e.by)).collect();
+        assert_eq!(fired, [(near, TrippedBy::Ghost), (motion, TrippedBy::Player)]);
+        assert!(!fired.iter().any(|(s, _)| *s == far));
+        let admin = serde_json::to_value(sim.get_gameupdate()).unwrap();
+        assert_eq!(admin["Sim"]["sensor_events"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn cameras_go_down_and_come_up_within_reach() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        for name in ["ann", "bob"] {
+            player_mut(&mut sim, name).inventory.add(EquipmentKind::VideoCamera).unwrap();
+        }
+        let e = sim.place_camera(addr(0), 6).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(6)));
+        let id = sim.place_camera(addr(0), 2).unwrap();
+        sim.kill_player("bob");
+        assert_eq!(sim.place_camera(addr(1), 0).unwrap_err(), "bob is dead");
+
+        // not from upstairs, but from the stairwell next door
+        place(&mut sim, "ann", 14);
+        let e = sim.remove_camera(addr(0), id).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(2)));
+        assert_eq!(sim.remove_camera(addr(1), id).unwrap_err(), "bob is dead");
+        place(&mut sim, "ann", 6);
+        sim.remove_camera(addr(0), id).unwrap();
+        assert!(sim.flags.dropped_items == [(2, EquipmentKind::VideoCamera)]);
+        assert_eq!(sim.remove_camera(addr(0), id).unwrap_err(), "No such camera");
+    }
+
+    #[test]
+    fn the_projector_is_moved_by_someone_near_it() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        player_mut(&mut sim, "ann").inventory.add(EquipmentKind::DotsProjector).unwrap();
+        let e = sim.place_dots(addr(0), 6).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(6)));
+        assert!(sim.place_dots(addr(0), 15).is_err());
+        assert_eq!(sim.place_dots(addr(1), 2).unwrap_err(), "Item not in inventory");
+        sim.place_dots(addr(0), 2).unwrap();
+
+        // bob can't reach it from upstairs, or send it upstairs from the foyer
+        place(&mut sim, "bob", 14);
+        let e = sim.place_dots(addr(1), 14).unwrap_err();
+        assert_eq!(e, format!("bob is too far from {}", sim.room_name(2)));
+        place(&mut sim, "bob", 0);
+        let e = sim.place_dots(addr(1), 14).unwrap_err();
+        assert_eq!(e, format!("bob is too far from {}", sim.room_name(14)));
+        place(&mut sim, "bob", 6);
+        sim.place_dots(addr(1), 14).unwrap();
+        assert_eq!(sim.flags.dots_location, Some(14));
+        sim.kill_player("bob");
+        assert_eq!(sim.place_dots(addr(1), 6).unwrap_err(), "bob is dead");
+    }
+
+    #[test]
+    fn the_book_is_moved_by_someone_near_it() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        player_mut(&mut sim, "ann").inventory.add(EquipmentKind::Book).unwrap();
+        let e = sim.place_book(addr(0), 6).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(6)));
+        assert_eq!(sim.place_book(addr(0), 15).unwrap_err(), "The book has to go inside");
+        assert_eq!(sim.place_book(addr(1), 2).unwrap_err(), "Item not in inventory");
+        sim.place_book(addr(0), 2).unwrap();
+        sim.flags.ghost_writing_visible = true;
+
+        // nobody's writing goes from across the map
+        place(&mut sim, "bob", 14);
+        let e = sim.place_book(addr(1), 14).unwrap_err();
+        assert_eq!(e, format!("bob is too far from {}", sim.room_name(2)));
+        let e = sim.remove_book(addr(1)).unwrap_err();
+        assert_eq!(e, format!("bob is too far from {}", sim.room_name(2)));
+        place(&mut sim, "bob", 0);
+        let e = sim.place_book(addr(1), 14).unwrap_err();
+        assert_eq!(e, format!("bob is too far from {}", sim.room_name(14)));
+        assert!(sim.flags.ghost_writing_visible);
+
+        // but moving it wipes the page, and nobody has to pick it up first
+        place(&mut sim, "bob", 6);
+        sim.place_book(addr(1), 14).unwrap();
+        assert_eq!(sim.flags.book_location, Some(14));
+        assert!(!sim.flags.ghost_writing_visible);
+        sim.remove_book(addr(1)).unwrap();
+        assert_eq!(sim.remove_book(addr(1)).unwrap_err(), "The book isn't down");
+        sim.kill_player("bob");
+        assert_eq!(sim.place_book(addr(1), 6).unwrap_err(), "bob is dead");
+    }
+
+    #[test]
+    fn crucifixes_go_inside_within_reach() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        for name in ["ann", "bob"] {
+            player_mut(&mut sim, name).inventory.add(EquipmentKind::Crucifix).unwrap();
+        }
+        let e = sim.place_crucifix(addr(0), 6).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(6)));
+        let e = sim.place_crucifix(addr(0), 15).unwrap_err();
+        assert_eq!(e, "The crucifix has to go inside");
+        sim.place_crucifix(addr(0), 2).unwrap();
+        sim.kill_player("bob");
+        assert_eq!(sim.place_crucifix(addr(1), 0).unwrap_err(), "bob is dead");
+        let rooms: Vec<_> = sim.flags.crucifixes.iter().map(|c| c.room).collect();
+        assert_eq!(rooms, [2]);
+    }
+
+    #[test]
+    fn lights_are_switched_from_the_room_or_next_door() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.options.breaker_light_limit = 1;
+        let e = sim.set_lights(addr(0), 6, true).unwrap_err();
+        assert_eq!(e, format!("ann is too far from {}", sim.room_name(6)));
+        assert_eq!(sim.set_lights(addr(0), 99, true).unwrap_err(), "No such room");
+        sim.set_lights(addr(0), 2, true).unwrap();
+
+        // a second light trips the breaker, but only from where it's switched
+        place(&mut sim, "bob", 14);
+        assert!(sim.set_lights(addr(1), 0, true).is_err());
+        assert!(sim.flags.breaker_on);
+        sim.set_lights(addr(1), 14, true).unwrap();
+        assert!(!sim.flags.breaker_on);
+        sim.kill_player("bob");
+        assert_eq!(sim.set_lights(addr(1), 14, false).unwrap_err(), "bob is dead");
+    }
+
+    fn listening(sim: &mut Simulation, name: &str) -> Result<u32, String> {
+        let addr = player(sim, name).addr;
+        match sim.use_parabolic(addr, 2)? {
+            SensorReading::Parabolic { level, .. } => Ok(level),
+            _ => panic!("Not a parabolic reading"),
+        }
+    }
+
+    #[test]
+    fn the_parabolic_hears_the_room_its_pointed_at_and_past_it() {
+        let mut sim = started(1, QUIET, &["ann", "bob"]);
+        sim.set_ghost_room(14).unwrap();
+        sim.teleport_ghost(14, false).unwrap();
+        for name in ["ann", "bob"] {
+            let mic = EquipmentKind::ParabolicMicrophone;
+            player_mut(&mut sim, name).inventory.add(mic).unwrap();
+        }
+        // from the Foyer, at the Hallway with the Stairwell past it
+        sim.make_sound(2, 30.0);
+        sim.make_sound(6, 80.0);
+        assert_eq!(listening(&mut sim, "ann"), Ok(40));
+        let e = sim.use_parabolic(addr(0), 6).err().unwrap();
+        assert_eq!(e, format!("Can't point it at {} from here", sim.map.room_name(6)));
+        assert_eq!(
+            listening(&mut sim, "ann").unwrap_err(),
+            "Parabolic microphone is still listening"
+        );
+        sim.make_sound(2, 60.0);
+        assert_eq!(listening(&mut sim, "bob"), Ok(90));
+        sim.advance(sim.options.parabolic_cooldown);
+        assert!(listening(&mut sim, "ann").is_ok());
+
+        sim.kill_player("bob");
+        sim.advance(sim.options.parabolic_cooldown);
+        assert_eq!(listening(&mut sim, "bob").unwrap_err(), "bob is dead");
+        assert_eq!(sim.use_parabolic(addr(9), 2).err().unwrap(), "Not a player");
+    }
+
+    #[test]
+    fn the_parabolic_cooldown_stays_out_of_the_state() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        player_mut(&mut sim, "ann").last_parabolic = Some(Duration::from_secs(3));
+        let ann = serde_json::to_value(player(&sim, "ann")).unwrap();
+        assert!(ann.get("last_parabolic").is_none(), "{}", ann);
+    }
+
+    #[test]
+    fn sounds_die_down_at_the_decay_rate() {
+        let mut sim = started(1, QUIET, &["ann"]);
+        sim.options.sound_decay_rate = 10.0;
+        sim.make_sound(13, 70.0);
+        sim.make_sound(13, 50.0);
+        // never past the top
+        assert_eq!(sim.room_sound(13), 100.0);
+        sim.update(Duration::from_millis(2500));
+        assert!((sim.room_sound(13) - 75.0).abs() < 1e-9);
+        sim.update(Duration::from_secs(60));
+        assert_eq!(sim.room_sound(13), 0.0);
+    }
+
+    #[test]
+    fn interactions_cost_what_the_options_say() {
+        let mut costs: serde_json::Value = serde_json::from_str(QUIET).unwrap();
+        costs["interaction_sanity_loss"] = 12.0.into();
+        costs["interaction_sanity_loss_elsewhere"] = 3.0.into();
+        let mut sim = started(1, &costs.to_string(), &["ann", "bob", "cat"]);
+        sim.set_ghost_room(13).unwrap();
+        sim.teleport_ghost(13, false).unwrap();
+        place(&mut sim, "ann", 13);
+        place(&mut sim, "bob", 2);
+        place(&mut sim, "cat", 15);
+        sim.ghost_interaction();
+        let sanity: Vec<_> = ["ann", "bob", "cat"].iter().map(|n| player(&sim, n).sanity).collect();
+        assert_eq!(sanity, [88.0, 97.0, 100.0]);
+
+        let mut sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        for field in ["interaction_sanity_loss", "interaction_sanity_loss_elsewhere"] {
+            let e = sim.set_tunables(tunables(&format!(r#"{{"{}": 150}}"#, field)));
+            assert_eq!(e.unwrap_err(), format!("{} must be between 0 and 100, got 150", field));
+        }
+    }
+
+    // How many interactions a round with everyone held at `sanity` sees in
+    // `secs`, the options at their defaults otherwise
+    fn interactions_at(sanity: f64, seed: u64, secs: u64) -> usize {
+        let held = r#"{"setup_duration": 0, "sanity_drain_rate": 0, "ghost_event_rate": 0,
+            "ghost_hunt_rate": 0, "interaction_sanity_loss": 0,
+            "interaction_sanity_loss_elsewhere": 0}"#;
+        let mut sim = haunted_by(Some(GhostType::Spirit), seed, held, &["ann", "bob"]);
+        for name in ["ann", "bob"] {
+            player_mut(&mut sim, name).sanity = sanity;
+        }
+        sim.advance(Duration::from_secs(secs));
+        let log = sim.event_log();
+        log.iter().filter(|e| matches!(e.event, SimEvent::Interaction { .. })).count()
+    }
+
+    #[test]
+    fn the_ghost_gets_busier_as_sanity_falls() {
+        let counts: Vec<usize> = [100.0, 50.0, 0.0]
+            .iter()
+            .map(|sanity| (1..=5).map(|seed| interactions_at(*sanity, seed, 600)).sum())
+            .collect();
+        assert!(counts[0] < counts[1] && counts[1] < counts[2], "{:?}", counts);
+        // twice as busy with nothing left, low_sanity_activity is 1
+        let ratio = counts[2] as f64 / counts[0] as f64;
+        assert!((1.6..2.4).contains(&ratio), "{:?}", counts);
+    }
+
+    #[test]
+    fn a_lobby_at_full_sanity_sees_only_now_and_then() {
+        // a few a minute at the 3 a minute it's set to, never a flood
+        let minutes: Vec<_> = (1..=20).map(|seed| interactions_at(100.0, seed, 60)).collect();
+        assert!(minutes.iter().all(|n| *n <= 8), "{:?}", minutes);
+        let mean = minutes.iter().sum::<usize>() as f64 / minutes.len() as f64;
+        assert!((1.0..=4.0).contains(&mean), "{:?}", minutes);
+
+        // and with what it all costs back on, nobody's run dry a couple of
+        // minutes in
+        let costly = r#"{"setup_duration": 0}"#;
+        for seed in 1..=20 {
+            let mut sim = haunted_by(Some(GhostType::Spirit), seed, costly, &["ann"]);
+            sim.advance(Duration::from_secs(120));
+            assert!(player(&sim, "ann").sanity > 25.0, "seed {}", seed);
+        }
     }
 }
@@ -0,0 +1,462 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    time::Duration,
+};
+
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+
+use crate::{
+    broadcaster::{Frame, Outbound},
+    event_log::{RecordLine, RecordedEvent, RecordedInput},
+    ghost::GhostType,
+    map::{RoomLabel, RoomRef},
+    messages::Notice,
+    replay::SimInput,
+    sim::{Difficulty, GameUpdate, Simulation},
+    utils,
+};
+
+type Reply<T> = oneshot::Sender<T>;
+type Query = Box<dyn FnOnce(&mut Simulation) + Send>;
+
+pub(crate) enum AdminOp {
+    SelectMap(String),
+    FastForward(Duration),
+    SetPaused(bool),
+    TeleportGhost { room: RoomLabel, emf: bool },
+    SetGhostRoom(RoomLabel),
+    SetGhostType(Option<GhostType>),
+    Reset { clear_players: bool },
+}
+
+pub(crate) enum SimCommand {
+    AddPlayer {
+        addr: SocketAddr,
+        name: String,
+        reply: Reply<Result<(), String>>,
+    },
+    UpdateLoc {
+        addr: SocketAddr,
+        location: RoomRef,
+        reply: Reply<Result<Vec<Notice>, String>>,
+    },
+    // sends the map and then the first state on success
+    Start {
+        map: Option<String>,
+        seed: Option<u64>,
+        difficulty: Difficulty,
+        // starts without waiting for everyone to be ready
+        force: bool,
+        reply: Reply<Result<(), String>>,
+    },
+    Tick(Duration),
+    AdminOp(AdminOp, Reply<Result<(), String>>),
+    // anything else, the closure sends its own reply
+    Query(Query),
+    // the state changed, it goes out with the next tick
+    Broadcast,
+    // an input with nothing for the sim to do yet
+    Record(SimInput),
+    // the input went to the recorder with the command that handles it
+    Recorded(SimInput, Box<SimCommand>),
+}
+
+impl SimCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            SimCommand::AddPlayer { .. } => "AddPlayer",
+            SimCommand::UpdateLoc { .. } => "UpdateLoc",
+            SimCommand::Start { .. } => "Start",
+            SimCommand::Tick(_) => "Tick",
+            SimCommand::AdminOp(..) => "AdminOp",
+            SimCommand::Query(_) => "Query",
+            SimCommand::Broadcast => "Broadcast",
+            SimCommand::Record(_) => "Record",
+            SimCommand::Recorded(_, cmd) => cmd.name(),
+        }
+    }
+}
+
+// The server side of the actor. Cheap to clone, every connection gets one.
+#[derive(Clone)]
+pub(crate) struct SimHandle {
+    tx: UnboundedSender<SimCommand>,
+}
+
+impl SimHandle {
+    pub(crate) fn send(&self, cmd: SimCommand) {
+        let input = match cmd {
+            SimCommand::Broadcast | SimCommand::Record(_) => None,
+            _ => INPUT.try_with(|input| input.borrow_mut().take()).ok().flatten(),
+        };
+        let cmd = match input {
+            Some(input) => SimCommand::Recorded(input, Box::new(cmd)),
+            None => cmd,
+        };
+        if self.tx.send(cmd).is_err() {
+            println!("Sim task is gone");
+        }
+    }
+
+    // Runs f on the sim and waits for its result
+    pub(crate) async fn query<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Simulation) -> T + Send + 'static,
+    {
+        let (reply, rx) = oneshot::channel();
+        self.send(SimCommand::Query(Box::new(move |sim| {
+            let _ = reply.send(f(sim));
+        })));
+        rx.await.map_err(|_| "Sim task is gone".to_owned())
+    }
+
+    // For commands that reply with a Result, flattens a dead sim into the error
+    pub(crate) async fn request<T>(
+        &self,
+        make: impl FnOnce(Reply<Result<T, String>>) -> SimCommand,
+    ) -> Result<T, String> {
+        let (reply, rx) = oneshot::channel();
+        self.send(make(reply));
+        rx.await.map_err(|_| "Sim task is gone".to_owned())?
+    }
+
+    pub(crate) fn broadcast(&self) {
+        self.send(SimCommand::Broadcast);
+    }
+
+    pub(crate) fn record(&self, input: SimInput) {
+        self.send(SimCommand::Record(input));
+    }
+}
+
+tokio::task_local! {
+    // what's being handled, recorded with the first command it sends
+    static INPUT: RefCell<Option<SimInput>>;
+}
+
+// Runs f with the input going to the recorder alongside the first command
+// f sends the sim. A tick can't get in between them that way, which it
+// could if the input went on ahead.
+pub(crate) async fn with_input<F: Future>(input: Option<SimInput>, f: F) -> F::Output {
+    INPUT.scope(RefCell::new(input), f).await
+}
+
+// A recorder gets every input and every event the sim logs, tagged with the
+// lobby's code
+pub(crate) type Recorder = (String, UnboundedSender<RecordLine>);
+
+pub(crate) fn spawn_sim(
+    sim: Simulation,
+    outbound: UnboundedSender<Outbound>,
+    recorder: Option<Recorder>,
+) -> (SimHandle, tokio::task::JoinHandle<()>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(run_sim(sim, rx, outbound, recorder));
+    (SimHandle { tx }, task)
+}
+
+// Owns the sim. Commands are handled one at a time, so nothing else ever
+// sees it half updated.
+async fn run_sim(
+    mut sim: Simulation,
+    mut rx: UnboundedReceiver<SimCommand>,
+    outbound: UnboundedSender<Outbound>,
+    recorder: Option<Recorder>,
+) {
+    // changes since the last state, which goes out on the next tick
+    let mut dirty = false;
+    // the newest event the recorder has
+    let mut recorded = 0;
+    while let Some(cmd) = rx.recv().await {
+        let name = cmd.name();
+        // ticks are only worth recording when the sim runs them
+        let (input, cmd) = match cmd {
+            SimCommand::Recorded(input, cmd) => (Some(input), *cmd),
+            SimCommand::Record(input) => (Some(input.clone()), SimCommand::Record(input)),
+            SimCommand::Tick(dt) if sim.is_started() && !sim.is_paused() => {
+                (Some(SimInput::Tick { dt }), SimCommand::Tick(dt))
+            }
+            cmd => (None, cmd),
+        };
+        if let (Some((lobby, tx)), Some(input)) = (&recorder, input) {
+            let lobby = lobby.clone();
+            let _ = tx.send(RecordLine::Input(Box::new(RecordedInput { lobby, input })));
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            handle(&mut sim, cmd, &outbound, &mut dirty)
+        }));
+        // the sim may be half updated, so the task goes down with it and
+        // the server notices
+        if let Err(payload) = result {
+            println!(
+                "Sim panicked handling {} at {:.1}s: {}",
+                name,
+                sim.time().as_secs_f64(),
+                utils::panic_message(payload.as_ref())
+            );
+            panic::resume_unwind(payload);
+        }
+        if let Some((lobby, tx)) = &recorder {
+            let (last, events) = sim.events_since(recorded);
+            recorded = last;
+            for entry in events {
+                let lobby = lobby.clone();
+                let _ = tx.send(RecordLine::Event(RecordedEvent { lobby, entry }));
+            }
+        }
+    }
+}
+
+fn handle(
+    sim: &mut Simulation,
+    cmd: SimCommand,
+    outbound: &UnboundedSender<Outbound>,
+    dirty: &mut bool,
+) {
+    match cmd {
+        SimCommand::AddPlayer { addr, name, reply } => {
+            let _ = reply.send(sim.add_player(addr, &name));
+        }
+        SimCommand::UpdateLoc {
+            addr,
+            location,
+            reply,
+        } => {
+            let result = sim
+                .resolve_room(&location)
+                .and_then(|location| sim.update_player_loc(addr, location));
+            let _ = reply.send(result);
+        }
+        SimCommand::Start {
+            map,
+            seed,
+            difficulty,
+            force,
+            reply,
+        } => {
+            let result = sim.start(map, seed, difficulty, force);
+            if result.is_ok() {
+                // the map has to land before any Sim frame refers to
+                // its rooms
+                queue(outbound, Outbound::Broadcast(sim.get_map()));
+                broadcast_gamestate(sim, outbound);
+                queue(outbound, Outbound::Flush);
+                *dirty = false;
+            }
+            let _ = reply.send(result);
+        }
+        SimCommand::Tick(dt) => {
+            let changed = sim.is_started() && !sim.is_paused() && sim.update(dt);
+            // however many asked since the last tick, one state covers them
+            if changed || *dirty {
+                broadcast_gamestate(sim, outbound);
+                *dirty = false;
+            }
+            if sim.take_urgent() {
+                queue(outbound, Outbound::Flush);
+            }
+        }
+        SimCommand::AdminOp(op, reply) => {
+            let _ = reply.send(admin_op(sim, op));
+        }
+        SimCommand::Query(f) => f(sim),
+        SimCommand::Broadcast => *dirty = true,
+        SimCommand::Record(_) => (),
+        SimCommand::Recorded(_, cmd) => handle(sim, *cmd, outbound, dirty),
+    }
+
+    let decisions = sim.take_ghost_debug();
+    if !decisions.is_empty() {
+        queue(outbound, Outbound::Debug(GameUpdate::GhostDebug { decisions }));
+    }
+}
+
+pub(crate) fn admin_op(sim: &mut Simulation, op: AdminOp) -> Result<(), String> {
+    match op {
+        AdminOp::SelectMap(id) => sim.select_map(&id),
+        AdminOp::FastForward(dt) => {
+            if !sim.is_started() {
+                return Err("Sim not started".to_owned());
+            }
+            sim.advance(dt);
+            Ok(())
+        }
+        AdminOp::SetPaused(paused) => sim.set_paused(paused),
+        AdminOp::TeleportGhost { room, emf } => sim.teleport_ghost(room, emf),
+        AdminOp::SetGhostRoom(room) => sim.set_ghost_room(room),
+        AdminOp::SetGhostType(ghost_type) => sim.set_ghost_type(ghost_type),
+        AdminOp::Reset { clear_players } => {
+            sim.reset(clear_players);
+            Ok(())
+        }
+    }
+}
+
+// Only snapshots the state, the broadcaster does the rest
+fn broadcast_gamestate(sim: &mut Simulation, outbound: &UnboundedSender<Outbound>) {
+    let events = sim.take_lobby_events();
+    let update = sim.get_gameupdate();
+    let audio = sim.take_audio_queue();
+
+    if events.is_empty() {
+        let mut frame = Frame::new(update);
+        if sim.redacts() {
+            frame.views = sim
+                .players
+                .iter()
+                .map(|p| (p.addr, sim.get_gameupdate_for(Some(&p.name))))
+                .collect();
+            frame.outsider = Some(sim.get_gameupdate_for(None));
+        }
+        queue(outbound, Outbound::State(Box::new(frame)));
+    } else {
+        queue(
+            outbound,
+            Outbound::Lobby {
+                events,
+                snapshot: update,
+            },
+        );
+    }
+    for (addr, cue) in audio {
+        queue(outbound, Outbound::Direct(addr, GameUpdate::Audio { cue }));
+    }
+}
+
+fn queue(outbound: &UnboundedSender<Outbound>, out: Outbound) {
+    if outbound.send(out).is_err() {
+        println!("Broadcaster is gone");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{event_log::SimEvent, map::MapCatalog};
+
+    fn addr(n: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 7000 + n))
+    }
+
+    fn spawn() -> (SimHandle, tokio::task::JoinHandle<()>, UnboundedReceiver<Outbound>) {
+        let (outbound, rx) = mpsc::unbounded_channel();
+        let sim = Simulation::with_seed(MapCatalog::builtin(), 1);
+        let (handle, task) = spawn_sim(sim, outbound, None);
+        (handle, task, rx)
+    }
+
+    // whatever the sim's sent the broadcaster, once it's caught up
+    async fn sent(sim: &SimHandle, rx: &mut UnboundedReceiver<Outbound>) -> Vec<Outbound> {
+        sim.query(|_| ()).await.unwrap();
+        let mut sent = Vec::new();
+        while let Ok(out) = rx.try_recv() {
+            sent.push(out);
+        }
+        sent
+    }
+
+    #[tokio::test]
+    async fn commands_are_handled_in_the_order_they_were_sent() {
+        let (sim, _task, _rx) = spawn();
+        for (n, name) in ["ann", "bob"].iter().enumerate() {
+            let name = name.to_string();
+            let add = |reply| SimCommand::AddPlayer { addr: addr(n as u16), name, reply };
+            sim.request(add).await.unwrap();
+        }
+        let names = sim.query(|sim| sim.players.iter().map(|p| p.name.clone()).collect::<Vec<_>>());
+        assert_eq!(names.await.unwrap(), ["ann", "bob"]);
+        let taken = |reply| SimCommand::AddPlayer {
+            addr: addr(2),
+            name: "ann".to_owned(),
+            reply,
+        };
+        assert!(sim.request(taken).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_change_goes_out_with_the_next_tick() {
+        let (sim, _task, mut rx) = spawn();
+        sim.send(SimCommand::Tick(Duration::from_millis(100)));
+        assert!(sent(&sim, &mut rx).await.is_empty());
+
+        // asking twice still only sends the one state
+        sim.broadcast();
+        sim.broadcast();
+        assert!(sent(&sim, &mut rx).await.is_empty());
+        sim.send(SimCommand::Tick(Duration::from_millis(100)));
+        let out = sent(&sim, &mut rx).await;
+        assert_eq!(out.len(), 1);
+        assert!(matches!(&out[0], Outbound::State(frame)
+            if matches!(frame.full, GameUpdate::Lobby { .. })));
+    }
+
+    #[tokio::test]
+    async fn a_start_sends_the_map_before_the_first_state() {
+        let (sim, _task, mut rx) = spawn();
+        let add = |reply| SimCommand::AddPlayer {
+            addr: addr(0),
+            name: "ann".to_owned(),
+            reply,
+        };
+        sim.request(add).await.unwrap();
+        sent(&sim, &mut rx).await;
+        let start = |reply| SimCommand::Start {
+            map: None,
+            seed: None,
+            difficulty: Difficulty::default(),
+            force: true,
+            reply,
+        };
+        sim.request(start).await.unwrap();
+        let out = sent(&sim, &mut rx).await;
+        assert!(matches!(&out[0], Outbound::Broadcast(GameUpdate::Map { .. })));
+        assert!(matches!(&out[1], Outbound::State(frame)
+            if matches!(frame.full, GameUpdate::Sim { .. })));
+        assert!(matches!(&out[2], Outbound::Flush));
+    }
+
+    #[tokio::test]
+    async fn admins_can_put_the_ghost_anywhere_inside() {
+        let (sim, _task, _rx) = spawn();
+        let op = |op| sim.request(|reply| SimCommand::AdminOp(op, reply));
+        assert!(op(AdminOp::TeleportGhost { room: 2, emf: false }).await.is_err());
+        sim.query(|sim| sim.start(None, None, Difficulty::default(), true))
+            .await
+            .unwrap()
+            .unwrap();
+
+        op(AdminOp::TeleportGhost { room: 2, emf: false }).await.unwrap();
+        op(AdminOp::SetGhostRoom(13)).await.unwrap();
+        // off the map, outside and the safe room
+        for room in [999, 15, 0] {
+            assert!(op(AdminOp::TeleportGhost { room, emf: false }).await.is_err());
+            assert!(op(AdminOp::SetGhostRoom(room)).await.is_err());
+        }
+        let log = sim.query(|sim| sim.event_log()).await.unwrap();
+        let forced: Vec<_> = log
+            .into_iter()
+            .filter_map(|e| match e.event {
+                SimEvent::GhostMoved { to, .. } => Some(("moved", to)),
+                SimEvent::FavoriteRoomChanged { to, .. } => Some(("room", to)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(forced, [("moved", 2), ("room", 13)]);
+    }
+
+    #[tokio::test]
+    async fn a_panic_takes_the_sim_down_with_it() {
+        let (sim, task, _rx) = spawn();
+        let _ = sim.query(|_| panic!("on purpose")).await;
+        assert!(task.await.unwrap_err().is_panic());
+        assert_eq!(sim.query(|_| ()).await, Err("Sim task is gone".to_owned()));
+    }
+}
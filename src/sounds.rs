@@ -0,0 +1,118 @@
+use serde::Serialize;
+
+// Every sound the server can ask a client to play. Clients pre-load these
+// from the manifest, so anything new that makes a noise gets a variant here
+// and the manifest picks it up.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum SoundId {
+    #[serde(rename = "interaction.sound")]
+    InteractionSound,
+    #[serde(rename = "interaction.lights_flicker")]
+    LightsFlicker,
+    #[serde(rename = "interaction.throw_object")]
+    ThrowObject,
+    #[serde(rename = "interaction.door_open")]
+    DoorOpen,
+    #[serde(rename = "ghost.manifest")]
+    Manifest,
+    #[serde(rename = "ghost.writing")]
+    GhostWriting,
+    #[serde(rename = "hunt.start")]
+    HuntStart,
+    #[serde(rename = "player.death")]
+    PlayerDeath,
+    #[serde(rename = "player.body_found")]
+    BodyFound,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum SoundCategory {
+    Footstep,
+    Slam,
+    Whisper,
+    Ambience,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SoundInfo {
+    id: SoundId,
+    category: SoundCategory,
+}
+
+impl SoundId {
+    pub const ALL: [SoundId; 9] = [
+        SoundId::InteractionSound,
+        SoundId::LightsFlicker,
+        SoundId::ThrowObject,
+        SoundId::DoorOpen,
+        SoundId::Manifest,
+        SoundId::GhostWriting,
+        SoundId::HuntStart,
+        SoundId::PlayerDeath,
+        SoundId::BodyFound,
+    ];
+
+    pub fn category(&self) -> SoundCategory {
+        match self {
+            SoundId::InteractionSound => SoundCategory::Footstep,
+            SoundId::LightsFlicker => SoundCategory::Ambience,
+            SoundId::ThrowObject => SoundCategory::Slam,
+            SoundId::DoorOpen => SoundCategory::Slam,
+            SoundId::Manifest => SoundCategory::Whisper,
+            SoundId::GhostWriting => SoundCategory::Whisper,
+            SoundId::HuntStart => SoundCategory::Ambience,
+            SoundId::PlayerDeath => SoundCategory::Ambience,
+            SoundId::BodyFound => SoundCategory::Ambience,
+        }
+    }
+}
+
+pub fn manifest() -> Vec<SoundInfo> {
+    SoundId::ALL
+        .into_iter()
+        .map(|id| SoundInfo {
+            id,
+            category: id.category(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A new sound won't compile here until it's given a place, and then it
+    // has to be in ALL at that place
+    fn position(id: SoundId) -> usize {
+        match id {
+            SoundId::InteractionSound => 0,
+            SoundId::LightsFlicker => 1,
+            SoundId::ThrowObject => 2,
+            SoundId::DoorOpen => 3,
+            SoundId::Manifest => 4,
+            SoundId::GhostWriting => 5,
+            SoundId::HuntStart => 6,
+            SoundId::PlayerDeath => 7,
+            SoundId::BodyFound => 8,
+        }
+    }
+
+    #[test]
+    fn the_manifest_has_every_sound_once() {
+        for (i, id) in SoundId::ALL.into_iter().enumerate() {
+            assert_eq!(position(id), i, "{:?}", id);
+        }
+        let manifest = serde_json::to_value(manifest()).unwrap();
+        let mut ids: Vec<_> = manifest
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(ids.len(), SoundId::ALL.len());
+        assert!(ids.iter().all(|id| id.split_once('.').is_some()), "{:?}", ids);
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), SoundId::ALL.len());
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{behavior::Personality, profile::Profile, sim::Difficulty};
+
+pub const PROFILES_PATH: &str = "profiles.json";
+pub const ROUNDS_PATH: &str = "rounds.jsonl";
+
+// One line in the round history
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoundSummary {
+    pub map: String,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    // the ghost was chosen by an admin, not at random
+    #[serde(default)]
+    pub preset_ghost: bool,
+    #[serde(default)]
+    pub personality: Personality,
+    pub correct_guess: bool,
+    pub players: Vec<PlayerSummary>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayerSummary {
+    pub name: String,
+    pub survived: bool,
+    pub earned: u32,
+}
+
+// Where profiles and round history live. The game only talks to this, so
+// another backend only has to implement it.
+pub trait Storage {
+    fn load_profile(&self, name: &str) -> Option<Profile>;
+    fn save_profile(&mut self, profile: &Profile) -> io::Result<()>;
+    fn append_round_summary(&mut self, summary: &RoundSummary) -> io::Result<()>;
+}
+
+pub enum StorageConfig {
+    // nothing survives a restart
+    Memory,
+    File { profiles: String, rounds: String },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::File {
+            profiles: PROFILES_PATH.to_owned(),
+            rounds: ROUNDS_PATH.to_owned(),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn open(&self) -> Result<Box<dyn Storage + Send + Sync>, String> {
+        Ok(match self {
+            StorageConfig::Memory => Box::new(MemoryStorage::default()),
+            StorageConfig::File { profiles, rounds } => Box::new(FileStorage::open(profiles, rounds)?),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    profiles: HashMap<String, Profile>,
+    rounds: Vec<RoundSummary>,
+}
+
+impl MemoryStorage {
+    pub fn rounds(&self) -> &[RoundSummary] {
+        &self.rounds
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load_profile(&self, name: &str) -> Option<Profile> {
+        self.profiles.get(name).cloned()
+    }
+
+    fn save_profile(&mut self, profile: &Profile) -> io::Result<()> {
+        self.profiles.insert(profile.name.clone(), profile.clone());
+        Ok(())
+    }
+
+    fn append_round_summary(&mut self, summary: &RoundSummary) -> io::Result<()> {
+        self.rounds.push(summary.clone());
+        Ok(())
+    }
+}
+
+// Profiles are kept in memory and the whole file is rewritten on every
+// change. Rounds are appended as one JSON object per line.
+pub struct FileStorage {
+    profiles_path: PathBuf,
+    rounds_path: PathBuf,
+    profiles: HashMap<String, Profile>,
+}
+
+impl FileStorage {
+    // No file yet is an empty store. One that can't be read or parsed is
+    // left alone, since the first save would write over everyone's profile.
+    pub fn open(profiles_path: &str, rounds_path: &str) -> Result<Self, String> {
+        let profiles_path = PathBuf::from(profiles_path);
+        let profiles = match fs::read_to_string(&profiles_path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("Could not parse {}: {}", profiles_path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("Could not read {}: {}", profiles_path.display(), e)),
+        };
+
+        Ok(FileStorage {
+            profiles_path,
+            rounds_path: PathBuf::from(rounds_path),
+            profiles,
+        })
+    }
+
+    // Write to a temp file and rename over the old one so a crash mid-write
+    // leaves the previous file intact
+    fn write_profiles(&self) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.profiles)?;
+        let tmp_path = self.profiles_path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.profiles_path)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load_profile(&self, name: &str) -> Option<Profile> {
+        self.profiles.get(name).cloned()
+    }
+
+    fn save_profile(&mut self, profile: &Profile) -> io::Result<()> {
+        self.profiles.insert(profile.name.clone(), profile.clone());
+        self.write_profiles()
+    }
+
+    fn append_round_summary(&mut self, summary: &RoundSummary) -> io::Result<()> {
+        let mut line = serde_json::to_string(summary)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.rounds_path)?;
+        file.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempPath;
+
+    fn profile(name: &str, money: u32) -> Profile {
+        Profile {
+            name: name.to_owned(),
+            games_played: 1,
+            games_survived: 1,
+            correct_guesses: 0,
+            money,
+            total_earned: money,
+            map_plays: HashMap::from([("default".to_owned(), 1)]),
+        }
+    }
+
+    fn round(correct_guess: bool) -> RoundSummary {
+        RoundSummary {
+            map: "default".to_owned(),
+            difficulty: Difficulty::default(),
+            preset_ghost: false,
+            personality: Personality::default(),
+            correct_guess,
+            players: vec![PlayerSummary {
+                name: "ann".to_owned(),
+                survived: true,
+                earned: 30,
+            }],
+        }
+    }
+
+    // What every backend has to do
+    fn behaves(storage: &mut dyn Storage) {
+        assert!(storage.load_profile("ann").is_none());
+        storage.save_profile(&profile("ann", 30)).unwrap();
+        storage.save_profile(&profile("bob", 10)).unwrap();
+        storage.save_profile(&profile("ann", 60)).unwrap();
+        let ann = storage.load_profile("ann").unwrap();
+        assert_eq!((ann.money, ann.map_plays["default"]), (60, 1));
+        assert_eq!(storage.load_profile("bob").unwrap().money, 10);
+
+        storage.append_round_summary(&round(true)).unwrap();
+        storage.append_round_summary(&round(false)).unwrap();
+    }
+
+    struct Files {
+        profiles: TempPath,
+        rounds: TempPath,
+    }
+
+    impl Files {
+        fn new(test: &str) -> Self {
+            Files {
+                profiles: TempPath::new(&format!("storage-{}.json", test)),
+                rounds: TempPath::new(&format!("storage-{}.jsonl", test)),
+            }
+        }
+
+        fn open(&self) -> Result<FileStorage, String> {
+            FileStorage::open(self.profiles.as_str(), self.rounds.as_str())
+        }
+    }
+
+    #[test]
+    fn memory_storage_keeps_everything_until_its_dropped() {
+        let mut storage = MemoryStorage::default();
+        behaves(&mut storage);
+        let rounds: Vec<_> = storage.rounds().iter().map(|r| r.correct_guess).collect();
+        assert_eq!(rounds, [true, false]);
+    }
+
+    #[test]
+    fn file_storage_is_there_after_a_restart() {
+        let files = Files::new("restart");
+        behaves(&mut files.open().unwrap());
+
+        let reopened = files.open().unwrap();
+        assert_eq!(reopened.load_profile("ann").unwrap().money, 60);
+        let rounds = fs::read_to_string(&files.rounds).unwrap();
+        let rounds: Vec<RoundSummary> =
+            rounds.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(rounds.len(), 2);
+        assert!(rounds[0].correct_guess && !rounds[1].correct_guess);
+    }
+
+    #[test]
+    fn a_corrupt_profiles_file_is_left_alone() {
+        let files = Files::new("corrupt");
+        fs::write(&files.profiles, "{ not json").unwrap();
+        let e = files.open().err().unwrap();
+        assert!(e.starts_with(&format!("Could not parse {}", files.profiles.display())));
+        assert_eq!(fs::read_to_string(&files.profiles).unwrap(), "{ not json");
+
+        // only a missing file is a fresh start
+        fs::remove_file(&files.profiles).unwrap();
+        fs::create_dir(&files.profiles).unwrap();
+        let e = files.open().err().unwrap();
+        assert!(e.starts_with(&format!("Could not read {}", files.profiles.display())));
+    }
+}
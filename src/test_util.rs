@@ -0,0 +1,58 @@
+// What the unit tests share
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+// A path of its own in the temp dir, file or directory, with nothing
+// there before and nothing left after it's dropped. `name` only has to
+// differ from the other tests'.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let name = format!("phasmo-{}-{}", std::process::id(), name);
+        let temp = TempPath(std::env::temp_dir().join(name));
+        temp.clear();
+        temp
+    }
+
+    // Already holding `text`
+    pub fn with_text(name: &str, text: &str) -> Self {
+        let temp = TempPath::new(name);
+        fs::write(&temp, text).unwrap();
+        temp
+    }
+
+    // For what takes paths as strings
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    fn clear(&self) {
+        let _ = fs::remove_file(&self.0);
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fs, path::PathBuf};
+
+use native_tls::{Certificate, Identity};
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use ring::digest::{digest, SHA256};
+use time::{Duration, OffsetDateTime};
+use tokio_native_tls::TlsAcceptor;
+
+const CERT_NAME: &str = "phasmo-server";
+const CACHED_CERT: &str = "cert.pem";
+const CACHED_KEY: &str = "key.pem";
+const CERT_DAYS: i64 = 365;
+
+pub enum TlsConfig {
+    // plain websockets
+    Off,
+    Keystore { path: String, password: String },
+    // made up at startup, kept in the cache dir so the fingerprint stays the
+    // same across restarts
+    SelfSigned { cache_dir: Option<String> },
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig::SelfSigned { cache_dir: None }
+    }
+}
+
+impl TlsConfig {
+    pub fn acceptor(&self) -> Result<Option<TlsAcceptor>, String> {
+        let identity = match self {
+            TlsConfig::Off => return Ok(None),
+            TlsConfig::Keystore { path, password } => {
+                let der = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+                // a wrong password shows up here too
+                Identity::from_pkcs12(&der, password)
+                    .map_err(|e| format!("Could not load {}: {}", path, e))?
+            }
+            TlsConfig::SelfSigned { cache_dir } => {
+                let cert = self_signed(cache_dir.as_deref()).map_err(|e| {
+                    format!("{}. Supply a certificate with --keystore or pass --no-tls", e)
+                })?;
+                Identity::from_pkcs8(cert.cert.as_bytes(), cert.key.as_bytes())
+                    .map_err(|e| e.to_string())?
+            }
+        };
+
+        let acceptor = native_tls::TlsAcceptor::builder(identity)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Some(TlsAcceptor::from(acceptor)))
+    }
+}
+
+// A self-signed certificate and its key, both PEM
+struct SelfSigned {
+    cert: String,
+    key: String,
+    // what clients that pin the certificate compare against
+    fingerprint: String,
+}
+
+// Loads the cached certificate if there is one, otherwise generates one
+// and caches it
+fn self_signed(cache_dir: Option<&str>) -> Result<SelfSigned, String> {
+    let cached = cache_dir.map(PathBuf::from);
+    if let Some(dir) = &cached {
+        let cert_path = dir.join(CACHED_CERT);
+        let cached = (fs::read_to_string(&cert_path), fs::read_to_string(dir.join(CACHED_KEY)));
+        if let (Ok(cert), Ok(key)) = cached {
+            let fingerprint = fingerprint(&cert)
+                .map_err(|e| format!("Could not load {}: {}", cert_path.display(), e))?;
+            println!("Loaded cached self-signed certificate, SHA-256 {}", fingerprint);
+            return Ok(SelfSigned {
+                cert,
+                key,
+                fingerprint,
+            });
+        }
+    }
+
+    let generated = generate().map_err(|e| format!("Could not generate a certificate: {}", e))?;
+    println!("Generated self-signed certificate, SHA-256 {}", generated.fingerprint);
+    if let Some(dir) = &cached {
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(dir.join(CACHED_CERT), &generated.cert))
+            .and_then(|_| fs::write(dir.join(CACHED_KEY), &generated.key))
+            .map_err(|e| format!("Could not cache the certificate in {}: {}", dir.display(), e))?;
+    }
+    Ok(generated)
+}
+
+fn generate() -> Result<SelfSigned, String> {
+    let key = KeyPair::generate().map_err(|e| e.to_string())?;
+    let mut params = CertificateParams::new(vec![CERT_NAME.to_owned(), "localhost".to_owned()])
+        .map_err(|e| e.to_string())?;
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, CERT_NAME);
+    params.distinguished_name = name;
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + Duration::days(CERT_DAYS);
+    let cert = params.self_signed(&key).map_err(|e| e.to_string())?;
+
+    let pem = cert.pem();
+    Ok(SelfSigned {
+        fingerprint: fingerprint(&pem)?,
+        cert: pem,
+        key: key.serialize_pem(),
+    })
+}
+
+// The SHA-256 of the certificate's DER, the way browsers show it
+fn fingerprint(cert_pem: &str) -> Result<String, String> {
+    let der = Certificate::from_pem(cert_pem.as_bytes())
+        .and_then(|cert| cert.to_der())
+        .map_err(|e| e.to_string())?;
+    let hex: Vec<String> = digest(&SHA256, &der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    Ok(hex.join(":"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempPath;
+
+    #[test]
+    fn a_cached_certificate_keeps_its_fingerprint() {
+        let cache = TempPath::new("certs");
+        let first = self_signed(Some(cache.as_str())).unwrap();
+        assert!(cache.join(CACHED_CERT).exists() && cache.join(CACHED_KEY).exists());
+        assert_eq!(first.fingerprint.split(':').count(), 32);
+
+        let again = self_signed(Some(cache.as_str())).unwrap();
+        assert_eq!(again.fingerprint, first.fingerprint);
+        assert_eq!(again.cert, first.cert);
+        // and it's one native-tls will serve with
+        let config = TlsConfig::SelfSigned {
+            cache_dir: Some(cache.as_str().to_owned()),
+        };
+        assert!(config.acceptor().unwrap().is_some());
+    }
+
+    #[test]
+    fn without_a_cache_every_start_is_a_new_certificate() {
+        let first = self_signed(None).unwrap();
+        assert_ne!(self_signed(None).unwrap().fingerprint, first.fingerprint);
+        assert!(TlsConfig::default().acceptor().unwrap().is_some());
+    }
+
+    #[test]
+    fn a_broken_cache_says_what_to_do_instead() {
+        let cache = TempPath::new("broken-certs");
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(cache.join(CACHED_CERT), "not a certificate").unwrap();
+        fs::write(cache.join(CACHED_KEY), "not a key").unwrap();
+        let config = TlsConfig::SelfSigned {
+            cache_dir: Some(cache.as_str().to_owned()),
+        };
+        let e = config.acceptor().err().unwrap();
+        assert!(e.contains("--no-tls"), "{}", e);
+    }
+}
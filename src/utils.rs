@@ -1,16 +1,211 @@
+use std::{net::SocketAddr, time::Duration};
 
+use tokio::task::JoinError;
 
-pub fn rng_select<T>(list: &Vec<T>) -> T
-    where T:Clone
+use rand::Rng;
+use serde::Serializer;
+
+// Addresses go out as "ip:port" in every encoding, serde only writes
+// them that way for formats it takes to be human readable
+pub fn serialize_addr<S: Serializer>(addr: &SocketAddr, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(addr)
+}
+
+// None when there's nothing to pick from
+pub fn rng_select<'a, T, R>(list: &'a [T], rng: &mut R) -> Option<&'a T>
+    where R: Rng + ?Sized
+{
+    if list.is_empty() {
+        return None;
+    }
+    let ix = rng.gen_range(0..list.len());
+
+    Some(&list[ix])
+}
+
+// Picks an item with probability proportional to its weight. Zero,
+// negative and NaN weights are skipped, so those items are never picked,
+// and if nothing has weight there's no pick at all.
+pub fn weighted_select<'a, T, R>(items: &'a [(T, f64)], rng: &mut R) -> Option<&'a T>
+    where R: Rng + ?Sized
+{
+    let usable = |w: &f64| w.is_finite() && *w > 0.0;
+    let total: f64 = items.iter().map(|(_, w)| w).filter(|w| usable(w)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut pick = rng.gen_range(0.0..total);
+    for (item, weight) in items.iter().filter(|(_, w)| usable(w)) {
+        if pick < *weight {
+            return Some(item);
+        }
+        pick -= weight;
+    }
+    // float rounding can leave a sliver past the last item
+    items.iter().rev().find(|(_, w)| usable(w)).map(|(item, _)| item)
+}
+
+// Chances past 0 or 1 count as never or always, NaN as never
+pub fn roll<R>(chance: f64, rng: &mut R) -> bool
+    where R: Rng + ?Sized
+{
+    rng.gen_range(0.0..1.0) < chance.clamp(0.0, 1.0)
+}
+
+// Chance of at least one event happening within `window` for something
+// that happens `rate_per_min` times a minute on average
+pub fn prob_from_rate(rate_per_min: f64, window: Duration) -> f64 {
+    if !rate_per_min.is_finite() || rate_per_min <= 0.0 {
+        return 0.0;
+    }
+    1.0 - (-rate_per_min * window.as_secs_f64() / 60.0).exp()
+}
+
+// How long until the next event for something that happens `rate_per_min`
+// times a minute on average. None if it never happens, or not until
+// later than a Duration can hold.
+pub fn sample_exponential<R>(rate_per_min: f64, rng: &mut R) -> Option<Duration>
+    where R: Rng + ?Sized
 {
-    let mut rng = rand::thread_rng();
-    let ix = rand::Rng::gen_range(&mut rng, 0..list.len());
+    if !rate_per_min.is_finite() || rate_per_min <= 0.0 {
+        return None;
+    }
+    let u: f64 = rng.gen_range(0.0..1.0);
+    let mins = -(1.0 - u).ln() / rate_per_min;
+    Duration::try_from_secs_f64(mins * 60.0).ok()
+}
+
+// Looks at every byte whichever one differs, so how long it takes only
+// gives away the length
+pub fn same_bytes(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// What a panic said, if it said anything we can print
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
 
-    list[ix].clone()
+// Why a task that should have run forever stopped
+pub fn task_ended(task: &str, ended: Result<(), JoinError>) -> String {
+    match ended {
+        Ok(()) => format!("{} task stopped", task),
+        Err(e) if e.is_panic() => {
+            let msg = panic_message(e.into_panic().as_ref());
+            format!("{} task panicked: {}", task, msg)
+        }
+        Err(e) => format!("{} task failed: {}", task, e),
+    }
 }
 
-pub fn roll(chance: f64) -> bool {
-    let mut rng = rand::thread_rng();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn selecting_from_nothing_is_none() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let empty: [u8; 0] = [];
+        assert_eq!(rng_select(&empty, &mut rng), None);
+        assert_eq!(weighted_select::<u8, _>(&[], &mut rng), None);
+    }
+
+    #[test]
+    fn every_item_gets_picked() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let list = [1, 2, 3];
+        let mut seen = [false; 3];
+        for _ in 0..100 {
+            seen[*rng_select(&list, &mut rng).unwrap() - 1] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+    }
+
+    #[test]
+    fn weightless_items_are_never_picked() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let none = [("a", 0.0), ("b", -1.0), ("c", f64::NAN)];
+        assert_eq!(weighted_select(&none, &mut rng), None);
+
+        let one = [("a", 0.0), ("b", -3.0), ("c", 0.5), ("d", f64::INFINITY)];
+        for _ in 0..100 {
+            assert_eq!(weighted_select(&one, &mut rng), Some(&"c"));
+        }
+    }
+
+    #[test]
+    fn picks_follow_the_weights() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let items = [("light", 1.0), ("heavy", 3.0)];
+        let heavy = (0..4000)
+            .filter(|_| weighted_select(&items, &mut rng) == Some(&"heavy"))
+            .count();
+        assert!((2800..3200).contains(&heavy), "{}", heavy);
+    }
+
+    #[test]
+    fn rates_that_never_happen_have_no_chance() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            assert_eq!(prob_from_rate(rate, Duration::from_secs(1)), 0.0, "{}", rate);
+        }
+        assert_eq!(prob_from_rate(3.0, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn chances_dont_depend_on_how_often_they_are_rolled() {
+        // once a minute is 1 - 1/e within a minute
+        let minute = prob_from_rate(1.0, Duration::from_secs(60));
+        assert!((minute - (1.0 - (-1.0f64).exp())).abs() < 1e-12);
+
+        // two half-minute rolls come out the same as one minute-long one
+        let half = prob_from_rate(1.0, Duration::from_secs(30));
+        let either = 1.0 - (1.0 - half) * (1.0 - half);
+        assert!((either - minute).abs() < 1e-12);
+        assert!(prob_from_rate(1000.0, Duration::from_secs(60)) <= 1.0);
+    }
+
+    #[test]
+    fn rates_too_slow_for_a_duration_never_happen() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut never = 0;
+        for _ in 0..100 {
+            match sample_exponential(1e-18, &mut rng) {
+                Some(wait) => assert!(wait.as_secs() > 1_000_000_000, "{:?}", wait),
+                None => never += 1,
+            }
+        }
+        assert!(never > 50);
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(sample_exponential(rate, &mut rng), None, "{}", rate);
+        }
+        assert!(sample_exponential(3.0, &mut rng).is_some());
+    }
+
+    #[test]
+    fn bytes_are_the_same_only_all_the_way_through() {
+        assert!(same_bytes(b"secret", b"secret"));
+        assert!(same_bytes(b"", b""));
+        assert!(!same_bytes(b"secret", b"secreT"));
+        assert!(!same_bytes(b"secret", b"Secret"));
+        assert!(!same_bytes(b"secret", b"secret!"));
+        assert!(!same_bytes(b"secret", b""));
+    }
 
-    rand::Rng::gen_range(&mut rng, 0.0..1.0) < chance
+    #[tokio::test]
+    async fn a_dead_task_says_why() {
+        let panicked = tokio::spawn(async { panic!("out of ghosts") }).await;
+        assert_eq!(task_ended("Sim", panicked), "Sim task panicked: out of ghosts");
+        let formatted = tokio::spawn(async { panic!("{} ghosts", 0) }).await;
+        assert_eq!(task_ended("Sim", formatted), "Sim task panicked: 0 ghosts");
+        assert_eq!(task_ended("Listener", Ok(())), "Listener task stopped");
+    }
 }
@@ -0,0 +1,131 @@
+// What the integration tests share: a real server on a port of its own,
+// and clients that talk JSON to it. Not every test file uses all of it.
+#![allow(dead_code)]
+
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use phasmo_server::{
+    server::{self, ServerConfig},
+    storage::StorageConfig,
+    tls::TlsConfig,
+};
+use serde_json::Value;
+use tokio::{net::TcpStream, sync::mpsc, task::JoinHandle};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+// how long a client waits on a message that should be coming
+const WAIT: Duration = Duration::from_secs(5);
+
+// Plaintext, nothing kept and anyone can be admin. Every test file picks
+// its own ports.
+pub fn config(port: u16) -> ServerConfig {
+    ServerConfig {
+        addr: format!("127.0.0.1:{}", port),
+        storage: StorageConfig::Memory,
+        tls: TlsConfig::Off,
+        insecure_admin: true,
+        ..Default::default()
+    }
+}
+
+pub struct Server {
+    stop: mpsc::Sender<()>,
+    task: JoinHandle<Result<(), String>>,
+}
+
+impl Server {
+    pub fn start(config: ServerConfig) -> Self {
+        let (stop, rx) = mpsc::channel(1);
+        let task = tokio::spawn(server::run_server(config, Arc::new(tokio::sync::Mutex::new(rx))));
+        Server { stop, task }
+    }
+
+    pub async fn stop(self) {
+        let _ = self.stop.send(()).await;
+        let _ = self.task.await;
+    }
+}
+
+pub struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Client {
+    // Retries while the server's still coming up
+    pub async fn connect(port: u16) -> Self {
+        let url = format!("ws://127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if let Ok((ws, _)) = connect_async(&url).await {
+                return Client { ws };
+            }
+            pause(50).await;
+        }
+        panic!("No server on {}", url);
+    }
+
+    // Waits for the reply, so the sim's handled it before the next one.
+    // Anything else that comes first is dropped.
+    pub async fn send(&mut self, msg: Value) -> Result<(), String> {
+        let request = msg.as_object().unwrap().keys().next().unwrap().clone();
+        self.ws.send(Message::text(msg.to_string())).await.unwrap();
+        loop {
+            let value = self.next().await;
+            let reply = &value["Reply"];
+            if reply["Ack"]["request"] == request.as_str() {
+                return Ok(());
+            }
+            if reply["Error"]["request"] == request.as_str() {
+                return Err(reply["Error"]["reason"].as_str().unwrap().to_owned());
+            }
+        }
+    }
+
+    // The next JSON message, whatever it is
+    pub async fn next(&mut self) -> Value {
+        loop {
+            let msg = tokio::time::timeout(WAIT, self.ws.next())
+                .await
+                .expect("Nothing came")
+                .expect("Connection closed")
+                .unwrap();
+            if let Message::Text(text) = msg {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    // Everything that comes before it goes quiet for `ms`
+    pub async fn until_quiet(&mut self, ms: u64) -> Vec<Value> {
+        let mut seen = Vec::new();
+        let wait = Duration::from_millis(ms);
+        while let Ok(Some(msg)) = tokio::time::timeout(wait, self.ws.next()).await {
+            if let Message::Text(text) = msg.unwrap() {
+                seen.push(serde_json::from_str(&text).unwrap());
+            }
+        }
+        seen
+    }
+
+    // Everything up to and including the first message `last` matches
+    pub async fn until(&mut self, last: impl Fn(&Value) -> bool) -> Vec<Value> {
+        let mut seen = Vec::new();
+        loop {
+            let value = self.next().await;
+            let done = last(&value);
+            seen.push(value);
+            if done {
+                return seen;
+            }
+        }
+    }
+}
+
+pub async fn pause(ms: u64) {
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+}
+
+// The message's variant, for matching on
+pub fn kind(value: &Value) -> &str {
+    value.as_object().and_then(|o| o.keys().next()).map_or("", |k| k.as_str())
+}
@@ -0,0 +1,185 @@
+// Recorded sessions played again, to catch the sim behaving differently
+// than it used to. The fixtures are --record files of the sessions below,
+// run against a real server.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::{pause, Client, Server};
+use phasmo_server::{map::MapCatalog, replay};
+use serde_json::{json, Value};
+
+fn fixture(name: &str) -> String {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name].iter().collect();
+    fs::read_to_string(path).unwrap()
+}
+
+// A line of the recording, with the rest as they were
+fn edit_line(recording: &str, find: impl Fn(&Value) -> bool, edit: impl Fn(&mut Value)) -> String {
+    let mut lines: Vec<String> = recording.lines().map(str::to_owned).collect();
+    let ix = lines
+        .iter()
+        .position(|l| find(&serde_json::from_str(l).unwrap()))
+        .unwrap();
+    let mut value: Value = serde_json::from_str(&lines[ix]).unwrap();
+    edit(&mut value);
+    lines[ix] = value.to_string();
+    lines.join("\n")
+}
+
+fn remove_line(recording: &str, find: impl Fn(&Value) -> bool) -> (usize, String) {
+    let mut lines: Vec<&str> = recording.lines().collect();
+    let ix = lines
+        .iter()
+        .position(|l| find(&serde_json::from_str(l).unwrap()))
+        .unwrap();
+    lines.remove(ix);
+    (ix + 1, lines.join("\n"))
+}
+
+#[test]
+fn fixtures_play_out_the_same() {
+    for name in ["solo_round.jsonl", "two_players.jsonl"] {
+        let reruns = replay::rerun(&fixture(name), &MapCatalog::builtin())
+            .unwrap_or_else(|e| panic!("{} diverged: {}", name, e));
+        assert_eq!(reruns.len(), 1, "{}", name);
+        assert!(reruns[0].events > 0, "{}", name);
+        assert!(reruns[0].inputs > 0, "{}", name);
+    }
+}
+
+#[test]
+fn a_changed_event_is_the_divergence() {
+    let recording = fixture("solo_round.jsonl");
+    let is_move = |v: &Value| v["event"].get("GhostMoved").is_some();
+    let changed = edit_line(&recording, is_move, |v| v["event"]["GhostMoved"]["to"] = json!(99));
+    let line = recording
+        .lines()
+        .position(|l| is_move(&serde_json::from_str(l).unwrap()))
+        .unwrap();
+
+    let e = replay::rerun(&changed, &MapCatalog::builtin()).unwrap_err();
+    assert!(e.contains(&format!("diverged at line {}", line + 1)), "{}", e);
+    assert!(e.contains("\"to\":99"), "{}", e);
+}
+
+#[test]
+fn a_missing_tick_is_the_divergence() {
+    // from part way through, once the round's underway
+    let recording = fixture("solo_round.jsonl");
+    let ticks: Vec<usize> = recording
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| l.contains("\"Tick\""))
+        .map(|(i, _)| i)
+        .collect();
+    let skip = ticks[ticks.len() / 2];
+    let mut lines: Vec<&str> = recording.lines().collect();
+    lines.remove(skip);
+
+    let e = replay::rerun(&lines.join("\n"), &MapCatalog::builtin()).unwrap_err();
+    assert!(e.contains("diverged at line"), "{}", e);
+}
+
+#[test]
+fn an_input_needs_the_lobby_opened_first() {
+    let recording = fixture("solo_round.jsonl");
+    let (line, without) = remove_line(&recording, |v| v["input"].get("Opened").is_some());
+    let e = replay::rerun(&without, &MapCatalog::builtin()).unwrap_err();
+    assert!(e.contains(&format!("diverged at line {}", line)), "{}", e);
+    assert!(e.contains("no Opened before it"), "{}", e);
+}
+
+#[test]
+fn reviews_skip_the_inputs() {
+    let recording = fixture("solo_round.jsonl");
+    let events = recording.lines().filter(|l| l.contains("\"event\"")).count();
+    let updates = phasmo_server::event_log::replay(&recording, None).unwrap();
+    assert_eq!(updates.len(), events);
+}
+
+// Whatever's recorded is replayed exactly, so the wall clock doesn't matter
+async fn solo_round(port: u16) {
+    let mut ann = Client::connect(port).await;
+    ann.send(json!({"JoinLobby": {"name": "ann", "protocol": 3}})).await.unwrap();
+    let mut admin = Client::connect(port).await;
+    admin.send(json!({"Hello": {"protocol": 3}})).await.unwrap();
+    admin.send(json!({"ConnectAsAdmin": {}})).await.unwrap();
+    admin.send(json!({"SetGhostType": {"ghost": "Spirit"}})).await.unwrap();
+    admin.send(json!({"StartSim": {"force": true}})).await.unwrap();
+
+    let _ = ann.send(json!({"LocationUpdate": {"location": 0}})).await;
+    pause(300).await;
+    let _ = ann.send(json!({"LocationUpdate": {"location": 2}})).await;
+    ann.send(json!({"SetFlashlight": {"on": true}})).await.unwrap();
+    admin.send(json!({"FastForward": {"secs": 90}})).await.unwrap();
+    let _ = ann.send(json!({"ReadThermometer": {}})).await;
+    admin.send(json!({"TeleportGhost": {"location": 2, "emf": true}})).await.unwrap();
+    let _ = ann.send(json!({"ReadEmf": {}})).await;
+    pause(300).await;
+    admin.send(json!({"FastForward": {"secs": 240}})).await.unwrap();
+    ann.send(json!({"SubmitGuess": {"ghost_type": "Spirit"}})).await.unwrap();
+}
+
+async fn two_players(port: u16) {
+    let mut ann = Client::connect(port).await;
+    ann.send(json!({"JoinLobby": {"name": "ann", "protocol": 3}})).await.unwrap();
+    let mut bob = Client::connect(port).await;
+    bob.send(json!({"JoinLobby": {"name": "bob", "protocol": 3}})).await.unwrap();
+    ann.send(json!({"SetReady": {"ready": true}})).await.unwrap();
+    bob.send(json!({"SetReady": {"ready": true}})).await.unwrap();
+    let mut admin = Client::connect(port).await;
+    admin.send(json!({"Hello": {"protocol": 3}})).await.unwrap();
+    admin.send(json!({"ConnectAsAdmin": {}})).await.unwrap();
+    admin.send(json!({"StartSim": {}})).await.unwrap();
+
+    let _ = ann.send(json!({"LocationUpdate": {"location": 0}})).await;
+    let _ = bob.send(json!({"LocationUpdate": {"location": 0}})).await;
+    pause(300).await;
+    let _ = bob.send(json!({"LocationUpdate": {"location": 13}})).await;
+    let _ = bob.send(json!({"Equip": {"item": "EmfReader"}})).await;
+    let _ = bob.send(json!({"SetActiveEquipment": {"items": ["EmfReader"]}})).await;
+    admin.send(json!({"FastForward": {"secs": 300}})).await.unwrap();
+    // ann's connection going shows up as a Left
+    drop(ann);
+    pause(300).await;
+    admin.send(json!({"FastForward": {"secs": 300}})).await.unwrap();
+    let _ = bob.send(json!({"SubmitGuess": {"ghost_type": "Demon"}})).await;
+    admin.send(json!({"ResetSim": {}})).await.unwrap();
+}
+
+// Plays the session against a server recording to a file of its own, and
+// gives back what it recorded
+async fn record(port: u16, seed: u64, session: impl std::future::Future<Output = ()>) -> String {
+    let name = format!("phasmo-replay-{}-{}.jsonl", std::process::id(), port);
+    let path = std::env::temp_dir().join(name);
+    let _ = fs::remove_file(&path);
+    let mut config = common::config(port);
+    config.seed = Some(seed);
+    config.record = Some(path.to_string_lossy().into_owned());
+    let server = Server::start(config);
+    session.await;
+    // the recorder writes on its own time
+    pause(500).await;
+    server.stop().await;
+    let recording = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    recording
+}
+
+#[tokio::test]
+async fn a_live_session_plays_out_the_same() {
+    let recording = record(38471, 11, solo_round(38471)).await;
+    let reruns = replay::rerun(&recording, &MapCatalog::builtin()).unwrap();
+    assert_eq!(reruns.len(), 1);
+    // joined, started, moved and guessed at the least
+    assert!(reruns[0].events >= 4, "{:?}", reruns);
+}
+
+#[tokio::test]
+async fn a_live_session_with_a_leaver_plays_out_the_same() {
+    let recording = record(38472, 12, two_players(38472)).await;
+    assert!(recording.contains("\"Left\""));
+    replay::rerun(&recording, &MapCatalog::builtin()).unwrap();
+}
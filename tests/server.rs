@@ -0,0 +1,227 @@
+// The server end to end, over real websockets
+
+mod common;
+
+use common::{kind, pause, Client, Server};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Error, Message},
+};
+
+#[tokio::test]
+async fn the_map_comes_before_the_first_sim_frame() {
+    let port = 38481;
+    let server = Server::start(common::config(port));
+    let mut ann = Client::connect(port).await;
+    ann.send(json!({"JoinLobby": {"name": "ann", "protocol": 3}})).await.unwrap();
+    let mut admin = Client::connect(port).await;
+    admin.send(json!({"Hello": {"protocol": 3}})).await.unwrap();
+    admin.send(json!({"ConnectAsAdmin": {}})).await.unwrap();
+    admin.send(json!({"SelectMap": {"map": "school"}})).await.unwrap();
+
+    // the lobby says what's picked before anything starts
+    let lobby = ann.until(|v| kind(v) == "Lobby" && v["Lobby"]["selected_map"] == "school").await;
+    let hash = lobby.last().unwrap()["Lobby"]["map_hash"].clone();
+
+    admin.send(json!({"StartSim": {"force": true}})).await.unwrap();
+    let seen = ann.until(|v| kind(v) == "Sim").await;
+    let map = seen.iter().position(|v| kind(v) == "Map").expect("No Map before the Sim");
+    assert_eq!(seen[map]["Map"]["map_id"], "school");
+    assert_eq!(seen[map]["Map"]["map_hash"], hash);
+    assert!(!seen[map]["Map"]["map"]["rooms"].as_array().unwrap().is_empty());
+    let sim = &seen.last().unwrap()["Sim"];
+    assert_eq!(sim["map_id"], "school");
+    assert_eq!(sim["map_hash"], hash);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn joins_reach_everyone_already_in_as_events() {
+    let port = 38482;
+    let server = Server::start(common::config(port));
+    let mut peers = Vec::new();
+    for name in ["ann", "bob", "cat"] {
+        let mut peer = Client::connect(port).await;
+        peer.send(json!({"JoinLobby": {"name": name, "protocol": 3}})).await.unwrap();
+        peers.push(peer);
+    }
+
+    // past a peer's own lobby, which can land after the join's reply,
+    // there's only the joins after it
+    let names = ["ann", "bob", "cat"];
+    for (n, peer) in peers.iter_mut().enumerate() {
+        let seen = peer.until_quiet(300).await;
+        let after: Vec<_> = seen
+            .iter()
+            .skip_while(|v| kind(v) == "Lobby")
+            .map(|v| (kind(v), v["PlayerJoined"]["player"]["name"].as_str().unwrap_or("")))
+            .collect();
+        let expected: Vec<_> = names[n + 1..].iter().map(|name| ("PlayerJoined", *name)).collect();
+        assert_eq!(after, expected, "{}", names[n]);
+    }
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn only_subscribed_admins_see_the_ghost_think() {
+    let port = 38483;
+    let server = Server::start(common::config(port));
+    let mut ann = Client::connect(port).await;
+    ann.send(json!({"JoinLobby": {"name": "ann", "protocol": 3}})).await.unwrap();
+    assert!(ann.send(json!({"SubscribeGhostDebug": {}})).await.is_err());
+    let mut admin = Client::connect(port).await;
+    admin.send(json!({"Hello": {"protocol": 3}})).await.unwrap();
+    admin.send(json!({"ConnectAsAdmin": {}})).await.unwrap();
+    admin.send(json!({"StartSim": {"force": true}})).await.unwrap();
+    admin.send(json!({"SubscribeGhostDebug": {}})).await.unwrap();
+
+    admin.send(json!({"FastForward": {"secs": 60}})).await.unwrap();
+    let seen = admin.until(|v| kind(v) == "GhostDebug").await;
+    assert!(!seen.last().unwrap()["GhostDebug"]["decisions"].as_array().unwrap().is_empty());
+    assert!(ann.until_quiet(300).await.iter().all(|v| kind(v) != "GhostDebug"));
+
+    admin.send(json!({"UnsubscribeGhostDebug": {}})).await.unwrap();
+    admin.until_quiet(300).await;
+    admin.send(json!({"FastForward": {"secs": 60}})).await.unwrap();
+    assert!(admin.until_quiet(300).await.iter().all(|v| kind(v) != "GhostDebug"));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn the_handshake_picks_the_encoding() {
+    let port = 38484;
+    let mut config = common::config(port);
+    config.legacy_clients = false;
+    let server = Server::start(config);
+    let url = format!("ws://127.0.0.1:{}", port);
+    let offer = |protocols: &str| {
+        let mut request = url.as_str().into_client_request().unwrap();
+        let protocols = protocols.parse().unwrap();
+        request.headers_mut().insert("Sec-WebSocket-Protocol", protocols);
+        request
+    };
+    let mut connected = None;
+    for _ in 0..50 {
+        if let Ok(ok) = connect_async(offer("chat, phasmo.v1.msgpack")).await {
+            connected = Some(ok);
+            break;
+        }
+        pause(50).await;
+    }
+    let (mut ws, response) = connected.expect("No server");
+    assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "phasmo.v1.msgpack");
+    ws.send(Message::text(json!({"Hello": {"protocol": 3}}).to_string())).await.unwrap();
+    // past any keepalive pings
+    let reply = loop {
+        let msg = ws.next().await.unwrap().unwrap();
+        if msg.is_text() || msg.is_binary() {
+            break msg;
+        }
+    };
+    assert!(reply.is_binary(), "{:?}", reply);
+
+    // offering nothing, with legacy clients turned off
+    match connect_async(url.as_str()).await {
+        Err(Error::Http(response)) => assert_eq!(response.status(), 400),
+        other => panic!("Not turned away: {:?}", other.map(|(_, r)| r.status())),
+    }
+    server.stop().await;
+}
+
+type Ws = tokio_tungstenite::WebSocketStream<
+    tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+>;
+
+// A peer that asked for msgpack, retrying while the server comes up
+async fn msgpack_peer(port: u16) -> Ws {
+    for _ in 0..50 {
+        let mut request = format!("ws://127.0.0.1:{}", port).into_client_request().unwrap();
+        let protocols = "phasmo.v1.msgpack".parse().unwrap();
+        request.headers_mut().insert("Sec-WebSocket-Protocol", protocols);
+        if let Ok((ws, _)) = connect_async(request).await {
+            return ws;
+        }
+        pause(50).await;
+    }
+    panic!("No server on {}", port);
+}
+
+// The next reply, past whatever else comes first
+async fn next_reply(ws: &mut Ws) -> Value {
+    loop {
+        if let Message::Binary(bytes) = ws.next().await.unwrap().unwrap() {
+            let value: Value = rmp_serde::from_slice(&bytes).unwrap();
+            if kind(&value) == "Reply" {
+                return value["Reply"].clone();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn msgpack_peers_can_write_it_too() {
+    let port = 38485;
+    let server = Server::start(common::config(port));
+    let mut ws = msgpack_peer(port).await;
+    for msg in [
+        json!({"JoinLobby": {"name": "ann", "protocol": 3}}),
+        json!({"SetReady": {"ready": true}}),
+    ] {
+        let request = kind(&msg).to_owned();
+        ws.send(Message::binary(rmp_serde::to_vec_named(&msg).unwrap())).await.unwrap();
+        assert_eq!(next_reply(&mut ws).await["Ack"]["request"], request.as_str());
+    }
+
+    // bytes that aren't a message get an error, not a dropped connection
+    ws.send(Message::binary(vec![0xc1])).await.unwrap();
+    let reply = next_reply(&mut ws).await;
+    assert!(reply.get("Error").is_some(), "{}", reply);
+    let msg = rmp_serde::to_vec_named(&json!({"GetProfile": {}})).unwrap();
+    ws.send(Message::binary(msg)).await.unwrap();
+    assert_eq!(next_reply(&mut ws).await["Ack"]["request"], "GetProfile");
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn pings_that_arent_text_dont_drop_the_peer() {
+    let port = 38488;
+    let server = Server::start(common::config(port));
+    let mut ws = msgpack_peer(port).await;
+    ws.send(Message::Ping(vec![0xff, 0xfe])).await.unwrap();
+    let msg = rmp_serde::to_vec_named(&json!({"Hello": {"protocol": 3}})).unwrap();
+    ws.send(Message::binary(msg)).await.unwrap();
+    assert_eq!(next_reply(&mut ws).await["Ack"]["request"], "Hello");
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn admins_need_the_token_unless_told_otherwise() {
+    // out of the box, nobody
+    let port = 38486;
+    let mut config = common::config(port);
+    config.insecure_admin = false;
+    let server = Server::start(config);
+    let mut admin = Client::connect(port).await;
+    admin.send(json!({"Hello": {"protocol": 3}})).await.unwrap();
+    let e = admin.send(json!({"ConnectAsAdmin": {}})).await.unwrap_err();
+    assert_eq!(e, "No admin token is set on this server");
+    let e = admin.send(json!({"SelectMap": {"map": "school"}})).await.unwrap_err();
+    assert!(e.starts_with("Not allowed"), "{}", e);
+    server.stop().await;
+
+    // with a token, only those who send it
+    let port = 38487;
+    let mut config = common::config(port);
+    config.admin_token = Some("secret".to_owned());
+    let server = Server::start(config);
+    let mut admin = Client::connect(port).await;
+    admin.send(json!({"Hello": {"protocol": 3}})).await.unwrap();
+    let e = admin.send(json!({"ConnectAsAdmin": {}})).await.unwrap_err();
+    assert_eq!(e, "Wrong admin token");
+    admin.send(json!({"ConnectAsAdmin": {"token": "secret"}})).await.unwrap();
+    admin.send(json!({"SelectMap": {"map": "school"}})).await.unwrap();
+    server.stop().await;
+}
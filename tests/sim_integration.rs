@@ -0,0 +1,85 @@
+// The sim driven the way the server would, through nothing but its public
+// API and with no network in sight
+
+use std::{net::SocketAddr, time::Duration};
+
+use phasmo_server::{
+    event_log::SimEvent,
+    ghost::GhostType,
+    map::MapCatalog,
+    sim::{Difficulty, GamePhase, Simulation},
+};
+
+fn addr(n: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 5000 + n))
+}
+
+fn lobby(seed: u64, names: &[&str]) -> Simulation {
+    let mut sim = Simulation::with_seed(MapCatalog::builtin(), seed);
+    for (i, name) in names.iter().enumerate() {
+        sim.add_player(addr(i as u16), name).unwrap();
+    }
+    sim
+}
+
+fn events(sim: &Simulation) -> Vec<SimEvent> {
+    sim.event_log().into_iter().map(|e| e.event).collect()
+}
+
+#[test]
+fn a_round_from_lobby_to_results() {
+    let mut sim = lobby(1, &["ann", "bob"]);
+    sim.set_ghost_type(Some(GhostType::Spirit)).unwrap();
+    assert!(sim.start(None, None, Difficulty::default(), false).is_err());
+    sim.set_ready(addr(0), true).unwrap();
+    sim.set_ready(addr(1), true).unwrap();
+    sim.start(None, None, Difficulty::default(), false).unwrap();
+    assert_eq!(sim.phase(), GamePhase::Running);
+
+    sim.update_player_loc(addr(0), 0).unwrap();
+    sim.update_player_loc(addr(1), 0).unwrap();
+    sim.advance(Duration::from_secs(120));
+    assert!(sim.time() >= Duration::from_secs(120));
+
+    let summary = sim.submit_guess(addr(0), GhostType::Spirit).unwrap();
+    assert!(summary.correct_guess);
+    assert_eq!(sim.phase(), GamePhase::Finished);
+    assert!(sim.submit_guess(addr(1), GhostType::Spirit).is_err());
+
+    let log = events(&sim);
+    assert!(matches!(log[2], SimEvent::RoundStarted { ghost_type: GhostType::Spirit, .. }));
+    assert_eq!(log.last(), Some(&SimEvent::RoundEnded));
+    let rewarded = log.iter().filter(|e| matches!(e, SimEvent::Rewarded { .. })).count();
+    assert_eq!(rewarded, 2);
+}
+
+#[test]
+fn a_seed_plays_out_the_same_every_time() {
+    let play = |seed| {
+        let mut sim = lobby(seed, &["ann"]);
+        sim.start(None, None, Difficulty::default(), true).unwrap();
+        sim.update_player_loc(addr(0), 0).unwrap();
+        sim.advance(Duration::from_secs(300));
+        sim.event_log()
+    };
+    assert_eq!(play(7), play(7));
+    assert_ne!(play(7), play(8));
+}
+
+#[test]
+fn players_can_only_be_where_the_map_has_rooms() {
+    let mut sim = lobby(1, &["ann"]);
+    sim.start(None, None, Difficulty::default(), true).unwrap();
+    assert!(sim.update_player_loc(addr(0), 999).is_err());
+    sim.update_player_loc(addr(0), 0).unwrap();
+}
+
+#[test]
+fn everyone_leaving_sends_the_round_back_to_the_lobby() {
+    let mut sim = lobby(1, &["ann"]);
+    sim.start(None, None, Difficulty::default(), true).unwrap();
+    assert_eq!(sim.remove_player(addr(0)).as_deref(), Some("ann"));
+    sim.advance(Duration::from_secs(3600));
+    assert_eq!(sim.phase(), GamePhase::Lobby);
+    assert_eq!(events(&sim).last(), Some(&SimEvent::RoundEnded));
+}